@@ -0,0 +1,20 @@
+use criterion as c;
+use criterion::{criterion_group, criterion_main};
+use ellidri::bench::Broadcast;
+
+const TEXT: &str = "Hello there! This is what a typical message looks like.";
+
+fn broadcast(c: &mut c::Criterion) {
+    let mut group = c.benchmark_group("PRIVMSG fan-out");
+    for members in [100, 5_000] {
+        let mut channel = Broadcast::new(members);
+        assert_eq!(channel.privmsg(TEXT), members - 1);
+        group.bench_function(format!("{members} members"), |b| {
+            b.iter(|| channel.privmsg(c::black_box(TEXT)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcast);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Records when ellidri is built, for the INFO command.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Reproducible builds pin the date with SOURCE_DATE_EPOCH.
+    let built = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map_or_else(SystemTime::now, |secs| {
+            UNIX_EPOCH + Duration::from_secs(secs)
+        });
+    println!(
+        "cargo:rustc-env=ELLIDRI_BUILD_DATE={}",
+        humantime::format_rfc3339_seconds(built)
+    );
+}
@@ -0,0 +1,53 @@
+use criterion as c;
+use criterion::{criterion_group, criterion_main};
+use ellidri_tokens::{rpl, Buffer, Command, ReplyBuffer};
+
+const TEXT: &str = "Hello there! This is what a typical message looks like.";
+
+fn buffers(c: &mut c::Criterion) {
+    c.bench_function("Buffer PRIVMSG", |b| {
+        let mut buf = Buffer::with_capacity(512);
+        b.iter(|| {
+            buf.clear();
+            buf.tagged_message("+draft/reply=41")
+                .tag("msgid", Some("dGhpcyBpcyBhIG1zZ2lk"))
+                .tag("time", Some("2020-06-01T12:00:00.000Z"))
+                .prefixed_command("nick!~user@127.0.0.1", Command::PrivMsg)
+                .param("#ellidri")
+                .trailing_param(c::black_box(TEXT));
+            c::black_box(buf.get());
+        })
+    })
+    .bench_function("ReplyBuffer labeled WHOIS", |b| {
+        b.iter(|| {
+            let mut rb = ReplyBuffer::new("ellidri.dev", "senpai", "label123");
+            rb.lr_batch_begin();
+            rb.reply(rpl::WHOISUSER)
+                .param("kouhai")
+                .param("~user")
+                .param("127.0.0.1")
+                .param("*")
+                .trailing_param("Kouhai");
+            rb.reply(rpl::WHOISCHANNELS)
+                .param("kouhai")
+                .trailing_param("@#ellidri +#rust #irc");
+            rb.reply(rpl::WHOISSERVER)
+                .param("kouhai")
+                .param("ellidri.dev")
+                .trailing_param("Your kawaii IRC server");
+            rb.reply(rpl::WHOISIDLE)
+                .param("kouhai")
+                .fmt_param(42)
+                .fmt_param(1_591_000_000)
+                .trailing_param("seconds idle, signon time");
+            rb.reply(rpl::ENDOFWHOIS)
+                .param("kouhai")
+                .trailing_param("End of WHOIS list");
+            rb.lr_end();
+            c::black_box(rb.build())
+        })
+    });
+}
+
+criterion_group!(benches, buffers);
+criterion_main!(benches);
@@ -7,13 +7,18 @@ use ellidri_tokens as irc;
 const MESSAGE: &str = "@label=hi;;+=;++;+;=;+data=iodjziidd,e15f531e5f3z5efzef\\s\\s;+=++;msgid=hello;;a;a;a;a;a;a;AD.AD.AD.;;KKKKKKKKKKKKKKKKKKKKKKKKKKKKKKKKK=VVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVVV :wow-thats-what-i-call-a-domain-name-dot-com.pizza.beer USER someusername what is :going on this is not what I paid for please stop using fake real names the more so if they're this long!!!";
 
 const TAG: &str = "my_really_good_tag.surprise.its.actually_a-domoain_name.com/therealnameofthevendoredtagorshouldisaykeyinsteadofnametoreallyfollowircv3sbehavior=This\\sis\\sthe\\svalue\\sof\\sthe\\key.\\nYou\\scan\\sput\\sescapes\\sin\\sthe\\value\\sso\\sthat\\sit\\scan\\scontain\\sspaces\\sor\\snewlines\\nFor\\sexample:\\s\\\\s\\sis\\sthe\\sescaped\\sspace,\\sand\\s\\\\:\\sis\"\\:\".\\sSimple,\\sright?";
+const PRIVMSG: &str = "@+draft/reply=41;+typing=done :nick!~user@127.0.0.1 PRIVMSG #ellidri :Hello there! This is what a typical message looks like.\r\n";
+
 const _COMPLEX_TAGS: &str = "@msgid=42;time=12:12:12:12:12;+=;=;;++++;+draft/reply=41;+do.it/dont";
 
 fn message(c: &mut c::Criterion) {
     let mut long_message = String::with_capacity(4096);
     long_message.push_str(MESSAGE);
     (0..3000).for_each(|_| long_message.push('a'));
-    c.bench_function("Message::parse() + tags()", |b| {
+    c.bench_function("Message::parse() PRIVMSG", |b| {
+        b.iter(|| irc::Message::parse(c::black_box(PRIVMSG)).unwrap())
+    })
+    .bench_function("Message::parse() + tags()", |b| {
         b.iter(|| {
             let msg = irc::Message::parse(c::black_box(&long_message)).unwrap();
             irc::tags(msg.tags).for_each(|tag| {
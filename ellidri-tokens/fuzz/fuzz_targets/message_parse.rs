@@ -0,0 +1,21 @@
+#![no_main]
+
+use ellidri_tokens::{Message, PARAMS_LENGTH};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    let msg = match Message::parse(data) {
+        Some(msg) => msg,
+        None => return,
+    };
+    assert!(msg.num_params <= PARAMS_LENGTH);
+    assert!(msg.params[msg.num_params..].iter().all(|p| p.is_empty()));
+    if let Err(command) = msg.command {
+        assert!(!command.is_empty());
+    }
+    let _ = msg.has_enough_params();
+    for tag in msg.tags() {
+        assert!(!tag.key.is_empty());
+        let _ = tag.unescape_value();
+    }
+});
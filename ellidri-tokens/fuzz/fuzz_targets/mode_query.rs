@@ -0,0 +1,22 @@
+#![no_main]
+
+use ellidri_tokens::mode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    // "<modes> <param> <param>...", like the parameters of a MODE command.
+    let mut words = data.split(' ');
+    let modes = words.next().unwrap_or("");
+    let params: Vec<&str> = words.collect();
+
+    for change in mode::channel_query(modes, &params).flatten() {
+        let _ = (change.value(), change.symbol());
+        if let Some(param) = change.param() {
+            assert!(param == "*" || params.contains(&param));
+        }
+    }
+    for change in mode::user_query(modes).flatten() {
+        let _ = (change.value(), change.symbol());
+    }
+    let _ = mode::is_channel_mode_string(modes);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use ellidri_tokens::{tags, Tag};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    for tag in tags(data) {
+        let value = tag.unescape_value();
+        assert!(value.len() <= tag.value.map_or(0, str::len));
+    }
+
+    let tag = Tag {
+        key: "",
+        value: Some(data),
+    };
+    let mut buf = String::from("prefix");
+    tag.unescape_value_into(&mut buf);
+    assert!(buf.starts_with("prefix"));
+});
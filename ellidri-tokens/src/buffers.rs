@@ -1,7 +1,9 @@
-use crate::{Command, MESSAGE_LENGTH};
+use crate::{Command, CLIENT_TAGS_LENGTH, MESSAGE_LENGTH, TAGS_LENGTH};
+use alloc::string::String;
+use core::fmt;
+use core::fmt::Write as _;
+#[cfg(feature = "std")]
 use std::cell::RefCell;
-use std::fmt;
-use std::fmt::Write as _;
 
 /// Helper to build an IRC message.
 ///
@@ -123,32 +125,34 @@ impl Drop for MessageBuffer<'_> {
     }
 }
 
-thread_local! {
-    static UNESCAPED_VALUE: RefCell<String> = RefCell::new(String::new());
-}
-
-fn write_escaped(buf: &mut String, value: impl fmt::Display) {
-    UNESCAPED_VALUE.with(|s| {
-        let mut s = s.borrow_mut();
-
-        s.clear();
-        let _ = write!(s, "{value}");
+/// Escapes tag values as they are written.
+struct Escaper<'a>(&'a mut String);
 
-        buf.reserve(s.len());
+impl fmt::Write for Escaper<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.reserve(s.len());
         for c in s.chars() {
             match c {
-                ';' => buf.push_str("\\:"),
-                ' ' => buf.push_str("\\s"),
-                '\r' => buf.push_str("\\r"),
-                '\n' => buf.push_str("\\n"),
-                '\\' => buf.push_str("\\\\"),
-                c => buf.push(c),
+                ';' => self.0.push_str("\\:"),
+                ' ' => self.0.push_str("\\s"),
+                '\r' => self.0.push_str("\\r"),
+                '\n' => self.0.push_str("\\n"),
+                '\\' => self.0.push_str("\\\\"),
+                c => self.0.push(c),
             }
         }
-    });
+        Ok(())
+    }
+}
+
+fn write_escaped(buf: &mut String, value: impl fmt::Display) {
+    let _ = write!(Escaper(buf), "{value}");
 }
 
 /// Helper to build the tags of an IRC message.
+///
+/// Tags that would make the tags longer than `TAGS_LENGTH` are left out, as well as client-only
+/// tags past `CLIENT_TAGS_LENGTH`.
 pub struct TagBuffer<'a> {
     buf: &'a mut String,
     tag_start: usize,
@@ -169,8 +173,16 @@ impl<'a> TagBuffer<'a> {
         self.buf.len() == self.tag_start + 1
     }
 
+    /// The length of the tag data, without the leading '@'.
+    fn data_len(&self) -> usize {
+        self.buf.len() - self.tag_start - 1
+    }
+
     /// Adds a new tag to the buffer, with the given `key` and `value`.
+    ///
+    /// The tag is left out if it doesn't fit in `TAGS_LENGTH`.
     pub fn tag(self, key: &str, value: Option<impl fmt::Display>) -> Self {
+        let len = self.buf.len();
         if !self.is_empty() {
             self.buf.push(';');
         }
@@ -179,12 +191,21 @@ impl<'a> TagBuffer<'a> {
             self.buf.push('=');
             write_escaped(self.buf, value);
         }
+        // The leading '@' and the trailing space count too.
+        if TAGS_LENGTH < self.data_len() + 2 {
+            self.buf.truncate(len);
+        }
         self
     }
 
-    /// Adds the tag string `s`.
+    /// Adds the client tag string `s`, unless the client tag data would exceed
+    /// `CLIENT_TAGS_LENGTH`.
     fn raw_tag(self, s: &str) -> Self {
-        if !self.is_empty() {
+        let sep = usize::from(!self.is_empty());
+        if CLIENT_TAGS_LENGTH < self.data_len() + sep + s.len() {
+            return self;
+        }
+        if sep == 1 {
             self.buf.push(';');
         }
         self.buf.push_str(s);
@@ -344,18 +365,25 @@ impl Buffer {
     }
 }
 
+#[cfg(feature = "std")]
 thread_local! {
     static DOMAIN: RefCell<String> = RefCell::new(String::with_capacity(128));
     static NICKNAME: RefCell<String> = RefCell::new(String::with_capacity(64));
     static LABEL: RefCell<String> = RefCell::new(String::with_capacity(64));
 }
 
+#[cfg(feature = "std")]
 pub struct ReplyBuffer {
     buf: Buffer,
     batch: Option<usize>,
     has_label: bool,
+
+    /// The start of the message that carries the label, when it is the only reply so far and is
+    /// not in a batch.  It is moved to a labeled-response batch if other replies follow.
+    labeled: Option<usize>,
 }
 
+#[cfg(feature = "std")]
 impl ReplyBuffer {
     pub fn new(domain: &str, nickname: &str, label: &str) -> Self {
         Self::set_nick(nickname);
@@ -365,6 +393,7 @@ impl ReplyBuffer {
             buf: Buffer::new(),
             batch: None,
             has_label: !label.is_empty(),
+            labeled: None,
         }
     }
 
@@ -372,12 +401,22 @@ impl ReplyBuffer {
         self.buf.is_empty()
     }
 
+    /// Whether the next message will carry the label of the request.
+    pub fn has_label(&self) -> bool {
+        self.has_label
+    }
+
     pub fn tagged_message(&mut self, tags: &str) -> TagBuffer<'_> {
+        if let Some(start) = self.labeled.take() {
+            self.lr_batch_wrap(start);
+        }
         self.buf.reserve(crate::MESSAGE_LENGTH);
+        let start = self.buf.buf.len();
         let mut msg = self.buf.tagged_message(tags);
 
         if self.has_label {
             self.has_label = false;
+            self.labeled = Some(start);
             msg = LABEL.with(|s| msg.tag("label", Some(&s.borrow())));
         }
         if let Some(batch) = self.batch {
@@ -421,7 +460,24 @@ impl ReplyBuffer {
         });
     }
 
+    /// Moves the message at `start`, which carries the label, to a new labeled-response batch.
+    fn lr_batch_wrap(&mut self, start: usize) {
+        let labeled = self.buf.buf.split_off(start);
+        let (tags, rest) = labeled[1..].split_once(' ').unwrap_or_default();
+        self.has_label = true;
+        self.lr_batch_begin();
+        let batch = self.batch.unwrap_or_default();
+        let buf = &mut self.buf.buf;
+        buf.push('@');
+        for tag in tags.split(';').filter(|tag| !tag.starts_with("label=")) {
+            buf.push_str(tag);
+            buf.push(';');
+        }
+        let _ = write!(buf, "batch={batch} {rest}");
+    }
+
     pub fn lr_end(&mut self) {
+        self.labeled = None;
         if !self.has_label && self.batch.is_none() {
             return;
         }
@@ -438,10 +494,28 @@ impl ReplyBuffer {
     }
 
     pub fn batch_begin(&mut self, name: &str) {
-        let new_batch = self.new_batch();
-        self.prefixed_message("BATCH")
+        self.batch_begin_with(name, &[]);
+    }
+
+    /// Starts a batch whose type takes parameters, e.g. the target of a `chathistory` batch.
+    pub fn batch_begin_with(&mut self, name: &str, params: &[&str]) {
+        // A batch sent in response to a labeled request goes in the labeled-response batch, along
+        // with the reply that carries the label, if any, so that the new batch is nested in it.
+        match self.labeled.take() {
+            Some(start) => self.lr_batch_wrap(start),
+            None => self.lr_batch_begin(),
+        }
+        // The start of the batch is part of the parent batch, if any.
+        let new_batch = self.batch.map_or(0, |prev| prev + 1);
+        let mut msg = self
+            .prefixed_message("BATCH")
             .fmt_param(format_args!("+{new_batch}"))
             .param(name);
+        for param in params {
+            msg = msg.param(param);
+        }
+        drop(msg);
+        self.batch = Some(new_batch);
     }
 
     pub fn batch_end(&mut self) {
@@ -491,3 +565,98 @@ impl ReplyBuffer {
         next
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    fn replies(label: &str, write: impl FnOnce(&mut ReplyBuffer)) -> String {
+        let mut rb = ReplyBuffer::new("ellidri.test", "nick", label);
+        write(&mut rb);
+        rb.lr_end();
+        rb.build()
+    }
+
+    #[test]
+    fn test_tag_limits() {
+        let mut buf = Buffer::new();
+        let client_tags = format!("+a={};+b=1;+c=2;label=3", "x".repeat(4086));
+        let value = "y".repeat(4080);
+        buf.tagged_message(&client_tags)
+            .tag("first", Some(&value))
+            .tag("second", Some(&value))
+            .tag("third", None::<&str>)
+            .prefixed_command("nick", "TAGMSG")
+            .param("#chan");
+        let res = buf.build();
+        let (tags, rest) = res.split_once(' ').unwrap();
+        assert_eq!(rest, ":nick TAGMSG #chan\r\n");
+        assert!(tags.len() < TAGS_LENGTH);
+        assert!(tags.starts_with(&format!("@{};first=y", &client_tags[..4094])));
+        assert!(tags.ends_with("y;third"));
+        assert!(!tags.contains("+c=2") && !tags.contains("second"));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_labeled_response() {
+        let res = replies("", |rb| {
+            rb.reply("001");
+            rb.reply("002");
+        });
+        assert_eq!(res, ":ellidri.test 001 nick\r\n:ellidri.test 002 nick\r\n");
+
+        let res = replies("abc", |_| {});
+        assert_eq!(res, "@label=abc :ellidri.test ACK\r\n");
+
+        let res = replies("abc", |rb| {
+            rb.reply("001");
+        });
+        assert_eq!(res, "@label=abc :ellidri.test 001 nick\r\n");
+
+        let res = replies("abc", |rb| {
+            rb.tagged_message("+draft/react=x")
+                .prefixed_command("nick", "TAGMSG")
+                .param("#chan");
+            rb.reply("002");
+        });
+        assert_eq!(
+            res,
+            "@label=abc :ellidri.test BATCH +0 labeled-response\r\n\
+             @+draft/react=x;batch=0 :nick TAGMSG #chan\r\n\
+             @batch=0 :ellidri.test 002 nick\r\n\
+             :ellidri.test BATCH -0\r\n"
+        );
+
+        let res = replies("abc", |rb| {
+            rb.batch_begin("netjoin");
+            rb.reply("001");
+            rb.batch_end();
+        });
+        assert_eq!(
+            res,
+            "@label=abc :ellidri.test BATCH +0 labeled-response\r\n\
+             @batch=0 :ellidri.test BATCH +1 netjoin\r\n\
+             @batch=1 :ellidri.test 001 nick\r\n\
+             @batch=0 :ellidri.test BATCH -1\r\n\
+             :ellidri.test BATCH -0\r\n"
+        );
+
+        let res = replies("abc", |rb| {
+            rb.reply("001");
+            rb.batch_begin_with("chathistory", &["#chan"]);
+            rb.reply("002");
+            rb.batch_end();
+        });
+        assert_eq!(
+            res,
+            "@label=abc :ellidri.test BATCH +0 labeled-response\r\n\
+             @batch=0 :ellidri.test 001 nick\r\n\
+             @batch=0 :ellidri.test BATCH +1 chathistory #chan\r\n\
+             @batch=1 :ellidri.test 002 nick\r\n\
+             @batch=0 :ellidri.test BATCH -1\r\n\
+             :ellidri.test BATCH -0\r\n"
+        );
+    }
+} // mod tests
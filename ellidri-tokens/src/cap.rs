@@ -0,0 +1,256 @@
+//! Capability negotiation messages (CAP LS, LIST, REQ, ACK, NAK, NEW and DEL).
+//!
+//! <https://ircv3.net/specs/extensions/capability-negotiation>
+
+#[cfg(feature = "std")]
+use crate::ReplyBuffer;
+use crate::{Command, Message};
+use core::fmt;
+
+/// The maximum length of the capability list of a CAP reply.  It leaves room for the prefix, the
+/// nickname and the subcommand in 512 bytes.  Longer lists are split, see `Split`.
+pub const LIST_LENGTH: usize = 400;
+
+/// A capability, with its value if any, as listed by CAP LS and CAP NEW (e.g. `sasl=PLAIN`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capability<'a> {
+    pub name: &'a str,
+    pub value: Option<&'a str>,
+}
+
+impl<'a> Capability<'a> {
+    /// Parses `name[=value]`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use ellidri_tokens::cap::Capability;
+    /// let sasl = Capability::parse("sasl=PLAIN,EXTERNAL");
+    /// assert_eq!(sasl.name, "sasl");
+    /// assert_eq!(sasl.value, Some("PLAIN,EXTERNAL"));
+    /// assert_eq!(sasl.values().collect::<Vec<_>>(), ["PLAIN", "EXTERNAL"]);
+    ///
+    /// assert_eq!(Capability::parse("batch").value, None);
+    /// ```
+    pub fn parse(s: &'a str) -> Self {
+        match s.split_once('=') {
+            Some((name, value)) => Self {
+                name,
+                value: Some(value),
+            },
+            None => Self {
+                name: s,
+                value: None,
+            },
+        }
+    }
+
+    /// The comma-separated items of the value, as used by `sasl` and `draft/metadata-2`.
+    pub fn values(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .unwrap_or_default()
+            .split(',')
+            .filter(|value| !value.is_empty())
+    }
+}
+
+impl fmt::Display for Capability<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if let Some(value) = self.value {
+            write!(f, "={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the capabilities of a CAP LS or CAP NEW list.
+pub fn capabilities(list: &str) -> impl Iterator<Item = Capability<'_>> {
+    list.split_whitespace().map(Capability::parse)
+}
+
+/// Returns the capabilities of a CAP REQ, ACK or NAK list, with `false` for those prefixed with
+/// '-', which are disabled.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::cap;
+/// let mut changes = cap::changes("batch -echo-message");
+/// assert_eq!(changes.next(), Some(("batch", true)));
+/// assert_eq!(changes.next(), Some(("echo-message", false)));
+/// assert_eq!(changes.next(), None);
+/// ```
+pub fn changes(list: &str) -> impl Iterator<Item = (&str, bool)> {
+    list.split_whitespace()
+        .map(|word| match word.strip_prefix('-') {
+            Some(word) => (word, false),
+            None => (word, true),
+        })
+}
+
+/// A CAP message sent by a server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reply<'a> {
+    /// The subcommand, e.g. "LS" or "ACK".
+    pub subcommand: &'a str,
+
+    /// Whether the list continues in the next reply, for multi-line CAP LS and CAP LIST.
+    pub more: bool,
+
+    pub list: &'a str,
+}
+
+impl<'a> Reply<'a> {
+    /// Returns the CAP reply in `msg`, or `None` if it is not a CAP message with a subcommand.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use ellidri_tokens::{cap, Message};
+    /// let msg = Message::parse(":ellidri.dev CAP * LS * :batch sasl=PLAIN").unwrap();
+    /// let reply = cap::Reply::parse(&msg).unwrap();
+    /// assert_eq!(reply.subcommand, "LS");
+    /// assert!(reply.more);
+    /// assert_eq!(cap::capabilities(reply.list).count(), 2);
+    ///
+    /// let msg = Message::parse(":ellidri.dev CAP nick ACK :-batch").unwrap();
+    /// let reply = cap::Reply::parse(&msg).unwrap();
+    /// assert_eq!((reply.subcommand, reply.more, reply.list), ("ACK", false, "-batch"));
+    /// ```
+    pub fn parse(msg: &Message<'a>) -> Option<Self> {
+        if msg.command != Ok(Command::Cap) || msg.num_params < 2 {
+            return None;
+        }
+        let params = &msg.params[1..msg.num_params];
+        let (more, list) = match params {
+            [_, "*", list] => (true, *list),
+            [_, list, ..] => (false, *list),
+            [_] => (false, ""),
+            [] => return None,
+        };
+        Some(Self {
+            subcommand: params[0],
+            more,
+            list,
+        })
+    }
+}
+
+/// How capability lists longer than `LIST_LENGTH` are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// On one line anyway, for clients that don't support multi-line replies (before CAP 302).
+    Never,
+
+    /// In several replies, all but the last one marked with `*`, for CAP LS and CAP LIST.
+    Continued,
+
+    /// In several independent replies, for CAP NEW and CAP DEL.
+    Separate,
+}
+
+/// Writes the CAP replies of the given subcommand and capabilities to `rb`.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::{cap, ReplyBuffer};
+/// let mut rb = ReplyBuffer::new("ellidri.dev", "nick", "");
+/// let caps = ["batch", "sasl=PLAIN"].iter().copied().map(cap::Capability::parse);
+/// cap::write_replies(&mut rb, "LS", caps, cap::Split::Continued);
+/// assert_eq!(&rb.build(), ":ellidri.dev CAP nick LS :batch sasl=PLAIN\r\n");
+/// ```
+#[cfg(feature = "std")]
+pub fn write_replies<I>(rb: &mut ReplyBuffer, subcommand: &str, caps: I, split: Split)
+where
+    I: IntoIterator,
+    I::Item: fmt::Display,
+{
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for cap in caps {
+        let cap = cap.to_string();
+        if split != Split::Never && !line.is_empty() && LIST_LENGTH < line.len() + 1 + cap.len() {
+            lines.push(core::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&cap);
+    }
+    for line in lines {
+        let msg = rb.reply(Command::Cap).param(subcommand);
+        let msg = if split == Split::Continued {
+            msg.param("*")
+        } else {
+            msg
+        };
+        msg.trailing_param(&line);
+    }
+    rb.reply(Command::Cap)
+        .param(subcommand)
+        .trailing_param(&line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    fn replies(caps: &[&str], split: Split) -> Vec<String> {
+        let mut rb = ReplyBuffer::new("ellidri.dev", "nick", "");
+        write_replies(&mut rb, "LS", caps, split);
+        rb.build().lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_write_replies() {
+        assert_eq!(
+            replies(&[], Split::Continued),
+            [":ellidri.dev CAP nick LS :"]
+        );
+
+        let long = "a".repeat(250);
+        let caps = [long.as_str(), long.as_str(), "batch"];
+        let res = replies(&caps, Split::Continued);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0], format!(":ellidri.dev CAP nick LS * :{long}"));
+        assert_eq!(res[1], format!(":ellidri.dev CAP nick LS :{long} batch"));
+
+        let res = replies(&caps, Split::Separate);
+        assert_eq!(res[0], format!(":ellidri.dev CAP nick LS :{long}"));
+        assert_eq!(res.len(), 2);
+
+        let res = replies(&caps, Split::Never);
+        assert_eq!(res.len(), 1);
+        assert!(res[0].ends_with(&format!("{long} {long} batch")));
+    }
+
+    #[test]
+    fn test_parse() {
+        let caps: Vec<_> =
+            capabilities(" batch  draft/metadata-2=max-subs=10,max-keys=5 ").collect();
+        assert_eq!(caps[0], Capability::parse("batch"));
+        assert_eq!(caps[1].name, "draft/metadata-2");
+        assert_eq!(caps[1].value, Some("max-subs=10,max-keys=5"));
+        assert_eq!(
+            caps[1].to_string(),
+            "draft/metadata-2=max-subs=10,max-keys=5"
+        );
+
+        let msg = Message::parse("CAP * NEW :sasl").unwrap();
+        let reply = Reply::parse(&msg).unwrap();
+        assert_eq!(
+            (reply.subcommand, reply.more, reply.list),
+            ("NEW", false, "sasl")
+        );
+        let msg = Message::parse("CAP * LIST").unwrap();
+        assert_eq!(Reply::parse(&msg).unwrap().list, "");
+        let msg = Message::parse("CAP *").unwrap();
+        assert_eq!(Reply::parse(&msg), None);
+        let msg = Message::parse("PRIVMSG * LS").unwrap();
+        assert_eq!(Reply::parse(&msg), None);
+    }
+} // mod tests
@@ -0,0 +1,256 @@
+//! A tokio-util codec to read and write IRC messages.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use ellidri_tokens::codec::{Frame, IrcCodec};
+//! # use tokio_util::codec::Decoder;
+//! # use bytes::BytesMut;
+//! let mut codec = IrcCodec::new();
+//! let mut buf = BytesMut::from("PING :ellidri.dev\r\nPRIV");
+//!
+//! let frame = codec.decode(&mut buf).unwrap().unwrap();
+//! assert_eq!(frame, Frame::Line("PING :ellidri.dev".to_owned()));
+//! assert_eq!(frame.message().unwrap().params[0], "ellidri.dev");
+//!
+//! assert_eq!(codec.decode(&mut buf).unwrap(), None);
+//! ```
+
+use crate::{Message, MESSAGE_LENGTH, TAGS_LENGTH};
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// What `IrcCodec` reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// A line, without its trailing CRLF.
+    Line(String),
+
+    /// A line longer than the maximum length, which has been discarded.
+    TooLong,
+}
+
+impl Frame {
+    /// Parses the line, if any.
+    pub fn message(&self) -> Option<Message<'_>> {
+        match self {
+            Self::Line(line) => Message::parse(line),
+            Self::TooLong => None,
+        }
+    }
+}
+
+/// Splits a byte stream into IRC lines, and writes IRC messages.
+///
+/// Lines end with LF, optionally preceded by CR.  Like IRCv3 says, the tags of a line can be
+/// `TAGS_LENGTH` bytes long, and the rest of it `MESSAGE_LENGTH` bytes, LF included.  Longer lines
+/// are discarded and read as `Frame::TooLong`.  Invalid UTF-8 is an error.
+///
+/// Messages are written as is, with CRLF appended if they don't end with LF.  They can hold
+/// several lines, like the output of `Buffer`.
+#[derive(Clone, Debug)]
+pub struct IrcCodec {
+    message_length: usize,
+
+    /// Where to look for LF next, so that bytes are not searched twice.
+    next_index: usize,
+
+    /// Whether the rest of the current line is being discarded.
+    discarding: bool,
+}
+
+impl Default for IrcCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IrcCodec {
+    /// Creates a codec that accepts messages of `MESSAGE_LENGTH` bytes, without tags.
+    pub fn new() -> Self {
+        Self::with_message_length(MESSAGE_LENGTH)
+    }
+
+    /// Creates a codec that accepts messages of `message_length` bytes, without tags and LF
+    /// included.
+    pub fn with_message_length(message_length: usize) -> Self {
+        Self {
+            message_length,
+            next_index: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn message_length(&self) -> usize {
+        self.message_length
+    }
+
+    /// The maximum length of the line at the start of `buf`.
+    fn line_length(&self, buf: &[u8]) -> usize {
+        if buf.first() != Some(&b'@') {
+            return self.message_length;
+        }
+        let tags = &buf[..buf.len().min(TAGS_LENGTH)];
+        match tags.iter().position(|&b| b == b' ') {
+            Some(i) => i + 1 + self.message_length,
+            // The end of the tags has not been read yet, or the tags are too long.
+            None => TAGS_LENGTH,
+        }
+    }
+
+    fn line(mut line: BytesMut) -> io::Result<Frame> {
+        if line.ends_with(b"\n") {
+            line.truncate(line.len() - 1);
+        }
+        if line.ends_with(b"\r") {
+            line.truncate(line.len() - 1);
+        }
+        match String::from_utf8(line.to_vec()) {
+            Ok(line) => Ok(Frame::Line(line)),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+impl Decoder for IrcCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Frame>> {
+        if !self.discarding {
+            let line_length = self.line_length(buf);
+            let read_to = buf.len().min(line_length);
+            let lf = buf[self.next_index..read_to]
+                .iter()
+                .position(|&b| b == b'\n');
+            if let Some(i) = lf {
+                let line = buf.split_to(self.next_index + i + 1);
+                self.next_index = 0;
+                return Self::line(line).map(Some);
+            }
+            if buf.len() < line_length {
+                self.next_index = read_to;
+                return Ok(None);
+            }
+            self.next_index = 0;
+            self.discarding = true;
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                buf.advance(i + 1);
+                self.discarding = false;
+                Ok(Some(Frame::TooLong))
+            }
+            None => {
+                buf.clear();
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> io::Result<Option<Frame>> {
+        if let Some(frame) = self.decode(buf)? {
+            return Ok(Some(frame));
+        }
+        self.next_index = 0;
+        if self.discarding {
+            self.discarding = false;
+            buf.clear();
+            return Ok(Some(Frame::TooLong));
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let line = buf.split();
+        Self::line(line).map(Some)
+    }
+}
+
+impl<T: AsRef<str>> Encoder<T> for IrcCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: T, buf: &mut BytesMut) -> io::Result<()> {
+        let msg = msg.as_ref();
+        buf.reserve(msg.len() + 2);
+        buf.put(msg.as_bytes());
+        if !msg.ends_with('\n') {
+            buf.put(&b"\r\n"[..]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(s: &str) -> Option<Frame> {
+        Some(Frame::Line(s.to_owned()))
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::from("PING a\nPING b\r\n\r\nPI");
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("PING a"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("PING b"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), line(""));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"NG c");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), line("PING c"));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+
+        let mut buf = BytesMut::from(&b"PRIVMSG a :\xff\r\n"[..]);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decode_too_long() {
+        let mut codec = IrcCodec::with_message_length(8);
+        let mut buf = BytesMut::from("PING 123");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"456789");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"\r\nPING 1\r\nPING 12\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::TooLong));
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("PING 1"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::TooLong));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"PING 1234");
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some(Frame::TooLong));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_tags() {
+        let mut codec = IrcCodec::with_message_length(8);
+        let tags = format!("@a={}", "x".repeat(TAGS_LENGTH - 4));
+        let mut buf = BytesMut::from(tags.as_str());
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b" PING 1\r\n@a PING 12\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            line(&format!("{tags} PING 1"))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::TooLong));
+
+        buf.extend_from_slice(tags.as_bytes());
+        buf.extend_from_slice(b"x PING\r\n@a=b\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::TooLong));
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("@a=b"));
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut codec = IrcCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode("PING a", &mut buf).unwrap();
+        codec.encode("PING b\r\nPING c\r\n", &mut buf).unwrap();
+        assert_eq!(&buf[..], b"PING a\r\nPING b\r\nPING c\r\n");
+    }
+} // mod tests
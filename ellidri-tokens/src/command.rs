@@ -1,4 +1,4 @@
-use std::fmt;
+use core::fmt;
 
 macro_rules! commands {
     ( $( $cmd:ident $cmd_str:literal $n:literal )* ) => {
@@ -13,6 +13,9 @@ macro_rules! commands {
         }
 
         impl Command {
+            /// All the known commands, in the order of the table.
+            pub const ALL: &'static [Command] = &[ $( Command::$cmd, )* ];
+
             /// From a given command string, returns the corresponding command, or `None`
             /// otherwise.
             ///
@@ -101,35 +104,68 @@ macro_rules! commands {
 
 commands! {
 //  Ident.   String     Minimum # of params
+    Access   "ACCESS"   1
     Admin    "ADMIN"    0
     Authenticate "AUTHENTICATE" 1
+    AutoAway "AUTOAWAY" 0
+    AutoReplay "AUTOREPLAY" 0
     Away     "AWAY"     0
     Cap      "CAP"      1
+    Challenge "CHALLENGE" 1
+    ChatHistory "CHATHISTORY" 4
+    ChgHost  "CHGHOST"  2
+    Dump     "DUMP"     0
+    Founder  "FOUNDER"  1
+    Ghost    "GHOST"    1
+    GlobOps  "GLOBOPS"  1
+    Help     "HELP"     0
+    HelpOp   "HELPOP"   0
     Info     "INFO"     0
     Invite   "INVITE"   2
+    IsOn     "ISON"     1
     Join     "JOIN"     1
     Kick     "KICK"     2
     Kill     "KILL"     2
+    Language "LANGUAGE" 1
     List     "LIST"     0
+    Lockdown "LOCKDOWN" 0
     LUsers   "LUSERS"   0
+    MassKill "MASSKILL" 1
+    Metadata "METADATA" 2
     Mode     "MODE"     1
     Motd     "MOTD"     0
+    Mute     "MUTE"     3
     Names    "NAMES"    0
     Nick     "NICK"     1
     Notice   "NOTICE"   2
-    Oper     "OPER"     2
+    Oper     "OPER"     1
+    OperMotd "OPERMOTD" 0
     Part     "PART"     1
     Pass     "PASS"     1
     Ping     "PING"     1
     Pong     "PONG"     1
     PrivMsg  "PRIVMSG"  2
     Quit     "QUIT"     0
+    Regain   "REGAIN"   1
     Rehash   "REHASH"   0
+    SaJoin   "SAJOIN"   2
+    SaMode   "SAMODE"   2
+    SaNick   "SANICK"   2
+    SaPart   "SAPART"   2
+    SaTopic  "SATOPIC"  2
+    Search   "SEARCH"   1
+    SetHost  "SETHOST"  1
     SetName  "SETNAME"  1
+    SpamFilter "SPAMFILTER" 0
+    Stats    "STATS"    0
     TagMsg   "TAGMSG"   1
     Time     "TIME"     0
     Topic    "TOPIC"    1
+    TopicHistory "TOPICHISTORY" 1
+    Trace    "TRACE"    0
+    Unmute   "UNMUTE"   2
     User     "USER"     4
+    UserHost "USERHOST" 1
     Version  "VERSION"  0
     Who      "WHO"      0
     WhoIs    "WHOIS"    1
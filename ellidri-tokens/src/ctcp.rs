@@ -0,0 +1,104 @@
+//! Client-To-Client Protocol messages, and DCC offers in particular.
+//!
+//! <https://modern.ircdocs.horse/ctcp.html>
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+const DELIM: char = '\x01';
+
+/// The DCC requests that offer a direct connection to the recipient.
+const DCC_OFFERS: [&str; 4] = ["SEND", "CHAT", "SSEND", "SCHAT"];
+
+/// Whether the given CTCP message body (without delimiters) is a DCC offer.
+fn is_offer(body: &str) -> bool {
+    let mut words = body.split(' ');
+    let is_dcc = words.next().is_some_and(|w| w.eq_ignore_ascii_case("DCC"));
+    is_dcc
+        && words
+            .next()
+            .is_some_and(|w| DCC_OFFERS.iter().any(|offer| w.eq_ignore_ascii_case(offer)))
+}
+
+/// Returns the CTCP messages of `s` as byte ranges, delimiters included.  The final delimiter is
+/// optional.
+fn ctcp_ranges(s: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = 0;
+    core::iter::from_fn(move || {
+        let begin = start + s[start..].find(DELIM)?;
+        let end = s[begin + 1..]
+            .find(DELIM)
+            .map_or(s.len(), |i| begin + 1 + i + 1);
+        start = end;
+        Some((begin, end))
+    })
+}
+
+fn body(ctcp: &str) -> &str {
+    let ctcp = &ctcp[1..];
+    ctcp.strip_suffix(DELIM).unwrap_or(ctcp)
+}
+
+/// Returns the command of the given message content (e.g. "ACTION" or "VERSION") if it is a CTCP
+/// message.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::ctcp_command;
+/// assert_eq!(ctcp_command("\x01VERSION\x01"), Some("VERSION"));
+/// assert_eq!(ctcp_command("\x01ACTION waves\x01"), Some("ACTION"));
+/// assert_eq!(ctcp_command("\x01PING 1234"), Some("PING"));
+/// assert_eq!(ctcp_command("hello \x01VERSION\x01"), None);
+/// ```
+pub fn ctcp_command(s: &str) -> Option<&str> {
+    let rest = s.strip_prefix(DELIM)?;
+    let body = &rest[..rest.find(DELIM).unwrap_or(rest.len())];
+    body.split(' ').next().filter(|command| !command.is_empty())
+}
+
+/// Whether the given message content contains a DCC SEND or DCC CHAT offer (or their secure
+/// variants SSEND and SCHAT).
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::is_dcc_offer;
+/// assert!(is_dcc_offer("\x01DCC SEND file.exe 2130706433 4000 1024\x01"));
+/// assert!(is_dcc_offer("\x01dcc chat chat 2130706433 4000"));
+/// assert!(!is_dcc_offer("\x01ACTION sends a file\x01"));
+/// assert!(!is_dcc_offer("DCC SEND is how files are sent"));
+/// ```
+pub fn is_dcc_offer(s: &str) -> bool {
+    ctcp_ranges(s).any(|(begin, end)| is_offer(body(&s[begin..end])))
+}
+
+/// Removes the DCC offers, as defined by `is_dcc_offer`, from the given message content.
+///
+/// Does not allocate if the content has no DCC offer.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::strip_dcc_offers;
+/// assert_eq!(strip_dcc_offers("take this \x01DCC SEND a.exe 1 2 3\x01!"), "take this !");
+/// assert_eq!(strip_dcc_offers("\x01DCC CHAT chat 1 2\x01"), "");
+/// assert_eq!(strip_dcc_offers("\x01ACTION waves\x01"), "\x01ACTION waves\x01");
+/// ```
+pub fn strip_dcc_offers(s: &str) -> Cow<'_, str> {
+    if !is_dcc_offer(s) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut res = String::with_capacity(s.len());
+    let mut last = 0;
+    for (begin, end) in ctcp_ranges(s) {
+        if is_offer(body(&s[begin..end])) {
+            res.push_str(&s[last..begin]);
+            last = end;
+        }
+    }
+    res.push_str(&s[last..]);
+
+    Cow::Owned(res)
+}
@@ -0,0 +1,129 @@
+//! mIRC formatting codes (colors, bold, italics...).
+//!
+//! <https://modern.ircdocs.horse/formatting.html>
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+const BOLD: u8 = 0x02;
+const COLOR: u8 = 0x03;
+const HEX_COLOR: u8 = 0x04;
+const RESET: u8 = 0x0f;
+const MONOSPACE: u8 = 0x11;
+const REVERSE: u8 = 0x16;
+const ITALICS: u8 = 0x1d;
+const STRIKETHROUGH: u8 = 0x1e;
+const UNDERLINE: u8 = 0x1f;
+
+fn is_formatting_code(c: char) -> bool {
+    c.is_ascii()
+        && matches!(
+            c as u8,
+            BOLD | COLOR
+                | HEX_COLOR
+                | RESET
+                | MONOSPACE
+                | REVERSE
+                | ITALICS
+                | STRIKETHROUGH
+                | UNDERLINE
+        )
+}
+
+/// Returns the number of leading bytes of `s` that satisfy `is_digit`, up to `max`.
+fn count_digits(s: &str, max: usize, is_digit: impl Fn(&u8) -> bool) -> usize {
+    s.bytes().take(max).take_while(is_digit).count()
+}
+
+/// Skips the `<fg>[,<bg>]` parameters of a color code.
+fn skip_color(s: &str, max: usize, is_digit: impl Fn(&u8) -> bool + Copy) -> &str {
+    let fg = count_digits(s, max, is_digit);
+    if fg == 0 {
+        return s;
+    }
+    let s = &s[fg..];
+    if let Some(rest) = s.strip_prefix(',') {
+        let bg = count_digits(rest, max, is_digit);
+        if bg != 0 {
+            return &rest[bg..];
+        }
+    }
+    s
+}
+
+/// Whether the given string contains formatting codes.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::is_formatted;
+/// assert!(is_formatted("\x0304red\x03 and \x02bold\x02"));
+/// assert!(!is_formatted("plain text"));
+/// ```
+pub fn is_formatted(s: &str) -> bool {
+    s.contains(is_formatting_code)
+}
+
+/// Removes formatting codes, and the parameters of color codes, from the given string.
+///
+/// Does not allocate if the string has no formatting codes.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::strip_formatting;
+/// assert_eq!(strip_formatting("\x0304,12red\x03 and \x02bold\x02"), "red and bold");
+/// assert_eq!(strip_formatting("\x04ff0000hex"), "hex");
+/// ```
+pub fn strip_formatting(s: &str) -> Cow<'_, str> {
+    if !is_formatted(s) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find(is_formatting_code) {
+        res.push_str(&rest[..i]);
+        let code = rest.as_bytes()[i];
+        rest = &rest[i + 1..];
+        match code {
+            COLOR => rest = skip_color(rest, 2, u8::is_ascii_digit),
+            HEX_COLOR => rest = skip_color(rest, 6, u8::is_ascii_hexdigit),
+            _ => {}
+        }
+    }
+    res.push_str(rest);
+
+    Cow::Owned(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_formatting() {
+        let cases = [
+            ("", ""),
+            ("hello", "hello"),
+            ("\x02bold\x0f", "bold"),
+            ("\x1ditalics\x1d \x1funderline", "italics underline"),
+            ("\x033green", "green"),
+            ("\x0312blue", "blue"),
+            ("\x03123", "3"),
+            ("\x0312,4on red", "on red"),
+            ("\x0312,", ","),
+            ("\x03,12comma", ",12comma"),
+            ("\x03reset", "reset"),
+            ("\x04C0FFEEhex", "hex"),
+            ("\x04C0FFEE,000000hex", "hex"),
+            ("\x04ABCxyz", "xyz"),
+            ("ünï\x02cödé", "ünïcödé"),
+        ];
+
+        for (s, expected) in &cases {
+            assert_eq!(strip_formatting(s), *expected, "strip_formatting({s:?})");
+            assert_eq!(is_formatted(s), s != expected, "is_formatted({s:?})");
+        }
+    }
+} // mod tests
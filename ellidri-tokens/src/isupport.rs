@@ -0,0 +1,261 @@
+//! Parameters advertised by servers with RPL_ISUPPORT.
+//!
+//! <https://modern.ircdocs.horse/#rplisupport-005>
+
+use crate::{rpl, Message};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Parameters whose value is a length or a number.
+const LIMITS: [&str; 16] = [
+    "AWAYLEN",
+    "CHANNELLEN",
+    "CHATHISTORY",
+    "HOSTLEN",
+    "KEYLEN",
+    "KICKLEN",
+    "LINELEN",
+    "MAXTARGETS",
+    "MODES",
+    "MONITOR",
+    "NAMELEN",
+    "NICKLEN",
+    "SILENCE",
+    "TOPICLEN",
+    "USERLEN",
+    "WATCH",
+];
+
+/// The channel modes of the CHANMODES parameter, by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChanModes<'a> {
+    /// Type A: modes that add or remove an address to or from a list (e.g. +b).
+    pub list: &'a str,
+
+    /// Type B: modes that always take a parameter (e.g. +k).
+    pub always_param: &'a str,
+
+    /// Type C: modes that take a parameter only when set (e.g. +l).
+    pub set_param: &'a str,
+
+    /// Type D: modes that never take a parameter (e.g. +n).
+    pub no_param: &'a str,
+}
+
+impl<'a> ChanModes<'a> {
+    fn parse(value: &'a str) -> Self {
+        let mut groups = value.split(',');
+        let mut next = || groups.next().unwrap_or_default();
+        Self {
+            list: next(),
+            always_param: next(),
+            set_param: next(),
+            no_param: next(),
+        }
+    }
+
+    /// Whether setting (`value` is true) or unsetting the given mode takes a parameter, or `None`
+    /// if the mode is unknown.  List modes without parameter list the entries.
+    pub fn takes_param(&self, mode: char, value: bool) -> Option<bool> {
+        if self.list.contains(mode) || self.always_param.contains(mode) {
+            Some(true)
+        } else if self.set_param.contains(mode) {
+            Some(value)
+        } else if self.no_param.contains(mode) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// A parameter of RPL_ISUPPORT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// `-NAME`: the parameter is not advertised anymore.
+    Removed(&'a str),
+
+    /// `CASEMAPPING`, e.g. "ascii" or "rfc1459".
+    CaseMapping(&'a str),
+
+    /// `CHANMODES`.
+    ChanModes(ChanModes<'a>),
+
+    /// `CHANTYPES`, the prefixes of channel names.
+    ChanTypes(&'a str),
+
+    /// `CHANLIMIT`: how many channels of the given types clients can join, or `None` when there
+    /// is no limit.
+    ChanLimit(Vec<(&'a str, Option<usize>)>),
+
+    /// `MAXLIST`: how many entries the given list modes can hold.
+    MaxList(Vec<(&'a str, Option<usize>)>),
+
+    /// `PREFIX`: the channel membership modes and their prefix, from the highest to the lowest.
+    Prefix(Vec<(char, char)>),
+
+    /// `TARGMAX`: how many targets the given commands accept, or `None` when there is no limit.
+    TargMax(Vec<(&'a str, Option<usize>)>),
+
+    /// A length or a number, such as `NICKLEN` or `MODES`, or `None` when it is not given.
+    Limit(&'a str, Option<usize>),
+
+    /// Any other parameter, with its unescaped value if any.
+    Other(&'a str, Option<Cow<'a, str>>),
+}
+
+/// Parses `key:limit,key:limit`.
+fn limits(value: &str) -> Vec<(&str, Option<usize>)> {
+    value
+        .split(',')
+        .filter_map(|item| item.split_once(':'))
+        .map(|(key, limit)| (key, limit.parse().ok()))
+        .collect()
+}
+
+/// Parses `(modes)prefixes`.
+fn prefix(value: &str) -> Vec<(char, char)> {
+    let (modes, prefixes) = match value.strip_prefix('(').and_then(|v| v.split_once(')')) {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    modes.chars().zip(prefixes.chars()).collect()
+}
+
+/// Replaces the `\xHH` escapes of `value` by the characters they stand for.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::isupport;
+/// assert_eq!(isupport::unescape(r"Libera\x20Chat"), "Libera Chat");
+/// assert_eq!(isupport::unescape(r"back\x5Cslash"), r"back\slash");
+/// assert_eq!(isupport::unescape(r"bad\x2"), r"bad\x2");
+/// ```
+pub fn unescape(value: &str) -> Cow<'_, str> {
+    if !value.contains("\\x") {
+        return Cow::Borrowed(value);
+    }
+    let mut res = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find("\\x") {
+        res.push_str(&rest[..i]);
+        let byte = rest
+            .get(i + 2..i + 4)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .filter(u8::is_ascii);
+        match byte {
+            Some(byte) => {
+                res.push(char::from(byte));
+                rest = &rest[i + 4..];
+            }
+            None => {
+                res.push_str("\\x");
+                rest = &rest[i + 2..];
+            }
+        }
+    }
+    res.push_str(rest);
+    Cow::Owned(res)
+}
+
+impl<'a> Token<'a> {
+    /// Parses one parameter of RPL_ISUPPORT.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use ellidri_tokens::isupport::Token;
+    /// assert_eq!(Token::parse("PREFIX=(ov)@+"), Token::Prefix(vec![('o', '@'), ('v', '+')]));
+    /// assert_eq!(Token::parse("NICKLEN=16"), Token::Limit("NICKLEN", Some(16)));
+    /// assert_eq!(Token::parse("-EXCEPTS"), Token::Removed("EXCEPTS"));
+    /// ```
+    pub fn parse(s: &'a str) -> Self {
+        if let Some(name) = s.strip_prefix('-') {
+            return Self::Removed(name);
+        }
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        let value_str = value.unwrap_or_default();
+        match name {
+            "CASEMAPPING" => Self::CaseMapping(value_str),
+            "CHANMODES" => Self::ChanModes(ChanModes::parse(value_str)),
+            "CHANTYPES" => Self::ChanTypes(value_str),
+            "CHANLIMIT" => Self::ChanLimit(limits(value_str)),
+            "MAXLIST" => Self::MaxList(limits(value_str)),
+            "PREFIX" => Self::Prefix(prefix(value_str)),
+            "TARGMAX" => Self::TargMax(limits(value_str)),
+            _ if LIMITS.contains(&name) => Self::Limit(name, value_str.parse().ok()),
+            _ => Self::Other(name, value.map(unescape)),
+        }
+    }
+}
+
+/// Returns the parameters of the given RPL_ISUPPORT message, or nothing if `msg` is another
+/// message.
+///
+/// # Example
+///
+/// ```rust
+/// # use ellidri_tokens::{isupport::{self, Token}, Message};
+/// let msg = Message::parse(":ellidri.dev 005 nick SAFELIST AWAYLEN=300 :are supported").unwrap();
+/// let mut tokens = isupport::tokens(&msg);
+/// assert_eq!(tokens.next(), Some(Token::Other("SAFELIST", None)));
+/// assert_eq!(tokens.next(), Some(Token::Limit("AWAYLEN", Some(300))));
+/// assert_eq!(tokens.next(), None);
+/// ```
+pub fn tokens<'a>(msg: &Message<'a>) -> impl Iterator<Item = Token<'a>> {
+    let params = msg.params;
+    let range = if msg.command == Err(rpl::ISUPPORT) && 2 < msg.num_params {
+        1..msg.num_params - 1
+    } else {
+        0..0
+    };
+    range.map(move |i| Token::parse(params[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let chanmodes = match Token::parse("CHANMODES=beIq,k,fHjl,cdimnpsSt") {
+            Token::ChanModes(chanmodes) => chanmodes,
+            token => panic!("{:?}", token),
+        };
+        assert_eq!(chanmodes.list, "beIq");
+        assert_eq!(chanmodes.takes_param('k', false), Some(true));
+        assert_eq!(chanmodes.takes_param('l', true), Some(true));
+        assert_eq!(chanmodes.takes_param('l', false), Some(false));
+        assert_eq!(chanmodes.takes_param('n', true), Some(false));
+        assert_eq!(chanmodes.takes_param('X', true), None);
+
+        assert_eq!(
+            Token::parse("TARGMAX=JOIN:,PRIVMSG:1"),
+            Token::TargMax(vec![("JOIN", None), ("PRIVMSG", Some(1))])
+        );
+        assert_eq!(
+            Token::parse("CHANLIMIT=#&:10"),
+            Token::ChanLimit(vec![("#&", Some(10))])
+        );
+        assert_eq!(Token::parse("MODES"), Token::Limit("MODES", None));
+        assert_eq!(Token::parse("PREFIX="), Token::Prefix(Vec::new()));
+        assert_eq!(
+            Token::parse(r"NETWORK=Some\x20Net"),
+            Token::Other("NETWORK", Some(Cow::Borrowed("Some Net")))
+        );
+        assert_eq!(Token::parse("EXCEPTS"), Token::Other("EXCEPTS", None));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let msg = Message::parse(":ellidri.dev 004 nick ellidri.dev 1.0 :x").unwrap();
+        assert_eq!(tokens(&msg).count(), 0);
+        let msg = Message::parse(":ellidri.dev 005 nick :are supported").unwrap();
+        assert_eq!(tokens(&msg).count(), 0);
+    }
+} // mod tests
@@ -2,22 +2,40 @@
 //!
 //! This library provides helpers to tokenize and build IRC messages, while keeping the number of
 //! allocations minimal.
+//!
+//! Without the default `std` feature, the crate is `no_std` and only needs `alloc`.  `ReplyBuffer`,
+//! which keeps its state in thread-local storage, and `cap::write_replies` are then unavailable.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![forbid(unsafe_code)]
 #![warn(clippy::all, rust_2018_idioms)]
 #![allow(
-    clippy::filter_map,
-    clippy::find_map,
+    clippy::manual_filter_map,
+    clippy::manual_find_map,
     clippy::shadow_unrelated,
     clippy::use_self
 )]
 
-pub use buffers::{Buffer, MessageBuffer, ReplyBuffer, TagBuffer};
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub use buffers::ReplyBuffer;
+pub use buffers::{Buffer, MessageBuffer, TagBuffer};
 pub use command::Command;
-pub use message::{tag_escape, tags, Message, Tag, MESSAGE_LENGTH, PARAMS_LENGTH};
+pub use ctcp::{ctcp_command, is_dcc_offer, strip_dcc_offers};
+pub use formatting::{is_formatted, strip_formatting};
+pub use message::{
+    tag_escape, tags, Message, Tag, CLIENT_TAGS_LENGTH, MESSAGE_LENGTH, PARAMS_LENGTH, TAGS_LENGTH,
+};
 
 mod buffers;
+pub mod cap;
+#[cfg(feature = "codec")]
+pub mod codec;
 mod command;
+mod ctcp;
+mod formatting;
+pub mod isupport;
 mod message;
 pub mod mode;
 pub mod rpl;
@@ -1,4 +1,5 @@
 use crate::Command;
+use alloc::string::String;
 
 /// The recommended length of a message.
 ///
@@ -9,6 +10,15 @@ pub const MESSAGE_LENGTH: usize = 512;
 /// The number of elements in `Message::params`.
 pub const PARAMS_LENGTH: usize = 15;
 
+/// The maximum length of the tags of a message, including the leading '@' and the trailing space.
+///
+/// See <https://ircv3.net/specs/extensions/message-tags#size-limit>.
+pub const TAGS_LENGTH: usize = 8191;
+
+/// The maximum length of the tag data sent by clients, without the leading '@' and the trailing
+/// space.  `TagBuffer` doesn't relay more client-only tag data than that either.
+pub const CLIENT_TAGS_LENGTH: usize = 4094;
+
 /// Returns `(word, rest)` where `word` is the first word of the given string and `rest` is the
 /// substring starting at the first character of the second word.
 ///
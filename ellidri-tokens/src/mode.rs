@@ -1,20 +1,20 @@
 //! Mode parsing and validation
 
-use std::str;
+use core::str;
 
 /// User modes supported by ellidri.  Advertised in welcome messages.
-pub const USER_MODES: &str = "aio";
+pub const USER_MODES: &str = "aioBDRT";
 
 /// Channel modes that have no parameters and are supported by ellidri.  Advertised in welcome
 /// messages.
-pub const SIMPLE_CHAN_MODES: &str = "imnst";
+pub const SIMPLE_CHAN_MODES: &str = "cdimnpsSt";
 
 /// Channel modes that require a parameter and are supported by ellidri.  Advertised in welcome
 /// messages.
-pub const EXTENDED_CHAN_MODES: &str = "beIkl";
+pub const EXTENDED_CHAN_MODES: &str = "beIfHjklq";
 
 /// CHANMODES feature advertised in RPL_ISUPPORT.
-pub const CHANMODES: &str = "CHANMODES=beI,k,l,imnst";
+pub const CHANMODES: &str = "CHANMODES=beIq,k,fHjl,cdimnpsSt";
 
 /// Iterator over the modes of a string.
 struct SimpleQuery<'a> {
@@ -66,12 +66,16 @@ pub enum Error {
 }
 
 /// Alias to std's Result using this module's Error.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// Item of a user mode query.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UserChange {
     Invisible(bool),
+    Bot(bool),
+    Deaf(bool),
+    NoCtcp(bool),
+    RegisteredOnly(bool),
     DeOperator,
 }
 
@@ -79,7 +83,11 @@ impl UserChange {
     /// Whether this change is enabling or disabling a mode.
     pub fn value(self) -> bool {
         match self {
-            Self::Invisible(v) => v,
+            Self::Invisible(v)
+            | Self::Bot(v)
+            | Self::Deaf(v)
+            | Self::NoCtcp(v)
+            | Self::RegisteredOnly(v) => v,
             Self::DeOperator => false,
         }
     }
@@ -88,6 +96,10 @@ impl UserChange {
     pub fn symbol(self) -> char {
         match self {
             Self::Invisible(_) => 'i',
+            Self::Bot(_) => 'B',
+            Self::Deaf(_) => 'D',
+            Self::NoCtcp(_) => 'T',
+            Self::RegisteredOnly(_) => 'R',
             Self::DeOperator => 'o',
         }
     }
@@ -111,6 +123,10 @@ impl UserChange {
 pub fn user_query(modes: &str) -> impl Iterator<Item = Result<UserChange>> + '_ {
     SimpleQuery::new(modes).map(|(value, mode)| match mode {
         'i' => Ok(UserChange::Invisible(value)),
+        'B' => Ok(UserChange::Bot(value)),
+        'D' => Ok(UserChange::Deaf(value)),
+        'T' => Ok(UserChange::NoCtcp(value)),
+        'R' => Ok(UserChange::RegisteredOnly(value)),
         'o' if !value => Ok(UserChange::DeOperator),
         other if USER_MODES.contains(other) => Err(Error::Unchangeable(other, value)),
         other => Err(Error::Unknown(other, value)),
@@ -122,17 +138,27 @@ pub fn user_query(modes: &str) -> impl Iterator<Item = Result<UserChange>> + '_
 pub enum ChannelChange<'a> {
     InviteOnly(bool),
     Moderated(bool),
+    NoColors(bool),
+    NoDcc(bool),
     NoPrivMsgFromOutside(bool),
+    Private(bool),
     Secret(bool),
+    StripColors(bool),
     TopicRestricted(bool),
     Key(bool, &'a str),
     UserLimit(Option<&'a str>),
+    FloodLimit(Option<&'a str>),
+    JoinLimit(Option<&'a str>),
+    HistoryLimit(Option<&'a str>),
     GetBans,
     GetExceptions,
     GetInvitations,
+    GetQuiets,
     ChangeBan(bool, &'a str),
     ChangeException(bool, &'a str),
     ChangeInvitation(bool, &'a str),
+    ChangeQuiet(bool, &'a str),
+    ChangeFounder(bool, &'a str),
     ChangeOperator(bool, &'a str),
     ChangeHalfop(bool, &'a str),
     ChangeVoice(bool, &'a str),
@@ -145,17 +171,23 @@ impl ChannelChange<'_> {
         match self {
             InviteOnly(v)
             | Moderated(v)
+            | NoColors(v)
+            | NoDcc(v)
             | NoPrivMsgFromOutside(v)
+            | Private(v)
             | Secret(v)
+            | StripColors(v)
             | TopicRestricted(v)
             | Key(v, _)
             | ChangeBan(v, _)
             | ChangeException(v, _)
             | ChangeInvitation(v, _)
+            | ChangeQuiet(v, _)
+            | ChangeFounder(v, _)
             | ChangeOperator(v, _)
             | ChangeHalfop(v, _)
             | ChangeVoice(v, _) => *v,
-            UserLimit(l) => l.is_some(),
+            UserLimit(l) | FloodLimit(l) | JoinLimit(l) | HistoryLimit(l) => l.is_some(),
             _ => false,
         }
     }
@@ -166,14 +198,23 @@ impl ChannelChange<'_> {
         match self {
             InviteOnly(_) => 'i',
             Moderated(_) => 'm',
+            NoColors(_) => 'c',
+            NoDcc(_) => 'd',
             NoPrivMsgFromOutside(_) => 'n',
+            Private(_) => 'p',
             Secret(_) => 's',
+            StripColors(_) => 'S',
             TopicRestricted(_) => 't',
             Key(_, _) => 'k',
             UserLimit(_) => 'l',
+            FloodLimit(_) => 'f',
+            JoinLimit(_) => 'j',
+            HistoryLimit(_) => 'H',
             ChangeBan(_, _) | GetBans => 'b',
             ChangeException(_, _) | GetExceptions => 'e',
             ChangeInvitation(_, _) | GetInvitations => 'I',
+            ChangeQuiet(_, _) | GetQuiets => 'q',
+            ChangeFounder(_, _) => 'O',
             ChangeOperator(_, _) => 'o',
             ChangeHalfop(_, _) => 'h',
             ChangeVoice(_, _) => 'v',
@@ -188,10 +229,12 @@ impl ChannelChange<'_> {
             | ChangeBan(_, p)
             | ChangeException(_, p)
             | ChangeInvitation(_, p)
+            | ChangeQuiet(_, p)
+            | ChangeFounder(_, p)
             | ChangeOperator(_, p)
             | ChangeHalfop(_, p)
             | ChangeVoice(_, p) => Some(p),
-            UserLimit(l) => *l,
+            UserLimit(l) | FloodLimit(l) | JoinLimit(l) | HistoryLimit(l) => *l,
             _ => None,
         }
     }
@@ -228,10 +271,14 @@ where
     SimpleQuery::new(modes).map(move |(value, mode)| {
         use ChannelChange::*;
         match mode {
+            'c' => Ok(NoColors(value)),
+            'd' => Ok(NoDcc(value)),
             'i' => Ok(InviteOnly(value)),
             'm' => Ok(Moderated(value)),
             'n' => Ok(NoPrivMsgFromOutside(value)),
+            'p' => Ok(Private(value)),
             's' => Ok(Secret(value)),
+            'S' => Ok(StripColors(value)),
             't' => Ok(TopicRestricted(value)),
             'k' => {
                 if let Some(param) = params.next() {
@@ -254,6 +301,39 @@ where
                     Ok(UserLimit(None))
                 }
             }
+            'f' => {
+                if value {
+                    if let Some(param) = params.next() {
+                        Ok(FloodLimit(Some(param)))
+                    } else {
+                        Err(Error::MissingParam('f', value))
+                    }
+                } else {
+                    Ok(FloodLimit(None))
+                }
+            }
+            'j' => {
+                if value {
+                    if let Some(param) = params.next() {
+                        Ok(JoinLimit(Some(param)))
+                    } else {
+                        Err(Error::MissingParam('j', value))
+                    }
+                } else {
+                    Ok(JoinLimit(None))
+                }
+            }
+            'H' => {
+                if value {
+                    if let Some(param) = params.next() {
+                        Ok(HistoryLimit(Some(param)))
+                    } else {
+                        Err(Error::MissingParam('H', value))
+                    }
+                } else {
+                    Ok(HistoryLimit(None))
+                }
+            }
             'b' => {
                 if let Some(param) = params.next() {
                     Ok(ChangeBan(value, param))
@@ -275,6 +355,20 @@ where
                     Ok(GetInvitations)
                 }
             }
+            'q' => {
+                if let Some(param) = params.next() {
+                    Ok(ChangeQuiet(value, param))
+                } else {
+                    Ok(GetQuiets)
+                }
+            }
+            'O' => {
+                if let Some(param) = params.next() {
+                    Ok(ChangeFounder(value, param))
+                } else {
+                    Err(Error::MissingParam('O', value))
+                }
+            }
             'o' => {
                 if let Some(param) = params.next() {
                     Ok(ChangeOperator(value, param))
@@ -303,7 +397,7 @@ where
 
 /// Same as `channel_query`, but with no mode parameters.
 pub fn simple_channel_query(modes: &str) -> impl Iterator<Item = Result<ChannelChange<'_>>> {
-    channel_query::<_, String>(modes, &[])
+    channel_query::<_, &str>(modes, &[])
 }
 
 /// Whether the given string is a valid channel MODE query.
@@ -410,4 +504,32 @@ mod tests {
         assert_eq!(q.next(), Some(Ok(ChannelChange::Key(false, "wine"))));
         assert_eq!(q.next(), None);
     }
+
+    #[test]
+    fn test_chanmode_history() {
+        let mut q = channel_query("+H-H", &["50:3600"]);
+        let change = q.next().unwrap().unwrap();
+        assert_eq!(change, ChannelChange::HistoryLimit(Some("50:3600")));
+        assert_eq!((change.symbol(), change.param()), ('H', Some("50:3600")));
+        assert_eq!(q.next(), Some(Ok(ChannelChange::HistoryLimit(None))));
+        assert_eq!(q.next(), None);
+
+        let mut q = channel_query::<_, String>("+H", &[]);
+        assert_eq!(q.next(), Some(Err(Error::MissingParam('H', true))));
+        assert_eq!(q.next(), None);
+    }
+
+    #[test]
+    fn test_chanmode_founder() {
+        let mut q = channel_query("+O-Oo", &["alice", "bob"]);
+        let change = q.next().unwrap().unwrap();
+        assert_eq!(change, ChannelChange::ChangeFounder(true, "alice"));
+        assert_eq!((change.symbol(), change.param()), ('O', Some("alice")));
+        assert_eq!(
+            q.next(),
+            Some(Ok(ChannelChange::ChangeFounder(false, "bob")))
+        );
+        assert_eq!(q.next(), Some(Err(Error::MissingParam('o', false))));
+        assert_eq!(q.next(), None);
+    }
 } // mod tests
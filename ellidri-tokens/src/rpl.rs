@@ -6,14 +6,34 @@
 //!
 //! - <https://tools.ietf.org/html/rfc2812.html#section-5>
 //! - <https://modern.ircdocs.horse/#numerics>
+//! - <https://ircv3.net/registry#numerics>
 
 pub const WELCOME: &str = "001"; // :Welcome message
 pub const YOURHOST: &str = "002"; // :Your host is...
 pub const CREATED: &str = "003"; // :This server was created...
 pub const MYINFO: &str = "004"; // <servername> <version> <umodes> <chan modes> <chan modes with a parameter>
 pub const ISUPPORT: &str = "005"; // 1*13<TOKEN[=value]> :are supported by this server
+pub const BOUNCE: &str = "010"; // <hostname> <port> :<info>
 
+pub const TRACELINK: &str = "200"; // Link <version> <destination> <next server> <protocol version> <link uptime> <backstream sendq> <upstream sendq>
+pub const TRACECONNECTING: &str = "201"; // Try. <class> <server>
+pub const TRACEHANDSHAKE: &str = "202"; // H.S. <class> <server>
+pub const TRACEUNKNOWN: &str = "203"; // ???? <class> <host> <ip> <lag> <time open> :<state>
+pub const TRACEOPERATOR: &str = "204"; // Oper <class> <full name> <ip> <lag> <time open> :<state>
+pub const TRACEUSER: &str = "205"; // User <class> <full name> <ip> <lag> <time open> :<state>
+pub const TRACESERVER: &str = "206"; // Serv <class> <int>S <int>C <server> <nick!user|*!*>@<host|server> V<protocol version>
+pub const TRACESERVICE: &str = "207"; // Service <class> <name> <type> <active type>
+pub const TRACENEWTYPE: &str = "208"; // <newtype> 0 <client name>
+pub const TRACECLASS: &str = "209"; // Class <class> <count>
+pub const STATSLINKINFO: &str = "211"; // <linkname> <sendq> <sent messages> <sent Kbytes> <received messages> <received Kbytes> <time open>
+pub const STATSCOMMANDS: &str = "212"; // <command> <count> <byte count> <remote count>
+pub const ENDOFSTATS: &str = "219"; // <stats letter> :End of STATS report
 pub const UMODEIS: &str = "221"; // <modes>
+pub const SERVLIST: &str = "234"; // <name> <server> <mask> <type> <hopcount> <info>
+pub const SERVLISTEND: &str = "235"; // <mask> <type> :End of service listing
+pub const STATSUPTIME: &str = "242"; // :Server Up <days> days <hours>:<minutes>:<seconds>
+pub const STATSOLINE: &str = "243"; // O <hostmask> * <name>
+pub const STATSCONN: &str = "250"; // :Highest connection count: <int> (<int> clients)
 pub const LUSERCLIENT: &str = "251"; // :<int> users and <int> services on <int> servers
 pub const LUSEROP: &str = "252"; // <int> :operator(s) online
 pub const LUSERUNKNOWN: &str = "253"; // <int> :unknown connection(s)
@@ -23,23 +43,40 @@ pub const ADMINME: &str = "256"; // <server> :Admin info
 pub const ADMINLOC1: &str = "257"; // :<info>
 pub const ADMINLOC2: &str = "258"; // :<info>
 pub const ADMINMAIL: &str = "259"; // :<info>
+pub const TRACELOG: &str = "261"; // File <logfile> <debug level>
+pub const TRACEEND: &str = "262"; // <server> <version> :End of TRACE
+pub const TRYAGAIN: &str = "263"; // <command> :Please wait a while and try again.
+pub const LOCALUSERS: &str = "265"; // <int> <int> :Current local users <int>, max <int>
+pub const GLOBALUSERS: &str = "266"; // <int> <int> :Current global users <int>, max <int>
+pub const WHOISCERTFP: &str = "276"; // <nick> :has client certificate fingerprint <fingerprint>
 
+pub const NONE: &str = "300"; // Undefined format
 pub const AWAY: &str = "301"; // <nick> :<away message>
+pub const USERHOST: &str = "302"; // :*1<reply> *( " " <reply> )
+pub const ISON: &str = "303"; // :*1<nick> *( " " <nick> )
 pub const UNAWAY: &str = "305"; // :You are no longer marked as being away
 pub const NOWAWAY: &str = "306"; // :You have been marked as being away
+pub const WHOISREGNICK: &str = "307"; // <nick> :has identified for this nick
 pub const WHOISUSER: &str = "311"; // <nick> <user> <host> * :<realname>
 pub const WHOISSERVER: &str = "312"; // <nick> <server> :<server info>
 pub const WHOISOPERATOR: &str = "313"; // <nick> :is an IRC operator
+pub const WHOWASUSER: &str = "314"; // <nick> <user> <host> * :<realname>
 pub const ENDOFWHO: &str = "315"; // <name> :End of WHO list
 pub const WHOISIDLE: &str = "317"; // <nick> <integer> [<integer>] :seconds idle [, signon time]
 pub const ENDOFWHOIS: &str = "318"; // <nick> :End of WHOIS list
 pub const WHOISCHANNELS: &str = "319"; // <nick> :*( (@/+) <channel> " " )
+pub const WHOISSPECIAL: &str = "320"; // <nick> :<info>
+pub const LISTSTART: &str = "321"; // Channel :Users  Name
 pub const LIST: &str = "322"; // <channel> <# of visible members> <topic>
 pub const LISTEND: &str = "323"; // :End of list
 pub const CHANNELMODEIS: &str = "324"; // <channel> <modes> <mode params>
+pub const CREATIONTIME: &str = "329"; // <channel> <creation time>
+pub const WHOISACCOUNT: &str = "330"; // <nick> <account> :is logged in as
 pub const NOTOPIC: &str = "331"; // <channel> :No topic set
 pub const TOPIC: &str = "332"; // <channel> <topic>
 pub const TOPICWHOTIME: &str = "333"; // <channel> <nick> <setat>
+pub const WHOISBOT: &str = "335"; // <nick> :is a bot
+pub const WHOISACTUALLY: &str = "338"; // <nick> [<user>@<host>] [<ip>] :Is actually using host
 pub const INVITING: &str = "341"; // <nick> <channel>
 pub const INVITELIST: &str = "346"; // <channel> <invite mask>
 pub const ENDOFINVITELIST: &str = "347"; // <channel> :End of invite list
@@ -48,30 +85,47 @@ pub const ENDOFEXCEPTLIST: &str = "349"; // <channel> :End of exception list
 pub const VERSION: &str = "351"; // <version> <servername> :<comments>
 pub const WHOREPLY: &str = "352"; // <channel> <user> <host> <server> <nick> "H"/"G" ["*"] [("@"/"+")] :<hop count> <nick>
 pub const NAMREPLY: &str = "353"; // <=/*/@> <channel> :1*(@/ /+user)
+pub const WHOSPCRPL: &str = "354"; // [token] [channel] [user] [ip] [host] [server] [nick] [flags] [hopcount] [idle] [account] [oplevel] [:realname]
+pub const LINKS: &str = "364"; // <mask> <server> :<hopcount> <server info>
+pub const ENDOFLINKS: &str = "365"; // <mask> :End of LINKS list
 pub const ENDOFNAMES: &str = "366"; // <channel> :End of names list
 pub const BANLIST: &str = "367"; // <channel> <ban mask>
 pub const ENDOFBANLIST: &str = "368"; // <channel> :End of ban list
+pub const ENDOFWHOWAS: &str = "369"; // <nick> :End of WHOWAS
 pub const INFO: &str = "371"; // :<info>
 pub const MOTD: &str = "372"; // :- <text>
 pub const ENDOFINFO: &str = "374"; // :End of INFO
 pub const MOTDSTART: &str = "375"; // :- <servername> Message of the day -
 pub const ENDOFMOTD: &str = "376"; // :End of MOTD command
+pub const WHOISHOST: &str = "378"; // <nick> :is connecting from <user>@<host> <ip>
+pub const WHOISMODES: &str = "379"; // <nick> :is using modes <modes>
 pub const YOUREOPER: &str = "381"; // :You are now an operator
 pub const REHASHING: &str = "382"; // <config file> :Rehashing
 pub const TIME: &str = "391"; // <servername> :<time in whatever format>
+pub const HOSTHIDDEN: &str = "396"; // <host> :is now your displayed host
 
+pub const ERR_UNKNOWNERROR: &str = "400"; // <command> [<subcommand>] :<info>
 pub const ERR_NOSUCHNICK: &str = "401"; // <nick> :No such nick/channel
+pub const ERR_NOSUCHSERVER: &str = "402"; // <server> :No such server
 pub const ERR_NOSUCHCHANNEL: &str = "403"; // <channel> :No such channel
 pub const ERR_CANNOTSENDTOCHAN: &str = "404"; // <channel> :Cannot send to channel
+pub const ERR_TOOMANYCHANNELS: &str = "405"; // <channel> :You have joined too many channels
+pub const ERR_WASNOSUCHNICK: &str = "406"; // <nick> :There was no such nickname
+pub const ERR_TOOMANYTARGETS: &str = "407"; // <target> :Duplicate recipients. No message delivered
+pub const ERR_NOORIGIN: &str = "409"; // :No origin specified
 pub const ERR_INVALIDCAPCMD: &str = "410"; // <command> :Unknown cap command
 pub const ERR_NORECIPIENT: &str = "411"; // :No recipient given
 pub const ERR_NOTEXTTOSEND: &str = "412"; // :No text to send
 pub const ERR_INPUTTOOLONG: &str = "417"; // :Input line was too long
 pub const ERR_UNKNOWNCOMMAND: &str = "421"; // <command> :Unknown command
 pub const ERR_NOMOTD: &str = "422"; // :MOTD file missing
+pub const ERR_NOADMININFO: &str = "423"; // <server> :No administrative info available
+pub const ERR_NOOPERMOTD: &str = "425"; // :OPERMOTD file is missing
 pub const ERR_NONICKNAMEGIVEN: &str = "431"; // :No nickname given
 pub const ERR_ERRONEUSNICKNAME: &str = "432"; // <nick> :Erroneous nickname
 pub const ERR_NICKNAMEINUSE: &str = "433"; // <nick> :Nickname in use
+pub const ERR_NICKCOLLISION: &str = "436"; // <nick> :Nickname collision KILL from <user>@<host>
+pub const ERR_UNAVAILRESOURCE: &str = "437"; // <nick/channel> :Nick/channel is temporarily unavailable
 pub const ERR_USERNOTINCHANNEL: &str = "441"; // <nick> <channel> :User not in channel
 pub const ERR_NOTONCHANNEL: &str = "442"; // <channel> :You're not on that channel
 pub const ERR_USERONCHANNEL: &str = "443"; // <user> <channel> :is already on channel
@@ -86,11 +140,48 @@ pub const ERR_UNKNOWNMODE: &str = "472"; // <char> :Don't know this mode for <ch
 pub const ERR_INVITEONLYCHAN: &str = "473"; // <channel> :Cannot join channel (+I)
 pub const ERR_BANNEDFROMCHAN: &str = "474"; // <channel> :Cannot join channel (+b)
 pub const ERR_BADCHANKEY: &str = "475"; // <channel> :Cannot join channel (+k)
+pub const ERR_BADCHANMASK: &str = "476"; // <channel> :Bad channel mask
+pub const ERR_NEEDREGGEDNICK: &str = "477"; // <target> :You need to be logged in
+pub const ERR_BANLISTFULL: &str = "478"; // <channel> <char> :Channel list is full
+pub const ERR_THROTTLE: &str = "480"; // <channel> :Cannot join channel (+j)
 pub const ERR_NOPRIVILEDGES: &str = "481"; // :Permission Denied- You're not an IRC operator
 pub const ERR_CHANOPRIVSNEEDED: &str = "482"; // <channel> :You're not an operator
+pub const ERR_CANTKILLSERVER: &str = "483"; // :You can't kill a server!
+pub const ERR_NOOPERHOST: &str = "491"; // :No O-lines for your host
 
 pub const ERR_UMODEUNKNOWNFLAG: &str = "501"; // :Unknown mode flag
 pub const ERR_USERSDONTMATCH: &str = "502"; // :Can't change mode for other users
+pub const ERR_HELPNOTFOUND: &str = "524"; // <subject> :No help available on this topic
+pub const ERR_INVALIDKEY: &str = "525"; // <channel> :Key is not well-formed
+
+pub const STARTTLS: &str = "670"; // :STARTTLS successful, proceed with TLS handshake
+pub const WHOISSECURE: &str = "671"; // <nick> :is using a secure connection
+pub const ERR_STARTTLS: &str = "691"; // :STARTTLS failed
+pub const ERR_INVALIDMODEPARAM: &str = "696"; // <target> <mode char> <parameter> :<description>
+
+pub const HELPSTART: &str = "704"; // <subject> :<first line of help section>
+pub const HELPTXT: &str = "705"; // <subject> :<line of help text>
+pub const ENDOFHELP: &str = "706"; // <subject> :<last line of help text>
+
+pub const OMOTDSTART: &str = "720"; // :- <servername> Message of the day for operators -
+pub const OMOTD: &str = "721"; // :- <text>
+pub const ENDOFOMOTD: &str = "722"; // :End of OPERMOTD command
+pub const ERR_NOPRIVS: &str = "723"; // <priv> :Insufficient oper privileges.
+pub const QUIETLIST: &str = "728"; // <channel> q <quiet mask>
+pub const ENDOFQUIETLIST: &str = "729"; // <channel> q :End of quiet list
+pub const MONONLINE: &str = "730"; // :<nick!user@host>[,<nick!user@host>]*
+pub const MONOFFLINE: &str = "731"; // :<nick>[,<nick>]*
+pub const MONLIST: &str = "732"; // :<nick>[,<nick>]*
+pub const ENDOFMONLIST: &str = "733"; // :End of MONITOR list
+pub const ERR_MONLISTFULL: &str = "734"; // <limit> <nicks> :Monitor list is full
+
+pub const WHOISKEYVALUE: &str = "760"; // <target> <key> <visibility> :<value>
+pub const KEYVALUE: &str = "761"; // <target> <key> <visibility> :<value>
+pub const KEYNOTSET: &str = "766"; // <target> <key> :key not set
+pub const METADATASUBOK: &str = "770"; // <key1> [<key2> ...]
+pub const METADATAUNSUBOK: &str = "771"; // <key1> [<key2> ...]
+pub const METADATASUBS: &str = "772"; // <key1> [<key2> ...]
+pub const METADATASYNCLATER: &str = "774"; // <target> [<retry after>]
 
 pub const LOGGEDIN: &str = "900"; // <nick> <nick>!<ident>@<host> <account> :You are now logged in as <user>
 pub const LOGGEDOUT: &str = "901"; // <nick> <nick>!<ident>@<host> :You are now logged out
@@ -33,8 +33,8 @@
 
 #![warn(clippy::all, rust_2018_idioms)]
 #![allow(
-    clippy::filter_map,
-    clippy::find_map,
+    clippy::manual_filter_map,
+    clippy::manual_find_map,
     clippy::shadow_unrelated,
     clippy::use_self
 )]
@@ -85,7 +85,7 @@ impl CaseMapping for Rfc1459Strict {
         match b {
             b'[' => b'{',
             b']' => b'}',
-            b'\\' => b'\\',
+            b'\\' => b'|',
             b => Ascii::canonical_byte(b),
         }
     }
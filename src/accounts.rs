@@ -0,0 +1,324 @@
+//! Account database, used for SASL authentication.
+//!
+//! Accounts are stored in a YAML file (`account_db`), as a list of names and argon2 password
+//! hashes.  Accounts are managed with the `ellidri account` subcommand; the server reads the file
+//! at startup and on rehash, and only writes to it, away from the state, to save the settings of
+//! accounts.
+
+use crate::metadata::Metadata;
+use crate::util;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::{fs, io};
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Account {
+    pub name: String,
+
+    /// The argon2 hash of the password of the account.
+    pub password: String,
+
+    /// The metadata of the account, restored when its user logs in.
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+
+    /// Whether the user of the account stays on the network when all its connections are closed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub always_on: bool,
+
+    /// Seconds of inactivity after which the user of the account is marked as away, if it
+    /// overrides `auto_away_delay`.  0 disables automatic away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_away: Option<u64>,
+
+    /// Number of messages replayed to the user of the account when it joins a channel, if it
+    /// overrides `autoreplay_len`.  0 disables autoreplay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoreplay: Option<usize>,
+
+    /// The host displayed instead of the real one when the user of the account logs in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vhost: Option<String>,
+}
+
+/// A private message sent to an account while it was offline.
+pub struct OfflineMessage {
+    /// The full name of the sender.
+    pub from: String,
+
+    /// The account of the sender, if any.
+    pub account: Option<String>,
+
+    pub msgid: String,
+
+    /// The time at which the message was sent, for the `server-time` tag.
+    pub time: String,
+
+    pub content: String,
+}
+
+#[derive(Default)]
+pub struct Accounts {
+    /// Path to the account database, or empty if no database is configured.
+    path: String,
+
+    /// Writes the changes made by the server to the database.  Only set by `load`.
+    writer: Option<util::FileWriter>,
+
+    /// Accounts, indexed by their lowercase name (ellidri uses the `ascii` casemapping).
+    accounts: BTreeMap<String, Account>,
+}
+
+impl Accounts {
+    /// Reads the account database at `path`.  A database that does not exist yet is empty.
+    pub fn open(path: &str) -> Result<Self> {
+        let mut res = Self {
+            path: path.to_owned(),
+            ..Self::default()
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(res),
+            Err(err) => return Err(err).with_context(|| format!("failed to read {path}")),
+        };
+        let accounts: Vec<Account> =
+            serde_yaml::from_str(&contents).with_context(|| format!("failed to parse {path}"))?;
+        for account in accounts {
+            res.accounts
+                .insert(account.name.to_ascii_lowercase(), account);
+        }
+        Ok(res)
+    }
+
+    /// Loads the account database at `path`, logging errors.
+    ///
+    /// If `path` is empty, or if the database cannot be read, there is no account.
+    pub fn load(path: &str) -> Self {
+        if path.is_empty() {
+            return Self::default();
+        }
+        log::info!("Loading accounts from {:?}", path);
+        match Self::open(path) {
+            Ok(mut accounts) => {
+                accounts.writer = Some(util::FileWriter::spawn());
+                accounts
+            }
+            Err(err) => {
+                log::error!("{:#}", err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.accounts.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the name of the account if `password` is the right one.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<&str> {
+        let account = self.accounts.get(&name.to_ascii_lowercase())?;
+        util::verify_password_hash(&account.password, password).ok()?;
+        Some(&account.name)
+    }
+
+    pub fn is_always_on(&self, name: &str) -> bool {
+        self.accounts
+            .get(&name.to_ascii_lowercase())
+            .is_some_and(|account| account.always_on)
+    }
+
+    /// Returns whether the account exists.
+    pub fn set_always_on(&mut self, name: &str, always_on: bool) -> bool {
+        match self.accounts.get_mut(&name.to_ascii_lowercase()) {
+            Some(account) => {
+                account.always_on = always_on;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn metadata(&self, name: &str) -> Option<&Metadata> {
+        self.accounts
+            .get(&name.to_ascii_lowercase())
+            .map(|account| &account.metadata)
+    }
+
+    /// Replaces the metadata of an account and saves it to the database.
+    pub fn set_metadata(&mut self, name: &str, metadata: Metadata) {
+        self.update(name, move |account| account.metadata = metadata.clone());
+    }
+
+    pub fn auto_away(&self, name: &str) -> Option<u64> {
+        self.accounts
+            .get(&name.to_ascii_lowercase())
+            .and_then(|account| account.auto_away)
+    }
+
+    /// Changes the automatic away delay of an account and saves it to the database.
+    pub fn set_auto_away(&mut self, name: &str, delay: u64) {
+        self.update(name, move |account| account.auto_away = Some(delay));
+    }
+
+    pub fn autoreplay(&self, name: &str) -> Option<usize> {
+        self.accounts
+            .get(&name.to_ascii_lowercase())
+            .and_then(|account| account.autoreplay)
+    }
+
+    /// Changes the autoreplay length of an account and saves it to the database.
+    pub fn set_autoreplay(&mut self, name: &str, len: usize) {
+        self.update(name, move |account| account.autoreplay = Some(len));
+    }
+
+    pub fn vhost(&self, name: &str) -> Option<&str> {
+        self.accounts
+            .get(&name.to_ascii_lowercase())
+            .and_then(|account| account.vhost.as_deref())
+    }
+
+    /// Assigns a vhost to an account, or removes it if `vhost` is `None`.
+    ///
+    /// Returns whether the account exists.
+    pub fn set_vhost(&mut self, name: &str, vhost: Option<String>) -> bool {
+        match self.accounts.get_mut(&name.to_ascii_lowercase()) {
+            Some(account) => {
+                account.vhost = vhost;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies `f` to an account and saves it to the database.
+    ///
+    /// The database is read again before being written, to keep the changes made to it with the
+    /// `ellidri account` subcommand since it was loaded.
+    fn update(&mut self, name: &str, f: impl Fn(&mut Account) + Send + 'static) {
+        let key = name.to_ascii_lowercase();
+        let account = match self.accounts.get_mut(&key) {
+            Some(account) => account,
+            None => return,
+        };
+        f(account);
+
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let path = self.path.clone();
+        writer.run(move || {
+            let mut accounts = match Self::open(&path) {
+                Ok(accounts) => accounts,
+                Err(err) => {
+                    log::error!("{:#}", err);
+                    return;
+                }
+            };
+            if let Some(account) = accounts.accounts.get_mut(&key) {
+                f(account);
+                if let Err(err) = accounts.save() {
+                    log::error!("{:#}", err);
+                }
+            }
+        });
+    }
+
+    /// Waits for the changes made so far to be written to the database.
+    pub fn sync(&self) {
+        if let Some(writer) = &self.writer {
+            writer.sync();
+        }
+    }
+
+    /// Adds an account, or changes its password if it already exists.
+    ///
+    /// Returns whether the account has been created.
+    pub fn set(&mut self, name: &str, password_hash: String) -> bool {
+        let key = name.to_ascii_lowercase();
+        match self.accounts.get_mut(&key) {
+            Some(account) => {
+                account.name = name.to_owned();
+                account.password = password_hash;
+                false
+            }
+            None => {
+                let account = Account {
+                    name: name.to_owned(),
+                    password: password_hash,
+                    metadata: Metadata::new(),
+                    always_on: false,
+                    auto_away: None,
+                    autoreplay: None,
+                    vhost: None,
+                };
+                self.accounts.insert(key, account);
+                true
+            }
+        }
+    }
+
+    /// Returns whether an account has been removed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.accounts.remove(&name.to_ascii_lowercase()).is_some()
+    }
+
+    /// Writes the accounts back to the database.
+    pub fn save(&self) -> Result<()> {
+        let accounts: Vec<&Account> = self.accounts.values().collect();
+        let contents = serde_yaml::to_string(&accounts).context("failed to serialize accounts")?;
+        util::write_atomic(&self.path, &contents)
+            .with_context(|| format!("failed to write {}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate() {
+        let mut accounts = Accounts::default();
+        assert!(accounts.set("Senpai", util::hash_password("hello").unwrap()));
+        assert!(accounts.contains("senpai"));
+
+        assert_eq!(accounts.authenticate("SENPAI", "hello"), Some("Senpai"));
+        assert_eq!(accounts.authenticate("senpai", "nope"), None);
+        assert_eq!(accounts.authenticate("kouhai", "hello"), None);
+
+        assert!(!accounts.set("senpai", util::hash_password("world").unwrap()));
+        assert_eq!(accounts.authenticate("senpai", "world"), Some("senpai"));
+
+        assert!(accounts.remove("SenPai"));
+        assert!(!accounts.remove("senpai"));
+        assert_eq!(accounts.authenticate("senpai", "world"), None);
+    }
+
+    #[test]
+    fn test_update() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-accounts-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut accounts = Accounts::open(path).unwrap();
+        accounts.set("alice", util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+
+        let mut loaded = Accounts::load(path);
+        // Changes made with `ellidri account` are kept.
+        let mut accounts = Accounts::open(path).unwrap();
+        accounts.set("bob", util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+        loaded.set_auto_away("Alice", 60);
+        assert_eq!(loaded.auto_away("alice"), Some(60));
+        loaded.sync();
+
+        let accounts = Accounts::open(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(accounts.auto_away("alice"), Some(60));
+        assert!(accounts.contains("bob"));
+    }
+} // mod tests
@@ -0,0 +1,270 @@
+//! Server announcements, sent periodically to all clients or to a channel.
+
+use crate::config;
+
+/// A cron schedule: `<minute> <hour> <day of month> <month> <day of week>`, in UTC.
+///
+/// Fields are `*`, numbers or ranges (e.g. `1-5`), optionally followed by a step (e.g. `*/15`),
+/// separated by commas.  Days of week go from 0 (sunday) to 6, and 7 is sunday too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether both the day of month and the day of week are restricted, in which case either
+    /// one can match, like cron does.
+    either_day: bool,
+}
+
+/// Returns the set of values of a cron field, as a bitset.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step != 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            // "5/15" means "5-59/15".
+            let start = range.parse().ok()?;
+            (start, if step == 1 { start } else { max })
+        };
+        if start < min || max < end || end < start {
+            return None;
+        }
+        for n in (start..=end).step_by(step) {
+            bits |= 1 << n;
+        }
+    }
+    Some(bits)
+}
+
+/// Returns the month (1 to 12) and day of month (1 to 31) of the given day since the unix epoch.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn month_day(days: u64) -> (u64, u64) {
+    let days = days + 719_468;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let m = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * m + 2) / 5 + 1;
+    let month = if m < 10 { m + 3 } else { m - 9 };
+    (month, day)
+}
+
+impl Cron {
+    pub fn parse(s: &str) -> Option<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        if weekday_bits & 1 << 7 != 0 {
+            weekday_bits |= 1;
+        }
+        Some(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            either_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+        })
+    }
+
+    /// Returns whether the minute of the given unix time is part of the schedule.
+    pub fn matches(&self, time: u64) -> bool {
+        let has = |bits: u64, n: u64| bits & 1 << n != 0;
+        let days = time / 86400;
+        let (month, day) = month_day(days);
+        // The unix epoch was a thursday.
+        let weekday = (days + 4) % 7;
+        let day_matches = if self.either_day {
+            has(self.days, day) || has(self.weekdays, weekday)
+        } else {
+            has(self.days, day) && has(self.weekdays, weekday)
+        };
+        has(self.minutes, time / 60 % 60)
+            && has(self.hours, time / 3600 % 24)
+            && has(self.months, month)
+            && day_matches
+    }
+}
+
+/// When an announcement is sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every given number of seconds.
+    Every(u64),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Returns the schedule of the given announcement, or `None` if it has both or none of
+    /// `interval` and `cron`, or if `cron` is invalid.
+    pub fn new(settings: &config::Announcement) -> Option<Self> {
+        match (settings.interval, settings.cron.as_str()) {
+            (0, "") => None,
+            (interval, "") => Some(Self::Every(interval)),
+            (0, cron) => Cron::parse(cron).map(Self::Cron),
+            _ => None,
+        }
+    }
+}
+
+struct Announcement {
+    settings: config::Announcement,
+    schedule: Schedule,
+    /// When the announcement was last sent, or added, as a unix time.
+    last: u64,
+}
+
+/// The list of announcements of the server.
+#[derive(Default)]
+pub struct Announcements {
+    list: Vec<Announcement>,
+}
+
+impl Announcements {
+    /// Schedules the announcements of the configuration.  Invalid schedules are skipped.
+    pub fn new(settings: Vec<config::Announcement>, now: u64) -> Self {
+        let mut res = Self::default();
+        for announcement in settings {
+            let name = announcement.name.clone();
+            if !res.add(announcement, now) {
+                log::warn!("Invalid schedule for announcement {:?}", name);
+            }
+        }
+        res
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &config::Announcement> {
+        self.list.iter().map(|announcement| &announcement.settings)
+    }
+
+    /// Adds an announcement, or replaces the announcement with the same name.  Returns false and
+    /// does nothing if its schedule is invalid.
+    pub fn add(&mut self, settings: config::Announcement, now: u64) -> bool {
+        let Some(schedule) = Schedule::new(&settings) else {
+            return false;
+        };
+        self.remove(&settings.name);
+        self.list.push(Announcement {
+            settings,
+            schedule,
+            last: now,
+        });
+        true
+    }
+
+    /// Returns whether an announcement has been removed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.list.len();
+        self.list
+            .retain(|announcement| announcement.settings.name != name);
+        self.list.len() != len
+    }
+
+    /// Returns the announcements that are due at `now`, and marks them as sent.
+    pub fn due(&mut self, now: u64) -> Vec<config::Announcement> {
+        let mut res = Vec::new();
+        for announcement in &mut self.list {
+            let due = match &announcement.schedule {
+                Schedule::Every(interval) => announcement.last + interval <= now,
+                Schedule::Cron(cron) => announcement.last / 60 != now / 60 && cron.matches(now),
+            };
+            if due {
+                announcement.last = now;
+                res.push(announcement.settings.clone());
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-29 13:45:00 UTC, a thursday.
+    const LEAP_DAY: u64 = 1_709_214_300;
+
+    fn announcement(name: &str, interval: u64, cron: &str) -> config::Announcement {
+        config::Announcement {
+            name: name.to_owned(),
+            text: String::from("Hello"),
+            interval,
+            cron: cron.to_owned(),
+            target: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_month_day() {
+        assert_eq!(month_day(0), (1, 1));
+        assert_eq!(month_day(LEAP_DAY / 86400), (2, 29));
+        assert_eq!(month_day(LEAP_DAY / 86400 + 1), (3, 1));
+    }
+
+    #[test]
+    fn test_cron() {
+        let matches = |cron: &str, time: u64| Cron::parse(cron).unwrap().matches(time);
+        assert!(matches("* * * * *", LEAP_DAY));
+        assert!(matches("45 13 29 2 4", LEAP_DAY));
+        assert!(matches("*/15 9-17 * * 1-5", LEAP_DAY));
+        assert!(matches("0,45 * * * *", LEAP_DAY));
+        assert!(!matches("0,30 * * * *", LEAP_DAY));
+        assert!(!matches("45 13 * * 0,6", LEAP_DAY));
+        assert!(!matches("45 13 * * 7", LEAP_DAY));
+        assert!(matches("45 13 1 * 4", LEAP_DAY)); // either day can match
+        assert!(!matches("45 13 * 3 *", LEAP_DAY));
+        assert!(matches("45 13 * * 0", LEAP_DAY + 3 * 86400));
+        assert!(matches("45 13 * * 7", LEAP_DAY + 3 * 86400));
+
+        assert!(Cron::parse("* * * *").is_none());
+        assert!(Cron::parse("60 * * * *").is_none());
+        assert!(Cron::parse("* * 0 * *").is_none());
+        assert!(Cron::parse("*/0 * * * *").is_none());
+        assert!(Cron::parse("5-1 * * * *").is_none());
+        assert!(Cron::parse("a * * * *").is_none());
+    }
+
+    #[test]
+    fn test_due() {
+        let mut announcements = Announcements::new(
+            vec![
+                announcement("rules", 3600, ""),
+                announcement("daily", 0, "0 12 * * *"),
+                announcement("invalid", 60, "* * * * *"),
+            ],
+            LEAP_DAY,
+        );
+        assert_eq!(announcements.iter().count(), 2);
+        assert!(announcements.due(LEAP_DAY + 3599).is_empty());
+        let due = announcements.due(LEAP_DAY + 3600);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].name, "rules");
+        assert!(announcements.due(LEAP_DAY + 3601).is_empty());
+
+        // 2024-03-01 12:00:00 UTC
+        let noon = LEAP_DAY + 22 * 3600 + 15 * 60;
+        let due = announcements.due(noon);
+        assert!(due.iter().any(|announcement| announcement.name == "daily"));
+        assert!(announcements.due(noon + 30).is_empty());
+
+        assert!(announcements.add(announcement("rules", 60, ""), noon));
+        assert_eq!(announcements.iter().count(), 2);
+        assert!(!announcements.add(announcement("never", 0, ""), noon));
+        assert!(announcements.remove("daily"));
+        assert!(!announcements.remove("daily"));
+        assert_eq!(announcements.due(noon + 60)[0].interval, 60);
+    }
+} // mod tests
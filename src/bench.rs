@@ -0,0 +1,90 @@
+//! Fixtures for the benchmarks of `benches/`.  This module is not part of the API of ellidri.
+
+use crate::client::MessageQueueItem;
+use crate::config::{self, BindingOptions};
+use crate::State;
+use ellidri_tokens::Message;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, Notify};
+
+const CHANNEL: &str = "#bench";
+
+/// A channel with many registered members, where the first one talks.
+pub struct Broadcast {
+    rt: Runtime,
+    shared: State,
+    sender: usize,
+    queues: Vec<mpsc::UnboundedReceiver<MessageQueueItem>>,
+}
+
+impl Broadcast {
+    /// Creates a channel with `members` registered clients.
+    pub fn new(members: usize) -> Self {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut cfg = config::State {
+            motd_file: String::new(),
+            ..config::State::default()
+        };
+        cfg.domain = String::from("bench.ellidri");
+        let shared = rt.block_on(State::new(cfg, Arc::new(Notify::new())));
+
+        let mut ids = Vec::with_capacity(members);
+        let mut queues = Vec::with_capacity(members);
+        rt.block_on(async {
+            let options = BindingOptions::default();
+            for i in 0..members {
+                let (queue, outgoing) = mpsc::unbounded_channel();
+                let addr = SocketAddr::from(([127, 0, 0, 1], i as u16));
+                let id = shared.peer_joined(addr, &options, false, None, queue).await;
+                let nick = format!("NICK member{i}");
+                shared
+                    .handle_message(id, Message::parse(&nick).unwrap())
+                    .await;
+                let user = Message::parse("USER member 0 * :Bench member").unwrap();
+                shared.handle_message(id, user).await;
+                shared.add_member(id, CHANNEL).await;
+                ids.push(id);
+                queues.push(outgoing);
+            }
+        });
+        let mut broadcast = Self {
+            rt,
+            shared,
+            sender: ids[0],
+            queues,
+        };
+        broadcast.drain();
+        broadcast
+    }
+
+    /// Sends a PRIVMSG with the given text to the channel, and returns the number of messages that
+    /// have been queued for the members.
+    pub fn privmsg(&mut self, text: &str) -> usize {
+        let line = format!("PRIVMSG {CHANNEL} :{text}");
+        let msg = Message::parse(&line).unwrap();
+        self.rt
+            .block_on(self.shared.handle_message(self.sender, msg));
+        self.drain()
+    }
+
+    /// Empties the queues of the members, and returns how many messages they had.
+    fn drain(&mut self) -> usize {
+        let queues = &mut self.queues;
+        let drain = std::future::poll_fn(|cx| {
+            let mut n = 0;
+            for queue in queues.iter_mut() {
+                while let Poll::Ready(Some(_)) = queue.poll_recv(cx) {
+                    n += 1;
+                }
+            }
+            Poll::Ready(n)
+        });
+        self.rt.block_on(tokio::task::unconstrained(drain))
+    }
+}
@@ -0,0 +1,411 @@
+//! Channel logs, written to disk.
+//!
+//! Messages and events of the channels listed in the configuration are written in
+//! `<channel_log_dir>/<channel>/<YYYY-MM-DD>.log`, in a format similar to ZNC's log module.  A new
+//! file is started every day (UTC).
+//!
+//! Lines are formatted by the state and written by a dedicated task, so that the state is never
+//! blocked on disk I/O.  They can be exported with `ellidri export-history`.
+
+use crate::client::Client;
+use ellidri_tokens::Command;
+use ellidri_unicase::{u, UniCase};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead as _;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs as std_fs, time};
+use tokio::io::{self, AsyncWriteExt};
+use tokio::{fs, sync::mpsc};
+
+/// A line of a channel log, sent to the writer task.
+struct Line {
+    /// The name of the directory of the channel.
+    channel: String,
+    date: String,
+    text: String,
+}
+
+#[derive(Default)]
+pub struct ChannelLogs {
+    channels: HashSet<UniCase<String>>,
+    queue: Option<mpsc::UnboundedSender<Line>>,
+}
+
+impl ChannelLogs {
+    /// Starts the writer task, unless `dir` is empty or there is no channel to log.
+    pub fn new(dir: &str, channels: &[String]) -> Self {
+        if dir.is_empty() || channels.is_empty() {
+            return Self::default();
+        }
+        let (queue, lines) = mpsc::unbounded_channel();
+        tokio::spawn(write_lines(PathBuf::from(dir), lines));
+        Self {
+            channels: channels
+                .iter()
+                .map(|name| UniCase::new(name.clone()))
+                .collect(),
+            queue: Some(queue),
+        }
+    }
+
+    pub fn is_logged(&self, channel: &str) -> bool {
+        self.queue.is_some() && self.channels.contains(u(channel))
+    }
+
+    /// Appends a line to the log of the given channel, if it is logged.
+    pub fn log(&self, channel: &str, line: fmt::Arguments<'_>) {
+        let queue = match self.queue {
+            Some(ref queue) if self.channels.contains(u(channel)) => queue,
+            _ => return,
+        };
+        // "YYYY-MM-DDTHH:MM:SSZ"
+        let now = humantime::format_rfc3339_seconds(time::SystemTime::now()).to_string();
+        let _ = queue.send(Line {
+            channel: dir_name(channel),
+            date: now[..10].to_owned(),
+            text: format!("[{}] {}\n", &now[11..19], line),
+        });
+    }
+
+    pub fn message(&self, channel: &str, command: Command, nick: &str, text: &str) {
+        if command == Command::Notice {
+            self.log(channel, format_args!("-{}- {}", nick, text));
+        } else if let Some(action) = text
+            .strip_prefix("\x01ACTION ")
+            .map(|action| action.trim_end_matches('\x01'))
+        {
+            self.log(channel, format_args!("* {} {}", nick, action));
+        } else {
+            self.log(channel, format_args!("<{}> {}", nick, text));
+        }
+    }
+
+    pub fn join(&self, channel: &str, client: &Client) {
+        self.log(
+            channel,
+            format_args!(
+                "*** Joins: {} (~{}@{})",
+                client.nick(),
+                client.user(),
+                client.host()
+            ),
+        );
+    }
+
+    pub fn part(&self, channel: &str, client: &Client, reason: Option<&str>) {
+        self.log(
+            channel,
+            format_args!(
+                "*** Parts: {} (~{}@{}) ({})",
+                client.nick(),
+                client.user(),
+                client.host(),
+                reason.unwrap_or("")
+            ),
+        );
+    }
+
+    pub fn quit(&self, channel: &str, client: &Client, reason: &str) {
+        self.log(
+            channel,
+            format_args!(
+                "*** Quits: {} (~{}@{}) ({})",
+                client.nick(),
+                client.user(),
+                client.host(),
+                reason
+            ),
+        );
+    }
+
+    pub fn kick(&self, channel: &str, kicked: &str, by: &str, reason: Option<&str>) {
+        self.log(
+            channel,
+            format_args!(
+                "*** {} was kicked by {} ({})",
+                kicked,
+                by,
+                reason.unwrap_or("")
+            ),
+        );
+    }
+
+    pub fn nick(&self, channel: &str, old: &str, new: &str) {
+        self.log(channel, format_args!("*** {} is now known as {}", old, new));
+    }
+
+    pub fn mode(&self, channel: &str, by: &str, modes: &str) {
+        self.log(channel, format_args!("*** {} sets mode: {}", by, modes));
+    }
+
+    pub fn topic(&self, channel: &str, by: &str, topic: &str) {
+        self.log(
+            channel,
+            format_args!("*** {} changes topic to '{}'", by, topic),
+        );
+    }
+}
+
+/// The name of the directory where the logs of the given channel are written.
+fn dir_name(channel: &str) -> String {
+    channel
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Writes the lines received from `lines` to the log files, until the channel is closed.
+async fn write_lines(dir: PathBuf, mut lines: mpsc::UnboundedReceiver<Line>) {
+    // Open log files, along with their date, by channel.
+    let mut files = HashMap::new();
+    while let Some(line) = lines.recv().await {
+        if let Err(err) = write_line(&dir, &mut files, &line).await {
+            log::warn!("Failed to write the log of {:?}: {}", line.channel, err);
+            files.remove(&line.channel);
+        }
+    }
+}
+
+async fn write_line(
+    dir: &Path,
+    files: &mut HashMap<String, (String, fs::File)>,
+    line: &Line,
+) -> io::Result<()> {
+    let is_open = files
+        .get(&line.channel)
+        .is_some_and(|(date, _)| *date == line.date);
+    if !is_open {
+        let channel_dir = dir.join(&line.channel);
+        fs::create_dir_all(&channel_dir).await?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(channel_dir.join(format!("{}.log", line.date)))
+            .await?;
+        files.insert(line.channel.clone(), (line.date.clone(), file));
+    }
+    let (_, file) = files.get_mut(&line.channel).unwrap();
+    file.write_all(line.text.as_bytes()).await?;
+    file.flush().await
+}
+
+/// The output format of `export`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The log lines, prefixed with their date.
+    Txt,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+/// Writes the lines of the logs of `channel` in `dir` that were written at or after `since` (an
+/// RFC 3339 UTC timestamp such as "2020-06-01T00:00:00Z") to `out`.
+///
+/// Returns the number of lines written.
+pub fn export(
+    dir: &str,
+    channel: &str,
+    since: &str,
+    format: ExportFormat,
+    out: &mut impl std::io::Write,
+) -> std::io::Result<usize> {
+    let channel_dir = Path::new(dir).join(dir_name(channel));
+    let mut dates: Vec<String> = std_fs::read_dir(channel_dir.as_path())?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let date = name.strip_suffix(".log")?;
+            (*date >= since[..10]).then(|| date.to_owned())
+        })
+        .collect();
+    dates.sort_unstable();
+
+    let mut n = 0;
+    for date in dates {
+        let file = std_fs::File::open(channel_dir.join(format!("{date}.log")))?;
+        for line in std::io::BufReader::new(file).lines() {
+            let line = line?;
+            let (time, text) = match parse_line(&line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            let time = format!("{date}T{time}Z");
+            if *time < *since {
+                continue;
+            }
+            match format {
+                ExportFormat::Txt => writeln!(out, "{date} {line}")?,
+                ExportFormat::Jsonl => {
+                    let (kind, nick, text) = parse_text(text);
+                    let entry = serde_json::json!({
+                        "time": time,
+                        "channel": channel,
+                        "kind": kind,
+                        "nick": nick,
+                        "text": text,
+                    });
+                    writeln!(out, "{entry}")?;
+                }
+            }
+            n += 1;
+        }
+    }
+    out.flush()?;
+    Ok(n)
+}
+
+/// Splits a log line into its time ("HH:MM:SS") and its text.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (time, text) = rest.split_once("] ")?;
+    Some((time, text))
+}
+
+/// Returns the kind of a log line, the nickname of its author if any and its content.
+fn parse_text(text: &str) -> (&'static str, Option<&str>, &str) {
+    if let Some(event) = text.strip_prefix("*** ") {
+        return ("event", None, event);
+    }
+    let parsed = if let Some(rest) = text.strip_prefix('<') {
+        rest.split_once("> ")
+            .map(|(nick, text)| ("message", nick, text))
+    } else if let Some(rest) = text.strip_prefix("* ") {
+        rest.split_once(' ')
+            .map(|(nick, text)| ("action", nick, text))
+    } else if let Some(rest) = text.strip_prefix('-') {
+        rest.split_once("- ")
+            .map(|(nick, text)| ("notice", nick, text))
+    } else {
+        None
+    };
+    match parsed {
+        Some((kind, nick, text)) => (kind, Some(nick), text),
+        None => ("event", None, text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_name() {
+        assert_eq!(dir_name("#Ellidri"), "#ellidri");
+        assert_eq!(dir_name("#a/../b"), "#a_.._b");
+    }
+
+    #[test]
+    fn test_write_lines() {
+        let dir =
+            std::env::temp_dir().join(format!("ellidri-chanlog-write-{}", std::process::id()));
+        let _ = std_fs::remove_dir_all(&dir);
+        let line = |channel: &str, date: &str, text: &str| Line {
+            channel: channel.to_owned(),
+            date: date.to_owned(),
+            text: format!("{text}\n"),
+        };
+
+        crate::sim::run({
+            let dir = dir.clone();
+            async move {
+                let (queue, lines) = mpsc::unbounded_channel();
+                let lines_sent = [
+                    line("#a", "2020-06-01", "[23:59:59] <x> one"),
+                    line("#b", "2020-06-01", "[23:59:59] <y> two"),
+                    line("#a", "2020-06-02", "[00:00:00] <x> three"),
+                ];
+                for line in lines_sent {
+                    assert!(queue.send(line).is_ok());
+                }
+                drop(queue);
+                write_lines(dir, lines).await;
+            }
+        });
+
+        let read = |path: &str| std_fs::read_to_string(dir.join(path)).unwrap();
+        assert_eq!(read("#a/2020-06-01.log"), "[23:59:59] <x> one\n");
+        assert_eq!(read("#a/2020-06-02.log"), "[00:00:00] <x> three\n");
+        assert_eq!(read("#b/2020-06-01.log"), "[23:59:59] <y> two\n");
+        std_fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export() {
+        let dir =
+            std::env::temp_dir().join(format!("ellidri-chanlog-export-{}", std::process::id()));
+        let channel_dir = dir.join("#chan");
+        std_fs::create_dir_all(&channel_dir).unwrap();
+        std_fs::write(channel_dir.join("2020-05-31.log"), "[23:00:00] <a> old\n").unwrap();
+        std_fs::write(
+            channel_dir.join("2020-06-01.log"),
+            "[11:59:59] <a> before\n[12:00:00] * a waves\ngarbage\n[13:00:00] *** b sets mode: +m\n",
+        )
+        .unwrap();
+        std_fs::write(channel_dir.join("2020-06-02.log"), "[08:00:00] -b- psst\n").unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let export_chan = |since, format| {
+            let mut out = Vec::new();
+            let n = export(dir, "#Chan", since, format, &mut out).unwrap();
+            (n, String::from_utf8(out).unwrap())
+        };
+        let (n, txt) = export_chan("2020-06-01T12:00:00Z", ExportFormat::Txt);
+        assert_eq!(n, 3);
+        assert_eq!(
+            txt,
+            "2020-06-01 [12:00:00] * a waves\n\
+             2020-06-01 [13:00:00] *** b sets mode: +m\n\
+             2020-06-02 [08:00:00] -b- psst\n"
+        );
+
+        let (n, jsonl) = export_chan("2020-06-02T00:00:00Z", ExportFormat::Jsonl);
+        assert_eq!(n, 1);
+        let entry: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+        assert_eq!(
+            entry,
+            serde_json::json!({
+                "time": "2020-06-02T08:00:00Z",
+                "channel": "#Chan",
+                "kind": "notice",
+                "nick": "b",
+                "text": "psst",
+            })
+        );
+
+        assert_eq!(export_chan("1970-01-01T00:00:00Z", ExportFormat::Txt).0, 5);
+        let mut out = Vec::new();
+        assert!(export(
+            dir,
+            "#nothing",
+            "1970-01-01T00:00:00Z",
+            ExportFormat::Txt,
+            &mut out
+        )
+        .is_err());
+        std_fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let parse = |line| parse_line(line).map(|(time, text)| (time, parse_text(text)));
+        assert_eq!(
+            parse("[12:00:00] <a> hi> there"),
+            Some(("12:00:00", ("message", Some("a"), "hi> there")))
+        );
+        assert_eq!(
+            parse("[12:00:01] * a waves"),
+            Some(("12:00:01", ("action", Some("a"), "waves")))
+        );
+        assert_eq!(
+            parse("[12:00:02] -a- psst"),
+            Some(("12:00:02", ("notice", Some("a"), "psst")))
+        );
+        assert_eq!(
+            parse("[12:00:03] *** a is now known as b"),
+            Some(("12:00:03", ("event", None, "a is now known as b")))
+        );
+        assert_eq!(parse("garbage"), None);
+    }
+} // mod tests
@@ -1,7 +1,14 @@
+use crate::client::Recipients;
 use crate::data::modes;
+use crate::history::{self, History};
+use crate::metadata::Metadata;
 use crate::util;
 use ellidri_tokens::{mode, rpl, MessageBuffer};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
 
 /// Modes applied to clients on a per-channel basis.
 ///
@@ -69,24 +76,105 @@ impl MemberModes {
 
         modes.iter().all(|mode| match mode {
             Err(_) => true,
-            Ok(GetBans) | Ok(GetExceptions) | Ok(GetInvitations) => true,
+            Ok(GetBans) | Ok(GetExceptions) | Ok(GetInvitations) | Ok(GetQuiets) => true,
             Ok(Moderated(_))
+            | Ok(NoColors(_))
+            | Ok(NoDcc(_))
+            | Ok(StripColors(_))
             | Ok(TopicRestricted(_))
             | Ok(UserLimit(_))
+            | Ok(FloodLimit(_))
+            | Ok(JoinLimit(_))
+            | Ok(HistoryLimit(_))
             | Ok(ChangeBan(_, _))
             | Ok(ChangeException(_, _))
             | Ok(ChangeInvitation(_, _))
+            | Ok(ChangeQuiet(_, _))
             | Ok(ChangeVoice(_, _)) => self.is_at_least_halfop(),
             Ok(InviteOnly(_))
             | Ok(NoPrivMsgFromOutside(_))
+            | Ok(Private(_))
             | Ok(Secret(_))
             | Ok(Key(_, _))
             | Ok(ChangeOperator(_, _))
             | Ok(ChangeHalfop(_, _)) => self.is_at_least_op(),
+            Ok(ChangeFounder(_, _)) => self.founder,
         })
     }
 }
 
+/// Status mode automatically given to channel members, see `store::AccessEntry`.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    Voice,
+    Halfop,
+    Operator,
+}
+
+impl AccessLevel {
+    /// Parses the letter of the corresponding channel mode ('o', 'h' or 'v').
+    pub fn from_letter(s: &str) -> Option<Self> {
+        match s {
+            "v" | "V" => Some(Self::Voice),
+            "h" | "H" => Some(Self::Halfop),
+            "o" | "O" => Some(Self::Operator),
+            _ => None,
+        }
+    }
+
+    /// The letter of the corresponding channel mode.
+    pub fn letter(self) -> char {
+        match self {
+            Self::Voice => 'v',
+            Self::Halfop => 'h',
+            Self::Operator => 'o',
+        }
+    }
+
+    /// Gives the mode to `modes`.  Returns whether `modes` has changed.
+    pub fn apply(self, modes: &mut MemberModes) -> bool {
+        let mode = match self {
+            Self::Voice => &mut modes.voice,
+            Self::Halfop => &mut modes.halfop,
+            Self::Operator => &mut modes.operator,
+        };
+        let applied = !*mode;
+        *mode = true;
+        applied
+    }
+}
+
+/// Parameter of the flood protection mode (+f): members can send at most `lines` messages every
+/// `seconds` seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloodLimit {
+    pub lines: u32,
+    pub seconds: u32,
+}
+
+impl std::str::FromStr for FloodLimit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lines, seconds) = s.split_once(':').ok_or(())?;
+        let lines = lines.parse().map_err(|_| ())?;
+        let seconds = seconds.parse().map_err(|_| ())?;
+        if lines == 0 || seconds == 0 {
+            return Err(());
+        }
+        Ok(Self { lines, seconds })
+    }
+}
+
+impl fmt::Display for FloodLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.lines, self.seconds)
+    }
+}
+
 pub struct Topic {
     pub content: String,
     pub who: String,
@@ -99,23 +187,63 @@ pub struct Channel {
     /// channel mode.
     pub members: HashMap<usize, MemberModes>,
 
+    /// The members as needed by worker tasks to deliver messages, built when the channel is sent
+    /// a message and dropped when its members change.  See `StateInner::fan_out`.
+    pub recipients: Option<Arc<Recipients>>,
+
     /// The topic.
     pub topic: Option<Topic>,
 
+    /// Previous topics, most recent first.
+    pub topic_history: VecDeque<Topic>,
+
+    /// The last messages sent to the channel, for CHATHISTORY.
+    pub history: History,
+
+    /// History mode (+H): restricts how many messages are kept, and for how long, below the
+    /// limits of the configuration.
+    pub history_limit: Option<history::Limit>,
+
     pub user_limit: Option<usize>,
     pub key: Option<String>,
 
+    pub flood_limit: Option<FloodLimit>,
+
+    /// For each member, the start of the current flood window and the number of messages sent
+    /// since then.
+    flood_counters: HashMap<usize, (Instant, u32)>,
+
+    /// Join throttle (+j): at most `lines` joins are accepted every `seconds` seconds.
+    pub join_limit: Option<FloodLimit>,
+
+    /// The start of the current join window and the number of joins since then.
+    join_counter: (Instant, u32),
+
     // https://tools.ietf.org/html/rfc2811.html#section-4.3
     pub ban_mask: util::MaskSet,
     pub exception_mask: util::MaskSet,
     pub invex_mask: util::MaskSet,
 
+    /// Users matching these masks can join but cannot talk.
+    pub quiet_mask: util::MaskSet,
+
+    /// Members that cannot talk until the given UNIX time, set with `MUTE`.
+    pub mutes: HashMap<usize, u64>,
+
     // Modes: https://tools.ietf.org/html/rfc2811.html#section-4.2
     pub invite_only: bool,
     pub moderated: bool,
+    pub no_colors: bool,
+    /// DCC offers are refused, see `config::DccAction`.
+    pub no_dcc: bool,
     pub no_msg_from_outside: bool,
+    /// Members are hidden from outside, but unlike secret channels, the channel is listed.
+    pub private: bool,
     pub secret: bool,
+    pub strip_colors: bool,
     pub topic_restricted: bool,
+
+    pub metadata: Metadata,
 }
 
 impl Channel {
@@ -127,29 +255,48 @@ impl Channel {
     pub fn new(modes: &str) -> Self {
         let mut channel = Channel {
             members: HashMap::new(),
+            recipients: None,
             topic: None,
+            topic_history: VecDeque::new(),
+            history: History::default(),
+            history_limit: None,
             user_limit: None,
             key: None,
+            flood_limit: None,
+            flood_counters: HashMap::new(),
+            join_limit: None,
+            join_counter: (Instant::now(), 0),
             ban_mask: util::MaskSet::new(),
             exception_mask: util::MaskSet::new(),
             invex_mask: util::MaskSet::new(),
+            quiet_mask: util::MaskSet::new(),
+            mutes: HashMap::new(),
             invite_only: false,
             moderated: false,
+            no_colors: false,
+            no_dcc: false,
             no_msg_from_outside: false,
+            private: false,
             secret: false,
+            strip_colors: false,
             topic_restricted: false,
+            metadata: Metadata::new(),
         };
         for change in mode::simple_channel_query(modes).filter_map(Result::ok) {
             channel
-                .apply_mode_change(change, usize::max_value(), |_| "")
+                .apply_mode_change(change, usize::MAX, usize::MAX, |_| None)
                 .unwrap();
         }
         channel
     }
 
     /// Adds a member with the default mode.
-    pub fn add_member(&mut self, id: usize) {
-        let modes = if self.members.is_empty() {
+    ///
+    /// The first member of the channel is given operator status, unless the channel is
+    /// `registered` (has a founder).  In that case, status modes are given from the persistent data
+    /// of the channel instead.
+    pub fn add_member(&mut self, id: usize, registered: bool) {
+        let modes = if self.members.is_empty() && !registered {
             MemberModes {
                 founder: false,
                 protected: false,
@@ -161,6 +308,22 @@ impl Channel {
             MemberModes::default()
         };
         self.members.insert(id, modes);
+        self.recipients = None;
+    }
+
+    /// Removes a member, and returns its modes if it was in the channel.
+    pub fn remove_member(&mut self, id: usize) -> Option<MemberModes> {
+        self.recipients = None;
+        self.members.remove(&id)
+    }
+
+    /// Replaces the topic, and keeps the old one in the history, which is truncated to
+    /// `history_len` topics.
+    pub fn set_topic(&mut self, topic: Option<Topic>, history_len: usize) {
+        if let Some(old) = std::mem::replace(&mut self.topic, topic) {
+            self.topic_history.push_front(old);
+        }
+        self.topic_history.truncate(history_len);
     }
 
     pub fn list_entry(&self, msg: MessageBuffer<'_>) {
@@ -181,12 +344,85 @@ impl Channel {
         !self.invite_only || self.invex_mask.is_match(nick)
     }
 
-    pub fn can_talk(&self, id: usize) -> bool {
+    pub fn is_quiet(&self, full_name: &str) -> bool {
+        self.quiet_mask.is_match(full_name) && !self.exception_mask.is_match(full_name)
+    }
+
+    pub fn can_talk(&self, id: usize, full_name: &str) -> bool {
         if let Some(member) = self.members.get(&id) {
-            !self.moderated || member.has_voice()
+            if self.mutes.contains_key(&id) {
+                return member.is_at_least_halfop();
+            }
+            member.has_voice() || (!self.moderated && !self.is_quiet(full_name))
         } else {
-            !self.moderated && !self.no_msg_from_outside
+            !self.moderated && !self.no_msg_from_outside && !self.is_quiet(full_name)
+        }
+    }
+
+    /// Removes the mutes that end at or before `now`, and returns the members they applied to.
+    pub fn expire_mutes(&mut self, now: u64) -> Vec<usize> {
+        let mut expired = Vec::new();
+        self.mutes.retain(|&id, &mut until| {
+            if until <= now {
+                expired.push(id);
+            }
+            now < until
+        });
+        expired
+    }
+
+    /// Counts a message sent by `id` and returns whether it exceeds the flood limit of the
+    /// channel.  Halfops and above are exempt.
+    pub fn is_flooding(&mut self, id: usize, now: Instant) -> bool {
+        let limit = match self.flood_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        if self
+            .members
+            .get(&id)
+            .is_some_and(|modes| modes.is_at_least_halfop())
+        {
+            return false;
+        }
+        if self.members.len() < self.flood_counters.len() {
+            let members = &self.members;
+            self.flood_counters.retain(|id, _| members.contains_key(id));
+        }
+
+        let window = Duration::from_secs(limit.seconds.into());
+        let (start, count) = self.flood_counters.entry(id).or_insert((now, 0));
+        if window <= now.duration_since(*start) {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        limit.lines < *count
+    }
+
+    /// Whether the join limit of the channel has been reached, see `Channel::count_join`.
+    pub fn is_join_throttled(&self, now: Instant) -> bool {
+        let limit = match self.join_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let (start, count) = self.join_counter;
+        let window = Duration::from_secs(limit.seconds.into());
+        now.duration_since(start) < window && limit.lines <= count
+    }
+
+    /// Counts a join towards the join limit of the channel.
+    pub fn count_join(&mut self, now: Instant) {
+        let limit = match self.join_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let (start, count) = &mut self.join_counter;
+        if Duration::from_secs(limit.seconds.into()) <= now.duration_since(*start) {
+            *start = now;
+            *count = 0;
         }
+        *count += 1;
     }
 
     pub fn can_invite(&self, id: usize) -> bool {
@@ -204,6 +440,12 @@ impl Channel {
     pub fn modes(&self, mut out: MessageBuffer<'_>, full_info: bool) {
         let modes = out.raw_param();
         modes.push('+');
+        if self.no_colors {
+            modes.push('c');
+        }
+        if self.no_dcc {
+            modes.push('d');
+        }
         if self.invite_only {
             modes.push('i');
         }
@@ -213,12 +455,27 @@ impl Channel {
         if self.no_msg_from_outside {
             modes.push('n');
         }
+        if self.private {
+            modes.push('p');
+        }
         if self.secret {
             modes.push('s');
         }
+        if self.strip_colors {
+            modes.push('S');
+        }
         if self.topic_restricted {
             modes.push('t');
         }
+        if self.flood_limit.is_some() {
+            modes.push('f');
+        }
+        if self.history_limit.is_some() {
+            modes.push('H');
+        }
+        if self.join_limit.is_some() {
+            modes.push('j');
+        }
         if self.user_limit.is_some() {
             modes.push('l');
         }
@@ -227,6 +484,15 @@ impl Channel {
         }
 
         if full_info {
+            if let Some(flood_limit) = self.flood_limit {
+                out = out.fmt_param(flood_limit);
+            }
+            if let Some(history_limit) = self.history_limit {
+                out = out.fmt_param(history_limit);
+            }
+            if let Some(join_limit) = self.join_limit {
+                out = out.fmt_param(join_limit);
+            }
             if let Some(user_limit) = self.user_limit {
                 out = out.fmt_param(user_limit);
             }
@@ -236,11 +502,14 @@ impl Channel {
         }
     }
 
-    pub fn apply_mode_change<'a>(
+    /// Applies the given mode change.  `id_of` resolves the nicknames given to +o, +h and +v into
+    /// client identifiers.
+    pub fn apply_mode_change(
         &mut self,
         change: mode::ChannelChange<'_>,
         keylen: usize,
-        nick_of: impl Fn(usize) -> &'a str,
+        maxlist: usize,
+        id_of: impl Fn(&str) -> Option<usize>,
     ) -> Result<bool, &'static str> {
         use mode::ChannelChange::*;
 
@@ -254,14 +523,30 @@ impl Channel {
                 applied = self.moderated != value;
                 self.moderated = value;
             }
+            NoColors(value) => {
+                applied = self.no_colors != value;
+                self.no_colors = value;
+            }
+            NoDcc(value) => {
+                applied = self.no_dcc != value;
+                self.no_dcc = value;
+            }
             NoPrivMsgFromOutside(value) => {
                 applied = self.no_msg_from_outside != value;
                 self.no_msg_from_outside = value;
             }
+            Private(value) => {
+                applied = self.private != value;
+                self.private = value;
+            }
             Secret(value) => {
                 applied = self.secret != value;
                 self.secret = value;
             }
+            StripColors(value) => {
+                applied = self.strip_colors != value;
+                self.strip_colors = value;
+            }
             TopicRestricted(value) => {
                 applied = self.topic_restricted != value;
                 self.topic_restricted = value;
@@ -281,9 +566,7 @@ impl Channel {
             }
             UserLimit(Some(s)) => {
                 if let Ok(limit) = s.parse() {
-                    applied = self
-                        .user_limit
-                        .map_or(true, |chan_limit| chan_limit != limit);
+                    applied = self.user_limit != Some(limit);
                     self.user_limit = Some(limit);
                 }
             }
@@ -291,8 +574,43 @@ impl Channel {
                 applied = self.user_limit.is_some();
                 self.user_limit = None;
             }
+            FloodLimit(Some(s)) => {
+                if let Ok(limit) = s.parse() {
+                    applied = self.flood_limit != Some(limit);
+                    self.flood_limit = Some(limit);
+                }
+            }
+            FloodLimit(None) => {
+                applied = self.flood_limit.is_some();
+                self.flood_limit = None;
+                self.flood_counters.clear();
+            }
+            JoinLimit(Some(s)) => {
+                if let Ok(limit) = s.parse() {
+                    applied = self.join_limit != Some(limit);
+                    self.join_limit = Some(limit);
+                }
+            }
+            JoinLimit(None) => {
+                applied = self.join_limit.is_some();
+                self.join_limit = None;
+                self.join_counter = (Instant::now(), 0);
+            }
+            HistoryLimit(Some(s)) => {
+                if let Ok(limit) = s.parse() {
+                    applied = self.history_limit != Some(limit);
+                    self.history_limit = Some(limit);
+                }
+            }
+            HistoryLimit(None) => {
+                applied = self.history_limit.is_some();
+                self.history_limit = None;
+            }
             ChangeBan(value, param) => {
                 applied = if value {
+                    if maxlist <= self.ban_mask.len() && !self.ban_mask.contains(param) {
+                        return Err(rpl::ERR_BANLISTFULL);
+                    }
                     self.ban_mask.insert(param)
                 } else {
                     self.ban_mask.remove(param)
@@ -300,6 +618,10 @@ impl Channel {
             }
             ChangeException(value, param) => {
                 applied = if value {
+                    if maxlist <= self.exception_mask.len() && !self.exception_mask.contains(param)
+                    {
+                        return Err(rpl::ERR_BANLISTFULL);
+                    }
                     self.exception_mask.insert(param)
                 } else {
                     self.exception_mask.remove(param)
@@ -307,61 +629,67 @@ impl Channel {
             }
             ChangeInvitation(value, param) => {
                 applied = if value {
+                    if maxlist <= self.invex_mask.len() && !self.invex_mask.contains(param) {
+                        return Err(rpl::ERR_BANLISTFULL);
+                    }
                     self.invex_mask.insert(param)
                 } else {
                     self.invex_mask.remove(param)
                 };
             }
-            ChangeOperator(value, param) => {
-                let mut has_it = false;
-                for (member, modes) in &mut self.members {
-                    if nick_of(*member) == param {
-                        has_it = true;
-                        applied = modes.operator != value;
-                        modes.operator = value;
-                        break;
+            ChangeQuiet(value, param) => {
+                applied = if value {
+                    if maxlist <= self.quiet_mask.len() && !self.quiet_mask.contains(param) {
+                        return Err(rpl::ERR_BANLISTFULL);
                     }
-                }
-                if !has_it {
-                    return Err(rpl::ERR_USERNOTINCHANNEL);
-                }
+                    self.quiet_mask.insert(param)
+                } else {
+                    self.quiet_mask.remove(param)
+                };
+            }
+            ChangeFounder(value, param) => {
+                let modes = id_of(param)
+                    .and_then(|id| self.members.get_mut(&id))
+                    .ok_or(rpl::ERR_USERNOTINCHANNEL)?;
+                applied = modes.founder != value;
+                modes.founder = value;
+            }
+            ChangeOperator(value, param) => {
+                let modes = id_of(param)
+                    .and_then(|id| self.members.get_mut(&id))
+                    .ok_or(rpl::ERR_USERNOTINCHANNEL)?;
+                applied = modes.operator != value;
+                modes.operator = value;
             }
             ChangeHalfop(value, param) => {
-                let mut has_it = false;
-                for (member, modes) in &mut self.members {
-                    if nick_of(*member) == param {
-                        has_it = true;
-                        applied = modes.halfop != value;
-                        modes.halfop = value;
-                        break;
-                    }
-                }
-                if !has_it {
-                    return Err(rpl::ERR_USERNOTINCHANNEL);
-                }
+                let modes = id_of(param)
+                    .and_then(|id| self.members.get_mut(&id))
+                    .ok_or(rpl::ERR_USERNOTINCHANNEL)?;
+                applied = modes.halfop != value;
+                modes.halfop = value;
             }
             ChangeVoice(value, param) => {
-                let mut has_it = false;
-                for (member, modes) in &mut self.members {
-                    if nick_of(*member) == param {
-                        has_it = true;
-                        applied = modes.voice != value;
-                        modes.voice = value;
-                        break;
-                    }
-                }
-                if !has_it {
-                    return Err(rpl::ERR_USERNOTINCHANNEL);
-                }
+                let modes = id_of(param)
+                    .and_then(|id| self.members.get_mut(&id))
+                    .ok_or(rpl::ERR_USERNOTINCHANNEL)?;
+                applied = modes.voice != value;
+                modes.voice = value;
             }
             _ => {}
         }
         Ok(applied)
     }
 
+    /// Whether the members of the channel are hidden from clients outside of it.
+    pub fn hides_members(&self) -> bool {
+        self.secret || self.private
+    }
+
     pub fn symbol(&self) -> &'static str {
         if self.secret {
             "@"
+        } else if self.private {
+            "*"
         } else {
             "="
         }
@@ -406,4 +734,167 @@ mod tests {
         assert!(!VOICE.is_at_least_halfop());
         assert!(!VOICE.is_at_least_op());
     }
+
+    #[test]
+    fn test_quiet() {
+        let mut channel = Channel::new("");
+        channel.members.insert(0, MemberModes::default());
+        channel.members.insert(1, VOICE);
+        channel.quiet_mask.insert("*!*@quiet");
+
+        assert!(!channel.can_talk(0, "a!~a@quiet"));
+        assert!(channel.can_talk(0, "a!~a@loud"));
+        assert!(channel.can_talk(1, "b!~b@quiet"));
+        assert!(!channel.can_talk(2, "c!~c@quiet"));
+
+        channel.exception_mask.insert("a!*@*");
+        assert!(channel.can_talk(0, "a!~a@quiet"));
+    }
+
+    #[test]
+    fn test_private() {
+        let mut channel = Channel::new("+p");
+        assert!(channel.private);
+        assert!(channel.hides_members());
+        assert_eq!(channel.symbol(), "*");
+
+        let mut apply = |change| channel.apply_mode_change(change, 0, 0, |_| None);
+        assert_eq!(apply(mode::ChannelChange::Secret(true)), Ok(true));
+        assert_eq!(apply(mode::ChannelChange::Private(false)), Ok(true));
+        assert_eq!(channel.symbol(), "@");
+        assert!(channel.hides_members());
+    }
+
+    #[test]
+    fn test_mute() {
+        let mut channel = Channel::new("");
+        channel.members.insert(0, MemberModes::default());
+        channel.members.insert(1, VOICE);
+        channel.members.insert(2, HALFOP);
+        for id in 0..3 {
+            channel.mutes.insert(id, 100);
+        }
+
+        assert!(!channel.can_talk(0, "a!~a@host"));
+        assert!(!channel.can_talk(1, "b!~b@host"));
+        assert!(channel.can_talk(2, "c!~c@host"));
+
+        channel.mutes.insert(0, 50);
+        assert!(channel.expire_mutes(49).is_empty());
+        assert_eq!(channel.expire_mutes(50), [0]);
+        assert!(channel.can_talk(0, "a!~a@host"));
+        let mut expired = channel.expire_mutes(100);
+        expired.sort_unstable();
+        assert_eq!(expired, [1, 2]);
+        assert!(channel.mutes.is_empty());
+    }
+
+    #[test]
+    fn test_topic_history() {
+        let topic = |content: &str| Topic {
+            content: content.to_owned(),
+            who: String::from("nick"),
+            time: 0,
+        };
+        let mut channel = Channel::new("");
+        channel.set_topic(Some(topic("a")), 2);
+        channel.set_topic(Some(topic("b")), 2);
+        channel.set_topic(None, 2);
+        channel.set_topic(Some(topic("c")), 2);
+
+        assert_eq!(channel.topic.as_ref().unwrap().content, "c");
+        let history: Vec<_> = channel.topic_history.iter().map(|t| &t.content).collect();
+        assert_eq!(history, ["b", "a"]);
+
+        channel.set_topic(Some(topic("d")), 2);
+        let history: Vec<_> = channel.topic_history.iter().map(|t| &t.content).collect();
+        assert_eq!(history, ["c", "b"]);
+    }
+
+    #[test]
+    fn test_maxlist() {
+        let mut channel = Channel::new("");
+        let mut ban = |mask| {
+            let change = mode::ChannelChange::ChangeBan(true, mask);
+            channel.apply_mode_change(change, 0, 1, |_| None)
+        };
+        assert_eq!(ban("a!*@*"), Ok(true));
+        assert_eq!(ban("a!*@*"), Ok(false));
+        assert_eq!(ban("b!*@*"), Err(rpl::ERR_BANLISTFULL));
+    }
+
+    #[test]
+    fn test_flood() {
+        let mut channel = Channel::new("");
+        channel.members.insert(0, MemberModes::default());
+        channel.members.insert(1, HALFOP);
+        let now = Instant::now();
+        assert!(!channel.is_flooding(0, now));
+
+        let change = mode::ChannelChange::FloodLimit(Some("2:10"));
+        assert_eq!(channel.apply_mode_change(change, 0, 0, |_| None), Ok(true));
+        assert!(!channel.is_flooding(0, now));
+        assert!(!channel.is_flooding(0, now));
+        assert!(channel.is_flooding(0, now));
+        assert!(!channel.is_flooding(0, now + Duration::from_secs(10)));
+        for _ in 0..5 {
+            assert!(!channel.is_flooding(1, now));
+        }
+
+        let change = mode::ChannelChange::FloodLimit(Some("0:10"));
+        assert_eq!(channel.apply_mode_change(change, 0, 0, |_| None), Ok(false));
+    }
+
+    #[test]
+    fn test_join_limit() {
+        let mut channel = Channel::new("");
+        let now = Instant::now();
+        assert!(!channel.is_join_throttled(now));
+
+        let change = mode::ChannelChange::JoinLimit(Some("2:10"));
+        assert_eq!(channel.apply_mode_change(change, 0, 0, |_| None), Ok(true));
+        channel.count_join(now);
+        assert!(!channel.is_join_throttled(now));
+        channel.count_join(now);
+        assert!(channel.is_join_throttled(now));
+        assert!(channel.is_join_throttled(now + Duration::from_secs(9)));
+        assert!(!channel.is_join_throttled(now + Duration::from_secs(10)));
+        channel.count_join(now + Duration::from_secs(10));
+        assert!(!channel.is_join_throttled(now + Duration::from_secs(10)));
+
+        let change = mode::ChannelChange::JoinLimit(None);
+        assert_eq!(channel.apply_mode_change(change, 0, 0, |_| None), Ok(true));
+        assert!(!channel.is_join_throttled(now));
+    }
+
+    #[test]
+    fn test_member_modes() {
+        let mut channel = Channel::new("");
+        channel.members.insert(0, MemberModes::default());
+        let id_of = |nick: &str| match nick {
+            "alice" => Some(0),
+            "bob" => Some(1),
+            _ => None,
+        };
+
+        let change = mode::ChannelChange::ChangeOperator(true, "alice");
+        assert_eq!(channel.apply_mode_change(change, 0, 0, id_of), Ok(true));
+        assert!(channel.members[&0].operator);
+        assert_eq!(channel.apply_mode_change(change, 0, 0, id_of), Ok(false));
+
+        let change = mode::ChannelChange::ChangeVoice(true, "bob");
+        let res = channel.apply_mode_change(change, 0, 0, id_of);
+        assert_eq!(res, Err(rpl::ERR_USERNOTINCHANNEL));
+        let change = mode::ChannelChange::ChangeHalfop(true, "carol");
+        let res = channel.apply_mode_change(change, 0, 0, id_of);
+        assert_eq!(res, Err(rpl::ERR_USERNOTINCHANNEL));
+
+        let change = mode::ChannelChange::ChangeFounder(true, "alice");
+        assert_eq!(channel.apply_mode_change(change, 0, 0, id_of), Ok(true));
+        assert_eq!(channel.members[&0].symbol(), Some('~'));
+
+        let founder_modes = modes::Channel::new("+O", &["alice"]);
+        assert!(channel.members[&0].can_change(founder_modes));
+        assert!(!OPERATOR.can_change(founder_modes));
+    }
 } // mod tests
@@ -0,0 +1,397 @@
+//! The command-line interface of ellidri.
+
+use crate::config::{self, Config};
+use crate::util::{self, hash_password};
+use crate::{accounts, chanlog, control, logging};
+use anyhow::{anyhow, Context, Result};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::net::SocketAddr;
+use std::{env, process};
+
+/// Returns the description of the command-line arguments.
+fn app() -> Command {
+    Command::new("Ellidri")
+        .about("irc server")
+        .subcommands(vec![
+            Command::new("gen-config")
+                .about("generate a new configuration file")
+                .arg(
+                    Arg::new("output-file")
+                        .long("output-file")
+                        .help("file to write config file to"),
+                ),
+            Command::new("start")
+                .about("start the ellidri irc server")
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .help("path to ellidri config file"),
+                )
+                .arg(
+                    Arg::new("bind")
+                        .long("bind")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(SocketAddr))
+                        .help(
+                            "plain-text address to listen on, instead of the configured bindings",
+                        ),
+                )
+                .arg(
+                    Arg::new("motd-file")
+                        .long("motd-file")
+                        .help("path to the MOTD file, instead of the configured one"),
+                )
+                .arg(
+                    Arg::new("workers")
+                        .long("workers")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("number of worker threads, instead of the configured number"),
+                )
+                .arg(
+                    Arg::new("log-level")
+                        .long("log-level")
+                        .help("log filter (e.g. info, or ellidri=trace), instead of $ELLIDRI_LOG"),
+                ),
+            Command::new("check-config")
+                .about("check the configuration file and exit")
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .help("path to ellidri config file"),
+                ),
+            Command::new("hash-oper-config")
+                .about("replace plaintext oper passwords in a configuration file with their hash")
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .help("path to ellidri config file"),
+                ),
+            Command::new("account")
+                .about("manage the accounts used for SASL authentication")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .global(true)
+                        .help("path to ellidri config file"),
+                )
+                .subcommands(vec![
+                    Command::new("add")
+                        .about("create an account")
+                        .arg(Arg::new("name").required(true)),
+                    Command::new("del")
+                        .about("delete an account")
+                        .arg(Arg::new("name").required(true)),
+                    Command::new("passwd")
+                        .about("change the password of an account")
+                        .arg(Arg::new("name").required(true)),
+                    Command::new("always-on")
+                        .about("keep the user of an account connected when its clients leave")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("enable")
+                                .required(true)
+                                .value_parser(["on", "off"]),
+                        ),
+                    Command::new("vhost")
+                        .about("display a vhost instead of the host of the user of an account")
+                        .arg(Arg::new("name").required(true))
+                        .arg(Arg::new("host").help("the vhost, or nothing to remove it")),
+                ]),
+            Command::new("export-history")
+                .about("dump the logs of a channel, as written with channel_log_dir")
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .help("path to ellidri config file"),
+                )
+                .arg(
+                    Arg::new("channel")
+                        .long("channel")
+                        .required(true)
+                        .help("channel to export"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("export messages sent at or after this UTC date or time (e.g. 2020-06-01 or 2020-06-01T12:00:00)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["txt", "jsonl"])
+                        .default_value("txt")
+                        .help("output format"),
+                ),
+            Command::new("hash-password")
+                .about("read user input, running it through argon2 hashing"),
+        ])
+}
+
+/// Returns the log filters given with `--log-level` to `start`, if any.
+fn log_level(app: &ArgMatches) -> Option<&String> {
+    match app.subcommand() {
+        Some(("start", start)) => start.get_one::<String>("log-level"),
+        _ => None,
+    }
+}
+
+/// Returns the settings given to `start` that take precedence over the configuration file.
+fn overrides(start: &ArgMatches) -> config::Overrides {
+    config::Overrides {
+        bindings: start
+            .get_many::<SocketAddr>("bind")
+            .map(|addrs| addrs.copied().collect())
+            .unwrap_or_default(),
+        motd_file: start.get_one::<String>("motd-file").cloned(),
+        workers: start.get_one::<usize>("workers").copied(),
+    }
+}
+
+/// Parses the command-line arguments and runs the corresponding subcommand.
+pub fn main() -> Result<()> {
+    if cfg!(debug_assertions) {
+        env::set_var("RUST_BACKTRACE", "1");
+    }
+
+    let app = app().get_matches();
+
+    let log_filters = match log_level(&app) {
+        Some(level) => level.clone(),
+        None => env::var("ELLIDRI_LOG").unwrap_or_else(|_| String::from("ellidri=debug")),
+    };
+    let mut logger = env_logger::Builder::new();
+    logger.parse_filters(&log_filters);
+    if let Ok(write_style) = env::var("ELLIDRI_LOG_STYLE") {
+        logger.parse_write_style(&write_style);
+    }
+    logger.format(|buf, r| {
+        use std::io::Write;
+        writeln!(buf, "[{:<5} {}] {}", r.level(), r.target(), r.args())
+    });
+    logging::init(logger.build());
+
+    if let Some(("start", start)) = app.subcommand() {
+        return control::load_config_and_run(
+            start
+                .get_one::<String>("config")
+                .context("failed to get config")?
+                .to_string(),
+            overrides(start),
+        );
+    }
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(run_command(app))
+}
+
+/// Runs the subcommands that don't start the server.
+async fn run_command(app: ArgMatches) -> Result<()> {
+    match app.subcommand() {
+        Some(("gen-config", gen)) => {
+            Config::default()
+                .write_to_file(
+                    gen.get_one::<String>("output-file")
+                        .context("failed to get output-file")?,
+                )
+                .await?;
+        }
+        Some(("check-config", check)) => {
+            let path = check
+                .get_one::<String>("config")
+                .context("failed to get config")?;
+            let config = match Config::from_file(path).await {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("{path}: {err:?}");
+                    process::exit(1);
+                }
+            };
+            let problems = config.check();
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("{path}: {problem}");
+                }
+                process::exit(1);
+            }
+            println!("{path}: ok");
+        }
+        Some(("hash-oper-config", hash)) => {
+            let path = hash
+                .get_one::<String>("config")
+                .context("failed to get config")?;
+            let hashed = config::hash_oper_passwords(path)?;
+            println!("{path}: hashed {hashed} oper password(s)");
+        }
+        Some(("account", account)) => manage_accounts(account).await?,
+        Some(("export-history", export)) => export_history(export).await?,
+        Some(("hash-password", _)) => {
+            let pass = rpassword::prompt_password("input password: ")
+                .context("failed to read user input")?;
+            let hashed_password = hash_password(&pass).unwrap();
+            println!("hashed password: {hashed_password}");
+            assert!(crate::util::verify_password_hash(&hashed_password, &pass).is_ok());
+        }
+        _ => return Err(anyhow!("invalid subcommand")),
+    }
+    Ok(())
+}
+
+/// Writes the logs of a channel to stdout.
+async fn export_history(matches: &ArgMatches) -> Result<()> {
+    let path = matches
+        .get_one::<String>("config")
+        .context("failed to get config")?;
+    let channel = matches
+        .get_one::<String>("channel")
+        .context("failed to get channel")?;
+    let since = parse_since(matches.get_one::<String>("since").map(String::as_str))?;
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("jsonl") => chanlog::ExportFormat::Jsonl,
+        _ => chanlog::ExportFormat::Txt,
+    };
+
+    let config = Config::from_file(path).await?;
+    let dir = &config.state.channel_log_dir;
+    if dir.is_empty() {
+        return Err(anyhow!("{path}: channel_log_dir is not set"));
+    }
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    chanlog::export(dir, channel, &since, format, &mut out)
+        .with_context(|| format!("failed to export the logs of {channel} from {dir}"))?;
+    Ok(())
+}
+
+/// Returns the value of `--since` as an RFC 3339 UTC timestamp, such as "2020-06-01T00:00:00Z".
+fn parse_since(since: Option<&str>) -> Result<String> {
+    let since = match since {
+        Some(since) if since.len() == 10 => format!("{since}T00:00:00Z"),
+        Some(since) => since.to_owned(),
+        None => String::from("1970-01-01T00:00:00Z"),
+    };
+    let since = humantime::parse_rfc3339_weak(&since)
+        .map_err(|err| anyhow!("invalid --since {since:?}: {err}"))?;
+    Ok(humantime::format_rfc3339_seconds(since).to_string())
+}
+
+/// Runs the `account` subcommands on the account database of the configuration.
+async fn manage_accounts(matches: &ArgMatches) -> Result<()> {
+    let (action, args) = matches.subcommand().context("failed to get subcommand")?;
+    let path = args
+        .get_one::<String>("config")
+        .context("failed to get config")?;
+    let name = args
+        .get_one::<String>("name")
+        .context("failed to get name")?;
+
+    let config = Config::from_file(path).await?;
+    if config.state.account_db.is_empty() {
+        return Err(anyhow!("{path}: account_db is not set"));
+    }
+    let mut accounts = accounts::Accounts::open(&config.state.account_db)?;
+
+    match action {
+        "add" | "passwd" => {
+            if action == "add" && accounts.contains(name) {
+                return Err(anyhow!("account {name:?} already exists"));
+            }
+            if action == "passwd" && !accounts.contains(name) {
+                return Err(anyhow!("account {name:?} does not exist"));
+            }
+            let pass = rpassword::prompt_password("input password: ")
+                .context("failed to read user input")?;
+            accounts.set(name, hash_password(&pass)?);
+        }
+        "del" => {
+            if !accounts.remove(name) {
+                return Err(anyhow!("account {name:?} does not exist"));
+            }
+        }
+        "always-on" => {
+            let enable = args.get_one::<String>("enable").map(String::as_str) == Some("on");
+            if !accounts.set_always_on(name, enable) {
+                return Err(anyhow!("account {name:?} does not exist"));
+            }
+        }
+        "vhost" => {
+            let host = args.get_one::<String>("host").cloned();
+            if let Some(ref host) = host {
+                if !util::is_valid_host(host) {
+                    return Err(anyhow!("{host:?} is not a valid host"));
+                }
+            }
+            if !accounts.set_vhost(name, host) {
+                return Err(anyhow!("account {name:?} does not exist"));
+            }
+        }
+        _ => return Err(anyhow!("invalid subcommand")),
+    }
+    accounts.save()?;
+
+    println!("{}: account {name:?} updated", config.state.account_db);
+    println!("Changes are applied once ellidri reloads its configuration.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let matches = app().get_matches_from(["ellidri", "start", "--config", "ellidri.yaml"]);
+        assert_eq!(log_level(&matches), None);
+        let mut cfg = Config::default();
+        overrides(matches.subcommand_matches("start").unwrap()).apply(&mut cfg);
+        assert_eq!(cfg.bindings, Config::default().bindings);
+        assert_eq!(cfg.state.motd_file, Config::default().state.motd_file);
+        assert_eq!(cfg.workers, Config::default().workers);
+
+        let matches = app().get_matches_from([
+            "ellidri",
+            "start",
+            "--config",
+            "ellidri.yaml",
+            "--bind",
+            "127.0.0.1:7000",
+            "--bind",
+            "[::1]:7001",
+            "--motd-file",
+            "/tmp/motd.txt",
+            "--workers",
+            "3",
+            "--log-level",
+            "ellidri=trace",
+        ]);
+        assert_eq!(
+            log_level(&matches).map(String::as_str),
+            Some("ellidri=trace")
+        );
+        overrides(matches.subcommand_matches("start").unwrap()).apply(&mut cfg);
+        let addresses: Vec<String> = cfg.bindings.iter().map(|b| b.address.to_string()).collect();
+        assert_eq!(addresses, ["127.0.0.1:7000", "[::1]:7001"]);
+        assert!(cfg.bindings.iter().all(|b| b.tls.is_none()));
+        assert_eq!(cfg.state.motd_file, "/tmp/motd.txt");
+        assert_eq!(cfg.workers, 3);
+
+        let err = app().try_get_matches_from(["ellidri", "start", "--workers", "many"]);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let cases = [
+            (None, "1970-01-01T00:00:00Z"),
+            (Some("2020-06-01"), "2020-06-01T00:00:00Z"),
+            (Some("2020-06-01T12:30:00"), "2020-06-01T12:30:00Z"),
+            (Some("2020-06-01 12:30:00"), "2020-06-01T12:30:00Z"),
+        ];
+        for (since, expected) in cases {
+            assert_eq!(parse_since(since).unwrap(), expected, "{since:?}");
+        }
+        assert!(parse_since(Some("yesterday")).is_err());
+        assert!(parse_since(Some("2020-13-01")).is_err());
+    }
+}
@@ -1,12 +1,18 @@
 //! Client data, connection state and capability logic.
 
-use crate::{data, util};
+use crate::channel::FloodLimit;
+use crate::metadata::Metadata;
+use crate::{config, data, util};
 use ellidri_tokens::{mode, Buffer, MessageBuffer, ReplyBuffer};
-use ellidri_unicase::UniCase;
-use std::collections::HashSet;
+use ellidri_unicase::{u, UniCase};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::Write as _;
+use std::net;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 
 #[derive(Clone, Debug)]
 pub struct MessageQueueItem {
@@ -47,8 +53,9 @@ pub type MessageQueue = mpsc::UnboundedSender<MessageQueueItem>;
 /// client can send.
 ///
 /// For example, a client that has only sent a "NICK" message cannot send a "JOIN" message.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum ConnectionState {
+    #[default]
     ConnectionEstablished,
     NickGiven,
     UserGiven,
@@ -56,23 +63,24 @@ pub enum ConnectionState {
     CapNickGiven,
     CapUserGiven,
     CapNegotiation,
+    /// NICK and USER have been given, but the challenge has not been answered yet.
+    Challenged,
     Registered,
     Quit,
 }
 
-impl Default for ConnectionState {
-    fn default() -> ConnectionState {
-        ConnectionState::ConnectionEstablished
-    }
-}
-
 impl ConnectionState {
     pub fn apply(self, request: &data::Request<'_>) -> Result<ConnectionState, ()> {
         use data::Request::*;
         match self {
             ConnectionState::ConnectionEstablished => match request {
                 CapLs { .. } | CapReq { .. } => Ok(ConnectionState::CapGiven),
-                CapEnd | CapList { .. } | Pass { .. } | Ping { .. } => Ok(self),
+                CapEnd
+                | CapList { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 Nick { .. } => Ok(ConnectionState::NickGiven),
                 User { .. } => Ok(ConnectionState::UserGiven),
                 Quit { .. } => Ok(ConnectionState::Quit),
@@ -80,23 +88,38 @@ impl ConnectionState {
             },
             ConnectionState::NickGiven => match request {
                 CapLs { .. } | CapReq { .. } => Ok(ConnectionState::CapGiven),
-                CapEnd | CapList { .. } | Nick { .. } | Pass { .. } | Ping { .. } => Ok(self),
+                CapEnd
+                | CapList { .. }
+                | Nick { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 User { .. } => Ok(ConnectionState::Registered),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Err(()),
             },
             ConnectionState::UserGiven => match request {
                 CapLs { .. } | CapReq { .. } => Ok(ConnectionState::CapGiven),
-                CapEnd | CapList { .. } | Pass { .. } | Ping { .. } => Ok(self),
+                CapEnd
+                | CapList { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 Nick { .. } => Ok(ConnectionState::Registered),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Err(()),
             },
             ConnectionState::CapGiven => match request {
                 CapEnd => Ok(ConnectionState::ConnectionEstablished),
-                CapList { .. } | CapLs { .. } | CapReq { .. } | Pass { .. } | Ping { .. } => {
-                    Ok(self)
-                }
+                CapList { .. }
+                | CapLs { .. }
+                | CapReq { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 Nick { .. } => Ok(ConnectionState::CapNickGiven),
                 User { .. } => Ok(ConnectionState::CapUserGiven),
                 Quit { .. } => Ok(ConnectionState::Quit),
@@ -109,16 +132,22 @@ impl ConnectionState {
                 | CapReq { .. }
                 | Nick { .. }
                 | Pass { .. }
-                | Ping { .. } => Ok(self),
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 User { .. } => Ok(ConnectionState::CapNegotiation),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Err(()),
             },
             ConnectionState::CapUserGiven => match request {
                 CapEnd => Ok(ConnectionState::UserGiven),
-                CapList { .. } | CapLs { .. } | CapReq { .. } | Pass { .. } | Ping { .. } => {
-                    Ok(self)
-                }
+                CapList { .. }
+                | CapLs { .. }
+                | CapReq { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
                 Nick { .. } => Ok(ConnectionState::CapNegotiation),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Err(()),
@@ -130,12 +159,26 @@ impl ConnectionState {
                 | CapReq { .. }
                 | Nick { .. }
                 | Pass { .. }
-                | Ping { .. } => Ok(self),
+                | Ping { .. }
+                | Authenticate { .. }
+                | Challenge { .. } => Ok(self),
+                Quit { .. } => Ok(ConnectionState::Quit),
+                _ => Err(()),
+            },
+            ConnectionState::Challenged => match request {
+                Challenge { .. } => Ok(ConnectionState::Registered),
+                CapEnd
+                | CapList { .. }
+                | CapLs { .. }
+                | CapReq { .. }
+                | Pass { .. }
+                | Ping { .. }
+                | Authenticate { .. } => Ok(self),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Err(()),
             },
             ConnectionState::Registered => match request {
-                Pass { .. } | User { .. } => Err(()),
+                Pass { .. } | User { .. } | Challenge { .. } => Err(()),
                 Quit { .. } => Ok(ConnectionState::Quit),
                 _ => Ok(self),
             },
@@ -146,17 +189,290 @@ impl ConnectionState {
     pub fn is_registered(self) -> bool {
         self == ConnectionState::Registered
     }
+
+    /// The name of the state, as shown by TRACE.
+    pub fn name(self) -> &'static str {
+        match self {
+            ConnectionState::ConnectionEstablished => "connected",
+            ConnectionState::NickGiven => "nick-given",
+            ConnectionState::UserGiven => "user-given",
+            ConnectionState::CapGiven => "cap-given",
+            ConnectionState::CapNickGiven => "cap-nick-given",
+            ConnectionState::CapUserGiven => "cap-user-given",
+            ConnectionState::CapNegotiation => "cap-negotiation",
+            ConnectionState::Challenged => "challenged",
+            ConnectionState::Registered => "registered",
+            ConnectionState::Quit => "quit",
+        }
+    }
 }
 
 const FULL_NAME_LENGTH: usize = 64;
 
+/// An invitation to a channel, issued with the INVITE command.
+pub struct Invite {
+    /// The nickname of the client that sent the invitation.
+    pub by: String,
+
+    /// The UNIX time after which the invitation is not valid anymore.
+    pub expires: u64,
+}
+
+/// Messages sent to an always-on client while no connection is attached to it.
+struct Backlog {
+    messages: RefCell<VecDeque<MessageQueueItem>>,
+
+    /// Maximum number of messages kept.
+    len: usize,
+}
+
+/// A message queue, with the key the outbox knows it by.
+#[derive(Clone)]
+struct Queue {
+    key: usize,
+    sender: MessageQueue,
+}
+
+/// Another connection of a multiclient session.
+struct Connection {
+    id: usize,
+    queue: Queue,
+    cap_enabled: data::Capabilities,
+}
+
+/// Returns `msg` as it is sent to a connection with the given capabilities.
+fn for_caps(cap_enabled: data::Capabilities, mut msg: MessageQueueItem) -> MessageQueueItem {
+    if cap_enabled.has_message_tags() {
+        msg.start = 0;
+    }
+    msg
+}
+
+fn send_to(outbox: &Outbox, queue: &Queue, cap_enabled: data::Capabilities, msg: MessageQueueItem) {
+    outbox.send(queue, for_caps(cap_enabled, msg));
+}
+
+/// Keeps the messages sent to connections in order with the channel messages that worker tasks
+/// are delivering.
+///
+/// While workers are running, messages and broadcasts to their recipients are held, and they are
+/// sent in order once the workers are done.  This way the state never waits for the workers,
+/// clients receive messages in the order the state has sent them, and clients that are not
+/// recipients of a running broadcast receive theirs right away.
+#[derive(Clone, Default)]
+pub struct Outbox(Arc<std::sync::Mutex<OutboxInner>>);
+
+#[derive(Default)]
+struct OutboxInner {
+    /// The key of the next queue.
+    next_key: usize,
+
+    /// The ID of the next broadcast.
+    next_id: usize,
+
+    /// The broadcasts whose workers are running, with their recipients and the number of workers
+    /// left.
+    running: Vec<(usize, Arc<Recipients>, usize)>,
+
+    /// What has been sent to the recipients of running broadcasts, in order.  Empty when
+    /// `running` is.
+    held: VecDeque<Held>,
+}
+
+enum Held {
+    Message(Queue, MessageQueueItem),
+    Broadcast(Broadcast),
+}
+
+impl Held {
+    /// Whether this and `other` have a queue in common.
+    fn overlaps(&self, other: &Recipients) -> bool {
+        match self {
+            Held::Message(queue, _) => other.keys.contains(&queue.key),
+            Held::Broadcast(broadcast) => {
+                let (a, b) = (&broadcast.recipients.keys, &other.keys);
+                let (small, big) = if a.len() < b.len() { (a, b) } else { (b, a) };
+                small.iter().any(|key| big.contains(key))
+            }
+        }
+    }
+}
+
+/// A message to be delivered to the recipients that match `filter`, by worker tasks that each
+/// deliver it to `chunk` recipients.
+pub struct Broadcast {
+    pub recipients: Arc<Recipients>,
+    pub msg: MessageQueueItem,
+    pub filter: Arc<dyn Fn(&Recipient) -> bool + Send + Sync>,
+    pub chunk: usize,
+}
+
+impl Outbox {
+    /// Gives `sender` a key, by which the outbox knows which queues running broadcasts write to.
+    fn queue(&self, sender: MessageQueue) -> Queue {
+        let mut inner = self.0.lock().unwrap();
+        let key = inner.next_key;
+        inner.next_key += 1;
+        Queue { key, sender }
+    }
+
+    fn send(&self, queue: &Queue, msg: MessageQueueItem) {
+        let mut inner = self.0.lock().unwrap();
+        self.send_or_hold(&mut inner, Held::Message(queue.clone(), msg));
+    }
+
+    /// Spawns the worker tasks of `broadcast`, or holds it until the running ones that write to
+    /// the same queues are done.
+    ///
+    /// # Panics
+    ///
+    /// When called outside of a tokio runtime.
+    pub fn broadcast(&self, broadcast: Broadcast) {
+        let mut inner = self.0.lock().unwrap();
+        self.send_or_hold(&mut inner, Held::Broadcast(broadcast));
+    }
+
+    /// Holds `item` when it goes to the recipients of a running broadcast, or to the queues of
+    /// what is already held.  Sends it otherwise.
+    fn send_or_hold(&self, inner: &mut OutboxInner, item: Held) {
+        let is_held = inner
+            .running
+            .iter()
+            .any(|(_, recipients, _)| item.overlaps(recipients))
+            || inner.held.iter().any(|held| match held {
+                Held::Message(queue, _) => match item {
+                    Held::Message(ref item_queue, _) => item_queue.key == queue.key,
+                    Held::Broadcast(ref broadcast) => {
+                        broadcast.recipients.keys.contains(&queue.key)
+                    }
+                },
+                Held::Broadcast(broadcast) => item.overlaps(&broadcast.recipients),
+            });
+        if is_held {
+            inner.held.push_back(item);
+            return;
+        }
+        match item {
+            Held::Message(queue, msg) => {
+                let _ = queue.sender.send(msg);
+            }
+            Held::Broadcast(broadcast) => self.spawn_workers(inner, broadcast),
+        }
+    }
+
+    fn spawn_workers(&self, inner: &mut OutboxInner, broadcast: Broadcast) {
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let len = broadcast.recipients.connected.len();
+        let chunk = broadcast.chunk.max(1);
+        let workers = len.div_ceil(chunk);
+        if workers == 0 {
+            return;
+        }
+        inner
+            .running
+            .push((id, broadcast.recipients.clone(), workers));
+        for start in (0..len).step_by(chunk) {
+            let outbox = self.clone();
+            let recipients = broadcast.recipients.clone();
+            let msg = broadcast.msg.clone();
+            let filter = broadcast.filter.clone();
+            tokio::spawn(async move {
+                let end = len.min(start + chunk);
+                for recipient in &recipients.connected[start..end] {
+                    if filter(recipient) {
+                        recipient.send(&msg);
+                    }
+                }
+                outbox.worker_done(id);
+            });
+        }
+    }
+
+    /// Sends what has been held for the recipients of the broadcast `id` once its last worker is
+    /// done, except what goes to the recipients of other running broadcasts.
+    fn worker_done(&self, id: usize) {
+        let mut inner = self.0.lock().unwrap();
+        let i = inner.running.iter().position(|(b, _, _)| *b == id).unwrap();
+        inner.running[i].2 -= 1;
+        if inner.running[i].2 != 0 {
+            return;
+        }
+        inner.running.swap_remove(i);
+        let held = std::mem::take(&mut inner.held);
+        for item in held {
+            self.send_or_hold(&mut inner, item);
+        }
+    }
+}
+
+/// A client, as needed to deliver channel messages to it away from the state.  See
+/// `Client::recipient`.
+pub struct Recipient {
+    pub id: usize,
+    pub cap_enabled: data::Capabilities,
+    pub no_ctcp: bool,
+    pub deaf: bool,
+
+    /// The queues of the connections of the client, with their capabilities.  Empty when the
+    /// client is detached.
+    queues: Vec<(Queue, data::Capabilities)>,
+}
+
+impl Recipient {
+    /// Sends `msg` to the connections of the client, without going through the outbox.
+    fn send(&self, msg: &MessageQueueItem) {
+        for (queue, cap_enabled) in &self.queues {
+            let _ = queue.sender.send(for_caps(*cap_enabled, msg.clone()));
+        }
+    }
+}
+
+/// The members of a channel, as needed to deliver its messages away from the state.
+///
+/// Detached clients are kept apart, since their messages go to their backlog, which only the
+/// state can write to.
+#[derive(Default)]
+pub struct Recipients {
+    pub connected: Vec<Recipient>,
+    pub detached: Vec<Recipient>,
+
+    /// The keys of the queues of `connected`.
+    keys: HashSet<usize>,
+}
+
+impl Recipients {
+    /// Adds `recipient`, to `detached` when it has no connection.
+    pub fn push(&mut self, recipient: Recipient) {
+        if recipient.queues.is_empty() {
+            self.detached.push(recipient);
+        } else {
+            self.keys
+                .extend(recipient.queues.iter().map(|(queue, _)| queue.key));
+            self.connected.push(recipient);
+        }
+    }
+}
+
+/// The channels of a LIST reply that have not been sent yet.
+pub struct PendingList {
+    /// The client the reply is for, which is the session of multiclient connections.
+    pub id: usize,
+    pub channels: VecDeque<String>,
+    /// Whether the reply is a labeled response, in which case it is in the "list" batch.
+    pub labeled: bool,
+}
+
 /// Client data.
 pub struct Client {
     /// The queue of messages to be sent to the client.
     ///
     /// This is the write end of a mpsc channel of messages (similar to go channels). It is
     /// currently unbounded, meaning sending messages to this channel does not block.
-    queue: MessageQueue,
+    queue: Queue,
+
+    /// Where messages go through before `queue`, shared by all clients.
+    outbox: Outbox,
 
     pub domain: Arc<str>,
 
@@ -182,23 +498,109 @@ pub struct Client {
     /// Whether the client has issued a PASS command with the right password.
     pub has_given_password: bool,
 
+    /// Hash of the password required by the binding the client connected to, if it overrides the
+    /// global password.
+    pub password: Option<String>,
+
+    /// The challenge the client must answer before registering, see `Binding::challenge_hosts`.
+    pub challenge: Option<config::Challenge>,
+
+    /// Whether the client is connected through TLS.
+    pub tls: bool,
+
+    /// The IP address the client connected from, unless it came through Tor.
+    pub ip: Option<net::IpAddr>,
+
+    /// The SHA-256 fingerprint of the TLS certificate of the client, if it gave one.
+    pub certfp: Option<String>,
+
+    /// When the last PING sent by the server to this connection was sent, if it hasn't been
+    /// answered yet.
+    pub ping_sent: Option<Instant>,
+
+    /// The round-trip time of the last PING sent by the server to this connection.
+    pub lag: Option<Duration>,
+
+    /// The number of channels the client is in, see `chanlimit`.
+    pub joined_channels: usize,
+
+    /// The start of the current CTCP window, and the number of CTCP requests sent since then.
+    ctcp_counter: (Instant, u32),
+
+    /// When the client last listed all channels.
+    pub last_list: Option<Instant>,
+
+    /// The rest of the LIST reply being sent to this connection.
+    pub pending_list: Option<PendingList>,
+
     // Modes: https://tools.ietf.org/html/rfc2812.html#section-3.1.5
     pub away_message: Option<String>,
     pub invisible: bool,
+    /// Whether CTCP requests sent to the client are refused (+T).  ACTIONs are still relayed.
+    pub no_ctcp: bool,
+    /// Whether the client is a bot (+B).  Its messages are tagged with `bot`.
+    pub bot: bool,
+    /// Whether messages sent to the channels of the client are not relayed to it (+D).
+    pub deaf: bool,
+    /// Whether private messages from clients that are not logged in are refused (+R).
+    pub registered_only: bool,
+
+    /// Whether `away_message` has been set automatically, after the client has been idle for
+    /// too long.
+    pub auto_away: bool,
+
+    /// Seconds of inactivity after which the client is marked as away, if it overrides the
+    /// server setting.  0 disables automatic away.
+    pub auto_away_delay: Option<u64>,
+
+    /// Number of messages replayed when the client joins a channel, if it overrides the server
+    /// setting.  0 disables autoreplay.
+    pub autoreplay: Option<usize>,
+
+    /// The language of the replies sent to the client, if it has chosen one with `LANGUAGE`.
+    pub language: Option<String>,
     pub operator: bool,
 
-    pub invites: HashSet<UniCase<String>>,
+    /// The privileges of the oper block the client used, if it is an IRC operator.
+    pub oper_privileges: Vec<config::OperPrivilege>,
+
+    /// Pending invitations, by channel name.
+    pub invites: HashMap<UniCase<String>, Invite>,
+
+    /// The base64 data received so far, when a SASL PLAIN authentication is in progress.
+    pub sasl_buffer: Option<String>,
+
+    pub metadata: Metadata,
+
+    /// The metadata keys the client wants to be notified about.
+    pub metadata_subs: BTreeSet<String>,
+
+    /// Set when the client is always-on and its connection has been closed.
+    backlog: Option<Backlog>,
+
+    /// The other connections attached to this client, with multiclient.
+    connections: Vec<Connection>,
+
+    /// When this is an additional connection of a multiclient session, the ID of the client that
+    /// holds the session.
+    pub session: Option<usize>,
+
+    /// Set when the client uses the nickname of an account it is not logged in to: when its
+    /// nickname is changed.
+    pub nick_deadline: Option<Instant>,
 }
 
 impl Client {
-    /// Initialize the data for a new client, given its message queue.
+    /// Initialize the data for a new client, given its message queue and the outbox of the
+    /// state.
     ///
     /// The nickname is set to "*", as it seems it's what freenode server does.  The username and
     /// the realname are set to empty strings.
-    pub fn new(domain: Arc<str>, queue: MessageQueue, host: String) -> Self {
+    pub fn new(domain: Arc<str>, queue: MessageQueue, outbox: Outbox, host: String) -> Self {
         let now = util::time();
         Self {
-            queue,
+            queue: outbox.queue(queue),
+            outbox,
             domain,
             full_name: String::with_capacity(FULL_NAME_LENGTH),
             cap_version: data::cap::Version::V300,
@@ -212,22 +614,168 @@ impl Client {
             signon_time: now,
             last_action_time: now,
             has_given_password: false,
+            password: None,
+            challenge: None,
+            tls: false,
+            ip: None,
+            certfp: None,
+            ping_sent: None,
+            lag: None,
+            last_list: None,
+            pending_list: None,
+            joined_channels: 0,
+            ctcp_counter: (Instant::now(), 0),
             away_message: None,
             invisible: false,
+            no_ctcp: false,
+            bot: false,
+            deaf: false,
+            registered_only: false,
+            auto_away: false,
+            auto_away_delay: None,
+            autoreplay: None,
+            language: None,
             operator: false,
-            invites: HashSet::new(),
+            oper_privileges: Vec::new(),
+            invites: HashMap::new(),
+            sasl_buffer: None,
+            metadata: Metadata::new(),
+            metadata_subs: BTreeSet::new(),
+            backlog: None,
+            connections: Vec::new(),
+            session: None,
+            nick_deadline: None,
         }
     }
 
+    /// Whether the client has been invited to the given channel, and the invitation has not
+    /// expired.
+    pub fn is_invited_to(&self, channel_name: &str) -> bool {
+        self.invites
+            .get(u(channel_name))
+            .is_some_and(|invite| util::time() < invite.expires)
+    }
+
     /// Add a message to the client message queue.
     ///
     /// Use this function to send messages to the client.
     pub fn send(&self, msg: impl Into<MessageQueueItem>) {
-        let mut msg = msg.into();
-        if self.cap_enabled.has_message_tags() {
-            msg.start = 0;
+        let msg = msg.into();
+        if let Some(ref backlog) = self.backlog {
+            let mut messages = backlog.messages.borrow_mut();
+            if backlog.len <= messages.len() {
+                messages.pop_front();
+            }
+            if backlog.len != 0 {
+                messages.push_back(msg);
+            }
+            return;
+        }
+        for conn in &self.connections {
+            send_to(&self.outbox, &conn.queue, conn.cap_enabled, msg.clone());
+        }
+        send_to(&self.outbox, &self.queue, self.cap_enabled, msg);
+    }
+
+    /// Returns what is needed to deliver channel messages to the client, of ID `id`, away from
+    /// the state.
+    pub fn recipient(&self, id: usize) -> Recipient {
+        let mut queues = Vec::new();
+        if self.backlog.is_none() {
+            let connections = self
+                .connections
+                .iter()
+                .map(|conn| (conn.queue.clone(), conn.cap_enabled));
+            queues.extend(connections);
+            queues.push((self.queue.clone(), self.cap_enabled));
+        }
+        Recipient {
+            id,
+            cap_enabled: self.cap_enabled,
+            no_ctcp: self.no_ctcp,
+            deaf: self.deaf,
+            queues,
+        }
+    }
+
+    /// Sends a message to the connections of the client, except `except`.  `id` is the ID of the
+    /// client.
+    pub fn send_except(&self, id: usize, except: usize, msg: impl Into<MessageQueueItem>) {
+        let msg = msg.into();
+        for conn in self.connections.iter().filter(|conn| conn.id != except) {
+            send_to(&self.outbox, &conn.queue, conn.cap_enabled, msg.clone());
+        }
+        if id != except {
+            send_to(&self.outbox, &self.queue, self.cap_enabled, msg);
+        }
+    }
+
+    /// Sends a message only to the connection the client has been created with.
+    pub fn send_to_own_connection(&self, msg: impl Into<MessageQueueItem>) {
+        send_to(&self.outbox, &self.queue, self.cap_enabled, msg.into());
+    }
+
+    pub fn has_connections(&self) -> bool {
+        !self.connections.is_empty()
+    }
+
+    pub fn connection_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.connections.iter().map(|conn| conn.id)
+    }
+
+    /// Adds `conn`, the client of ID `id`, to the connections of this client.
+    pub fn add_connection(&mut self, id: usize, conn: &Client) {
+        self.connections.push(Connection {
+            id,
+            queue: conn.queue.clone(),
+            cap_enabled: conn.cap_enabled,
+        });
+    }
+
+    pub fn remove_connection(&mut self, id: usize) {
+        self.connections.retain(|conn| conn.id != id);
+    }
+
+    /// Updates the capabilities of a connection after it issued a CAP command.
+    pub fn set_connection_caps(&mut self, id: usize, cap_enabled: data::Capabilities) {
+        for conn in self.connections.iter_mut().filter(|conn| conn.id == id) {
+            conn.cap_enabled = cap_enabled;
         }
-        let _ = self.queue.send(msg);
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.backlog.is_some()
+    }
+
+    /// The number of messages kept for the client while it is detached.
+    pub fn backlog_len(&self) -> usize {
+        self.backlog
+            .as_ref()
+            .map_or(0, |backlog| backlog.messages.borrow().len())
+    }
+
+    /// Detaches the client from its connection.  The last `backlog_len` messages sent to the
+    /// client are kept until a connection is attached again.
+    pub fn detach(&mut self, backlog_len: usize) {
+        self.backlog = Some(Backlog {
+            messages: RefCell::new(VecDeque::new()),
+            len: backlog_len,
+        });
+    }
+
+    /// Gives the connection of `conn`, the client of ID `id`, to this client, and returns the
+    /// messages sent to it while it was detached.
+    pub fn attach(&mut self, id: usize, conn: Client) -> VecDeque<MessageQueueItem> {
+        self.remove_connection(id);
+        self.queue = conn.queue;
+        self.cap_version = conn.cap_version;
+        self.cap_enabled = conn.cap_enabled;
+        self.metadata_subs = conn.metadata_subs;
+        self.update_idle_time();
+        self.backlog
+            .take()
+            .map(|backlog| backlog.messages.into_inner())
+            .unwrap_or_default()
     }
 
     pub fn reply(&self, label: &str) -> ReplyBuffer {
@@ -260,6 +808,11 @@ impl Client {
         self.state == ConnectionState::Registered
     }
 
+    /// Holds the registration of the client until it answers its challenge.
+    pub fn wait_for_challenge(&mut self) {
+        self.state = ConnectionState::Challenged;
+    }
+
     pub fn full_name(&self) -> &str {
         &self.full_name
     }
@@ -311,16 +864,32 @@ impl Client {
         &self.host
     }
 
+    /// Change the displayed host of the client.
+    pub fn set_host(&mut self, host: &str) {
+        self.host.clear();
+        self.host.push_str(host);
+        self.update_full_name();
+    }
+
     pub fn account(&self) -> Option<&str> {
         self.account.as_ref().map(|s| s.as_ref())
     }
 
+    pub fn set_account(&mut self, account: &str) {
+        self.account = Some(account.to_owned());
+    }
+
     pub fn signon_time(&self) -> u64 {
         self.signon_time
     }
 
     pub fn idle_time(&self) -> u64 {
-        util::time() - self.last_action_time
+        self.idle_time_at(util::time())
+    }
+
+    /// Seconds since the last activity of the client, at `now`, a unix time.
+    pub fn idle_time_at(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_action_time)
     }
 
     pub fn update_idle_time(&mut self) {
@@ -331,6 +900,23 @@ impl Client {
         self.away_message.as_ref().map(|s| s.as_ref())
     }
 
+    /// Records that the client has left (or has been kicked from) a channel.
+    pub fn left_channel(&mut self) {
+        self.joined_channels = self.joined_channels.saturating_sub(1);
+    }
+
+    /// Counts a CTCP request sent by the client, and returns whether it exceeds `limit`.
+    pub fn is_ctcp_flooding(&mut self, limit: FloodLimit, now: Instant) -> bool {
+        let window = Duration::from_secs(limit.seconds.into());
+        let (start, count) = &mut self.ctcp_counter;
+        if window <= now.duration_since(*start) {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        limit.lines < *count
+    }
+
     pub fn write_modes(&self, mut out: MessageBuffer<'_>) {
         let modes = out.raw_param();
         modes.push('+');
@@ -343,6 +929,18 @@ impl Client {
         if self.operator {
             modes.push('o');
         }
+        if self.bot {
+            modes.push('B');
+        }
+        if self.deaf {
+            modes.push('D');
+        }
+        if self.registered_only {
+            modes.push('R');
+        }
+        if self.no_ctcp {
+            modes.push('T');
+        }
     }
 
     pub fn apply_mode_change(&mut self, change: mode::UserChange) -> bool {
@@ -353,6 +951,22 @@ impl Client {
                 applied = self.invisible != value;
                 self.invisible = value;
             }
+            NoCtcp(value) => {
+                applied = self.no_ctcp != value;
+                self.no_ctcp = value;
+            }
+            Bot(value) => {
+                applied = self.bot != value;
+                self.bot = value;
+            }
+            Deaf(value) => {
+                applied = self.deaf != value;
+                self.deaf = value;
+            }
+            RegisteredOnly(value) => {
+                applied = self.registered_only != value;
+                self.registered_only = value;
+            }
             DeOperator => {
                 applied = self.operator;
                 self.operator = false;
@@ -361,3 +975,73 @@ impl Client {
         applied
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::run;
+
+    fn message(content: &str) -> MessageQueueItem {
+        let mut buf = Buffer::new();
+        buf.message("", "PRIVMSG").trailing_param(content);
+        buf.into()
+    }
+
+    fn recipient(id: usize, queue: &Queue) -> Recipient {
+        Recipient {
+            id,
+            cap_enabled: data::Capabilities::default(),
+            no_ctcp: false,
+            deaf: false,
+            queues: vec![(queue.clone(), data::Capabilities::default())],
+        }
+    }
+
+    #[test]
+    fn test_outbox_holds_recipients_only() {
+        run(async {
+            let outbox = Outbox::default();
+            let (bob_queue, mut bob) = mpsc::unbounded_channel();
+            let (carol_queue, mut carol) = mpsc::unbounded_channel();
+            let (eve_queue, mut eve) = mpsc::unbounded_channel();
+            let bob_queue = outbox.queue(bob_queue);
+            let carol_queue = outbox.queue(carol_queue);
+            let eve_queue = outbox.queue(eve_queue);
+
+            let mut recipients = Recipients::default();
+            recipients.push(recipient(0, &bob_queue));
+            recipients.push(recipient(1, &carol_queue));
+            outbox.broadcast(Broadcast {
+                recipients: Arc::new(recipients),
+                msg: message("broadcast"),
+                filter: Arc::new(|_| true),
+                chunk: 1,
+            });
+
+            // The workers have not run yet, since this task has not yielded.
+            outbox.send(&eve_queue, message("unrelated"));
+            outbox.send(&bob_queue, message("after"));
+            assert!(eve
+                .try_recv()
+                .unwrap()
+                .as_ref()
+                .ends_with(" :unrelated\r\n"));
+            assert!(bob.try_recv().is_err());
+
+            let msg = bob.recv().await.unwrap();
+            assert!(
+                msg.as_ref().ends_with(" :broadcast\r\n"),
+                "{}",
+                msg.as_ref()
+            );
+            let msg = bob.recv().await.unwrap();
+            assert!(msg.as_ref().ends_with(" :after\r\n"), "{}", msg.as_ref());
+            let msg = carol.recv().await.unwrap();
+            assert!(
+                msg.as_ref().ends_with(" :broadcast\r\n"),
+                "{}",
+                msg.as_ref()
+            );
+        });
+    }
+} // mod tests
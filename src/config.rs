@@ -4,23 +4,24 @@
 //!
 //! [1]: https://git.sr.ht/~taiite/ellidri/tree/master/doc/ellidri.conf
 
+use crate::channel::FloodLimit;
+use crate::{announce, spamfilter};
 use anyhow::{Context, Result};
 use ellidri_tokens::mode;
 use gethostname::gethostname;
-use std::{fmt, io, net, path};
+use std::collections::BTreeMap;
+use std::{env, fmt, io, net, path};
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
-    Content(String),
-    InvalidDomain,
+    InvalidAnnouncement(String),
+    InvalidCtcpLimit,
+    InvalidLinelen,
     InvalidModes,
-}
-
-impl Error {
-    fn s(message: impl Into<String>) -> Error {
-        Error::Content(message.into())
-    }
+    InvalidSpamFilter(String, regex::Error),
+    MissingEnvVar(String),
+    PlaintextOperPassword(String),
 }
 
 impl std::error::Error for Error {
@@ -42,53 +43,574 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Io(err) => err.fmt(f),
-            Self::Content(message) => message.fmt(f),
-            Self::InvalidDomain => write!(f, "'domain' must be a domain name (e.g. irc.com)"),
+            Self::InvalidAnnouncement(name) => write!(
+                f,
+                "announcement {name:?} needs either an 'interval' or a valid 'cron' schedule"
+            ),
+            Self::InvalidCtcpLimit => write!(f, "'ctcp_limit' must be of the form <requests>:<seconds>"),
+            Self::InvalidLinelen => write!(
+                f,
+                "'linelen' must be at least {}",
+                ellidri_tokens::MESSAGE_LENGTH
+            ),
             Self::InvalidModes => write!(f, "'default_chan_mode' must be a mode string (e.g. +nt)"),
+            Self::InvalidSpamFilter(pattern, err) => {
+                write!(f, "invalid spamfilter pattern {pattern:?}: {err}")
+            }
+            Self::MissingEnvVar(name) => write!(f, "environment variable {name:?} is not set"),
+            Self::PlaintextOperPassword(name) => write!(
+                f,
+                "the password of oper {name:?} is not an argon2 hash (see `ellidri hash-oper-config`)"
+            ),
         }
     }
 }
 
 /// TLS-related and needed information for TLS bindings.
-#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Tls {
     pub certificate: path::PathBuf,
     pub key: path::PathBuf,
+
+    /// PEM file of intermediate certificates, sent after those of `certificate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<path::PathBuf>,
+
+    /// DER-encoded OCSP response for the certificate, stapled to TLS handshakes.  It is read again
+    /// on rehash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocsp: Option<path::PathBuf>,
 }
 
-/// Listening address + port + optional TLS settings.
+/// Listening address + port + optional TLS settings + connection options.
 #[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Binding {
     pub address: net::SocketAddr,
     pub tls: Option<Tls>,
+
+    /// Hash of the password clients must give to connect on this binding.  Overrides the global
+    /// password.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+
+    /// Whether connections come from a Tor hidden service.  The host of these clients is hidden.
+    #[serde(default)]
+    pub tor: bool,
+
+    /// Whether an IPv6 binding only accepts IPv6 connections.  Only applied when the binding is
+    /// created.
+    #[serde(default)]
+    pub v6only: bool,
+
+    /// Whether other sockets can bind to the same address (SO_REUSEPORT), on Unix.  Only applied
+    /// when the binding is created.
+    #[serde(default)]
+    pub reuseport: bool,
+
+    /// Maximum number of connections waiting to be accepted.  Only applied when the binding is
+    /// created.
+    #[serde(default = "default_backlog")]
+    pub backlog: i32,
+
+    /// Whether to disable Nagle's algorithm (TCP_NODELAY) on connections.
+    #[serde(default)]
+    pub nodelay: bool,
+
+    /// Seconds of inactivity after which TCP keepalive probes are sent on connections, and between
+    /// probes.  0 disables keepalive.
+    #[serde(default)]
+    pub keepalive: u64,
+
+    /// Whether a TLS binding also accepts plain-text connections, told apart from TLS ones by
+    /// their first byte.
+    #[serde(default)]
+    pub plaintext: bool,
+
+    /// Number of connections from the same IP address above which `clone_action` is taken.  0
+    /// disables clone detection.
+    #[serde(default)]
+    pub max_clones: usize,
+
+    #[serde(default)]
+    pub clone_action: CloneAction,
+
+    /// Masks of the IP addresses (e.g. `203.0.113.*`) that must answer one of the `challenges`
+    /// before registering.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub challenge_hosts: Vec<String>,
+}
+
+pub fn default_backlog() -> i32 {
+    1024
+}
+
+impl Binding {
+    pub fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            v6only: self.v6only,
+            reuseport: self.reuseport,
+            backlog: self.backlog,
+        }
+    }
+
+    pub fn options(&self) -> BindingOptions {
+        BindingOptions {
+            password: self.password.clone(),
+            tor: self.tor,
+            nodelay: self.nodelay,
+            keepalive: self.keepalive,
+            plaintext: self.plaintext,
+            max_clones: self.max_clones,
+            clone_action: self.clone_action,
+            challenge_hosts: self.challenge_hosts.clone(),
+        }
+    }
+}
+
+/// Binding settings that apply to its listening socket, when the binding is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketOptions {
+    pub v6only: bool,
+    pub reuseport: bool,
+    pub backlog: i32,
+}
+
+/// Binding settings that apply to each connection, and can be changed at runtime.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BindingOptions {
+    pub password: String,
+    pub tor: bool,
+    pub nodelay: bool,
+    pub keepalive: u64,
+    pub plaintext: bool,
+    pub max_clones: usize,
+    pub clone_action: CloneAction,
+    pub challenge_hosts: Vec<String>,
+}
+
+/// What happens to connections from an IP address that has more than `max_clones` connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloneAction {
+    /// Only notify IRC operators.
+    #[default]
+    Notice,
+
+    /// Close the new connection.
+    Reject,
+
+    /// Close the new connection, and refuse connections from the IP address for `dline_duration`
+    /// seconds.
+    Dline,
+}
+
+impl CloneAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Notice => "notice",
+            Self::Reject => "reject",
+            Self::Dline => "dline",
+        }
+    }
+}
+
+/// A question asked to clients before they register, see `Binding::challenge_hosts`.  Answers
+/// are compared case-insensitively.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Challenge {
+    pub question: String,
+    pub answer: String,
 }
-/// OPER credentials
+
+/// History settings of the channels matching `channels` (a mask), which override `history_len`,
+/// `history_age` and `autoreplay_len`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChannelHistory {
+    pub channels: String,
+    pub len: usize,
+    pub age: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoreplay: Option<usize>,
+}
+
+/// OPER credentials.  `password` is an argon2 hash.
+///
+/// When `certfp` is set, the client must also use a TLS client certificate with this SHA-256
+/// fingerprint.  `password` can then be empty, in which case the certificate is enough.
 #[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Oper {
     pub name: String,
+    #[serde(default)]
     pub password: String,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub certfp: String,
+
+    /// What the operator can do on top of what all operators can do.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privileges: Vec<OperPrivilege>,
+
+    /// Whether OPER is refused over plain-text connections, even if `oper_require_tls` is off.
+    #[serde(default)]
+    pub require_tls: bool,
+}
+
+/// A command that only some IRC operators can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperPrivilege {
+    SaJoin,
+    SaPart,
+    SaMode,
+    SaNick,
+    SaTopic,
+}
+
+impl Oper {
+    /// Returns whether `certfp`, the fingerprint of the client certificate of a connection,
+    /// satisfies the `certfp` of this oper block.  Colons and case are ignored.
+    pub fn accepts_certfp(&self, certfp: Option<&str>) -> bool {
+        if self.certfp.is_empty() {
+            return true;
+        }
+        let Some(certfp) = certfp else {
+            return false;
+        };
+        let mut expected = self.certfp.chars().filter(|&c| c != ':');
+        let mut actual = certfp.chars().filter(|&c| c != ':');
+        loop {
+            match (expected.next(), actual.next()) {
+                (None, None) => return true,
+                (Some(a), Some(b)) if a.eq_ignore_ascii_case(&b) => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl OperPrivilege {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SaJoin => "sajoin",
+            Self::SaPart => "sapart",
+            Self::SaMode => "samode",
+            Self::SaNick => "sanick",
+            Self::SaTopic => "satopic",
+        }
+    }
+}
+
+/// What happens to members who exceed the flood limit of a channel (+f).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FloodAction {
+    /// Add the member's host to the quiet list.
+    Mute,
+
+    /// Kick the member from the channel.
+    #[default]
+    Kick,
+
+    /// Add the member's host to the ban list and kick them.
+    Kickban,
+}
+
+/// How nicknames are compared, advertised with the CASEMAPPING token of RPL_ISUPPORT.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseMapping {
+    /// ASCII letters match their upper case counterparts.
+    #[default]
+    Ascii,
+
+    /// Same as `ascii`, and `{}|^` match `[]\~`.
+    Rfc1459,
+
+    /// Same as `ascii`, and `{}|` match `[]\`.
+    Rfc1459Strict,
+}
+
+impl CaseMapping {
+    /// The name of the casemapping, as advertised to clients.
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::Rfc1459Strict => "rfc1459-strict",
+        }
+    }
+
+    /// Returns `s` with the characters that match replaced by the same one.
+    pub fn fold(self, s: &str) -> String {
+        use ellidri_unicase::{Ascii, CaseMapping as _, Rfc1459, Rfc1459Strict};
+
+        let canonical_byte = match self {
+            CaseMapping::Ascii => Ascii::canonical_byte,
+            CaseMapping::Rfc1459 => Rfc1459::canonical_byte,
+            CaseMapping::Rfc1459Strict => Rfc1459Strict::canonical_byte,
+        };
+        // Only ASCII bytes are changed, into other ASCII bytes, so the result is valid UTF-8.
+        String::from_utf8(s.bytes().map(canonical_byte).collect()).unwrap()
+    }
+}
+
+/// What happens to PRIVMSGs that contain DCC offers (DCC SEND, DCC CHAT...).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DccAction {
+    /// Let them through, except in channels with mode +d, where they are blocked.
+    #[default]
+    Allow,
+
+    /// Refuse the message and tell the sender.
+    Block,
+
+    /// Remove the offers from the message, and drop it if nothing is left.
+    Strip,
+}
+
+/// Where log messages are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    /// The standard error, filtered by `ELLIDRI_LOG` or `--log-level`.
+    Stderr,
+
+    /// The local syslog daemon, through `/dev/log`.
+    Syslog,
+
+    /// The systemd journal, with the connection, nickname and host of clients as fields.
+    Journald,
+}
+
+pub fn stderr_log_output() -> Vec<LogOutput> {
+    vec![LogOutput::Stderr]
+}
+
+/// Where the accounts used for SASL authentication are stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SaslBackend {
+    /// SASL is disabled.
+    #[default]
+    None,
+
+    /// Accounts are read from a YAML file (`account_db`).
+    File,
+}
+
+/// What happens to clients whose message matches a spamfilter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpamAction {
+    /// Drop the message (or the reason of a PART or QUIT).
+    Block,
+
+    /// Let the message through, but send a notice to IRC operators.
+    Warn,
+
+    /// Disconnect the client.
+    Kill,
+
+    /// Disconnect the client and prevent its host from connecting again.
+    Gline,
+}
+
+impl SpamAction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Block, Self::Warn, Self::Kill, Self::Gline]
+            .into_iter()
+            .find(|action| action.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Warn => "warn",
+            Self::Kill => "kill",
+            Self::Gline => "gline",
+        }
+    }
+}
+
+/// The kind of messages a spamfilter applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpamTarget {
+    Privmsg,
+    Notice,
+    Part,
+    Quit,
+}
+
+impl SpamTarget {
+    /// The name of the corresponding IRC command.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Privmsg => "PRIVMSG",
+            Self::Notice => "NOTICE",
+            Self::Part => "PART",
+            Self::Quit => "QUIT",
+        }
+    }
+}
+
+pub fn all_spam_targets() -> Vec<SpamTarget> {
+    vec![
+        SpamTarget::Privmsg,
+        SpamTarget::Notice,
+        SpamTarget::Part,
+        SpamTarget::Quit,
+    ]
+}
+
+/// Spamfilter settings.
+///
+/// Patterns are globs (e.g. `*buy cheap*`) matched case-insensitively, or regular expressions
+/// when enclosed in slashes (e.g. `/^spam\d+$/`).
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SpamFilter {
+    pub pattern: String,
+    pub action: SpamAction,
+    #[serde(default = "all_spam_targets")]
+    pub targets: Vec<SpamTarget>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// A server announcement, sent to all clients or to the channel `target`, every `interval`
+/// seconds or at the times given by `cron` (e.g. `0 12 * * 1` for mondays at noon, UTC).
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Announcement {
+    pub name: String,
+    pub text: String,
+    #[serde(default)]
+    pub interval: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub cron: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub target: String,
+}
+
+/// A services alias.
+///
+/// Commands sent with `command` (e.g. `NS GHOST nick`) or sent to `nick` (e.g. `PRIVMSG NickServ
+/// :GHOST nick`) are rewritten with the template of their first word, found in `subcommands`.
+/// In templates, `$N` is replaced by the Nth argument, and `$N-` by the Nth and following
+/// arguments.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Service {
+    pub nick: String,
+    pub command: String,
+    pub subcommands: BTreeMap<String, String>,
+}
+
+fn service(nick: &str, command: &str, subcommands: &[(&str, &str)]) -> Service {
+    Service {
+        nick: nick.to_owned(),
+        command: command.to_owned(),
+        subcommands: subcommands
+            .iter()
+            .map(|&(name, template)| (name.to_owned(), template.to_owned()))
+            .collect(),
+    }
+}
+
+pub fn default_services() -> Vec<Service> {
+    vec![
+        service(
+            "NickServ",
+            "NS",
+            &[
+                ("GHOST", "GHOST $1"),
+                ("INFO", "WHOIS $1"),
+                ("REGAIN", "REGAIN $1"),
+                ("RELEASE", "REGAIN $1"),
+            ],
+        ),
+        service(
+            "ChanServ",
+            "CS",
+            &[
+                ("ACCESS", "ACCESS $1 $2-"),
+                ("DEOP", "MODE $1 -o $2"),
+                ("DEVOICE", "MODE $1 -v $2"),
+                ("FOUNDER", "FOUNDER $1 $2-"),
+                ("INVITE", "INVITE $2 $1"),
+                ("KICK", "KICK $1 $2 :$3-"),
+                ("MUTE", "MUTE $1 $2 $3"),
+                ("OP", "MODE $1 +o $2"),
+                ("TOPIC", "TOPIC $1 :$2-"),
+                ("UNMUTE", "UNMUTE $1 $2"),
+                ("VOICE", "MODE $1 +v $2"),
+            ],
+        ),
+    ]
 }
 
 /// Settings for `State`.
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[serde(default)]
 pub struct State {
     pub domain: String,
     pub org_name: String,
     pub org_location: String,
     pub org_mail: String,
     pub default_chan_mode: String,
+    pub auto_join: Vec<String>,
     pub motd_file: String,
+    pub oper_motd_file: String,
+    pub channel_db: String,
+    pub sasl_backend: SaslBackend,
+    pub account_db: String,
+    pub casemapping: CaseMapping,
+    pub flood_action: FloodAction,
+    pub dcc_offers: DccAction,
+    pub ctcp_limit: String,
+    pub trusted_hosts: Vec<String>,
+    pub challenges: Vec<Challenge>,
+    pub spamfilters: Vec<SpamFilter>,
+    pub announcements: Vec<Announcement>,
+    pub services: Vec<Service>,
     pub opers: Vec<Oper>,
+    pub oper_require_tls: bool,
     pub password: String,
     pub awaylen: usize,
+    pub chanlimit: usize,
     pub channellen: usize,
     pub keylen: usize,
     pub kicklen: usize,
+    pub linelen: usize,
+    pub maxlist: usize,
     pub namelen: usize,
     pub nicklen: usize,
     pub topiclen: usize,
+    pub topic_history_len: usize,
+    pub history_len: usize,
+    pub history_age: u64,
+    pub channel_history: Vec<ChannelHistory>,
+    pub autoreplay_len: usize,
+    pub channel_log_dir: String,
+    pub logged_channels: Vec<String>,
+    pub otlp_endpoint: String,
+    pub locale_dir: String,
+    pub default_language: String,
+    pub metadata_max_keys: usize,
+    pub metadata_max_subs: usize,
+    pub metadata_max_value_len: usize,
+    pub metadata_private_keys: Vec<String>,
+    pub offline_messages_len: usize,
+    pub always_on_backlog_len: usize,
+    pub multiclient: bool,
+    pub nick_enforcement_delay: u64,
+    pub auto_away_delay: u64,
     pub userlen: usize,
     pub login_timeout: u64,
+    pub ping_interval: u64,
+    pub ping_timeout: u64,
+    pub invite_expiry: u64,
+    pub dline_duration: u64,
+    pub fanout_threshold: usize,
 }
 
 impl Default for State {
@@ -99,18 +621,60 @@ impl Default for State {
             org_location: String::from("unspecified"),
             org_mail: String::from("unspecified"),
             default_chan_mode: String::from("+nst"),
+            auto_join: Vec::new(),
             motd_file: String::from("/etc/motd"),
+            oper_motd_file: String::new(),
+            channel_db: String::new(),
+            sasl_backend: SaslBackend::default(),
+            account_db: String::new(),
+            casemapping: CaseMapping::default(),
+            flood_action: FloodAction::default(),
+            dcc_offers: DccAction::default(),
+            ctcp_limit: String::new(),
+            trusted_hosts: Vec::new(),
+            challenges: Vec::new(),
+            spamfilters: Vec::new(),
+            announcements: Vec::new(),
+            services: default_services(),
             opers: Vec::new(),
+            oper_require_tls: false,
             password: String::new(),
             awaylen: 300,
+            chanlimit: 100,
             channellen: 50,
             keylen: 24,
             kicklen: 300,
+            linelen: ellidri_tokens::MESSAGE_LENGTH,
+            maxlist: 100,
             namelen: 64,
             nicklen: 32,
             topiclen: 300,
+            topic_history_len: 10,
+            history_len: 100,
+            history_age: 86400,
+            channel_history: Vec::new(),
+            autoreplay_len: 0,
+            channel_log_dir: String::new(),
+            logged_channels: Vec::new(),
+            otlp_endpoint: String::new(),
+            locale_dir: String::new(),
+            default_language: String::from("en"),
+            metadata_max_keys: 20,
+            metadata_max_subs: 50,
+            metadata_max_value_len: 300,
+            metadata_private_keys: Vec::new(),
+            offline_messages_len: 0,
+            always_on_backlog_len: 500,
+            multiclient: false,
+            nick_enforcement_delay: 60,
+            auto_away_delay: 0,
             userlen: 64,
             login_timeout: 60_000,
+            ping_interval: 120,
+            ping_timeout: 60,
+            invite_expiry: 3600,
+            dline_duration: 600,
+            fanout_threshold: 1000,
         }
     }
 }
@@ -120,6 +684,8 @@ impl Default for State {
 pub struct Config {
     pub bindings: Vec<Binding>,
     pub workers: usize,
+    #[serde(default = "stderr_log_output")]
+    pub log_output: Vec<LogOutput>,
     pub state: State,
 }
 
@@ -129,32 +695,570 @@ impl Default for Config {
             bindings: vec![Binding {
                 address: net::SocketAddr::from(([127, 0, 0, 1], 6667)),
                 tls: None,
+                password: String::new(),
+                tor: false,
+                v6only: false,
+                reuseport: false,
+                backlog: default_backlog(),
+                nodelay: false,
+                keepalive: 0,
+                plaintext: false,
+                max_clones: 0,
+                clone_action: CloneAction::Notice,
+                challenge_hosts: Vec::new(),
             }],
             workers: 0,
+            log_output: stderr_log_output(),
             state: State::default(),
         }
     }
 }
 
+/// Settings given on the command line, which take precedence over those of the configuration
+/// file, including when it is reloaded.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// Plain-text bindings that replace those of the configuration file, when not empty.
+    pub bindings: Vec<net::SocketAddr>,
+    pub motd_file: Option<String>,
+    pub workers: Option<usize>,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        if !self.bindings.is_empty() {
+            config.bindings = self
+                .bindings
+                .iter()
+                .map(|&address| Binding {
+                    address,
+                    tls: None,
+                    password: String::new(),
+                    tor: false,
+                    v6only: false,
+                    reuseport: false,
+                    backlog: default_backlog(),
+                    nodelay: false,
+                    keepalive: 0,
+                    plaintext: false,
+                    max_clones: 0,
+                    clone_action: CloneAction::Notice,
+                    challenge_hosts: Vec::new(),
+                })
+                .collect();
+        }
+        if let Some(motd_file) = &self.motd_file {
+            config.state.motd_file = motd_file.clone();
+        }
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+    }
+}
+
+/// Replaces the `${NAME}` variables in `s` with the value of the environment variable `NAME`.
+///
+/// Returns an error if one of the variables is not set.
+fn expand_env(s: &str) -> std::result::Result<Option<String>, Error> {
+    if !s.contains("${") {
+        return Ok(None);
+    }
+
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        let value = env::var(name).map_err(|_| Error::MissingEnvVar(name.to_owned()))?;
+        res.push_str(&rest[..start]);
+        res.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+
+    Ok(Some(res))
+}
+
+/// Expands environment variables in all the strings of the given YAML document.
+fn expand_yaml(value: &mut serde_yaml::Value) -> std::result::Result<(), Error> {
+    use serde_yaml::Value;
+
+    match value {
+        Value::String(s) => {
+            if let Some(expanded) = expand_env(s)? {
+                *s = expanded;
+            }
+        }
+        Value::Sequence(seq) => seq.iter_mut().try_for_each(expand_yaml)?,
+        Value::Mapping(map) => map.values_mut().try_for_each(expand_yaml)?,
+        Value::Tagged(tagged) => expand_yaml(&mut tagged.value)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+/// Maximum nesting of `include` directives, to break include loops.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Merges `other` into `base`.  Mappings are merged recursively, sequences are concatenated and
+/// other values of `other` replace those of `base`.
+fn merge_yaml(base: &mut serde_yaml::Value, other: serde_yaml::Value) {
+    use serde_yaml::Value;
+
+    match (base, other) {
+        (Value::Mapping(base), Value::Mapping(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_yaml(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(other)) => base.extend(other),
+        (base, other) => *base = other,
+    }
+}
+
+/// Reads the configuration file at `path` and the files it includes into one document, with
+/// environment variables expanded.
+fn read_document(path: &path::Path, depth: usize) -> Result<serde_yaml::Value> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut document: serde_yaml::Value = match Format::of(path) {
+        Format::Yaml => serde_yaml::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?,
+        Format::Toml => {
+            let document: toml::Value = toml::from_str(&contents)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            serde_yaml::to_value(document)?
+        }
+    };
+    expand_yaml(&mut document).with_context(|| format!("in {}", path.display()))?;
+
+    let includes = match document.as_mapping_mut() {
+        Some(mapping) => mapping.remove("include"),
+        None => None,
+    };
+    let includes: Vec<path::PathBuf> = match includes {
+        Some(includes) => serde_yaml::from_value(includes)
+            .with_context(|| format!("in {}: 'include' must be a list of paths", path.display()))?,
+        None => Vec::new(),
+    };
+    if !includes.is_empty() && MAX_INCLUDE_DEPTH <= depth {
+        anyhow::bail!("in {}: too many nested includes", path.display());
+    }
+    let dir = path.parent().unwrap_or_else(|| path::Path::new(""));
+    for include in includes {
+        let included = read_document(&dir.join(include), depth + 1)?;
+        merge_yaml(&mut document, included);
+    }
+
+    Ok(document)
+}
+
+/// Replaces the plaintext oper passwords of the configuration file at `path` with their hash.
+///
+/// Only the given file is rewritten, not the files it includes.  Returns the number of passwords
+/// that have been hashed.
+pub fn hash_oper_passwords(path: &str) -> Result<usize> {
+    let contents = std::fs::read_to_string(path).context("failed to read config file")?;
+    let format = Format::of(path);
+    let mut document: serde_yaml::Value = match format {
+        Format::Yaml => serde_yaml::from_str(&contents).context("failed to parse config file")?,
+        Format::Toml => {
+            let document: toml::Value =
+                toml::from_str(&contents).context("failed to parse config file")?;
+            serde_yaml::to_value(document)?
+        }
+    };
+
+    let mut hashed = 0;
+    let opers = document
+        .get_mut("state")
+        .and_then(|state| state.get_mut("opers"))
+        .and_then(serde_yaml::Value::as_sequence_mut);
+    for oper in opers.into_iter().flatten() {
+        let password = match oper.get_mut("password") {
+            Some(serde_yaml::Value::String(password)) => password,
+            _ => continue,
+        };
+        // Leave alone passwords that are empty, hashed, or given through the environment.
+        if password.is_empty()
+            || argon2::PasswordHash::new(password).is_ok()
+            || password.contains("${")
+        {
+            continue;
+        }
+        *password = crate::util::hash_password(password)?;
+        hashed += 1;
+    }
+
+    if hashed != 0 {
+        let contents = match format {
+            Format::Yaml => {
+                serde_yaml::to_string(&document).context("failed to serialize config")?
+            }
+            Format::Toml => toml::to_string(&document).context("failed to serialize config")?,
+        };
+        std::fs::write(path, contents).context("failed to write config file")?;
+    }
+    Ok(hashed)
+}
+
+/// The format of a configuration file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Guesses the format of the file from its extension.  Defaults to YAML.
+    fn of(path: impl AsRef<path::Path>) -> Self {
+        let is_toml = path
+            .as_ref()
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        if is_toml {
+            Self::Toml
+        } else {
+            Self::Yaml
+        }
+    }
+}
+
 impl Config {
+    /// Reads the configuration file at `path`, in YAML or TOML depending on its extension, along
+    /// with the files it includes.
     pub async fn from_file(path: &str) -> Result<Self> {
-        let config: Self = serde_yaml::from_str(
-            &tokio::fs::read_to_string(path)
-                .await
-                .context("failed to read file")?,
-        )
-        .context("failed to deserialize config file")?;
+        let document = read_document(path::Path::new(path), 0)?;
+        let config: Self =
+            serde_yaml::from_value(document).context("failed to deserialize config file")?;
 
         if !mode::is_channel_mode_string(&config.state.default_chan_mode) {
             return Err(Error::InvalidModes.into());
         }
+        if config.state.linelen < ellidri_tokens::MESSAGE_LENGTH {
+            return Err(Error::InvalidLinelen.into());
+        }
+        let ctcp_limit = &config.state.ctcp_limit;
+        if !ctcp_limit.is_empty() && ctcp_limit.parse::<FloodLimit>().is_err() {
+            return Err(Error::InvalidCtcpLimit.into());
+        }
+        for filter in &config.state.spamfilters {
+            if let Err(err) = spamfilter::Pattern::new(&filter.pattern) {
+                return Err(Error::InvalidSpamFilter(filter.pattern.clone(), err).into());
+            }
+        }
+        for announcement in &config.state.announcements {
+            if announce::Schedule::new(announcement).is_none() {
+                return Err(Error::InvalidAnnouncement(announcement.name.clone()).into());
+            }
+        }
+        for oper in &config.state.opers {
+            let certfp_only = oper.password.is_empty() && !oper.certfp.is_empty();
+            if !certfp_only && argon2::PasswordHash::new(&oper.password).is_err() {
+                return Err(Error::PlaintextOperPassword(oper.name.clone()).into());
+            }
+        }
         Ok(config)
     }
+
+    /// Looks for problems that would only show up once the server is running, such as unreadable
+    /// TLS files or malformed password hashes.  Returns a description of each problem found.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let check_readable = |problems: &mut Vec<String>, what: &str, path: &path::Path| {
+            if let Err(err) = std::fs::File::open(path) {
+                problems.push(format!("{what} {}: {err}", path.display()));
+            }
+        };
+        let check_hash = |problems: &mut Vec<String>, what: &str, hash: &str| {
+            if hash.is_empty() {
+                return;
+            }
+            if let Err(err) = argon2::PasswordHash::new(hash) {
+                problems.push(format!("{what}: not an argon2 password hash ({err})"));
+            }
+        };
+
+        if self.bindings.is_empty() {
+            problems.push(String::from(
+                "no bindings: the server would not accept connections",
+            ));
+        }
+        for (i, binding) in self.bindings.iter().enumerate() {
+            let what = format!("binding {}", binding.address);
+            if self.bindings[..i]
+                .iter()
+                .any(|b| b.address == binding.address)
+            {
+                problems.push(format!("{what}: address is used by several bindings"));
+            }
+            if let Some(tls) = &binding.tls {
+                if cfg!(not(feature = "tls")) {
+                    problems.push(format!("{what}: ellidri is built without TLS support"));
+                }
+                check_readable(
+                    &mut problems,
+                    &format!("{what}: certificate"),
+                    &tls.certificate,
+                );
+                check_readable(&mut problems, &format!("{what}: key"), &tls.key);
+                if let Some(chain) = &tls.chain {
+                    check_readable(&mut problems, &format!("{what}: chain"), chain);
+                }
+                if let Some(ocsp) = &tls.ocsp {
+                    check_readable(&mut problems, &format!("{what}: OCSP response"), ocsp);
+                }
+            }
+            check_hash(
+                &mut problems,
+                &format!("{what}: password"),
+                &binding.password,
+            );
+        }
+
+        check_hash(&mut problems, "password", &self.state.password);
+        for channel in &self.state.auto_join {
+            if crate::data::ChannelName::try_from(channel.as_str()).is_err() {
+                problems.push(format!("auto_join: {channel} is not a valid channel name"));
+            }
+        }
+        // A missing MOTD file is fine, ellidri just doesn't send a MOTD.
+        let motd_file = path::Path::new(&self.state.motd_file);
+        if motd_file.exists() {
+            check_readable(&mut problems, "motd_file", motd_file);
+        }
+        if !self.state.oper_motd_file.is_empty() {
+            let oper_motd_file = path::Path::new(&self.state.oper_motd_file);
+            check_readable(&mut problems, "oper_motd_file", oper_motd_file);
+        }
+        if !self.state.channel_db.is_empty() {
+            let dir = path::Path::new(&self.state.channel_db)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| path::Path::new("."));
+            if !dir.is_dir() {
+                problems.push(format!(
+                    "channel_db: directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+        if self.state.sasl_backend == SaslBackend::File {
+            if self.state.account_db.is_empty() {
+                problems.push(String::from(
+                    "sasl_backend is file, but account_db is not set",
+                ));
+            } else if let Err(err) = crate::accounts::Accounts::open(&self.state.account_db) {
+                problems.push(format!("account_db: {err:#}"));
+            }
+        }
+        for (i, oper) in self.state.opers.iter().enumerate() {
+            if oper.name.is_empty() || (oper.password.is_empty() && oper.certfp.is_empty()) {
+                problems.push(format!(
+                    "oper #{}: needs a name, and a password or a certfp",
+                    i + 1
+                ));
+            } else if self.state.opers[..i].iter().any(|o| o.name == oper.name) {
+                problems.push(format!("oper {:?}: defined several times", oper.name));
+            }
+        }
+
+        problems
+    }
+
+    /// Describes how the settings of `new` differ from those of `self`, one line per change (e.g.
+    /// `nicklen: 32 -> 40` or `opers: added "root"`).  Values of passwords are left out, and so
+    /// are bindings, whose changes are only known once they are applied.
+    pub fn diff(&self, new: &Config) -> Vec<String> {
+        fn to_value(value: &impl serde::Serialize) -> serde_json::Value {
+            serde_json::to_value(value).unwrap_or_default()
+        }
+
+        let mut res = Vec::new();
+        if self.workers != new.workers {
+            res.push(format!(
+                "workers: {} -> {} (needs a restart)",
+                self.workers, new.workers
+            ));
+        }
+        diff_value(
+            &mut res,
+            "log_output",
+            &to_value(&self.log_output),
+            &to_value(&new.log_output),
+        );
+        let old_state = to_value(&self.state);
+        let new_state = to_value(&new.state);
+        if let (Some(old_state), Some(new_state)) = (old_state.as_object(), new_state.as_object()) {
+            for (key, new_value) in new_state {
+                let old_value = old_state.get(key).unwrap_or(&serde_json::Value::Null);
+                diff_value(&mut res, key, old_value, new_value);
+            }
+        }
+        res
+    }
+
+    /// Writes the configuration to `path`, in YAML or TOML depending on its extension.
     pub async fn write_to_file(&self, path: &str) -> Result<()> {
-        let conf = serde_yaml::to_string(self).context("failed to serialize config")?;
+        let conf = match Format::of(path) {
+            Format::Yaml => serde_yaml::to_string(self).context("failed to serialize config")?,
+            Format::Toml => toml::to_string(self).context("failed to serialize config")?,
+        };
         tokio::fs::write(path, conf)
             .await
             .context("failed to write config file")?;
         Ok(())
     }
 }
+
+/// Pushes to `res` the description of how the setting `key` has changed.
+fn diff_value(res: &mut Vec<String>, key: &str, old: &serde_json::Value, new: &serde_json::Value) {
+    use serde_json::Value;
+
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        _ if key.contains("password") => res.push(format!("{key}: changed")),
+        (Value::Array(old), Value::Array(new)) => {
+            for entry in new.iter().filter(|entry| !old.contains(entry)) {
+                let label = entry_label(entry);
+                if old.iter().any(|old_entry| entry_label(old_entry) == label) {
+                    res.push(format!("{key}: modified {label}"));
+                } else {
+                    res.push(format!("{key}: added {label}"));
+                }
+            }
+            for entry in old.iter().filter(|entry| !new.contains(entry)) {
+                let label = entry_label(entry);
+                if !new.iter().any(|new_entry| entry_label(new_entry) == label) {
+                    res.push(format!("{key}: removed {label}"));
+                }
+            }
+        }
+        (Value::Object(_), _) | (_, Value::Object(_)) => res.push(format!("{key}: modified")),
+        _ => res.push(format!("{key}: {old} -> {new}")),
+    }
+}
+
+/// Returns how an entry of a list setting is shown in diffs, without the secrets it may hold
+/// (e.g. `"root"` for an oper block).
+fn entry_label(entry: &serde_json::Value) -> String {
+    const LABEL_FIELDS: &[&str] = &["name", "pattern", "nick", "channels", "question"];
+
+    match entry {
+        serde_json::Value::Object(fields) => LABEL_FIELDS
+            .iter()
+            .find_map(|field| fields.get(*field))
+            .map_or_else(|| String::from("an entry"), ToString::to_string),
+        _ => entry.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_env() {
+        env::set_var("ELLIDRI_TEST_VAR", "value");
+        env::remove_var("ELLIDRI_TEST_UNSET");
+
+        assert_eq!(expand_env("plain $argon2id$").unwrap(), None);
+        assert_eq!(
+            expand_env("${ELLIDRI_TEST_VAR}").unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(
+            expand_env("a ${ELLIDRI_TEST_VAR} b ${ELLIDRI_TEST_VAR}")
+                .unwrap()
+                .as_deref(),
+            Some("a value b value")
+        );
+        assert_eq!(
+            expand_env("unclosed ${ELLIDRI_TEST_VAR")
+                .unwrap()
+                .as_deref(),
+            Some("unclosed ${ELLIDRI_TEST_VAR")
+        );
+        assert!(matches!(
+            expand_env("${ELLIDRI_TEST_UNSET}"),
+            Err(Error::MissingEnvVar(name)) if name == "ELLIDRI_TEST_UNSET"
+        ));
+    }
+
+    #[test]
+    fn test_merge_yaml() {
+        let mut base: serde_yaml::Value =
+            serde_yaml::from_str("domain: a\nopers: [[x, y]]\nstate: {nicklen: 9}").unwrap();
+        let other: serde_yaml::Value =
+            serde_yaml::from_str("domain: b\nopers: [[z, w]]\nstate: {kicklen: 3}").unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            "domain: b\nopers: [[x, y], [z, w]]\nstate: {nicklen: 9, kicklen: 3}",
+        )
+        .unwrap();
+
+        merge_yaml(&mut base, other);
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::default();
+        let mut new = Config::default();
+        assert!(old.diff(&new).is_empty());
+
+        new.workers = 4;
+        new.state.nicklen = 40;
+        new.state.org_name = String::from("ellidri");
+        new.state.password = String::from("$argon2id$secret");
+        new.state.opers.push(Oper {
+            name: String::from("root"),
+            password: String::from("$argon2id$secret"),
+            ..Oper::default()
+        });
+        new.state.services[0].subcommands.clear();
+        new.state.services.pop();
+        new.bindings.clear();
+        let diff = new.diff(&new.clone());
+        assert!(diff.is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            [
+                "workers: 0 -> 4 (needs a restart)",
+                "nicklen: 32 -> 40",
+                "opers: added \"root\"",
+                "org_name: \"unspecified\" -> \"ellidri\"",
+                "password: changed",
+                "services: modified \"NickServ\"",
+                "services: removed \"ChanServ\"",
+            ]
+        );
+        assert!(diff.iter().all(|change| !change.contains("secret")));
+    }
+
+    #[test]
+    fn test_binding_options() {
+        let binding: Binding =
+            serde_yaml::from_str("{address: '[::]:6667', reuseport: true, keepalive: 60}").unwrap();
+        assert_eq!(
+            binding.socket_options(),
+            SocketOptions {
+                v6only: false,
+                reuseport: true,
+                backlog: 1024,
+            }
+        );
+        let options = binding.options();
+        assert_eq!((options.nodelay, options.keepalive), (false, 60));
+    }
+} // mod tests
@@ -39,16 +39,26 @@
 //! Bindings are identified by their socket address (IP address + TCP port).  TLS identities are
 //! not kept track of, thus ellidri might reload the same TLS identity for a binding (it is fine to
 //! let it do we are not reading thousands for TLS identities here).
+//!
+//! # Embedding
+//!
+//! `Server::builder` runs the same loop on the runtime of another program, with a configuration
+//! given in memory instead of a file.  The returned `ServerHandle` replaces the configuration the
+//! same way a rehash does, and stops the server.
 
-use crate::config::{Binding, Tls};
-use crate::{net, tls, Config, State};
-use anyhow::Result;
+use crate::config::{Binding, BindingOptions, Overrides};
+use crate::events::Event;
+use crate::hooks::{CommandHandler, Hook, Hooks};
+use crate::{config, logging, net, tls, Config, State};
+use anyhow::{bail, Context, Result};
 use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::{fs, process};
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
 
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
+use tokio::task::JoinHandle;
 
 /// A command from `Control` to binding tasks.
 pub enum Command {
@@ -57,6 +67,9 @@ pub enum Command {
 
     /// Ask the binding task to listen for TLS connections with the given acceptor.
     UseTls(tls::Acceptor),
+
+    /// Ask the binding task to apply the given options to new connections.
+    SetOptions(BindingOptions),
 }
 
 /// A binding task that is ready to be spawned on the runtime.
@@ -68,6 +81,9 @@ struct LoadedBinding<F> {
     /// bindings listens for TLS connections with `acceptor`.
     acceptor: Option<tls::Acceptor>,
 
+    /// The options applied to connections.
+    options: BindingOptions,
+
     /// The sending end of the channel that brings commands to the task.
     handle: mpsc::Sender<Command>,
 
@@ -75,71 +91,195 @@ struct LoadedBinding<F> {
     future: F,
 }
 
+/// Where the configuration is read from, when it is reloaded with REHASH or SIGUSR1.
+enum ConfigSource {
+    /// The configuration file at `path`, with the settings given on the command line.
+    File { path: String, overrides: Overrides },
+
+    /// The configuration given to `ServerBuilder::config`, or to the last `ServerHandle::reload`.
+    Memory(Box<Config>),
+}
+
+impl ConfigSource {
+    async fn read(&self) -> Option<Config> {
+        match self {
+            ConfigSource::File { path, overrides } => match Config::from_file(path).await {
+                Ok(mut cfg) => {
+                    overrides.apply(&mut cfg);
+                    Some(cfg)
+                }
+                Err(err) => {
+                    log::error!("Failed to read {:?}: {}", path, err);
+                    None
+                }
+            },
+            ConfigSource::Memory(cfg) => Some(Config::clone(cfg)),
+        }
+    }
+}
+
+/// A request from a `ServerHandle` to the task running the server.
+enum Request {
+    Reload(Box<Config>),
+    Subscribe(oneshot::Sender<broadcast::Receiver<Event>>),
+    Dump(oneshot::Sender<serde_json::Value>),
+    Announcements(oneshot::Sender<Vec<config::Announcement>>),
+    AddAnnouncement(Box<config::Announcement>, oneshot::Sender<bool>),
+    RemoveAnnouncement(String, oneshot::Sender<bool>),
+    Shutdown,
+}
+
+/// How often the MOTD file is checked for modifications.
+const MOTD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the nicknames of clients are checked against the accounts they are logged in to.
+const NICK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often clients are checked for inactivity, to mark them as away.
+const AWAY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often channel mutes are checked for expiry.
+const MUTE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often announcements are checked, to send those that are due.
+const ANNOUNCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often channel history is purged of the messages past their channel's limits.
+const HISTORY_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the peaks shown by LUSERS are written to the channel database.
+const PEAKS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps track of the modification time of the MOTD file, to reload it when it changes.
+struct MotdWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl MotdWatcher {
+    fn new(path: String) -> Self {
+        let modified = modified_time(&path);
+        Self { path, modified }
+    }
+
+    /// Reloads the MOTD of `shared` if the file has been modified since the last check.
+    async fn check(&mut self, shared: &State) {
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        log::info!("Reloading MOTD from {:?}", self.path);
+        let motd = match tokio::fs::read_to_string(&self.path).await {
+            Ok(motd) => Some(motd),
+            Err(err) => {
+                log::warn!("Failed to read {:?}: {}", self.path, err);
+                None
+            }
+        };
+        shared.set_motd(motd).await;
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
 /// Creates the bindings tasks and spawns them on the given runtime.
 ///
-/// This function is what `Control` calls on startup to generate the bindings.  Because it exits
-/// the program on failure, it is not to be called for reloading.
+/// This function is what `Control` calls on startup to generate the bindings.  Because it fails
+/// when a TLS identity cannot be loaded, it is not to be called for reloading.
 ///
 /// It spawns all the generated bindings on the runtime, and returns their listening address and
-/// command channel.
+/// command channel.  On failure, nothing is spawned.
 fn load_bindings(
     bindings: Vec<Binding>,
     shared: &State,
     stop: &mpsc::Sender<SocketAddr>,
-) -> Vec<(SocketAddr, mpsc::Sender<Command>)> {
+) -> Result<Vec<(SocketAddr, mpsc::Sender<Command>)>> {
     let mut res = Vec::with_capacity(bindings.len());
     let mut store = tls::IdentityStore::default();
+    let mut acceptors = Vec::with_capacity(bindings.len());
+
+    for binding in &bindings {
+        let acceptor = match &binding.tls {
+            Some(tls) => match store.acceptor(tls) {
+                Ok(acceptor) => Some(acceptor),
+                Err(err) => bail!("binding {}: {}", binding.address, err),
+            },
+            None => None,
+        };
+        acceptors.push(acceptor);
+    }
 
-    for Binding { address, tls } in bindings {
+    for (binding, acceptor) in bindings.into_iter().zip(acceptors) {
         let (handle, commands) = mpsc::channel(8);
-        if let Some(Tls {
-            certificate, key, ..
-        }) = tls
-        {
-            let acceptor = match store.acceptor(certificate, key) {
-                Ok(acceptor) => acceptor,
-                Err(_) => process::exit(1),
-            };
-            let server = net::listen(
-                address,
-                shared.clone(),
-                Some(acceptor),
-                stop.clone(),
-                commands,
-            );
-            res.push((address, handle));
-            tokio::spawn(server);
-        } else {
-            let server = net::listen(address, shared.clone(), None, stop.clone(), commands);
-            res.push((address, handle));
-            tokio::spawn(server);
-        }
+        let server = net::listen(
+            binding.address,
+            binding.socket_options(),
+            binding.options(),
+            shared.clone(),
+            acceptor,
+            stop.clone(),
+            commands,
+        );
+        res.push((binding.address, handle));
+        tokio::spawn(server);
     }
 
-    res
+    Ok(res)
 }
 
-/// Reloads the configuration at `config_path`.
+/// Reloads the configuration from `source`.
+///
+/// See `apply_config` for what happens once it is read.
+async fn do_rehash(
+    source: &ConfigSource,
+    shared: &State,
+    stop: mpsc::Sender<SocketAddr>,
+    bindings: &mut Vec<(SocketAddr, mpsc::Sender<Command>)>,
+    motd: &mut MotdWatcher,
+    current: &mut Config,
+) {
+    if let ConfigSource::File { path, .. } = source {
+        log::info!("Reloading configuration from {:?}", path);
+    }
+    if let Some(cfg) = source.read().await {
+        apply_config(cfg, shared, stop, bindings, motd, current).await;
+    }
+}
+
+/// Replaces the running configuration, `current`, with `cfg`.
 ///
 /// In four steps:
 ///
-/// - Read the configuration and load the authentication provider,
 /// - Remove old bindings that are not used anymore,
 /// - Add new bindings, or send them a command to listen for raw TCP or TLS connections,
-/// - Update the shared state.
-async fn do_rehash(
-    config_path: String,
+/// - Update the shared state,
+/// - Log what has changed, and report it to the operator who sent REHASH, if any.
+async fn apply_config(
+    cfg: Config,
     shared: &State,
     stop: mpsc::Sender<SocketAddr>,
     bindings: &mut Vec<(SocketAddr, mpsc::Sender<Command>)>,
+    motd: &mut MotdWatcher,
+    current: &mut Config,
 ) {
-    log::info!("Reloading configuration from {:?}", config_path);
-    let shared_clone = shared.clone();
-    let reloaded = reload_config(config_path, shared_clone, stop).await;
-    let (cfg, new_bindings) = match reloaded {
-        Some(reloaded) => reloaded,
-        _ => return,
-    };
+    let mut changes = current.diff(&cfg);
+    for new_b in &cfg.bindings {
+        let modified = current
+            .bindings
+            .iter()
+            .any(|old_b| old_b.address == new_b.address && old_b != new_b);
+        if modified {
+            changes.push(format!("binding {}: modified", new_b.address));
+        }
+    }
+    *current = cfg.clone();
+
+    logging::set_outputs(&cfg.log_output);
+    let (cfg, new_bindings) = reload_config(cfg, shared.clone(), stop, motd);
 
     let mut i = 0;
     while i < bindings.len() {
@@ -149,6 +289,7 @@ async fn do_rehash(
             .all(|new_b| old_address != new_b.address)
         {
             bindings.swap_remove(i);
+            changes.push(format!("binding {old_address}: stopped"));
         } else {
             i += 1;
         }
@@ -156,13 +297,16 @@ async fn do_rehash(
 
     for new_b in new_bindings {
         if let Some(i) = bindings.iter().position(|old_b| old_b.0 == new_b.address) {
-            let res = bindings[i]
-                .1
+            let handle = &bindings[i].1;
+            let mut res = handle
                 .send(match new_b.acceptor {
                     Some(acceptor) => Command::UseTls(acceptor),
                     None => Command::UsePlain,
                 })
                 .await;
+            if res.is_ok() {
+                res = handle.send(Command::SetOptions(new_b.options)).await;
+            }
             if res.is_err() {
                 // Failure to send the command means either the binding task have dropped the
                 // command channel, or the binding task doesn't exist anymore.  Both possibilities
@@ -174,6 +318,7 @@ async fn do_rehash(
                 bindings.push((new_b.address, new_b.handle));
             }
         } else {
+            changes.push(format!("binding {}: started", new_b.address));
             tokio::spawn(new_b.future);
             bindings.push((new_b.address, new_b.handle));
         }
@@ -182,27 +327,27 @@ async fn do_rehash(
     shared.rehash(cfg.state).await;
 
     log::info!("Configuration reloaded");
+    for change in &changes {
+        log::info!("Changed {}", change);
+    }
+    shared.report_rehash(&changes).await;
 }
 
-/// Re-read the configuration file and re-generate the bindings.
+/// Re-generate the bindings of the given configuration.
 ///
 /// See documentation of `reload_bindings` for how bindings are re-generated.
 ///
-/// This function will put the contents of the MOTD file into `Config.motd_file`, so that the
+/// This function will put the contents of the MOTD files into `Config.motd_file` and
+/// `Config.oper_motd_file`, so that the
 /// shared state can use the field as-is, since it must not use blocking operations such as reading
 /// a file.
-async fn reload_config(
-    config_path: String,
+fn reload_config(
+    mut cfg: Config,
     shared: State,
     stop: mpsc::Sender<SocketAddr>,
-) -> Option<(Config, Vec<LoadedBinding<impl Future<Output = ()>>>)> {
-    let mut cfg = match Config::from_file(&config_path).await {
-        Ok(cfg) => cfg,
-        Err(err) => {
-            log::error!("Failed to read {:?}: {}", config_path, err);
-            return None;
-        }
-    };
+    motd: &mut MotdWatcher,
+) -> (Config, Vec<LoadedBinding<impl Future<Output = ()>>>) {
+    *motd = MotdWatcher::new(cfg.state.motd_file.clone());
     cfg.state.motd_file = match fs::read_to_string(&cfg.state.motd_file) {
         Ok(motd) => motd,
         Err(err) => {
@@ -210,8 +355,17 @@ async fn reload_config(
             String::new()
         }
     };
+    if !cfg.state.oper_motd_file.is_empty() {
+        cfg.state.oper_motd_file = match fs::read_to_string(&cfg.state.oper_motd_file) {
+            Ok(motd) => motd,
+            Err(err) => {
+                log::warn!("Failed to read {:?}: {}", cfg.state.oper_motd_file, err);
+                String::new()
+            }
+        };
+    }
     let new_bindings = reload_bindings(&cfg.bindings, &shared, &stop);
-    Some((cfg, new_bindings))
+    (cfg, new_bindings)
 }
 
 /// Equivalent of `load_bindings` for when exiting the program is not acceptable.
@@ -229,18 +383,19 @@ fn reload_bindings(
     let mut res = Vec::with_capacity(bindings.len());
     let mut store = tls::IdentityStore::default();
 
-    for Binding { address, tls } in bindings {
+    for binding in bindings {
         let (handle, commands) = mpsc::channel(8);
-        if let Some(Tls {
-            certificate, key, ..
-        }) = tls
-        {
-            let acceptor = match store.acceptor(certificate, key) {
+        let address = &binding.address;
+        let options = binding.options();
+        if let Some(tls) = &binding.tls {
+            let acceptor = match store.acceptor(tls) {
                 Ok(acceptor) => acceptor,
                 Err(_) => continue,
             };
             let future = net::listen(
                 *address,
+                binding.socket_options(),
+                options.clone(),
                 shared.clone(),
                 Some(acceptor.clone()),
                 stop.clone(),
@@ -249,14 +404,24 @@ fn reload_bindings(
             res.push(LoadedBinding {
                 address: *address,
                 acceptor: Some(acceptor),
+                options,
                 handle,
                 future,
             });
         } else {
-            let future = net::listen(*address, shared.clone(), None, stop.clone(), commands);
+            let future = net::listen(
+                *address,
+                binding.socket_options(),
+                options.clone(),
+                shared.clone(),
+                None,
+                stop.clone(),
+                commands,
+            );
             res.push(LoadedBinding {
                 address: *address,
                 acceptor: None,
+                options,
                 handle,
                 future,
             });
@@ -266,63 +431,414 @@ fn reload_bindings(
     res
 }
 
-pub async fn load_config_and_run(config_path: String) -> Result<()> {
-    let cfg = Config::from_file(&config_path).await?;
-    run(config_path, cfg).await;
-    Ok(())
+/// Reads the configuration file, builds the tokio runtime with the configured number of workers
+/// and runs the server on it.
+pub fn load_config_and_run(config_path: String, overrides: Overrides) -> Result<()> {
+    let mut cfg = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(Config::from_file(&config_path))?;
+    overrides.apply(&mut cfg);
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if cfg.workers != 0 {
+        runtime.worker_threads(cfg.workers);
+    }
+    runtime.enable_all().build()?.block_on(async {
+        let signals =
+            reload_signal().context("cannot listen for signals to reload the configuration")?;
+        let source = ConfigSource::File {
+            path: config_path,
+            overrides,
+        };
+        let server = Running::start(source, cfg, Hooks::default()).await?;
+        // Nothing sends requests to the server when it is run from the command line.
+        let (_, requests) = mpsc::channel(1);
+        server.run(Some(signals), requests).await;
+        Ok(())
+    })
 }
 
-pub async fn run(config_path: String, cfg: Config) {
-    let signal_fail = |err| {
-        log::error!(
-            "Cannot listen for signals to reload the configuration: {}",
-            err
-        );
-        process::exit(1);
-    };
+#[cfg(unix)]
+type ReloadSignal = tokio::signal::unix::Signal;
 
+#[cfg(windows)]
+type ReloadSignal = tokio::signal::windows::CtrlBreak;
+
+/// Listens for the signal that reloads the configuration file: SIGUSR1 on UNIX systems, and
+/// CTRL-BREAK on windows.
+fn reload_signal() -> io::Result<ReloadSignal> {
     #[cfg(unix)]
-    let mut signals = {
+    {
         use tokio::signal::unix;
-
-        unix::signal(unix::SignalKind::user_defined1()).unwrap_or_else(signal_fail)
-    };
+        unix::signal(unix::SignalKind::user_defined1())
+    }
 
     #[cfg(windows)]
-    let mut signals = {
-        use tokio::signal::windows;
+    {
+        tokio::signal::windows::ctrl_break()
+    }
+}
 
-        windows::ctrl_break().unwrap_or_else(signal_fail)
-    };
+/// A server whose bindings are listening.
+struct Running {
+    /// Where the configuration is reloaded from on REHASH.
+    source: ConfigSource,
+    shared: State,
+    rehash: Arc<Notify>,
 
-    let (stop, mut failures) = mpsc::channel(8);
-    let rehash = Arc::new(Notify::new());
+    /// Bindings send their address to `stop` when they fail, and it is received by `failures`.
+    stop: mpsc::Sender<SocketAddr>,
+    failures: mpsc::Receiver<SocketAddr>,
+    bindings: Vec<(SocketAddr, mpsc::Sender<Command>)>,
+    motd: MotdWatcher,
+
+    /// The configuration in use, to report what a reload changes.
+    current: Config,
+}
 
-    let shared = State::new(cfg.state, rehash.clone()).await;
-    let mut bindings = load_bindings(cfg.bindings, &shared, &stop);
+impl Running {
+    /// Creates the state of the server with `cfg` and the given hooks and command handlers, and
+    /// spawns its bindings.
+    ///
+    /// Fails when the TLS identity of a binding cannot be loaded.
+    async fn start(source: ConfigSource, cfg: Config, hooks: Hooks) -> Result<Self> {
+        logging::set_outputs(&cfg.log_output);
 
-    loop {
-        tokio::select! {
-            addr = failures.recv() => match addr {
-                Some(addr) => for i in 0..bindings.len() {
-                    if bindings[i].0 == addr {
-                        bindings.swap_remove(i);
-                        break;
+        let (stop, failures) = mpsc::channel(8);
+        let rehash = Arc::new(Notify::new());
+        let motd = MotdWatcher::new(cfg.state.motd_file.clone());
+        let current = cfg.clone();
+        let shared = State::new(cfg.state, rehash.clone()).await;
+        shared.set_hooks(hooks).await;
+        let bindings = load_bindings(cfg.bindings, &shared, &stop)?;
+
+        Ok(Self {
+            source,
+            shared,
+            rehash,
+            stop,
+            failures,
+            bindings,
+            motd,
+            current,
+        })
+    }
+
+    /// Runs the server until all bindings have failed or a `Request::Shutdown` is received.
+    ///
+    /// The configuration is reloaded from `source` on REHASH, and when `signals` receives a
+    /// signal.
+    async fn run(self, mut signals: Option<ReloadSignal>, mut requests: mpsc::Receiver<Request>) {
+        let Self {
+            mut source,
+            shared,
+            rehash,
+            stop,
+            mut failures,
+            mut bindings,
+            mut motd,
+            mut current,
+        } = self;
+        let mut requests_open = true;
+
+        let mut motd_check = tokio::time::interval(MOTD_CHECK_INTERVAL);
+        let mut nick_check = tokio::time::interval(NICK_CHECK_INTERVAL);
+        let mut mute_check = tokio::time::interval(MUTE_CHECK_INTERVAL);
+        let mut away_check = tokio::time::interval(AWAY_CHECK_INTERVAL);
+        let mut announce_check = tokio::time::interval(ANNOUNCE_CHECK_INTERVAL);
+        let mut history_purge = tokio::time::interval(HISTORY_PURGE_INTERVAL);
+        let mut peaks_save = tokio::time::interval(PEAKS_SAVE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                addr = failures.recv() => match addr {
+                    Some(addr) => for i in 0..bindings.len() {
+                        if bindings[i].0 == addr {
+                            bindings.swap_remove(i);
+                            break;
+                        }
                     }
-                }
-                None => {
-                    // `failures.recv()` returns `None` when all senders have been dropped, so
-                    // when all bindings tasks have stopped.
-                    log::error!("No binding left, exiting.");
-                    return;
-                }
-            },
-            _ = rehash.notified() => {
-                do_rehash(config_path.clone(), &shared, stop.clone(), &mut bindings).await;
-            },
-            _ = signals.recv() => {
-                do_rehash(config_path.clone(), &shared, stop.clone(), &mut bindings).await;
-            },
+                    None => {
+                        // `failures.recv()` returns `None` when all senders have been dropped, so
+                        // when all bindings tasks have stopped.
+                        log::error!("No binding left, exiting.");
+                        return;
+                    }
+                },
+                _ = rehash.notified() => {
+                    do_rehash(&source, &shared, stop.clone(), &mut bindings, &mut motd, &mut current).await;
+                },
+                _ = async { signals.as_mut()?.recv().await }, if signals.is_some() => {
+                    do_rehash(&source, &shared, stop.clone(), &mut bindings, &mut motd, &mut current).await;
+                },
+                request = requests.recv(), if requests_open => match request {
+                    Some(Request::Reload(cfg)) => {
+                        log::info!("Reloading configuration");
+                        if let ConfigSource::Memory(_) = source {
+                            source = ConfigSource::Memory(cfg.clone());
+                        }
+                        apply_config(*cfg, &shared, stop.clone(), &mut bindings, &mut motd, &mut current).await;
+                    }
+                    Some(Request::Subscribe(reply)) => {
+                        let _ = reply.send(shared.subscribe().await);
+                    }
+                    Some(Request::Dump(reply)) => {
+                        let _ = reply.send(shared.dump().await);
+                    }
+                    Some(Request::Announcements(reply)) => {
+                        let _ = reply.send(shared.announcements().await);
+                    }
+                    Some(Request::AddAnnouncement(announcement, reply)) => {
+                        let _ = reply.send(shared.add_announcement(*announcement).await);
+                    }
+                    Some(Request::RemoveAnnouncement(name, reply)) => {
+                        let _ = reply.send(shared.remove_announcement(&name).await);
+                    }
+                    Some(Request::Shutdown) => {
+                        log::info!("Shutting down");
+                        shared.shutdown().await;
+                        return;
+                    }
+                    None => requests_open = false,
+                },
+                _ = motd_check.tick() => {
+                    motd.check(&shared).await;
+                },
+                _ = nick_check.tick() => {
+                    shared.rename_expired_nicks().await;
+                },
+                _ = mute_check.tick() => {
+                    shared.expire_mutes(crate::util::time()).await;
+                },
+                _ = away_check.tick() => {
+                    shared.set_idle_clients_away(crate::util::time()).await;
+                },
+                _ = announce_check.tick() => {
+                    shared.send_announcements(crate::util::time()).await;
+                },
+                _ = history_purge.tick() => {
+                    shared.purge_history(crate::util::time()).await;
+                },
+                _ = peaks_save.tick() => {
+                    shared.save_peaks().await;
+                },
+            }
+        }
+    }
+}
+
+/// Entry point of embedded servers.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let mut config = ellidri::Config::default();
+/// config.state.domain = String::from("irc.example.com");
+/// let server = ellidri::Server::builder().config(config).spawn().await?;
+/// // ...
+/// server.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+}
+
+/// The settings of a server that is yet to be spawned.
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Config,
+    hooks: Hooks,
+}
+
+impl ServerBuilder {
+    /// Sets the configuration of the server.  Defaults to `Config::default()`.
+    ///
+    /// `workers` is ignored, since the server runs on the runtime of the caller.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a hook to the server.  Hooks are called in the order they are added.
+    ///
+    /// See the `hooks` module for details.
+    pub fn hook(mut self, hook: impl Hook) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Adds the command `name` to the server, handled by `handler`.  It replaces the handler
+    /// given before for the same command, but not the commands known to ellidri.
+    pub fn command(mut self, name: &str, handler: impl CommandHandler) -> Self {
+        self.hooks.add_command(name, Box::new(handler));
+        self
+    }
+
+    /// Starts the server on the current tokio runtime.
+    ///
+    /// Fails when the TLS identity of a binding cannot be loaded.
+    ///
+    /// # Panics
+    ///
+    /// When called outside of a tokio runtime.
+    pub async fn spawn(self) -> Result<ServerHandle> {
+        let (requests, receiver) = mpsc::channel(8);
+        let source = ConfigSource::Memory(Box::new(self.config.clone()));
+        let server = Running::start(source, self.config, self.hooks).await?;
+        let task = tokio::spawn(server.run(None, receiver));
+        Ok(ServerHandle { requests, task })
+    }
+}
+
+/// A handle to a running server, returned by `ServerBuilder::spawn`.
+///
+/// Dropping the handle leaves the server running.
+pub struct ServerHandle {
+    requests: mpsc::Sender<Request>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Replaces the configuration of the server, the same way a rehash does.
+    ///
+    /// Later REHASH commands reapply this configuration, and reload the MOTD file.
+    pub async fn reload(&self, config: Config) {
+        let _ = self.requests.send(Request::Reload(Box::new(config))).await;
+    }
+
+    /// Returns a receiver of the events that happen from now on.  See the `events` module.
+    pub async fn subscribe(&self) -> broadcast::Receiver<Event> {
+        let (reply, receiver) = oneshot::channel();
+        let _ = self.requests.send(Request::Subscribe(reply)).await;
+        // If the server has stopped, return a receiver that is already closed.
+        receiver.await.unwrap_or_else(|_| broadcast::channel(1).1)
+    }
+
+    /// Returns a snapshot of the clients and channels of the server, for debugging.  Hosts, real
+    /// names, channel keys, topics and messages are left out.
+    ///
+    /// Returns `Value::Null` if the server has stopped.
+    pub async fn dump(&self) -> serde_json::Value {
+        let (reply, receiver) = oneshot::channel();
+        let _ = self.requests.send(Request::Dump(reply)).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Returns the announcements of the server.
+    ///
+    /// Returns an empty list if the server has stopped.
+    pub async fn announcements(&self) -> Vec<config::Announcement> {
+        let (reply, receiver) = oneshot::channel();
+        let _ = self.requests.send(Request::Announcements(reply)).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Adds an announcement, or replaces the one with the same name.  Returns false if its
+    /// schedule is invalid, or if the server has stopped.
+    ///
+    /// Like `SPAMFILTER`, the change lasts until the configuration is reloaded.
+    pub async fn add_announcement(&self, announcement: config::Announcement) -> bool {
+        let (reply, receiver) = oneshot::channel();
+        let request = Request::AddAnnouncement(Box::new(announcement), reply);
+        let _ = self.requests.send(request).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Removes the announcement with the given name, until the configuration is reloaded.
+    /// Returns false if there was none.
+    pub async fn remove_announcement(&self, name: &str) -> bool {
+        let (reply, receiver) = oneshot::channel();
+        let request = Request::RemoveAnnouncement(name.to_owned(), reply);
+        let _ = self.requests.send(request).await;
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Disconnects all clients, closes the bindings and waits for the server to stop.
+    pub async fn shutdown(self) {
+        let _ = self.requests.send(Request::Shutdown).await;
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{self, Simulation};
+
+    /// Registers a new client, and returns the first line of the MOTD it is sent.
+    async fn first_motd_line(sim: &mut Simulation, nick: &str) -> String {
+        let mut conn = sim.connect();
+        conn.send(&format!("NICK {nick}")).await;
+        conn.send("USER user 0 * :Simulated user").await;
+        loop {
+            let line = conn.recv().await.unwrap();
+            if line.contains(" 372 ") || line.contains(" 422 ") {
+                return line;
+            }
         }
     }
+
+    #[test]
+    fn test_motd_watcher() {
+        sim::run(async {
+            let path = std::env::temp_dir().join(format!("ellidri-motd-{}", std::process::id()));
+            let _ = fs::remove_file(&path);
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut motd = MotdWatcher::new(path.to_str().unwrap().to_owned());
+
+            // The file is read as soon as it appears.
+            fs::write(&path, "Hello from {server}").unwrap();
+            motd.check(sim.state()).await;
+            let line = first_motd_line(&mut sim, "alice").await;
+            assert!(
+                line.ends_with(" 372 alice :- Hello from sim.ellidri"),
+                "{line}"
+            );
+
+            // The MOTD is not reloaded while the file is left untouched.
+            sim.state().set_motd(None).await;
+            motd.check(sim.state()).await;
+            let line = first_motd_line(&mut sim, "bob").await;
+            assert!(line.contains(" 422 bob "), "{line}"); // ERR_NOMOTD
+
+            // A removed file removes the MOTD.
+            sim.state().set_motd(Some(String::from("stale"))).await;
+            fs::remove_file(&path).unwrap();
+            motd.check(sim.state()).await;
+            let line = first_motd_line(&mut sim, "carol").await;
+            assert!(line.contains(" 422 carol "), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_config_source_overrides() {
+        sim::run(async {
+            let path =
+                std::env::temp_dir().join(format!("ellidri-overrides-{}.yaml", std::process::id()));
+            let path = path.to_str().unwrap().to_owned();
+            Config::default().write_to_file(&path).await.unwrap();
+            let overrides = Overrides {
+                bindings: vec![SocketAddr::from(([127, 0, 0, 1], 7000))],
+                motd_file: Some(String::from("/tmp/motd.txt")),
+                workers: Some(3),
+            };
+
+            // Command-line settings outlive configuration reloads.
+            let source = ConfigSource::File { path, overrides };
+            let cfg = source.read().await.unwrap();
+            assert_eq!(cfg.bindings.len(), 1);
+            assert_eq!(cfg.bindings[0].address.to_string(), "127.0.0.1:7000");
+            assert_eq!(cfg.state.motd_file, "/tmp/motd.txt");
+            assert_eq!(cfg.workers, 3);
+
+            if let ConfigSource::File { path, .. } = source {
+                fs::remove_file(path).unwrap();
+            }
+        });
+    }
 }
@@ -23,21 +23,8 @@ macro_rules! caps {
         $( pub const $cap: &str = $cap_str; )*
         $( pub const $specap: &str = $specap_str; )*
 
-        const _LS_COMMON: &str = concat!( $( $cap_str, " " ),* );
-
-        pub fn ls_common() -> &'static str {
-            &_LS_COMMON[.._LS_COMMON.len() - 1]
-        }
-
-        pub fn query(buf: &str) -> impl Iterator<Item=(&str, bool)> {
-            buf.split_whitespace().map(|word| {
-                if word.starts_with('-') {
-                    (&word[1..], false)
-                } else {
-                    (word, true)
-                }
-            })
-        }
+        /// The capabilities that are always available, in CAP LS.
+        pub const COMMON: &[&str] = &[ $( $cap_str ),* ];
 
         #[derive(Clone, Copy, Debug, Default)]
         pub struct Diff {
@@ -75,7 +62,7 @@ macro_rules! caps {
 
             fn try_from(val: &'a str) -> Result<Self, Self::Error> {
                 let mut res = Self::default();
-                for (capability, enable) in query(val) {
+                for (capability, enable) in ellidri_tokens::cap::changes(val) {
                     match capability {
                     $(
                         $cap => res.$cap_member = Some(enable),
@@ -110,43 +97,47 @@ macro_rules! caps {
             )*
             }
 
-            pub fn write(&self, buf: &mut String) {
-                let len = buf.len();
+            /// The names of the enabled capabilities, for CAP LIST.
+            pub fn names(&self) -> Vec<&'static str> {
+                let mut res = Vec::new();
             $(
                 if self.$cap_member {
-                    buf.push_str($cap);
-                    buf.push(' ');
+                    res.push($cap);
                 }
             )*
             $(
                 if self.$specap_member {
-                    buf.push_str($specap);
-                    buf.push(' ');
+                    res.push($specap);
                 }
             )*
-                if len < buf.len() { buf.pop(); }
+                res
             }
         }
     };
 }
 
 caps! {
-    ACCOUNT_NOTIFY    "account-notify"     account_notify
-    ACCOUNT_TAG       "account-tag"        account_tag
-    AWAY_NOTIFY       "away-notify"        away_notify
-    BATCH             "batch"              batch
-    CAP_NOTIFY        "cap-notify"         cap_notify
-    ECHO_MESSAGE      "echo-message"       echo_message
-    EXTENDED_JOIN     "extended-join"      extended_join
-    INVITE_NOTIFY     "invite-notify"      invite_notify
-    LABELED_RESPONSE  "labeled-response"   labeled_response
-    MESSAGE_TAGS      "message-tags"       message_tags
-    MULTI_PREFIX      "multi-prefix"       multi_prefix
-    SERVER_TIME       "server-time"        server_time
-    SETNAME           "setname"            setname
-    USERHOST_IN_NAMES "userhost-in-names"  userhost_in_names
+    ACCOUNT_NOTIFY    "account-notify"       account_notify
+    ACCOUNT_TAG       "account-tag"          account_tag
+    AWAY_NOTIFY       "away-notify"          away_notify
+    BATCH             "batch"                batch
+    CAP_NOTIFY        "cap-notify"           cap_notify
+    CHATHISTORY       "draft/chathistory"    chathistory
+    CHGHOST           "chghost"              chghost
+    ECHO_MESSAGE      "echo-message"         echo_message
+    EVENT_PLAYBACK    "draft/event-playback" event_playback
+    EXTENDED_JOIN     "extended-join"        extended_join
+    INVITE_NOTIFY     "invite-notify"        invite_notify
+    LABELED_RESPONSE  "labeled-response"     labeled_response
+    MESSAGE_TAGS      "message-tags"         message_tags
+    MULTI_PREFIX      "multi-prefix"         multi_prefix
+    SEARCH            "draft/search"         search
+    SERVER_TIME       "server-time"          server_time
+    SETNAME           "setname"              setname
+    USERHOST_IN_NAMES "userhost-in-names"    userhost_in_names
     |
-    SASL "sasl" sasl
+    METADATA "draft/metadata-2" metadata
+    SASL     "sasl"             sasl
 }
 
 impl Capabilities {
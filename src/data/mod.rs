@@ -1,6 +1,6 @@
 pub use self::cap::Capabilities;
 pub use self::req::Request;
-pub use self::strings::{ChannelName, HostName, JoinList, Key, List, Mask, Nickname};
+pub use self::strings::{ChannelName, JoinList, Key, List, Mask, Nickname};
 pub mod cap;
 pub mod modes;
 pub mod req;
@@ -15,4 +15,5 @@ pub enum Error<'a> {
     NoSuchNick(&'a str),
     NeedMoreParams(ellidri_tokens::Command, usize),
     UnknownCommand(&'a str),
+    UnknownMode(&'a str),
 }
@@ -1,4 +1,5 @@
 use ellidri_tokens::mode;
+use std::fmt;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Channel<'a>(&'a str, &'a [&'a str]);
@@ -13,6 +14,16 @@ impl<'a> Channel<'a> {
     }
 }
 
+impl fmt::Display for Channel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)?;
+        for param in self.1 {
+            write!(f, " {param}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct User<'a>(&'a str);
 
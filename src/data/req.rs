@@ -1,4 +1,6 @@
 use super::*;
+use crate::channel::AccessLevel;
+use crate::config::SpamAction;
 use ellidri_tokens::{Command, Message};
 use std::convert::TryFrom;
 
@@ -11,9 +13,8 @@ impl<'a> From<&'a str> for WhoFilter {
     fn from(val: &'a str) -> Self {
         let mut res = Self::default();
         for c in val.chars() {
-            match c {
-                'o' => res.operator = true,
-                _ => {}
+            if c == 'o' {
+                res.operator = true;
             }
         }
         res
@@ -36,17 +37,88 @@ pub struct WhoUser<'a> {
     pub filter: WhoFilter,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct AccessAdd<'a> {
+    pub channel: ChannelName<'a>,
+    pub level: AccessLevel,
+    pub mask: &'a str,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct AccessDel<'a> {
+    pub channel: ChannelName<'a>,
+    pub mask: &'a str,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FounderTransfer<'a> {
+    pub channel: ChannelName<'a>,
+    pub account: &'a str,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct FounderSuccessor<'a> {
+    pub channel: ChannelName<'a>,
+    pub account: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpamFilterAdd<'a> {
+    pub action: SpamAction,
+    pub pattern: &'a str,
+    pub reason: &'a str,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChatHistory<'a> {
+    pub subcommand: &'a str,
+    pub params: &'a [&'a str],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChgHost<'a> {
+    pub who: Nickname<'a>,
+    pub host: &'a str,
+}
 #[derive(Clone, Copy, Debug)]
 pub struct Kill<'a> {
     pub who: Nickname<'a>,
     pub reason: &'a str,
 }
 #[derive(Clone, Copy, Debug)]
+pub struct MassKill<'a> {
+    pub mask: &'a str,
+
+    /// Whether to disconnect the matching clients, or only count them.
+    pub confirm: bool,
+
+    pub reason: Option<&'a str>,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct SaJoin<'a> {
+    pub who: Nickname<'a>,
+    pub channels: &'a str,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct SaNick<'a> {
+    pub who: Nickname<'a>,
+    pub nick: Nickname<'a>,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct SaPart<'a> {
+    pub who: Nickname<'a>,
+    pub part: Part<'a>,
+}
+#[derive(Clone, Copy, Debug)]
 pub struct Oper<'a> {
     pub name: &'a str,
     pub password: &'a str,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct TopicRestore<'a> {
+    pub channel: ChannelName<'a>,
+    pub index: &'a str,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct TopicSet<'a> {
     pub channel: ChannelName<'a>,
@@ -64,6 +136,18 @@ pub struct ModeUserSet<'a> {
     pub modes: modes::User<'a>,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct MetadataGet<'a> {
+    pub target: &'a str,
+    pub keys: &'a [&'a str],
+}
+#[derive(Clone, Copy, Debug)]
+pub struct MetadataSet<'a> {
+    pub target: &'a str,
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Invite<'a> {
     pub who: Nickname<'a>,
@@ -75,6 +159,7 @@ pub struct Kick<'a> {
     pub from: ChannelName<'a>,
     pub reason: Option<&'a str>,
 }
+#[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
 pub struct MessageAll<'a> {
     pub feedback: bool,
@@ -101,6 +186,17 @@ pub struct ModeChannelSet<'a> {
     pub modes: modes::Channel<'a>,
 }
 #[derive(Clone, Copy, Debug)]
+pub struct Mute<'a> {
+    pub channel: ChannelName<'a>,
+    pub who: Nickname<'a>,
+    pub duration: &'a str,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct Unmute<'a> {
+    pub channel: ChannelName<'a>,
+    pub who: Nickname<'a>,
+}
+#[derive(Clone, Copy, Debug)]
 pub struct Part<'a> {
     pub from: List<'a, ChannelName<'a>>,
     pub reason: Option<&'a str>,
@@ -110,21 +206,41 @@ pub struct Part<'a> {
 pub enum Request<'a> {
     // Requests about general server info.
     Admin,
+    Help(&'a str),
     Info,
     LUsers,
     Motd,
     Time,
+    Trace(&'a str),
     Version,
     WhoChannel(WhoChannel<'a>),
     WhoMask(WhoMask<'a>),
     WhoUser(WhoUser<'a>),
     WhoAll(WhoFilter),
     WhoIs(Nickname<'a>),
+    IsOn(&'a [&'a str]),
+    UserHost(&'a [&'a str]),
 
     // IRCop restricted requests.
+    ChgHost(ChgHost<'a>),
+    Dump,
+    GlobOps(&'a str),
     Kill(Kill<'a>),
+    Lockdown(Option<&'a str>),
+    MassKill(MassKill<'a>),
     Oper(Oper<'a>),
+    OperMotd,
     Rehash,
+    SaJoin(SaJoin<'a>),
+    SaMode(ModeChannelSet<'a>),
+    SaNick(SaNick<'a>),
+    SaPart(SaPart<'a>),
+    SaTopic(TopicSet<'a>),
+    SetHost(&'a str),
+    SpamFilterList,
+    SpamFilterAdd(SpamFilterAdd<'a>),
+    SpamFilterDel(&'a str),
+    Stats(&'a str),
 
     // Requests about channel info.
     List(List<'a, ChannelName<'a>>),
@@ -133,12 +249,16 @@ pub enum Request<'a> {
     NamesAll,
     TopicGet(ChannelName<'a>),
     TopicSet(TopicSet<'a>),
+    TopicHistory(ChannelName<'a>),
+    TopicRestore(TopicRestore<'a>),
 
     // Client session related requests.
     CapLs(cap::Version),
     CapList,
     CapReq(cap::Diff),
     CapEnd,
+    Authenticate(&'a str),
+    Challenge(&'a str),
     Pass(&'a str),
     Ping(&'a str),
     Pong(&'a str),
@@ -147,12 +267,37 @@ pub enum Request<'a> {
 
     // Client info related requests.
     Away(Option<&'a str>),
+    AutoAway(Option<&'a str>),
+    AutoReplay(Option<&'a str>),
+    Language(&'a str),
     ModeUserGet(Nickname<'a>),
     ModeUserSet(ModeUserSet<'a>),
     Nick(Nickname<'a>),
     SetName(&'a str),
+    Ghost(Nickname<'a>),
+    Regain(Nickname<'a>),
+    MetadataGet(MetadataGet<'a>),
+    MetadataList(&'a str),
+    MetadataSet(MetadataSet<'a>),
+    MetadataClear(&'a str),
+    MetadataSub(&'a [&'a str]),
+    MetadataUnsub(&'a [&'a str]),
+    MetadataSubs,
+    MetadataSync(&'a str),
+
+    // History requests.
+    ChatHistory(ChatHistory<'a>),
+    Search(&'a str),
 
     // Channel management requests.
+    AccessList(ChannelName<'a>),
+    AccessAdd(AccessAdd<'a>),
+    AccessDel(AccessDel<'a>),
+    FounderGet(ChannelName<'a>),
+    FounderRegister(ChannelName<'a>),
+    FounderDrop(ChannelName<'a>),
+    FounderTransfer(FounderTransfer<'a>),
+    FounderSuccessor(FounderSuccessor<'a>),
     Invite(Invite<'a>),
     Join(JoinList<'a>),
     Kick(Kick<'a>),
@@ -161,6 +306,8 @@ pub enum Request<'a> {
     MessageUser(MessageUser<'a>),
     ModeChannelGet(ChannelName<'a>),
     ModeChannelSet(ModeChannelSet<'a>),
+    Mute(Mute<'a>),
+    Unmute(Unmute<'a>),
     Part(Part<'a>),
     PartAll,
 }
@@ -175,10 +322,12 @@ impl<'a> Request<'a> {
 
         Ok(match command {
             Command::Admin => Self::Admin,
+            Command::Help | Command::HelpOp => Self::Help(msg.params[0]),
             Command::Info => Self::Info,
             Command::LUsers => Self::LUsers,
             Command::Motd => Self::Motd,
             Command::Time => Self::Time,
+            Command::Trace => Self::Trace(msg.params[0]),
             Command::Version => Self::Version,
             Command::Who => {
                 let mask = msg.params[0];
@@ -198,18 +347,110 @@ impl<'a> Request<'a> {
                 let mask = Nickname::try_from(msg.params[0])?;
                 Self::WhoIs(mask)
             }
+            Command::IsOn => Self::IsOn(&msg.params[..msg.num_params]),
+            Command::UserHost => Self::UserHost(&msg.params[..msg.num_params]),
 
+            Command::ChgHost => {
+                let who = Nickname::try_from(msg.params[0])?;
+                let host = msg.params[1];
+                Self::ChgHost(ChgHost { who, host })
+            }
+            Command::GlobOps => Self::GlobOps(msg.params[0]),
             Command::Kill => {
                 let who = Nickname::try_from(msg.params[0])?;
                 let reason = msg.params[1];
                 Self::Kill(Kill { who, reason })
             }
+            Command::Lockdown => {
+                let level = if msg.params[0].is_empty() {
+                    None
+                } else {
+                    Some(msg.params[0])
+                };
+                Self::Lockdown(level)
+            }
+            Command::MassKill => {
+                let n = msg.num_params;
+                let confirm = 1 < n;
+                if confirm && !msg.params[1].eq_ignore_ascii_case("CONFIRM") {
+                    return Err(Error::UnknownCommand(msg.params[1]));
+                }
+                let reason = if n < 3 { None } else { Some(msg.params[2]) };
+                Self::MassKill(MassKill {
+                    mask: msg.params[0],
+                    confirm,
+                    reason,
+                })
+            }
             Command::Oper => {
                 let name = msg.params[0];
                 let password = msg.params[1];
                 Self::Oper(Oper { name, password })
             }
+            Command::Dump => Self::Dump,
+            Command::OperMotd => Self::OperMotd,
             Command::Rehash => Self::Rehash,
+            Command::SaJoin => {
+                let who = Nickname::try_from(msg.params[0])?;
+                let channels = msg.params[1];
+                Self::SaJoin(SaJoin { who, channels })
+            }
+            Command::SaMode => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let modes = modes::Channel::new(msg.params[1], &msg.params[2..msg.num_params]);
+                Self::SaMode(ModeChannelSet { channel, modes })
+            }
+            Command::SaNick => {
+                let who = Nickname::try_from(msg.params[0])?;
+                let nick = Nickname::try_from(msg.params[1])
+                    .map_err(|_| Error::ErroneousNickname(msg.params[1]))?;
+                Self::SaNick(SaNick { who, nick })
+            }
+            Command::SaPart => {
+                let who = Nickname::try_from(msg.params[0])?;
+                let from = List::new(msg.params[1], ',');
+                let reason = if msg.params[2].is_empty() {
+                    None
+                } else {
+                    Some(msg.params[2])
+                };
+                Self::SaPart(SaPart {
+                    who,
+                    part: Part { from, reason },
+                })
+            }
+            Command::SaTopic => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let topic = msg.params[1];
+                Self::SaTopic(TopicSet { channel, topic })
+            }
+            Command::SetHost => Self::SetHost(msg.params[0]),
+            Command::SpamFilter => {
+                let n = msg.num_params;
+                let subcommand = msg.params[0];
+                if n == 0 || subcommand.eq_ignore_ascii_case("LIST") {
+                    Self::SpamFilterList
+                } else if subcommand.eq_ignore_ascii_case("ADD") {
+                    if n < 3 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    let action = SpamAction::from_name(msg.params[1])
+                        .ok_or(Error::UnknownCommand(msg.params[1]))?;
+                    Self::SpamFilterAdd(SpamFilterAdd {
+                        action,
+                        pattern: msg.params[2],
+                        reason: msg.params[3],
+                    })
+                } else if subcommand.eq_ignore_ascii_case("DEL") {
+                    if n < 2 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    Self::SpamFilterDel(msg.params[1])
+                } else {
+                    return Err(Error::UnknownCommand(subcommand));
+                }
+            }
+            Command::Stats => Self::Stats(msg.params[0]),
 
             Command::List => {
                 let channel_names = msg.params[0];
@@ -238,8 +479,27 @@ impl<'a> Request<'a> {
                     Self::TopicSet(TopicSet { channel, topic })
                 }
             }
+            Command::TopicHistory => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let n = msg.num_params;
+                let subcommand = msg.params[1];
+                if n == 1 || subcommand.eq_ignore_ascii_case("LIST") {
+                    Self::TopicHistory(channel)
+                } else if subcommand.eq_ignore_ascii_case("RESTORE") {
+                    if n < 3 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    Self::TopicRestore(TopicRestore {
+                        channel,
+                        index: msg.params[2],
+                    })
+                } else {
+                    return Err(Error::UnknownCommand(subcommand));
+                }
+            }
 
-            Command::Authenticate => return Err(Error::UnknownCommand("AUTHENTICATE")),
+            Command::Authenticate => Self::Authenticate(msg.params[0]),
+            Command::Challenge => Self::Challenge(msg.params[0]),
             Command::Cap => match msg.params[0] {
                 "LS" => {
                     let version = cap::Version::from(msg.params[1]);
@@ -287,6 +547,22 @@ impl<'a> Request<'a> {
                 };
                 Self::Away(reason)
             }
+            Command::AutoAway => {
+                let delay = if msg.params[0].is_empty() {
+                    None
+                } else {
+                    Some(msg.params[0])
+                };
+                Self::AutoAway(delay)
+            }
+            Command::AutoReplay => {
+                let len = if msg.params[0].is_empty() {
+                    None
+                } else {
+                    Some(msg.params[0])
+                };
+                Self::AutoReplay(len)
+            }
             Command::Mode => {
                 let n = msg.num_params;
                 if let Ok(channel) = ChannelName::try_from(msg.params[0]) {
@@ -311,11 +587,113 @@ impl<'a> Request<'a> {
                     .map_err(|_| Error::ErroneousNickname(msg.params[0]))?;
                 Self::Nick(nickname)
             }
+            Command::Ghost => Self::Ghost(Nickname::try_from(msg.params[0])?),
+            Command::Language => Self::Language(msg.params[0]),
+            Command::Regain => Self::Regain(Nickname::try_from(msg.params[0])?),
             Command::SetName => {
                 let realname = msg.params[0];
                 Self::SetName(realname)
             }
+            Command::Metadata => {
+                let target = msg.params[0];
+                let subcommand = msg.params[1];
+                let params = &msg.params[2..msg.num_params];
+                let need_params = |n| {
+                    if params.len() < n {
+                        Err(Error::NeedMoreParams(command, msg.num_params))
+                    } else {
+                        Ok(())
+                    }
+                };
+                if subcommand.eq_ignore_ascii_case("GET") {
+                    need_params(1)?;
+                    Self::MetadataGet(MetadataGet {
+                        target,
+                        keys: params,
+                    })
+                } else if subcommand.eq_ignore_ascii_case("LIST") {
+                    Self::MetadataList(target)
+                } else if subcommand.eq_ignore_ascii_case("SET") {
+                    need_params(1)?;
+                    Self::MetadataSet(MetadataSet {
+                        target,
+                        key: params[0],
+                        value: params.get(1).copied().filter(|value| !value.is_empty()),
+                    })
+                } else if subcommand.eq_ignore_ascii_case("CLEAR") {
+                    Self::MetadataClear(target)
+                } else if subcommand.eq_ignore_ascii_case("SUB") {
+                    need_params(1)?;
+                    Self::MetadataSub(params)
+                } else if subcommand.eq_ignore_ascii_case("UNSUB") {
+                    need_params(1)?;
+                    Self::MetadataUnsub(params)
+                } else if subcommand.eq_ignore_ascii_case("SUBS") {
+                    Self::MetadataSubs
+                } else if subcommand.eq_ignore_ascii_case("SYNC") {
+                    Self::MetadataSync(target)
+                } else {
+                    return Err(Error::UnknownCommand(subcommand));
+                }
+            }
+
+            Command::ChatHistory => Self::ChatHistory(ChatHistory {
+                subcommand: msg.params[0],
+                params: &msg.params[1..msg.num_params],
+            }),
+            Command::Search => Self::Search(msg.params[0]),
 
+            Command::Access => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let n = msg.num_params;
+                let subcommand = msg.params[1];
+                if n == 1 || subcommand.eq_ignore_ascii_case("LIST") {
+                    Self::AccessList(channel)
+                } else if subcommand.eq_ignore_ascii_case("ADD") {
+                    if n < 4 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    let level = AccessLevel::from_letter(msg.params[2])
+                        .ok_or(Error::UnknownMode(msg.params[2]))?;
+                    let mask = msg.params[3];
+                    Self::AccessAdd(AccessAdd {
+                        channel,
+                        level,
+                        mask,
+                    })
+                } else if subcommand.eq_ignore_ascii_case("DEL") {
+                    if n < 3 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    let mask = msg.params[2];
+                    Self::AccessDel(AccessDel { channel, mask })
+                } else {
+                    return Err(Error::UnknownCommand(subcommand));
+                }
+            }
+            Command::Founder => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let n = msg.num_params;
+                let subcommand = msg.params[1];
+                if n == 1 {
+                    Self::FounderGet(channel)
+                } else if subcommand.eq_ignore_ascii_case("REGISTER") {
+                    Self::FounderRegister(channel)
+                } else if subcommand.eq_ignore_ascii_case("DROP") {
+                    Self::FounderDrop(channel)
+                } else if subcommand.eq_ignore_ascii_case("TRANSFER") {
+                    if n < 3 {
+                        return Err(Error::NeedMoreParams(command, n));
+                    }
+                    let account = msg.params[2];
+                    Self::FounderTransfer(FounderTransfer { channel, account })
+                } else if subcommand.eq_ignore_ascii_case("SUCCESSOR") {
+                    let account = if n < 3 { None } else { Some(msg.params[2]) };
+                    Self::FounderSuccessor(FounderSuccessor { channel, account })
+                } else {
+                    return Err(Error::UnknownCommand(subcommand));
+                }
+            }
             Command::Invite => {
                 let who = Nickname::try_from(msg.params[0])?;
                 let to = ChannelName::try_from(msg.params[1])?;
@@ -329,6 +707,21 @@ impl<'a> Request<'a> {
                     Self::Join(channels)
                 }
             }
+            Command::Mute => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let who = Nickname::try_from(msg.params[1])?;
+                let duration = msg.params[2];
+                Self::Mute(Mute {
+                    channel,
+                    who,
+                    duration,
+                })
+            }
+            Command::Unmute => {
+                let channel = ChannelName::try_from(msg.params[0])?;
+                let who = Nickname::try_from(msg.params[1])?;
+                Self::Unmute(Unmute { channel, who })
+            }
             Command::Kick => {
                 let from = ChannelName::try_from(msg.params[0])?;
                 let who = List::new(msg.params[1], ',');
@@ -392,25 +785,77 @@ impl<'a> Request<'a> {
         })
     }
 
+    /// Whether the request only concerns the connection that issued it, and not the client it is
+    /// attached to (see multiclient).
+    pub fn is_connection_specific(&self) -> bool {
+        matches!(
+            self,
+            Request::CapLs(_)
+                | Request::CapList
+                | Request::CapReq(_)
+                | Request::CapEnd
+                | Request::Quit(_)
+        )
+    }
+
+    /// Whether the replies to the request tell the client about a change of its own state (e.g.
+    /// its nickname, or the channels it is in).  These replies are also sent to the other
+    /// connections of the client.
+    pub fn changes_client_state(&self) -> bool {
+        matches!(
+            self,
+            Request::Away(_)
+                | Request::Join(_)
+                | Request::Kick(_)
+                | Request::ModeChannelSet(_)
+                | Request::ModeUserSet(_)
+                | Request::Nick(_)
+                | Request::Part(_)
+                | Request::PartAll
+                | Request::SetName(_)
+                | Request::TopicSet(_)
+        )
+    }
+
     pub fn points(&self) -> u32 {
         match self {
             // Requests about general server info.
             Self::Admin => 2,
+            Self::Help(_) => 2,
             Self::Info => 3,
             Self::LUsers => 3,
             Self::Motd => 3,
             Self::Time => 2,
+            Self::Trace(_) => 4,
             Self::Version => 2,
             Self::WhoChannel(_) => 5,
             Self::WhoMask(_) => 10,
             Self::WhoUser(_) => 4,
             Self::WhoAll(_) => 8,
             Self::WhoIs(_) => 4,
+            Self::IsOn(_) => 2,
+            Self::UserHost(_) => 2,
 
             // IRCop restricted requests.
+            Self::ChgHost(_) => 8,
+            Self::Dump => 16,
+            Self::GlobOps(_) => 4,
             Self::Kill(_) => 16,
+            Self::Lockdown(_) => 8,
+            Self::MassKill(_) => 16,
             Self::Oper(_) => 16,
+            Self::OperMotd => 3,
             Self::Rehash => 16,
+            Self::SaJoin(_) => 8,
+            Self::SaMode(_) => 8,
+            Self::SaNick(_) => 8,
+            Self::SaPart(_) => 8,
+            Self::SaTopic(_) => 8,
+            Self::SetHost(_) => 8,
+            Self::SpamFilterList => 4,
+            Self::SpamFilterAdd(_) => 8,
+            Self::SpamFilterDel(_) => 8,
+            Self::Stats(_) => 8,
 
             // Requests about channel info.
             Self::List(_) => 4,
@@ -419,12 +864,16 @@ impl<'a> Request<'a> {
             Self::NamesAll => 2,
             Self::TopicGet(_) => 4,
             Self::TopicSet(_) => 7,
+            Self::TopicHistory(_) => 4,
+            Self::TopicRestore(_) => 7,
 
             // Client session related requests.
             Self::CapLs(_) => 1,
             Self::CapList => 1,
             Self::CapReq(_) => 1,
             Self::CapEnd => 1,
+            Self::Authenticate(_) => 4,
+            Self::Challenge(_) => 8,
             Self::Pass(_) => 2,
             Self::Ping(_) => 2,
             Self::Pong(_) => 2,
@@ -433,12 +882,37 @@ impl<'a> Request<'a> {
 
             // Client info related requests.
             Self::Away(_) => 8,
+            Self::AutoAway(_) => 4,
+            Self::AutoReplay(_) => 4,
+            Self::Language(_) => 4,
             Self::ModeUserGet(_) => 4,
             Self::ModeUserSet(_) => 7,
             Self::Nick(_) => 8,
+            Self::Ghost(_) => 8,
+            Self::Regain(_) => 8,
             Self::SetName(_) => 8,
+            Self::MetadataGet(_) => 4,
+            Self::MetadataList(_) => 4,
+            Self::MetadataSet(_) => 8,
+            Self::MetadataClear(_) => 8,
+            Self::MetadataSub(_) => 2,
+            Self::MetadataUnsub(_) => 2,
+            Self::MetadataSubs => 2,
+            Self::MetadataSync(_) => 8,
+
+            // History requests.
+            Self::ChatHistory(_) => 8,
+            Self::Search(_) => 8,
 
             // Channel management requests.
+            Self::AccessList(_) => 4,
+            Self::AccessAdd(_) => 8,
+            Self::AccessDel(_) => 8,
+            Self::FounderGet(_) => 4,
+            Self::FounderRegister(_) => 8,
+            Self::FounderDrop(_) => 8,
+            Self::FounderTransfer(_) => 8,
+            Self::FounderSuccessor(_) => 8,
             Self::Invite(_) => 10,
             Self::Join(_) => 8,
             Self::Kick(_) => 6,
@@ -447,6 +921,8 @@ impl<'a> Request<'a> {
             Self::MessageUser(_) => 8,
             Self::ModeChannelGet(_) => 4,
             Self::ModeChannelSet(_) => 7,
+            Self::Mute(_) => 6,
+            Self::Unmute(_) => 6,
             Self::Part(_) => 6,
             Self::PartAll => 12,
         }
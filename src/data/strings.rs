@@ -21,13 +21,13 @@ fn is_valid(c: char) -> bool {
 }
 
 fn is_valid_mask(s: &str) -> bool {
-    s.chars().next().map_or(false, |first| {
+    s.chars().next().is_some_and(|first| {
         is_valid(first) && s.chars().skip(1).all(|c| is_valid(c) && !is_namespace(c))
     })
 }
 
 fn is_valid_name(s: &str) -> bool {
-    s.chars().next().map_or(false, |first| {
+    s.chars().next().is_some_and(|first| {
         !is_prefix(first)
             && s.chars()
                 .all(|c| is_valid(c) && !is_namespace(c) && !is_wildcard(c))
@@ -37,7 +37,7 @@ fn is_valid_name(s: &str) -> bool {
 fn is_valid_channel_name(s: &str) -> bool {
     s.chars()
         .next()
-        .map_or(false, |first| is_namespace(first) && is_valid_name(&s[1..]))
+        .is_some_and(|first| is_namespace(first) && is_valid_name(&s[1..]))
 }
 
 fn is_restricted_nickname(s: &str) -> bool {
@@ -145,21 +145,6 @@ impl<'a> TryFrom<&'a str> for Key<'a> {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct HostName<'a>(&'a str);
-
-impl<'a> TryFrom<&'a str> for HostName<'a> {
-    type Error = ();
-
-    fn try_from(val: &'a str) -> Result<Self, Self::Error> {
-        if is_valid_name(val) {
-            Ok(Self(val))
-        } else {
-            Err(())
-        }
-    }
-}
-
 #[derive(Clone, Copy, Debug)]
 pub struct List<'a, T>(&'a str, char, PhantomData<T>);
 
@@ -0,0 +1,99 @@
+//! Structured events of the server, for programs that embed it.
+//!
+//! Events are sent to the receivers returned by `ServerHandle::subscribe`.  They are only built
+//! when there is at least one receiver.
+
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+pub use ellidri_tokens::Command;
+
+/// How many events are kept for receivers that are late.  Receivers that fall behind more than
+/// that miss the oldest events, and get `RecvError::Lagged`.
+const CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Event {
+    /// A connection has been accepted.
+    Connected { id: usize, addr: SocketAddr },
+
+    /// A client has completed its registration.
+    Registered { id: usize, nick: String },
+
+    /// A client has left the network.  `nick` is "*" if it was not registered.
+    Quit {
+        id: usize,
+        nick: String,
+        reason: String,
+    },
+
+    /// A PRIVMSG, NOTICE or TAGMSG has been sent to a channel or a user.
+    Message {
+        from: String,
+        command: Command,
+        target: String,
+        text: Option<String>,
+    },
+
+    /// A client has been kicked from a channel.  `by` is the server's domain for automatic kicks.
+    Kick {
+        by: String,
+        channel: String,
+        kicked: String,
+        reason: Option<String>,
+    },
+
+    /// The modes of a channel or of a user have changed.
+    Mode {
+        by: String,
+        target: String,
+        modes: String,
+    },
+}
+
+/// The sending end of the events.
+pub(crate) struct Events(broadcast::Sender<Event>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+
+    /// Sends the event built by `f`, if anyone is listening.
+    pub fn emit(&self, f: impl FnOnce() -> Event) {
+        if self.0.receiver_count() != 0 {
+            let _ = self.0.send(f());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit() {
+        let events = Events::default();
+        events.emit(|| panic!("built without receivers"));
+
+        let mut receiver = events.subscribe();
+        events.emit(|| Event::Registered {
+            id: 1,
+            nick: String::from("alice"),
+        });
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::Registered { id: 1, nick }) if nick == "alice"
+        ));
+
+        drop(receiver);
+        events.emit(|| panic!("built after the receivers are gone"));
+    }
+}
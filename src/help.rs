@@ -0,0 +1,397 @@
+//! Help topics, sent in reply to HELP.
+//!
+//! Each command known to ellidri has a topic, with its parameters and a short description.  Topics
+//! of the commands restricted to IRC operators are only shown to IRC operators.
+
+use ellidri_tokens::Command;
+
+/// The help of a command.
+pub struct Topic {
+    pub command: Command,
+
+    /// The parameters of the command, as shown after its name.
+    pub usage: &'static str,
+
+    /// What the command does, line by line.
+    pub text: &'static [&'static str],
+
+    /// Whether only IRC operators can use the command.
+    pub oper: bool,
+}
+
+impl Topic {
+    const fn new(command: Command, usage: &'static str, text: &'static [&'static str]) -> Self {
+        Self {
+            command,
+            usage,
+            text,
+            oper: false,
+        }
+    }
+
+    const fn oper(command: Command, usage: &'static str, text: &'static [&'static str]) -> Self {
+        Self {
+            command,
+            usage,
+            text,
+            oper: true,
+        }
+    }
+}
+
+/// The help topics, in the same order as `Command::ALL`.
+pub const TOPICS: &[Topic] = &[
+    Topic::new(
+        Command::Access,
+        "<channel> [LIST | ADD <level> <mask> | DEL <mask>]",
+        &[
+            "Lists or changes the access list of a registered channel.",
+            "Users matching a mask get the level (q, a, o, h or v) when they join.",
+        ],
+    ),
+    Topic::new(
+        Command::Admin,
+        "[server]",
+        &["Shows who runs the server and how to contact them."],
+    ),
+    Topic::new(
+        Command::Authenticate,
+        "<data>",
+        &["Logs in to an account with SASL, during registration."],
+    ),
+    Topic::new(
+        Command::AutoAway,
+        "[<delay> | OFF]",
+        &["Shows or changes how long you can stay silent before being marked away."],
+    ),
+    Topic::new(
+        Command::AutoReplay,
+        "[<number> | OFF]",
+        &["Shows or changes how many messages are replayed when you join a channel."],
+    ),
+    Topic::new(
+        Command::Away,
+        "[message]",
+        &["Marks you as away with the given message, or back without one."],
+    ),
+    Topic::new(
+        Command::Cap,
+        "<LS | LIST | REQ | END> [capabilities]",
+        &["Negotiates IRCv3 capabilities, usually done by your client."],
+    ),
+    Topic::new(
+        Command::Challenge,
+        "<answer>",
+        &["Answers the question asked before registration."],
+    ),
+    Topic::new(
+        Command::ChatHistory,
+        "<subcommand> <target> <references...> <limit>",
+        &["Fetches past messages of a channel, usually done by your client."],
+    ),
+    Topic::oper(
+        Command::ChgHost,
+        "<nick> <host>",
+        &["Changes the host shown for a user."],
+    ),
+    Topic::oper(
+        Command::Dump,
+        "",
+        &["Writes a snapshot of the clients and channels to the server log."],
+    ),
+    Topic::new(
+        Command::Founder,
+        "<channel> [REGISTER | DROP | TRANSFER <account> | SUCCESSOR [account]]",
+        &[
+            "Shows the founder of a registered channel and its successor.  Channel operators",
+            "can register the channel to their account.  The founder can unregister it, give",
+            "it to another account, or choose the account that inherits it when the",
+            "founder's account is dropped.",
+        ],
+    ),
+    Topic::new(
+        Command::Ghost,
+        "<nick>",
+        &["Disconnects the client using the nickname of your account."],
+    ),
+    Topic::oper(
+        Command::GlobOps,
+        "<text>",
+        &["Sends a message to the other IRC operators, as a server notice."],
+    ),
+    Topic::new(
+        Command::Help,
+        "[command]",
+        &["Lists the commands, or shows how to use one of them."],
+    ),
+    Topic::new(Command::HelpOp, "[command]", &["Same as HELP."]),
+    Topic::new(
+        Command::Info,
+        "[server]",
+        &["Shows information about the server software."],
+    ),
+    Topic::new(
+        Command::Invite,
+        "<nick> <channel>",
+        &["Invites someone to a channel."],
+    ),
+    Topic::new(
+        Command::IsOn,
+        "<nick> [nick...]",
+        &["Tells which of the given nicknames are connected."],
+    ),
+    Topic::new(
+        Command::Join,
+        "<channel>[,<channel>...] [key[,key...]]",
+        &["Joins channels, with their keys if they have one."],
+    ),
+    Topic::new(
+        Command::Kick,
+        "<channel> <nick>[,<nick>...] [reason]",
+        &["Removes users from a channel.  You must be a channel operator."],
+    ),
+    Topic::oper(
+        Command::Kill,
+        "<nick> <reason>",
+        &["Disconnects a user from the server."],
+    ),
+    Topic::new(
+        Command::Language,
+        "<language>",
+        &["Changes the language of the messages of the server."],
+    ),
+    Topic::new(
+        Command::List,
+        "[channel[,channel...]]",
+        &["Lists the channels, with their number of members and their topic."],
+    ),
+    Topic::oper(
+        Command::Lockdown,
+        "[level]",
+        &[
+            "Shows or changes the lockdown level, to weather spam waves.",
+            "1: only logged in users send private messages.  2: only IRC operators",
+            "create channels.  3: plain-text connections are refused.  0: no lockdown.",
+        ],
+    ),
+    Topic::new(
+        Command::LUsers,
+        "",
+        &["Shows the number of users and channels of the server."],
+    ),
+    Topic::oper(
+        Command::MassKill,
+        "<mask> [CONFIRM [reason]]",
+        &[
+            "Counts the clients matching a nick!user@host mask or an IP range such as",
+            "192.0.2.0/24.  With CONFIRM, disconnects them.  IRC operators are spared.",
+        ],
+    ),
+    Topic::new(
+        Command::Metadata,
+        "<target> <subcommand> [params...]",
+        &["Gets or sets metadata of users and channels, usually done by your client."],
+    ),
+    Topic::new(
+        Command::Mode,
+        "<target> [modes [params...]]",
+        &["Shows or changes the modes of a channel or of yourself."],
+    ),
+    Topic::new(
+        Command::Motd,
+        "[server]",
+        &["Shows the message of the day."],
+    ),
+    Topic::new(
+        Command::Mute,
+        "<channel> <nick> <duration>",
+        &["Prevents someone from talking in a channel for a while."],
+    ),
+    Topic::new(
+        Command::Names,
+        "[channel[,channel...]]",
+        &["Lists the members of channels."],
+    ),
+    Topic::new(Command::Nick, "<nick>", &["Changes your nickname."]),
+    Topic::new(
+        Command::Notice,
+        "<target>[,<target>...] <text>",
+        &["Sends a notice to users or channels.  Notices are never replied to."],
+    ),
+    Topic::new(
+        Command::Oper,
+        "<name> [password]",
+        &[
+            "Makes you an IRC operator.  The password can be left out if your oper block only \
+             needs a client certificate.",
+        ],
+    ),
+    Topic::oper(
+        Command::OperMotd,
+        "",
+        &["Shows the message of the day of IRC operators."],
+    ),
+    Topic::new(
+        Command::Part,
+        "<channel>[,<channel>...] [reason]",
+        &["Leaves channels."],
+    ),
+    Topic::new(
+        Command::Pass,
+        "<password>",
+        &["Gives the password of the server, during registration."],
+    ),
+    Topic::new(
+        Command::Ping,
+        "<token>",
+        &["Checks that the server is still there."],
+    ),
+    Topic::new(Command::Pong, "<token>", &["Answers a PING."]),
+    Topic::new(
+        Command::PrivMsg,
+        "<target>[,<target>...] <text>",
+        &["Sends a message to users or channels."],
+    ),
+    Topic::new(
+        Command::Quit,
+        "[reason]",
+        &["Disconnects you from the server."],
+    ),
+    Topic::new(
+        Command::Regain,
+        "<nick>",
+        &["Takes back the nickname of your account from whoever uses it."],
+    ),
+    Topic::oper(Command::Rehash, "", &["Reloads the configuration file."]),
+    Topic::oper(
+        Command::SaJoin,
+        "<nick> <channel>[,<channel>...]",
+        &["Makes a user join channels, even if they are locked.  Needs the sajoin privilege."],
+    ),
+    Topic::oper(
+        Command::SaMode,
+        "<channel> <modes> [params...]",
+        &["Changes the modes of any channel.  Needs the samode privilege."],
+    ),
+    Topic::oper(
+        Command::SaNick,
+        "<nick> <new nick>",
+        &["Changes the nickname of a user.  Needs the sanick privilege."],
+    ),
+    Topic::oper(
+        Command::SaPart,
+        "<nick> <channel>[,<channel>...] [reason]",
+        &["Makes a user leave channels.  Needs the sapart privilege."],
+    ),
+    Topic::oper(
+        Command::SaTopic,
+        "<channel> <topic>",
+        &["Changes the topic of any channel.  Needs the satopic privilege."],
+    ),
+    Topic::new(
+        Command::Search,
+        "<attributes>",
+        &["Looks for past messages of your channels, usually done by your client."],
+    ),
+    Topic::oper(
+        Command::SetHost,
+        "<host>",
+        &["Changes the host shown for you."],
+    ),
+    Topic::new(Command::SetName, "<realname>", &["Changes your real name."]),
+    Topic::oper(
+        Command::SpamFilter,
+        "[LIST | ADD <action> <pattern> [reason] | DEL <pattern>]",
+        &["Lists or changes the spamfilters, until the next rehash."],
+    ),
+    Topic::new(
+        Command::Stats,
+        "[query]",
+        &["Shows statistics about the server.  IRC operators can query l and m."],
+    ),
+    Topic::new(
+        Command::TagMsg,
+        "<target>",
+        &["Sends message tags without text, usually done by your client."],
+    ),
+    Topic::new(
+        Command::Time,
+        "[server]",
+        &["Shows the local time of the server."],
+    ),
+    Topic::new(
+        Command::Topic,
+        "<channel> [topic]",
+        &["Shows or changes the topic of a channel."],
+    ),
+    Topic::new(
+        Command::TopicHistory,
+        "<channel> [RESTORE <index>]",
+        &["Lists the previous topics of a channel, or restores one."],
+    ),
+    Topic::new(
+        Command::Trace,
+        "[nick]",
+        &[
+            "Shows the class, state and lag of your connection.  IRC operators can trace other \
+             users, or every connection when no nick is given.",
+        ],
+    ),
+    Topic::new(
+        Command::Unmute,
+        "<channel> <nick>",
+        &["Lets a muted user talk again."],
+    ),
+    Topic::new(
+        Command::User,
+        "<user> <mode> <unused> <realname>",
+        &["Gives your user name and real name, during registration."],
+    ),
+    Topic::new(
+        Command::UserHost,
+        "<nick> [nick...]",
+        &["Shows the host of the given users."],
+    ),
+    Topic::new(
+        Command::Version,
+        "[server]",
+        &["Shows the version of the server."],
+    ),
+    Topic::new(
+        Command::Who,
+        "<mask>",
+        &["Lists the users matching a mask, or the members of a channel."],
+    ),
+    Topic::new(
+        Command::WhoIs,
+        "<nick>",
+        &["Shows information about a user."],
+    ),
+];
+
+/// Returns the help topic of the command `name`, case-insensitive.
+pub fn find(name: &str) -> Option<&'static Topic> {
+    let command = Command::parse(name)?;
+    TOPICS.iter().find(|topic| topic.command == command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        let commands: Vec<Command> = TOPICS.iter().map(|topic| topic.command).collect();
+        assert_eq!(commands, Command::ALL);
+        for topic in TOPICS {
+            assert!(!topic.text.is_empty(), "{}", topic.command);
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("kick").unwrap().command, Command::Kick);
+        assert!(find("rehash").unwrap().oper);
+        assert!(find("unknown").is_none());
+    }
+} // mod tests
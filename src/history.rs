@@ -0,0 +1,509 @@
+//! In-memory history of channel messages.
+//!
+//! Each channel keeps its last PRIVMSG and NOTICE messages in a `History`, bounded in length and
+//! age by `history_len` and `history_age` (or the matching `channel_history` of the
+//! configuration).  Channel operators can restrict these limits further with the history mode
+//! (+H).  Clients fetch them with `CHATHISTORY` and `SEARCH`.  Messages are lost when the channel
+//! is deleted or when ellidri is restarted.
+//!
+//! JOIN, PART, MODE and TOPIC events are kept along with messages, and only replayed to the
+//! clients that enable `draft/event-playback`.
+
+use crate::util;
+use ellidri_tokens::Command;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// How many messages are kept, and for how long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    /// The maximum number of messages kept.  0 disables history.
+    pub len: usize,
+
+    /// The number of seconds after which messages are removed.  0 means messages don't expire.
+    pub age: u64,
+}
+
+impl Limit {
+    /// Whether a message sent at the UNIX time `unix_time` has expired at the UNIX time `now`.
+    pub fn is_expired(self, unix_time: u64, now: u64) -> bool {
+        self.age != 0 && unix_time.saturating_add(self.age) <= now
+    }
+
+    /// Returns the limit that keeps no more messages, for no longer, than both `self` and `other`.
+    pub fn min(self, other: Self) -> Self {
+        let age = match (self.age, other.age) {
+            (0, age) | (age, 0) => age,
+            (a, b) => cmp::min(a, b),
+        };
+        Self {
+            len: cmp::min(self.len, other.len),
+            age,
+        }
+    }
+}
+
+/// Parses the parameter of the history mode (+H): `<len>:<age>`.
+impl std::str::FromStr for Limit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (len, age) = s.split_once(':').ok_or(())?;
+        let len = len.parse().map_err(|_| ())?;
+        let age = age.parse().map_err(|_| ())?;
+        Ok(Self { len, age })
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.len, self.age)
+    }
+}
+
+/// A message or an event sent to a channel.
+pub struct Entry {
+    /// PRIVMSG or NOTICE for messages, JOIN, PART, MODE or TOPIC for events.
+    pub command: Command,
+
+    /// The full name of the sender.
+    pub from: String,
+
+    /// The account of the sender, if any.
+    pub account: Option<String>,
+
+    pub msgid: String,
+
+    /// The time at which the message was sent, for the `server-time` tag.  Since it is an RFC 3339
+    /// UTC timestamp, comparing it with other timestamps compares the times.
+    pub time: String,
+
+    /// The UNIX time at which the message was sent, for expiry.
+    pub unix_time: u64,
+
+    /// The text of messages, the reason of PART, the topic of TOPIC, or the modes of MODE and
+    /// their parameters separated by spaces.
+    pub content: String,
+}
+
+impl Entry {
+    /// Returns an event sent by `from` now.
+    pub fn event(command: Command, from: &str, account: Option<&str>, content: String) -> Self {
+        Self {
+            command,
+            from: from.to_owned(),
+            account: account.map(str::to_owned),
+            msgid: util::new_message_id(),
+            time: util::time_precise(),
+            unix_time: util::time(),
+            content,
+        }
+    }
+
+    /// Whether the entry is a PRIVMSG or a NOTICE, rather than an event.
+    pub fn is_message(&self) -> bool {
+        matches!(self.command, Command::PrivMsg | Command::Notice)
+    }
+}
+
+/// A message given to `CHATHISTORY`, by its time or by its ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reference<'a> {
+    Timestamp(&'a str),
+    MsgId(&'a str),
+}
+
+impl<'a> Reference<'a> {
+    /// Parses `timestamp=<time>` and `msgid=<id>`.
+    pub fn parse(s: &'a str) -> Option<Self> {
+        let (kind, value) = s.split_once('=')?;
+        if value.is_empty() {
+            return None;
+        }
+        match kind {
+            "timestamp" => Some(Self::Timestamp(value)),
+            "msgid" => Some(Self::MsgId(value)),
+            _ => None,
+        }
+    }
+}
+
+/// Which messages to return, see the `draft/chathistory` specification.  References are
+/// excluded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Query<'a> {
+    /// The most recent messages, after the reference if any.
+    Latest(Option<Reference<'a>>),
+    Before(Reference<'a>),
+    After(Reference<'a>),
+    Around(Reference<'a>),
+
+    /// The messages between the two references.  The first messages after the first reference
+    /// are returned if it is the oldest, the last messages before it otherwise.
+    Between(Reference<'a>, Reference<'a>),
+}
+
+/// What to look for with `SEARCH`, see the `draft/search` specification.  Messages must match
+/// all the given criteria.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Search<'a> {
+    /// The nickname of the sender, case-insensitive.
+    pub from: Option<&'a str>,
+
+    /// The timestamp after which messages were sent, excluded.
+    pub after: Option<&'a str>,
+
+    /// The timestamp before which messages were sent, excluded.
+    pub before: Option<&'a str>,
+
+    /// Some text contained in the messages, case-insensitive.
+    pub text: Option<&'a str>,
+}
+
+impl Search<'_> {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let nick = entry.from.split('!').next().unwrap_or_default();
+        entry.is_message()
+            && self.from.is_none_or(|from| from.eq_ignore_ascii_case(nick))
+            && self.after.is_none_or(|after| after < entry.time.as_str())
+            && self
+                .before
+                .is_none_or(|before| entry.time.as_str() < before)
+            && self
+                .text
+                .is_none_or(|text| entry.content.to_lowercase().contains(&text.to_lowercase()))
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The most recent message.
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    /// Appends a message, and removes the messages that don't fit in `limit` anymore.
+    pub fn push(&mut self, entry: Entry, limit: Limit) {
+        let now = entry.unix_time;
+        self.entries.push_back(entry);
+        self.expire(limit, now);
+    }
+
+    /// Removes the messages that don't fit in `limit` at the UNIX time `now`.
+    pub fn expire(&mut self, limit: Limit, now: u64) {
+        while limit.len < self.entries.len() {
+            self.entries.pop_front();
+        }
+        while let Some(entry) = self.entries.front() {
+            if !limit.is_expired(entry.unix_time, now) {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns at most `limit` messages selected by `query`, from oldest to newest.  Events are
+    /// returned as well if `events` is true.
+    pub fn get(
+        &self,
+        query: Query<'_>,
+        limit: usize,
+        events: bool,
+    ) -> impl Iterator<Item = &Entry> + '_ {
+        let selected: Vec<usize> = (0..self.entries.len())
+            .filter(|&i| events || self.entries[i].is_message())
+            .collect();
+        // Indexes of `entries` are turned into indexes of `selected`.
+        let after = |reference| {
+            let after = self.after(reference);
+            selected.partition_point(|&i| i < after)
+        };
+        let before = |reference| {
+            let before = self.before(reference);
+            selected.partition_point(|&i| i < before)
+        };
+
+        let len = selected.len();
+        let (start, end) = match query {
+            Query::Latest(None) => (len.saturating_sub(limit), len),
+            Query::Latest(Some(reference)) => {
+                let start = after(reference);
+                (cmp::max(start, len.saturating_sub(limit)), len)
+            }
+            Query::Before(reference) => {
+                let end = before(reference);
+                (end.saturating_sub(limit), end)
+            }
+            Query::After(reference) => {
+                let start = after(reference);
+                (start, cmp::min(len, start.saturating_add(limit)))
+            }
+            Query::Around(reference) => {
+                let start = before(reference).saturating_sub(limit / 2);
+                let end = cmp::min(len, start.saturating_add(limit));
+                (end.saturating_sub(limit), end)
+            }
+            Query::Between(from, to) => {
+                let (start, end) = (after(from), before(to));
+                if start <= end {
+                    (start, cmp::min(end, start.saturating_add(limit)))
+                } else {
+                    let (start, end) = (after(to), before(from));
+                    (cmp::max(start, end.saturating_sub(limit)), end)
+                }
+            }
+        };
+        let end = cmp::max(start, end);
+        selected
+            .into_iter()
+            .skip(start)
+            .take(end - start)
+            .map(move |i| &self.entries[i])
+    }
+
+    /// Returns the last `limit` messages matching `search`, from oldest to newest.
+    pub fn search(&self, search: &Search<'_>, limit: usize) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| search.matches(entry))
+            .take(limit)
+            .collect();
+        entries.reverse();
+        entries
+    }
+
+    /// Returns the index of the first message after `reference`.  Unknown message IDs are
+    /// considered to be after all messages.
+    fn after(&self, reference: Reference<'_>) -> usize {
+        match reference {
+            Reference::Timestamp(time) => self.entries.partition_point(|e| *e.time <= *time),
+            Reference::MsgId(msgid) => self
+                .entries
+                .iter()
+                .position(|e| e.msgid == msgid)
+                .map_or(self.entries.len(), |i| i + 1),
+        }
+    }
+
+    /// Returns the index after the last message before `reference`.  Unknown message IDs are
+    /// considered to be before all messages.
+    fn before(&self, reference: Reference<'_>) -> usize {
+        match reference {
+            Reference::Timestamp(time) => self.entries.partition_point(|e| *e.time < *time),
+            Reference::MsgId(msgid) => self
+                .entries
+                .iter()
+                .position(|e| e.msgid == msgid)
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_LIMIT: Limit = Limit {
+        len: usize::MAX,
+        age: 0,
+    };
+
+    fn entry(i: u64) -> Entry {
+        Entry {
+            command: Command::PrivMsg,
+            from: String::from("nick!user@host"),
+            account: None,
+            msgid: format!("id{i}"),
+            time: format!("2000-01-01T00:00:{i:02}.000Z"),
+            unix_time: i,
+            content: format!("message {i}"),
+        }
+    }
+
+    fn history(n: u64) -> History {
+        let mut history = History::default();
+        for i in 0..n {
+            history.push(entry(i), NO_LIMIT);
+        }
+        history
+    }
+
+    fn ids<'a>(entries: impl Iterator<Item = &'a Entry>) -> Vec<u64> {
+        entries.map(|e| e.unix_time).collect()
+    }
+
+    #[test]
+    fn test_reference_parse() {
+        assert_eq!(Reference::parse("msgid=abc"), Some(Reference::MsgId("abc")));
+        assert_eq!(
+            Reference::parse("timestamp=2000-01-01T00:00:00.000Z"),
+            Some(Reference::Timestamp("2000-01-01T00:00:00.000Z"))
+        );
+        assert_eq!(Reference::parse("msgid="), None);
+        assert_eq!(Reference::parse("*"), None);
+        assert_eq!(Reference::parse("time=abc"), None);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut history = History::default();
+        let limit = Limit { len: 3, age: 10 };
+        for i in 0..5 {
+            history.push(entry(i), limit);
+        }
+        assert_eq!(ids(history.get(Query::Latest(None), 10, false)), [2, 3, 4]);
+
+        history.expire(limit, 13);
+        assert_eq!(ids(history.get(Query::Latest(None), 10, false)), [4]);
+        history.expire(limit, 14);
+        assert_eq!(history.len(), 0);
+
+        history.push(entry(0), Limit { len: 0, age: 0 });
+        assert_eq!(history.len(), 0);
+    }
+
+    #[test]
+    fn test_limit_min() {
+        let limit = |len, age| Limit { len, age };
+        assert_eq!(limit(100, 0).min(limit(10, 60)), limit(10, 60));
+        assert_eq!(limit(10, 3600).min(limit(100, 0)), limit(10, 3600));
+        assert_eq!(limit(100, 3600).min(limit(200, 60)), limit(100, 60));
+        assert_eq!(limit(100, 3600).min(limit(0, 0)), limit(0, 3600));
+
+        assert_eq!("50:600".parse(), Ok(limit(50, 600)));
+        assert_eq!("0:0".parse(), Ok(limit(0, 0)));
+        assert_eq!(limit(50, 600).to_string(), "50:600");
+        assert!("50".parse::<Limit>().is_err());
+        assert!("50:-1".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn test_get() {
+        let history = history(10);
+        let msgid = |s| Reference::MsgId(s);
+        let timestamp = |s| Reference::Timestamp(s);
+
+        assert_eq!(ids(history.get(Query::Latest(None), 3, false)), [7, 8, 9]);
+        assert_eq!(
+            ids(history.get(Query::Latest(Some(msgid("id8"))), 3, false)),
+            [9]
+        );
+        assert_eq!(
+            ids(history.get(Query::Before(msgid("id5")), 2, false)),
+            [3, 4]
+        );
+        assert_eq!(ids(history.get(Query::Before(msgid("id1")), 5, false)), [0]);
+        assert_eq!(
+            ids(history.get(Query::After(msgid("id5")), 2, false)),
+            [6, 7]
+        );
+        assert!(ids(history.get(Query::After(msgid("id9")), 2, false)).is_empty());
+        assert_eq!(
+            ids(history.get(Query::Around(msgid("id5")), 4, false)),
+            [3, 4, 5, 6]
+        );
+        assert_eq!(
+            ids(history.get(Query::Around(msgid("id0")), 3, false)),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            ids(history.get(Query::Around(msgid("id9")), 3, false)),
+            [7, 8, 9]
+        );
+
+        let t2 = timestamp("2000-01-01T00:00:02.000Z");
+        let t6 = timestamp("2000-01-01T00:00:06.000Z");
+        let between = |from, to, limit| ids(history.get(Query::Between(from, to), limit, false));
+        assert_eq!(between(t2, t6, 10), [3, 4, 5]);
+        assert_eq!(between(t2, t6, 2), [3, 4]);
+        assert_eq!(between(t6, t2, 2), [4, 5]);
+        assert!(between(t2, t2, 2).is_empty());
+        assert_eq!(ids(history.get(Query::Before(t2), 10, false)), [0, 1]);
+        assert_eq!(ids(history.get(Query::After(t6), 2, false)), [7, 8]);
+
+        assert!(ids(history.get(Query::Before(msgid("unknown")), 2, false)).is_empty());
+        assert!(ids(history.get(Query::After(msgid("unknown")), 2, false)).is_empty());
+    }
+
+    #[test]
+    fn test_get_events() {
+        let mut history = History::default();
+        for i in 0..10 {
+            let mut entry = entry(i);
+            if i % 2 == 1 {
+                entry.command = Command::Join;
+            }
+            history.push(entry, NO_LIMIT);
+        }
+        let msgid = |s| Reference::MsgId(s);
+
+        assert_eq!(ids(history.get(Query::Latest(None), 3, false)), [4, 6, 8]);
+        assert_eq!(ids(history.get(Query::Latest(None), 3, true)), [7, 8, 9]);
+        assert_eq!(
+            ids(history.get(Query::Before(msgid("id5")), 2, false)),
+            [2, 4]
+        );
+        assert_eq!(
+            ids(history.get(Query::After(msgid("id5")), 2, false)),
+            [6, 8]
+        );
+        assert_eq!(
+            ids(history.get(Query::After(msgid("id5")), 2, true)),
+            [6, 7]
+        );
+        assert_eq!(
+            ids(history.get(Query::Around(msgid("id5")), 4, false)),
+            [2, 4, 6, 8]
+        );
+
+        let search = Search::default();
+        assert_eq!(history.search(&search, 10).len(), 5);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut history = history(10);
+        let mut other = entry(10);
+        other.from = String::from("Other!user@host");
+        other.content = String::from("Hello WORLD");
+        history.push(other, NO_LIMIT);
+        let search = |search, limit| -> Vec<u64> {
+            history
+                .search(&search, limit)
+                .iter()
+                .map(|e| e.unix_time)
+                .collect()
+        };
+
+        assert_eq!(search(Search::default(), 2), [9, 10]);
+        let from = Search {
+            from: Some("other"),
+            ..Search::default()
+        };
+        assert_eq!(search(from, 10), [10]);
+        let text = Search {
+            text: Some("world"),
+            ..Search::default()
+        };
+        assert_eq!(search(text, 10), [10]);
+        let range = Search {
+            after: Some("2000-01-01T00:00:02.000Z"),
+            before: Some("2000-01-01T00:00:06.000Z"),
+            text: Some("MESSAGE"),
+            ..Search::default()
+        };
+        assert_eq!(search(range, 10), [3, 4, 5]);
+        assert_eq!(search(range, 2), [4, 5]);
+    }
+} // mod tests
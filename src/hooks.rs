@@ -0,0 +1,201 @@
+//! Hooks, to extend the behavior of the server without modifying it.
+//!
+//! Hooks are given to `ServerBuilder::hook` and are called by the state, in the order they were
+//! added, while it handles the messages of clients.  Each method returns a `Verdict`, so that
+//! hooks can observe events, or block them.  The first hook that blocks an event stops the others
+//! from being called.
+//!
+//! Since the state is locked while hooks run, they should return quickly and must not block on
+//! I/O.
+//!
+//! Programs can also add commands to the server with `ServerBuilder::command`, and a
+//! `CommandHandler`.
+
+use crate::client::Client;
+pub use ellidri_tokens::{Command, Message, ReplyBuffer};
+use std::collections::HashMap;
+
+/// What the server must do with an event, once a hook has seen it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Handle the event as usual.
+    Continue,
+
+    /// Drop the event.
+    Block,
+}
+
+/// The client an event comes from.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientInfo<'a> {
+    /// The identifier of the connection, which stays the same until the client quits.
+    pub id: usize,
+    /// The nickname of the client, or "*" when it has not chosen one yet.
+    pub nick: &'a str,
+    pub user: &'a str,
+    pub host: &'a str,
+    /// The account the client is logged in to.
+    pub account: Option<&'a str>,
+    /// Whether the client is an IRC operator.
+    pub operator: bool,
+}
+
+impl<'a> ClientInfo<'a> {
+    fn new(id: usize, client: &'a Client) -> Self {
+        Self {
+            id,
+            nick: client.nick(),
+            user: client.user(),
+            host: client.host(),
+            account: client.account(),
+            operator: client.operator,
+        }
+    }
+}
+
+/// Callbacks run by the server on client events.
+///
+/// All methods default to `Verdict::Continue`, so that hooks only implement the ones they need.
+pub trait Hook: Send + 'static {
+    /// Called on every message sent by a client, before it is handled.
+    ///
+    /// Blocked messages are silently dropped.
+    fn on_message(&mut self, client: &ClientInfo<'_>, msg: &Message<'_>) -> Verdict {
+        let _ = (client, msg);
+        Verdict::Continue
+    }
+
+    /// Called when a client has completed its registration.
+    ///
+    /// Blocked clients are disconnected.
+    fn on_register(&mut self, client: &ClientInfo<'_>) -> Verdict {
+        let _ = client;
+        Verdict::Continue
+    }
+
+    /// Called when a client is about to join a channel, after the checks of the channel modes.
+    ///
+    /// Blocked clients receive ERR_BANNEDFROMCHAN.
+    fn on_join(&mut self, client: &ClientInfo<'_>, channel: &str) -> Verdict {
+        let _ = (client, channel);
+        Verdict::Continue
+    }
+
+    /// Called on PRIVMSG and NOTICE messages sent to a channel or a user.
+    ///
+    /// `text` can be modified, and is then sent instead of the original text.  Blocked messages
+    /// are dropped, and the sender of a PRIVMSG is told so.
+    fn on_privmsg(
+        &mut self,
+        client: &ClientInfo<'_>,
+        command: Command,
+        target: &str,
+        text: &mut String,
+    ) -> Verdict {
+        let _ = (client, command, target, text);
+        Verdict::Continue
+    }
+}
+
+/// The handler of a command that is not known to ellidri.
+///
+/// It is implemented for closures with the same arguments as `handle`.
+pub trait CommandHandler: Send + 'static {
+    /// Handles `msg`, sent by a registered client.
+    ///
+    /// Replies written to `rb` (e.g. with `rb.reply("NOTICE").trailing_param("hi")`) are sent to
+    /// the client, as a labeled response when it asked for one.  Handlers that send more than one
+    /// reply should call `rb.lr_batch_begin()` first, otherwise the first reply is moved to the
+    /// labeled-response batch when the second one is written.
+    ///
+    /// Returns whether the command was handled.  When it is not, the client receives
+    /// ERR_UNKNOWNCOMMAND.
+    fn handle(&mut self, client: &ClientInfo<'_>, msg: &Message<'_>, rb: &mut ReplyBuffer) -> bool;
+}
+
+impl<F> CommandHandler for F
+where
+    F: FnMut(&ClientInfo<'_>, &Message<'_>, &mut ReplyBuffer) -> bool + Send + 'static,
+{
+    fn handle(&mut self, client: &ClientInfo<'_>, msg: &Message<'_>, rb: &mut ReplyBuffer) -> bool {
+        self(client, msg, rb)
+    }
+}
+
+/// The hooks and the command handlers registered on the state.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: Vec<Box<dyn Hook>>,
+
+    /// Command handlers, by upper-case command name.
+    commands: HashMap<String, Box<dyn CommandHandler>>,
+}
+
+impl Hooks {
+    pub fn push(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Registers the handler of `command`, replacing the previous one if any.
+    pub fn add_command(&mut self, command: &str, handler: Box<dyn CommandHandler>) {
+        self.commands.insert(command.to_ascii_uppercase(), handler);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Calls `f` on each hook, until one of them blocks the event.
+    fn run(&mut self, mut f: impl FnMut(&mut dyn Hook) -> Verdict) -> Verdict {
+        for hook in &mut self.hooks {
+            if f(hook.as_mut()) == Verdict::Block {
+                return Verdict::Block;
+            }
+        }
+        Verdict::Continue
+    }
+
+    pub fn message(&mut self, id: usize, client: &Client, msg: &Message<'_>) -> Verdict {
+        let info = ClientInfo::new(id, client);
+        self.run(|hook| hook.on_message(&info, msg))
+    }
+
+    pub fn register(&mut self, id: usize, client: &Client) -> Verdict {
+        let info = ClientInfo::new(id, client);
+        self.run(|hook| hook.on_register(&info))
+    }
+
+    pub fn join(&mut self, id: usize, client: &Client, channel: &str) -> Verdict {
+        let info = ClientInfo::new(id, client);
+        self.run(|hook| hook.on_join(&info, channel))
+    }
+
+    pub fn privmsg(
+        &mut self,
+        id: usize,
+        client: &Client,
+        command: Command,
+        target: &str,
+        text: &mut String,
+    ) -> Verdict {
+        let info = ClientInfo::new(id, client);
+        self.run(|hook| hook.on_privmsg(&info, command, target, text))
+    }
+
+    /// Runs the handler of the command `name`.  Returns false if there is none, or if it didn't
+    /// handle the command.
+    pub fn command(
+        &mut self,
+        id: usize,
+        client: &Client,
+        name: &str,
+        msg: &Message<'_>,
+        rb: &mut ReplyBuffer,
+    ) -> bool {
+        let handler = match self.commands.get_mut(&name.to_ascii_uppercase()) {
+            Some(handler) => handler,
+            None => return false,
+        };
+        handler.handle(&ClientInfo::new(id, client), msg, rb)
+    }
+}
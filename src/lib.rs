@@ -0,0 +1,57 @@
+//! ellidri, your *kawaii* IRC server.
+//!
+//! The server can be started with the `ellidri` binary, or embedded in another program with
+//! `Server::builder`.
+
+#![forbid(unsafe_code)]
+#![warn(clippy::all, rust_2018_idioms)]
+#![allow(
+    clippy::manual_filter_map,
+    clippy::manual_find_map,
+    clippy::shadow_unrelated,
+    clippy::use_self
+)]
+#![recursion_limit = "1024"]
+
+pub use crate::config::Config;
+pub use crate::control::{Server, ServerBuilder, ServerHandle};
+pub use crate::events::Event;
+pub use crate::hooks::Hook;
+
+use crate::channel::Channel;
+use crate::client::Client;
+use crate::state::State;
+
+mod accounts;
+mod announce;
+#[doc(hidden)]
+pub mod bench;
+mod chanlog;
+mod channel;
+#[doc(hidden)]
+pub mod cli;
+mod client;
+pub mod config;
+mod control;
+mod data;
+pub mod events;
+mod help;
+mod history;
+pub mod hooks;
+#[macro_use]
+mod lines;
+mod logging;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod net;
+mod otlp;
+#[cfg(test)]
+mod sim;
+mod spamfilter;
+mod state;
+mod store;
+mod tls;
+mod util;
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+mod websocket;
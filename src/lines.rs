@@ -1,4 +1,8 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Arguments;
+use std::sync::Arc;
 
 //
 // Network messages
@@ -21,12 +25,29 @@ where
     }
 }
 
+#[macro_export]
+macro_rules! lines_ping_timeout {
+    ( $seconds:expr ) => {
+        format_args!("Ping timeout: {} seconds", $seconds)
+    };
+}
+
+pub const REGISTRATION_REFUSED: &str = "Sorry senpai, you can't come in...";
+
 pub const REGISTRATION_TIMEOUT: &str = "Senpai is such a slowpoke... baka";
 
+pub const SERVER_SHUTDOWN: &str = "ellidri is going to sleep now, see you later senpai!";
+
 //
 // IRC replies
 //
 
+pub const ACCESS_ADDED: &str = "Okay! They will be welcome as they deserve";
+
+pub const ACCESS_DELETED: &str = "Okay! This mask is gone from the access list";
+
+pub const ACCESS_NOT_FOUND: &str = "This mask isn't on the access list, senpai";
+
 pub const ADMIN_ME: &str = "Administrative info";
 
 pub const ALREADY_REGISTERED: &str = "You can't re-register, dummy!";
@@ -35,20 +56,53 @@ pub const NOW_AWAY: &str = "See you later!";
 
 pub const UN_AWAY: &str = "Welcome back!";
 
+pub const AUTO_AWAY: &str = "Senpai fell asleep on the keyboard... zzz";
+
+pub const AUTO_AWAY_OFF: &str = "ellidri won't mark you away, even if you fall asleep~";
+
+pub const AUTOREPLAY_OFF: &str = "ellidri won't tell you what you missed when you join channels";
+
+pub const INVALID_AUTOREPLAY: &str = "How many messages is that supposed to be, senpai?";
+
 pub const BAD_CHAN_KEY: &str = "Whoops, guess you've entered the wrong channel key :s";
 
+pub const BAN_LIST_FULL: &str = "This list is full, senpai! Please remove some masks first";
+
 pub const BANNED_FROM_CHAN: &str = "They don't want you in here senpai...";
 
 pub const CANNOT_SEND_TO_CHAN: &str = "They can't hear you from here senpai...";
 
+pub const CANNOT_SEND_COLORS: &str = "No colors in here senpai, keep it plain!";
+
+pub const CANNOT_SEND_DCC: &str = "No file offers here senpai, share a link instead!";
+
+pub const CTCP_BLOCKED: &str = "This senpai doesn't want any CTCP, sorry~";
+
+pub const CTCP_FLOODING: &str = "So many CTCPs, senpai! Slow down a bit~";
+
+pub const CHALLENGE_FAILED: &str = "Nope, that's not it! Try again~";
+
 pub const CHAN_O_PRIVS_NEEDED: &str = "You need to ask a channel operator";
 
 pub const CHANNEL_IS_FULL: &str = "Please, this channel could not take it!";
 
+pub const CHATHISTORY_INVALID_PARAMS: &str = "ellidri doesn't understand this history request...";
+
+pub const CHATHISTORY_INVALID_TARGET: &str =
+    "You can only read the history of your channels, senpai";
+
+pub const SEARCH_INVALID_PARAMS: &str = "ellidri doesn't know what you're looking for...";
+
+pub const DLINED: &str = "Your address is not welcome on this server for now, senpai";
+
+pub const END_OF_ACCESS_LIST: &str = "End of access list";
+
 pub const END_OF_BAN_LIST: &str = "End of ban list";
 
 pub const END_OF_EXCEPT_LIST: &str = "End of except list";
 
+pub const END_OF_HELP: &str = "End of HELP";
+
 pub const END_OF_INFO: &str = "End of info";
 
 pub const END_OF_INVITE_LIST: &str = "End of invite list";
@@ -59,27 +113,106 @@ pub const END_OF_MOTD: &str = "End of MOTD";
 
 pub const END_OF_NAMES: &str = "End of names";
 
+pub const END_OF_QUIET_LIST: &str = "End of quiet list";
+
+pub const END_OF_SPAMFILTER_LIST: &str = "End of spamfilter list";
+
+pub const END_OF_STATS: &str = "End of STATS report";
+pub const END_OF_TRACE: &str = "End of TRACE";
+
+pub const END_OF_TOPIC_HISTORY: &str = "End of topic history";
+
 pub const END_OF_WHO: &str = "End of WHO list";
 
 pub const END_OF_WHOIS: &str = "End of WHOIS list";
 
 pub const ERRONEOUS_NICKNAME: &str = "Meh, this is obviously a bad nickname...";
 
+pub const FLOODING: &str = "Slow down senpai, you're flooding the channel!";
+
+pub const HELP_INDEX: &str = "Here's everything ellidri understands, senpai~";
+
+pub const HELP_NOT_FOUND: &str = "ellidri can't help you with that, sorry...";
+
+pub const HOST_HIDDEN: &str = "is now your displayed host, looking good senpai~";
+
+pub const GLINED: &str = "You are not welcome on this server anymore, senpai";
+
 pub const INPUT_TOO_LONG: &str =
     "Please wait senpai, that's too big!  If only there was one message at a time...";
 
+pub const INVALID_DURATION: &str = "How long is that supposed to be, senpai?";
+
+pub const INVALID_HOST: &str = "ellidri can't show that as a host, senpai...";
+
+pub const INVALID_LANGUAGE: &str = "ellidri doesn't speak this language, senpai...";
+
 pub const INVITE_ONLY_CHAN: &str = "They didn't invite you yet, keep trying~!";
 
+pub const JOIN_THROTTLED: &str = "Too many people are coming in at once! Try again in a bit~";
+
+pub const LANGUAGE_SET: &str = "Okay! ellidri will talk to you like this from now on~";
+
 pub const KEY_SET: &str = "The channel key is already here, senpai!";
 
+pub const LIST_TOO_OFTEN: &str = "Senpai, you just asked for the list! Try again in a bit~";
+
+pub const LOCKDOWN_CHANNELS: &str = "ellidri is in lockdown, no new channels for now!";
+
+pub const LOCKDOWN_INVALID: &str = "Senpai, the lockdown level goes from 0 to 3!";
+
+pub const REGISTERED_ONLY: &str = "This senpai only talks to logged in users, log in first~";
+
+pub const LOCKDOWN_MESSAGES: &str = "ellidri is in lockdown, log in to send private messages~";
+
+pub const LOCKDOWN_PLAINTEXT: &str = "ellidri is in lockdown, please connect with TLS, senpai";
+
+pub const METADATA_INVALID_TARGET: &str = "ellidri can't find this senpai or channel...";
+
+pub const METADATA_KEY_INVALID: &str = "This key looks weird, senpai";
+
+pub const METADATA_KEY_NOT_SET: &str = "Nothing is written there yet~";
+
+pub const METADATA_KEY_NO_PERMISSION: &str = "This isn't yours to look at, senpai!";
+
+pub const METADATA_LIMIT_REACHED: &str = "There's no room left for more keys...";
+
+pub const METADATA_TOO_MANY_SUBS: &str = "Senpai, you're following too many keys already!";
+
+pub const METADATA_VALUE_INVALID: &str = "This value is too big, ellidri can't hold it!";
+
+pub const MESSAGE_BLOCKED: &str = "Senpai, ellidri won't let this message through!";
+
 pub const NEED_MORE_PARAMS: &str = "You are not telling me everything, are you?";
 
 pub const NICKNAME_IN_USE: &str = "Another senpai already took this nickname...";
 
+pub const NO_SUCCESSOR: &str = "Nobody will inherit this channel";
+
+pub const NO_SUCH_ACCOUNT: &str = "ellidri doesn't know this account...";
+
+pub const NOT_FOUNDER: &str = "Only the founder can do that, senpai!";
+
+pub const CHANNEL_NOT_REGISTERED: &str = "This channel isn't registered, senpai";
+
+pub const CHANNEL_ALREADY_REGISTERED: &str = "This channel already has a founder, senpai";
+
+pub const CHANNEL_DROPPED: &str = "This channel is no longer registered";
+
+pub const NOT_LOGGED_IN: &str = "Log in to an account first, senpai~";
+
 pub const NO_MOTD: &str = "ellidri can't find the MOTD...";
 
+pub const NO_OPER_MOTD: &str = "There's nothing special for operators today";
+
+pub const END_OF_OPER_MOTD: &str = "End of OPERMOTD";
+
 pub const NO_TOPIC: &str = "It seems this channel doesn't have any topic";
 
+pub const NO_SUCH_TOPIC: &str = "This topic isn't in the history, senpai";
+
+pub const NO_PRIVS: &str = "Your oper block doesn't allow that, senpai";
+
 pub const NO_PRIVILEDGES: &str = "Senpai, could you stop doing that? ellidri doesn't like it...";
 
 pub const NO_SUCH_NICK: &str = "I can't find this senpai...";
@@ -88,14 +221,42 @@ pub const NO_SUCH_CHANNEL: &str = "I can't find this channel...";
 
 pub const NOT_ON_CHANNEL: &str = "Senpai... I can't do that if you're not on the channel!";
 
+pub const NOT_MUTED: &str = "This senpai isn't muted";
+
 pub const NOT_REGISTERED: &str = "You must register first!";
 
 pub const PASSWORD_MISMATCH: &str = "Nope! Wrong password";
 
+pub const OPER_NEEDS_TLS: &str = "Senpai, become an operator over TLS, not in plain text!";
+
 pub const PART_ALL: &str = "Baka!";
 
 pub const REHASHING: &str = "Oh~~!  Onwards to reload the configuration!";
 
+pub const SASL_ABORTED: &str = "Okay, let's forget about it";
+
+pub const SASL_ALREADY: &str = "Senpai, you're already logged in!";
+
+pub const SASL_FAILED: &str = "Nope! ellidri doesn't know you, senpai";
+
+pub const SASL_MECHANISMS: &str = "are the only ways ellidri knows to log you in";
+
+pub const SASL_SUCCESSFUL: &str = "Welcome back, senpai!";
+
+pub const SASL_TOO_LONG: &str = "Senpai, that's way too long for a password!";
+
+pub const SPAMFILTER_ADDED: &str = "Okay! ellidri will keep an eye out for this";
+
+pub const SPAMFILTER_DELETED: &str = "Okay! This spamfilter is gone";
+
+pub const SPAMFILTER_INVALID: &str = "Hnn... this pattern doesn't make sense";
+
+pub const SPAMFILTER_NOT_FOUND: &str = "There is no spamfilter with this pattern, senpai";
+
+pub const TOO_MANY_CHANNELS: &str = "Senpai is in so many channels already! Leave some first~";
+
+pub const TOO_MANY_CLONES: &str = "Senpai, there are too many of you here already!";
+
 pub const UNKNOWN_COMMAND: &str = "Hnn... What did you just say?";
 
 pub const UNKNOWN_MODE: &str = "This letter right here... what does it mean?";
@@ -108,12 +269,163 @@ pub const USER_ON_CHANNEL: &str = "Don't worry senpai! They're already on the ch
 
 pub const YOURE_OPER: &str = "You are now a BIG senpai!";
 
+pub const WHOIS_ACCOUNT: &str = "is logged in as";
+
+pub const WHOIS_BOT: &str = "is a bot, beep boop";
+
+pub const WHOIS_OPERATOR: &str = "is a BIG senpai!";
+
+pub const WHOIS_SECURE: &str = "is hiding behind TLS";
+
 pub const WHOIS_IDLE: &str = "Seconds since last activity, registration time";
 
+#[macro_export]
+macro_rules! lines_auto_away {
+    ( $seconds:expr ) => {
+        format_args!(
+            "ellidri will mark you away after {} seconds of silence",
+            $seconds
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_autoreplay {
+    ( $len:expr ) => {
+        format_args!(
+            "ellidri will tell you the last {} messages when you join channels",
+            $len
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_challenge {
+    ( $question:expr ) => {
+        format_args!(
+            "Before you come in, answer this with CHALLENGE <answer>: {}",
+            $question
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_mass_kill {
+    ( $count:expr, $mask:expr ) => {
+        format_args!(
+            "{} clients match {}, send MASSKILL {} CONFIRM [reason] to disconnect them",
+            $count, $mask, $mask
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_lockdown {
+    ( $level:expr ) => {
+        format_args!("The lockdown level is {} (0 to 3)", $level)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_state_dumped {
+    ( $clients:expr, $channels:expr ) => {
+        format_args!(
+            "State dumped to the server log ({} clients, {} channels)",
+            $clients, $channels
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_invite_sent {
+    ( $channel:expr, $by:expr, $who:expr ) => {
+        format_args!("[{}] {} invited {}", $channel, $by, $who)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_invite_used {
+    ( $channel:expr, $who:expr, $by:expr ) => {
+        format_args!(
+            "[{}] {} joined with the invitation of {}",
+            $channel, $who, $by
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_founder {
+    ( $channel:expr, $account:expr ) => {
+        format_args!("[{}] {} is the founder", $channel, $account)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_successor {
+    ( $channel:expr, $account:expr ) => {
+        format_args!("[{}] {} will inherit the channel", $channel, $account)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_muted {
+    ( $channel:expr, $by:expr, $who:expr, $seconds:expr ) => {
+        format_args!(
+            "[{}] {} muted {} for {} seconds",
+            $channel, $by, $who, $seconds
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_unmuted {
+    ( $channel:expr, $who:expr ) => {
+        format_args!("[{}] {} can talk again~", $channel, $who)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_offline_message_stored {
+    ( $who:expr ) => {
+        format_args!(
+            "{} is away from keyboard, ellidri will give them your message when they come back~",
+            $who
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_whois_certfp {
+    ( $certfp:expr ) => {
+        format_args!("has client certificate fingerprint {}", $certfp)
+    };
+}
+
 //
 // Welcome messages
 //
 
+#[macro_export]
+macro_rules! lines_info_build {
+    ( $version:expr, $date:expr ) => {
+        format_args!("This is {}, built on {}", $version, $date)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_info_features {
+    ( $features:expr ) => {
+        format_args!("Built with: {}", $features)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_info_admin {
+    ( $name:expr, $mail:expr ) => {
+        format_args!("Administrated by {} <{}>", $name, $mail)
+    };
+}
+
 #[macro_export]
 macro_rules! lines_your_host {
     ( $host:expr, $version:expr ) => {
@@ -146,6 +458,33 @@ macro_rules! lines_luser_me {
     };
 }
 
+#[macro_export]
+macro_rules! lines_local_users {
+    ( $users:expr, $max:expr ) => {
+        format_args!("{} senpai(s) here, and up to {} before", $users, $max)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_global_users {
+    ( $users:expr, $max:expr ) => {
+        format_args!(
+            "{} senpai(s) on the network, and up to {} before",
+            $users, $max
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_highest_connections {
+    ( $connections:expr, $users:expr ) => {
+        format_args!(
+            "ellidri had up to {} connections ({} senpai(s)) at once",
+            $connections, $users
+        )
+    };
+}
+
 pub const LUSER_OP: &str = "operator(s) online";
 
 pub const LUSER_UNKNOWN: &str = "unknown connection(s)";
@@ -157,6 +496,13 @@ macro_rules! lines_motd_start {
     };
 }
 
+#[macro_export]
+macro_rules! lines_oper_motd_start {
+    ( $domain:expr ) => {
+        format_args!("- {} message of the day for operators -", $domain)
+    };
+}
+
 #[macro_export]
 macro_rules! lines_welcome {
     ( $name:expr ) => {
@@ -171,8 +517,261 @@ macro_rules! lines_logged_in {
     };
 }
 
+//
+// Nickname ownership
+//
+
+pub const GHOST_SELF: &str = "Senpai, you can't ghost yourself!";
+
+pub const NOT_NICK_OWNER: &str =
+    "This nickname isn't yours, senpai... log in to its account first!";
+
+#[macro_export]
+macro_rules! lines_ghosted {
+    ( $by:expr ) => {
+        format_args!("Ghosted by {}", $by)
+    };
+}
+
+#[macro_export]
+macro_rules! lines_nick_owned {
+    ( $delay:expr ) => {
+        format_args!(
+            "This nickname belongs to an account.  Log in within {} seconds, or ellidri will change it~",
+            $delay
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! lines_service_help {
+    ( $command:expr, $subcommands:expr ) => {
+        format_args!("ellidri understands /{} {}", $command, $subcommands)
+    };
+}
+
 //
 // Setname
 //
 
 pub const INVALID_REALNAME: &str = "Meh, this is obviously a bad realname...";
+
+//
+// Translations
+//
+
+/// The lines of this file that can be translated, by name.
+const NAMES: &[(&str, &str)] = &[
+    ("ACCESS_ADDED", ACCESS_ADDED),
+    ("ACCESS_DELETED", ACCESS_DELETED),
+    ("ACCESS_NOT_FOUND", ACCESS_NOT_FOUND),
+    ("ADMIN_ME", ADMIN_ME),
+    ("ALREADY_REGISTERED", ALREADY_REGISTERED),
+    ("AUTO_AWAY", AUTO_AWAY),
+    ("AUTO_AWAY_OFF", AUTO_AWAY_OFF),
+    ("AUTOREPLAY_OFF", AUTOREPLAY_OFF),
+    ("BAD_CHAN_KEY", BAD_CHAN_KEY),
+    ("BAD_PASSWORD", BAD_PASSWORD),
+    ("BANNED_FROM_CHAN", BANNED_FROM_CHAN),
+    ("BAN_LIST_FULL", BAN_LIST_FULL),
+    ("CANNOT_SEND_COLORS", CANNOT_SEND_COLORS),
+    ("CANNOT_SEND_DCC", CANNOT_SEND_DCC),
+    ("CHANNEL_ALREADY_REGISTERED", CHANNEL_ALREADY_REGISTERED),
+    ("CHANNEL_DROPPED", CHANNEL_DROPPED),
+    ("CTCP_BLOCKED", CTCP_BLOCKED),
+    ("CTCP_FLOODING", CTCP_FLOODING),
+    ("CANNOT_SEND_TO_CHAN", CANNOT_SEND_TO_CHAN),
+    ("CHALLENGE_FAILED", CHALLENGE_FAILED),
+    ("CHANNEL_IS_FULL", CHANNEL_IS_FULL),
+    ("CHANNEL_NOT_REGISTERED", CHANNEL_NOT_REGISTERED),
+    ("CHAN_O_PRIVS_NEEDED", CHAN_O_PRIVS_NEEDED),
+    ("CHATHISTORY_INVALID_PARAMS", CHATHISTORY_INVALID_PARAMS),
+    ("CHATHISTORY_INVALID_TARGET", CHATHISTORY_INVALID_TARGET),
+    ("CLOSING_LINK", CLOSING_LINK),
+    ("CONNECTION_RESET", CONNECTION_RESET),
+    ("DLINED", DLINED),
+    ("END_OF_ACCESS_LIST", END_OF_ACCESS_LIST),
+    ("END_OF_BAN_LIST", END_OF_BAN_LIST),
+    ("END_OF_EXCEPT_LIST", END_OF_EXCEPT_LIST),
+    ("END_OF_HELP", END_OF_HELP),
+    ("END_OF_INFO", END_OF_INFO),
+    ("END_OF_INVITE_LIST", END_OF_INVITE_LIST),
+    ("END_OF_LIST", END_OF_LIST),
+    ("END_OF_MOTD", END_OF_MOTD),
+    ("END_OF_OPER_MOTD", END_OF_OPER_MOTD),
+    ("END_OF_NAMES", END_OF_NAMES),
+    ("END_OF_QUIET_LIST", END_OF_QUIET_LIST),
+    ("END_OF_SPAMFILTER_LIST", END_OF_SPAMFILTER_LIST),
+    ("END_OF_STATS", END_OF_STATS),
+    ("END_OF_TRACE", END_OF_TRACE),
+    ("END_OF_TOPIC_HISTORY", END_OF_TOPIC_HISTORY),
+    ("END_OF_WHO", END_OF_WHO),
+    ("END_OF_WHOIS", END_OF_WHOIS),
+    ("ERRONEOUS_NICKNAME", ERRONEOUS_NICKNAME),
+    ("FLOODING", FLOODING),
+    ("GHOST_SELF", GHOST_SELF),
+    ("GLINED", GLINED),
+    ("HELP_INDEX", HELP_INDEX),
+    ("HELP_NOT_FOUND", HELP_NOT_FOUND),
+    ("HOST_HIDDEN", HOST_HIDDEN),
+    ("INPUT_TOO_LONG", INPUT_TOO_LONG),
+    ("INVALID_AUTOREPLAY", INVALID_AUTOREPLAY),
+    ("INVALID_DURATION", INVALID_DURATION),
+    ("INVALID_HOST", INVALID_HOST),
+    ("INVALID_LANGUAGE", INVALID_LANGUAGE),
+    ("INVALID_REALNAME", INVALID_REALNAME),
+    ("INVITE_ONLY_CHAN", INVITE_ONLY_CHAN),
+    ("JOIN_THROTTLED", JOIN_THROTTLED),
+    ("I_SUPPORT", I_SUPPORT),
+    ("KEY_SET", KEY_SET),
+    ("LANGUAGE_SET", LANGUAGE_SET),
+    ("LIST_TOO_OFTEN", LIST_TOO_OFTEN),
+    ("LOCKDOWN_CHANNELS", LOCKDOWN_CHANNELS),
+    ("LOCKDOWN_INVALID", LOCKDOWN_INVALID),
+    ("LOCKDOWN_MESSAGES", LOCKDOWN_MESSAGES),
+    ("LOCKDOWN_PLAINTEXT", LOCKDOWN_PLAINTEXT),
+    ("LUSER_CHANNELS", LUSER_CHANNELS),
+    ("LUSER_OP", LUSER_OP),
+    ("LUSER_UNKNOWN", LUSER_UNKNOWN),
+    ("MESSAGE_BLOCKED", MESSAGE_BLOCKED),
+    ("METADATA_INVALID_TARGET", METADATA_INVALID_TARGET),
+    ("METADATA_KEY_INVALID", METADATA_KEY_INVALID),
+    ("METADATA_KEY_NOT_SET", METADATA_KEY_NOT_SET),
+    ("METADATA_KEY_NO_PERMISSION", METADATA_KEY_NO_PERMISSION),
+    ("METADATA_LIMIT_REACHED", METADATA_LIMIT_REACHED),
+    ("METADATA_TOO_MANY_SUBS", METADATA_TOO_MANY_SUBS),
+    ("METADATA_VALUE_INVALID", METADATA_VALUE_INVALID),
+    ("NEED_MORE_PARAMS", NEED_MORE_PARAMS),
+    ("NICKNAME_IN_USE", NICKNAME_IN_USE),
+    ("NOT_FOUNDER", NOT_FOUNDER),
+    ("NOT_LOGGED_IN", NOT_LOGGED_IN),
+    ("NOT_MUTED", NOT_MUTED),
+    ("NOT_NICK_OWNER", NOT_NICK_OWNER),
+    ("NOT_ON_CHANNEL", NOT_ON_CHANNEL),
+    ("NOT_REGISTERED", NOT_REGISTERED),
+    ("NOW_AWAY", NOW_AWAY),
+    ("NO_MOTD", NO_MOTD),
+    ("NO_OPER_MOTD", NO_OPER_MOTD),
+    ("NO_PRIVILEDGES", NO_PRIVILEDGES),
+    ("NO_PRIVS", NO_PRIVS),
+    ("NO_SUCCESSOR", NO_SUCCESSOR),
+    ("NO_SUCH_ACCOUNT", NO_SUCH_ACCOUNT),
+    ("NO_SUCH_CHANNEL", NO_SUCH_CHANNEL),
+    ("NO_SUCH_NICK", NO_SUCH_NICK),
+    ("NO_SUCH_TOPIC", NO_SUCH_TOPIC),
+    ("NO_TOPIC", NO_TOPIC),
+    ("OPER_NEEDS_TLS", OPER_NEEDS_TLS),
+    ("PART_ALL", PART_ALL),
+    ("PASSWORD_MISMATCH", PASSWORD_MISMATCH),
+    ("REGISTERED_ONLY", REGISTERED_ONLY),
+    ("REGISTRATION_REFUSED", REGISTRATION_REFUSED),
+    ("REGISTRATION_TIMEOUT", REGISTRATION_TIMEOUT),
+    ("REHASHING", REHASHING),
+    ("SASL_ABORTED", SASL_ABORTED),
+    ("SASL_ALREADY", SASL_ALREADY),
+    ("SASL_FAILED", SASL_FAILED),
+    ("SASL_MECHANISMS", SASL_MECHANISMS),
+    ("SASL_SUCCESSFUL", SASL_SUCCESSFUL),
+    ("SASL_TOO_LONG", SASL_TOO_LONG),
+    ("SEARCH_INVALID_PARAMS", SEARCH_INVALID_PARAMS),
+    ("SERVER_SHUTDOWN", SERVER_SHUTDOWN),
+    ("SPAMFILTER_ADDED", SPAMFILTER_ADDED),
+    ("SPAMFILTER_DELETED", SPAMFILTER_DELETED),
+    ("SPAMFILTER_INVALID", SPAMFILTER_INVALID),
+    ("SPAMFILTER_NOT_FOUND", SPAMFILTER_NOT_FOUND),
+    ("TOO_MANY_CHANNELS", TOO_MANY_CHANNELS),
+    ("TOO_MANY_CLONES", TOO_MANY_CLONES),
+    ("UNKNOWN_COMMAND", UNKNOWN_COMMAND),
+    ("UNKNOWN_MODE", UNKNOWN_MODE),
+    ("UN_AWAY", UN_AWAY),
+    ("USERS_DONT_MATCH", USERS_DONT_MATCH),
+    ("USER_NOT_IN_CHANNEL", USER_NOT_IN_CHANNEL),
+    ("USER_ON_CHANNEL", USER_ON_CHANNEL),
+    ("WHOIS_ACCOUNT", WHOIS_ACCOUNT),
+    ("WHOIS_BOT", WHOIS_BOT),
+    ("WHOIS_IDLE", WHOIS_IDLE),
+    ("WHOIS_OPERATOR", WHOIS_OPERATOR),
+    ("WHOIS_SECURE", WHOIS_SECURE),
+    ("YOURE_OPER", YOURE_OPER),
+];
+
+/// The translations of the lines of this file to a language.
+///
+/// Lines that are formatted with arguments are not translated yet.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// Translations, by English line.
+    lines: HashMap<&'static str, String>,
+}
+
+impl Catalog {
+    /// Reads a YAML map of line names (e.g. `NO_SUCH_NICK`) to their translation.  Unknown names
+    /// are ignored, and lines that are missing are sent in English.
+    pub fn from_yaml(contents: &str) -> serde_yaml::Result<Self> {
+        let translations: HashMap<String, String> = serde_yaml::from_str(contents)?;
+        let lines = NAMES
+            .iter()
+            .filter_map(|(name, line)| Some((*line, translations.get(*name)?.clone())))
+            .collect();
+        Ok(Self { lines })
+    }
+}
+
+thread_local! {
+    static CATALOG: RefCell<Option<Arc<Catalog>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous catalog when dropped.
+pub struct CatalogGuard(Option<Arc<Catalog>>);
+
+impl Drop for CatalogGuard {
+    fn drop(&mut self) {
+        CATALOG.with(|c| *c.borrow_mut() = self.0.take());
+    }
+}
+
+/// Makes `tr` use `catalog`, or English if `None`, until the returned guard is dropped.
+pub fn use_catalog(catalog: Option<Arc<Catalog>>) -> CatalogGuard {
+    CatalogGuard(CATALOG.with(|c| c.replace(catalog)))
+}
+
+/// Translates `line`, one of the constants of this file, with the catalog in use.
+pub fn tr(line: &'static str) -> Cow<'static, str> {
+    CATALOG.with(
+        |c| match c.borrow().as_ref().and_then(|c| c.lines.get(line)) {
+            Some(translation) => Cow::Owned(translation.clone()),
+            None => Cow::Borrowed(line),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let consts: Vec<&str> = include_str!("lines.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub const "))
+            .filter_map(|line| line.split(':').next())
+            .collect();
+        let names: Vec<&str> = NAMES.iter().map(|(name, _)| *name).collect();
+        for name in consts {
+            assert!(names.contains(&name), "{name} is missing from NAMES");
+        }
+    }
+
+    #[test]
+    fn test_tr() {
+        let catalog =
+            Catalog::from_yaml("NO_SUCH_NICK: Je ne trouve pas ce senpai...\nFOO: bar").unwrap();
+        assert_eq!(catalog.lines.len(), 1);
+        {
+            let _guard = use_catalog(Some(Arc::new(catalog)));
+            assert_eq!(tr(NO_SUCH_NICK), "Je ne trouve pas ce senpai...");
+            assert_eq!(tr(NO_SUCH_CHANNEL), NO_SUCH_CHANNEL);
+        }
+        assert_eq!(tr(NO_SUCH_NICK), NO_SUCH_NICK);
+    }
+} // mod tests
@@ -0,0 +1,373 @@
+//! Log outputs: the standard error, syslog and the systemd journal.
+//!
+//! Log messages are filtered once, by the `env_logger` filters given on the command line, and then
+//! written to each of the outputs of the configuration (`log_output`).  Syslog messages follow RFC
+//! 5424 and journal entries use the native protocol of journald, so that both carry the
+//! connection, the nickname and the host of the client being handled, if any.
+
+use crate::config::LogOutput;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The "daemon" syslog facility.
+const SYSLOG_FACILITY: u8 = 3;
+
+/// The sockets of the outputs in use.
+struct Outputs {
+    stderr: bool,
+    #[cfg(unix)]
+    syslog: Option<UnixDatagram>,
+    #[cfg(unix)]
+    journald: Option<UnixDatagram>,
+}
+
+static OUTPUTS: RwLock<Outputs> = RwLock::new(Outputs {
+    stderr: true,
+    #[cfg(unix)]
+    syslog: None,
+    #[cfg(unix)]
+    journald: None,
+});
+
+/// Whether the context of log messages is needed, that is, whether syslog or journald is in use.
+static WANTS_CONTEXT: AtomicBool = AtomicBool::new(false);
+
+/// The client being handled by the current thread.
+struct Context {
+    id: usize,
+    nick: String,
+    host: String,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Clears the context of log messages when dropped.
+pub struct ContextGuard(bool);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            CONTEXT.with(|c| *c.borrow_mut() = None);
+        }
+    }
+}
+
+/// Attaches the given client to the log messages of the current thread, until the returned guard
+/// is dropped.  Does nothing when only the standard error is used.
+pub fn set_context(id: usize, nick: &str, host: &str) -> ContextGuard {
+    if !WANTS_CONTEXT.load(Ordering::Relaxed) {
+        return ContextGuard(false);
+    }
+    CONTEXT.with(|c| {
+        *c.borrow_mut() = Some(Context {
+            id,
+            nick: nick.to_owned(),
+            host: host.to_owned(),
+        });
+    });
+    ContextGuard(true)
+}
+
+struct Logger(env_logger::Logger);
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.0.matches(record) {
+            return;
+        }
+        let outputs = match OUTPUTS.read() {
+            Ok(outputs) => outputs,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if outputs.stderr {
+            self.0.log(record);
+        }
+        #[cfg(unix)]
+        {
+            if let Some(ref socket) = outputs.syslog {
+                let _ = CONTEXT.with(|c| socket.send(&syslog_message(record, &c.borrow())));
+            }
+            if let Some(ref socket) = outputs.journald {
+                let _ = CONTEXT.with(|c| socket.send(&journald_message(record, &c.borrow())));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Installs `logger` as the global logger.  Messages are written to the standard error until
+/// `set_outputs` is called.
+pub fn init(logger: env_logger::Logger) {
+    let max_level = logger.filter();
+    if log::set_boxed_logger(Box::new(Logger(logger))).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Changes the outputs of log messages.
+///
+/// Outputs that cannot be opened are skipped, and messages go to the standard error if none is
+/// left.
+pub fn set_outputs(outputs: &[LogOutput]) {
+    set_outputs_at(outputs, SYSLOG_SOCKET, JOURNALD_SOCKET);
+}
+
+/// Same as `set_outputs`, with the paths of the sockets of syslog and journald.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn set_outputs_at(outputs: &[LogOutput], syslog_socket: &str, journald_socket: &str) {
+    let mut new = Outputs {
+        stderr: outputs.contains(&LogOutput::Stderr),
+        #[cfg(unix)]
+        syslog: None,
+        #[cfg(unix)]
+        journald: None,
+    };
+    let mut failed = Vec::new();
+    for output in outputs {
+        match output {
+            LogOutput::Stderr => {}
+            #[cfg(unix)]
+            LogOutput::Syslog => match connect(syslog_socket) {
+                Ok(socket) => new.syslog = Some(socket),
+                Err(err) => failed.push(format!("syslog ({syslog_socket}: {err})")),
+            },
+            #[cfg(unix)]
+            LogOutput::Journald => match connect(journald_socket) {
+                Ok(socket) => new.journald = Some(socket),
+                Err(err) => failed.push(format!("journald ({journald_socket}: {err})")),
+            },
+            #[cfg(not(unix))]
+            other => failed.push(format!("{other:?} (not supported on this platform)")),
+        }
+    }
+
+    #[cfg(unix)]
+    let wants_context = new.syslog.is_some() || new.journald.is_some();
+    #[cfg(not(unix))]
+    let wants_context = false;
+    if !wants_context {
+        new.stderr = true;
+    }
+    WANTS_CONTEXT.store(wants_context, Ordering::Relaxed);
+
+    match OUTPUTS.write() {
+        Ok(mut outputs) => *outputs = new,
+        Err(poisoned) => *poisoned.into_inner() = new,
+    }
+    for output in failed {
+        log::warn!("Cannot log to {}", output);
+    }
+}
+
+#[cfg(unix)]
+fn connect(path: &str) -> std::io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+/// The syslog severity of `level`.
+fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// Formats `record` as an RFC 5424 message.  The context, if any, is sent as structured data.
+fn syslog_message(record: &log::Record<'_>, context: &Option<Context>) -> Vec<u8> {
+    let pri = SYSLOG_FACILITY * 8 + severity(record.level());
+    // Timestamp and hostname are left for the daemon to fill.  ellidri has no enterprise number,
+    // so its structured data uses the one reserved for examples (RFC 5612).
+    let mut msg = format!("<{}>1 - - ellidri {} - ", pri, std::process::id());
+    match context {
+        Some(context) => {
+            let _ = write!(
+                msg,
+                "[irc@32473 connection=\"{}\" nick=\"{}\" host=\"{}\"]",
+                context.id,
+                escape_param(&context.nick),
+                escape_param(&context.host)
+            );
+        }
+        None => msg.push('-'),
+    }
+    let _ = write!(msg, " {}: {}", record.target(), record.args());
+    msg.into_bytes()
+}
+
+/// Escapes the characters that cannot appear in the value of an RFC 5424 parameter.
+fn escape_param(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Formats `record` as a journal entry of the native protocol of journald.
+fn journald_message(record: &log::Record<'_>, context: &Option<Context>) -> Vec<u8> {
+    let mut msg = Vec::new();
+    journald_field(&mut msg, "MESSAGE", &record.args().to_string());
+    journald_field(&mut msg, "PRIORITY", &severity(record.level()).to_string());
+    journald_field(&mut msg, "SYSLOG_IDENTIFIER", "ellidri");
+    journald_field(&mut msg, "TARGET", record.target());
+    if let Some(context) = context {
+        journald_field(&mut msg, "IRC_CONNECTION", &context.id.to_string());
+        journald_field(&mut msg, "IRC_NICK", &context.nick);
+        journald_field(&mut msg, "IRC_HOST", &context.host);
+    }
+    msg
+}
+
+/// Appends `KEY=value\n`, or the binary form of the field if `value` spans several lines.
+fn journald_field(msg: &mut Vec<u8>, key: &str, value: &str) {
+    msg.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        msg.push(b'\n');
+        msg.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        msg.push(b'=');
+    }
+    msg.extend_from_slice(value.as_bytes());
+    msg.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let context = Some(Context {
+            id: 4,
+            nick: String::from("a\"b"),
+            host: String::from("::1"),
+        });
+        let record = |f: &dyn Fn(&log::Record<'_>)| {
+            f(&log::Record::builder()
+                .args(format_args!("hello\nworld"))
+                .level(log::Level::Warn)
+                .target("ellidri::state")
+                .build());
+        };
+
+        record(&|r| {
+            let msg = String::from_utf8(syslog_message(r, &context)).unwrap();
+            assert_eq!(
+                msg,
+                format!(
+                    "<28>1 - - ellidri {} - [irc@32473 connection=\"4\" nick=\"a\\\"b\" \
+                     host=\"::1\"] ellidri::state: hello\nworld",
+                    std::process::id()
+                )
+            );
+        });
+        record(&|r| {
+            let msg = journald_message(r, &None);
+            let mut expected = b"MESSAGE\n\x0b\0\0\0\0\0\0\0hello\nworld\n".to_vec();
+            expected.extend_from_slice(
+                b"PRIORITY=4\nSYSLOG_IDENTIFIER=ellidri\nTARGET=ellidri::state\n",
+            );
+            assert_eq!(msg, expected);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_outputs() {
+        let dir = std::env::temp_dir().join(format!("ellidri-logging-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let syslog_path = dir.join("syslog.sock");
+        let journald_path = dir.join("journald.sock");
+        let syslog = UnixDatagram::bind(&syslog_path).unwrap();
+        let journald = UnixDatagram::bind(&journald_path).unwrap();
+        syslog.set_nonblocking(true).unwrap();
+        journald.set_nonblocking(true).unwrap();
+        let recv = |socket: &UnixDatagram| {
+            let mut buf = [0; 1024];
+            let n = socket.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        };
+
+        let logger = Logger(env_logger::Builder::new().parse_filters("info").build());
+        let log = |level, msg: &str| {
+            log::Log::log(
+                &logger,
+                &log::Record::builder()
+                    .args(format_args!("{msg}"))
+                    .level(level)
+                    .target("ellidri::state")
+                    .build(),
+            );
+        };
+
+        set_outputs_at(
+            &[LogOutput::Syslog, LogOutput::Journald],
+            syslog_path.to_str().unwrap(),
+            journald_path.to_str().unwrap(),
+        );
+        assert!(!OUTPUTS.read().unwrap().stderr);
+        {
+            let _context = set_context(7, "alice", "127.0.0.1");
+            log(log::Level::Info, "registered");
+            log(log::Level::Debug, "filtered out");
+        }
+        log(log::Level::Error, "no context");
+
+        let msg = recv(&syslog);
+        assert!(msg.starts_with("<30>1 - - ellidri "), "{msg}");
+        assert!(
+            msg.ends_with(
+                " [irc@32473 connection=\"7\" nick=\"alice\" host=\"127.0.0.1\"] \
+                 ellidri::state: registered"
+            ),
+            "{msg}"
+        );
+        let msg = recv(&syslog);
+        assert!(msg.starts_with("<27>1 ") && msg.ends_with(" - ellidri::state: no context"));
+        assert!(syslog.recv(&mut [0; 16]).is_err());
+
+        let msg = recv(&journald);
+        assert!(msg.starts_with("MESSAGE=registered\nPRIORITY=6\n"), "{msg}");
+        assert!(msg.ends_with("IRC_CONNECTION=7\nIRC_NICK=alice\nIRC_HOST=127.0.0.1\n"));
+        let msg = recv(&journald);
+        assert!(msg.starts_with("MESSAGE=no context\nPRIORITY=3\n") && !msg.contains("IRC_"));
+        assert!(journald.recv(&mut [0; 16]).is_err());
+
+        // Messages go to the standard error when no other output can be opened.
+        std::fs::remove_dir_all(&dir).unwrap();
+        set_outputs_at(
+            &[LogOutput::Syslog],
+            syslog_path.to_str().unwrap(),
+            journald_path.to_str().unwrap(),
+        );
+        assert!(OUTPUTS.read().unwrap().stderr);
+        assert!(OUTPUTS.read().unwrap().syslog.is_none());
+        assert!(!WANTS_CONTEXT.load(Ordering::Relaxed));
+    }
+} // mod tests
@@ -0,0 +1,36 @@
+//! Key/value metadata of users and channels.
+//!
+//! See the draft/metadata-2 specification: <https://ircv3.net/specs/extensions/metadata>
+
+use std::collections::BTreeMap;
+
+/// Metadata of a user or a channel, by key.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Whether `key` can be used as a metadata key.
+///
+/// Keys are made of lowercase letters, digits and the characters `_./-`, and cannot start with a
+/// dash.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('-')
+        && key
+            .bytes()
+            .all(|c| matches!(c, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'.' | b'/' | b'-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("avatar"));
+        assert!(is_valid_key("display-name"));
+        assert!(is_valid_key("example.org/pronouns_2"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("-avatar"));
+        assert!(!is_valid_key("Avatar"));
+        assert!(!is_valid_key("a b"));
+    }
+} // mod tests
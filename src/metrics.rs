@@ -0,0 +1,106 @@
+//! Per-command counters and latency histograms, enabled with the `metrics` feature.
+//!
+//! IRC operators can read them with `STATS m`.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets, in microseconds.  The last bucket has no upper bound.
+const BUCKETS: [u64; 11] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000,
+];
+
+/// How many times a command has been handled, and how long it took.
+#[derive(Default)]
+pub struct CommandStats {
+    count: u64,
+    total: Duration,
+    max: Duration,
+    buckets: [u64; BUCKETS.len() + 1],
+}
+
+impl CommandStats {
+    fn record(&mut self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean latency, in microseconds.
+    pub fn mean(&self) -> u128 {
+        if self.count == 0 {
+            return 0;
+        }
+        self.total.as_micros() / u128::from(self.count)
+    }
+
+    /// An upper bound of the latency under which `q` (between 0 and 1) of the calls have been
+    /// handled, in microseconds.
+    pub fn quantile(&self, q: f64) -> u128 {
+        let max = self.max.as_micros();
+        let rank = (q * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if rank <= seen && bucket < BUCKETS.len() {
+                return u128::from(BUCKETS[bucket]).min(max);
+            }
+        }
+        max
+    }
+}
+
+/// Statistics of all the commands handled by the server, by command name.
+#[derive(Default)]
+pub struct Metrics {
+    commands: BTreeMap<&'static str, CommandStats>,
+}
+
+impl Metrics {
+    pub fn record(&mut self, command: &'static str, elapsed: Duration) {
+        self.commands.entry(command).or_default().record(elapsed);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &CommandStats)> {
+        self.commands
+            .iter()
+            .map(|(command, stats)| (*command, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_stats() {
+        let mut metrics = Metrics::default();
+        for micros in [10, 20, 30, 40, 60, 70, 80, 90, 300, 200_000] {
+            metrics.record("PRIVMSG", Duration::from_micros(micros));
+        }
+        metrics.record("JOIN", Duration::from_micros(400));
+
+        let (command, stats) = metrics.iter().nth(1).unwrap();
+        assert_eq!(command, "PRIVMSG");
+        assert_eq!(stats.count(), 10);
+        assert_eq!(stats.mean(), 20_070);
+        assert_eq!(stats.quantile(0.4), 50);
+        assert_eq!(stats.quantile(0.8), 100);
+        assert_eq!(stats.quantile(0.9), 500);
+        assert_eq!(stats.quantile(1.0), 200_000);
+
+        let (command, stats) = metrics.iter().next().unwrap();
+        assert_eq!(command, "JOIN");
+        assert_eq!(stats.quantile(0.5), 400);
+    }
+} // mod tests
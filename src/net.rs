@@ -1,24 +1,99 @@
+use crate::config::{BindingOptions, SocketOptions};
 use crate::{control, lines, tls, State};
+use ellidri_tokens::codec::{Frame, IrcCodec};
 use ellidri_tokens::Message;
 use std::net::SocketAddr;
 use std::str;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::{io, net, sync, time};
+use tokio_stream::StreamExt as _;
+use tokio_util::codec::FramedRead;
 
 #[cfg(feature = "tls")]
 const TLS_TIMEOUT_SECS: u64 = 30;
-const MAX_MESSAGE_LENGTH: u64 = 4096;
+
+/// Time given to clients to send their first bytes, on TLS bindings that also accept plain-text
+/// connections.
+const DETECT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The first byte of TLS handshake records, such as the ClientHello that starts TLS connections.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Creates a listener bound to `addr`.
+fn bind(addr: SocketAddr, options: SocketOptions) -> io::Result<net::TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(options.v6only)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(options.reuseport)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    net::TcpListener::from_std(socket.into())
+}
+
+/// Enables TCP keepalive on `conn`, with probes after `secs` seconds of inactivity.
+///
+/// A failure to set keepalive is only logged, and the connection is handed back.  The connection
+/// goes through `std` because socket2 cannot borrow tokio's `TcpStream` directly, so the returned
+/// error is about handing it back to tokio.
+fn set_keepalive(
+    conn: net::TcpStream,
+    peer_addr: SocketAddr,
+    secs: u64,
+) -> io::Result<net::TcpStream> {
+    let time = time::Duration::from_secs(secs);
+    let keepalive = socket2::TcpKeepalive::new().with_time(time);
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        windows,
+    ))]
+    let keepalive = keepalive.with_interval(time);
+    let conn = conn.into_std()?;
+    if let Err(err) = socket2::SockRef::from(&conn).set_tcp_keepalive(&keepalive) {
+        log::warn!("Failed to set TCP keepalive for {}: {}", peer_addr, err);
+    }
+    net::TcpStream::from_std(conn)
+}
+
+/// Applies the TCP options of the binding to the accepted connection `conn`.
+fn set_socket_options(
+    conn: net::TcpStream,
+    peer_addr: SocketAddr,
+    options: &BindingOptions,
+) -> io::Result<net::TcpStream> {
+    if let Err(err) = conn.set_nodelay(options.nodelay) {
+        log::warn!("Failed to set TCP_NODELAY for {}: {}", peer_addr, err);
+    }
+    if options.keepalive == 0 {
+        return Ok(conn);
+    }
+    set_keepalive(conn, peer_addr, options.keepalive)
+}
 
 /// Returns a future that listens, accepts and handles incoming connections.
 pub async fn listen(
     addr: SocketAddr,
+    socket: SocketOptions,
+    options: BindingOptions,
     shared: State,
     mut acceptor: Option<tls::Acceptor>,
     stop: mpsc::Sender<SocketAddr>,
     mut commands: mpsc::Receiver<control::Command>,
 ) {
-    let ln = match net::TcpListener::bind(&addr).await {
+    let mut options = Arc::new(options);
+    let ln = match bind(addr, socket) {
         Ok(ln) => ln,
         Err(err) => {
             log::error!("Binding {} failed to come online: {}", addr, err);
@@ -27,7 +102,12 @@ pub async fn listen(
         }
     };
 
-    if acceptor.is_some() {
+    if acceptor.is_some() && options.plaintext {
+        log::info!(
+            "Binding {} online, accepting TLS and plain-text connections",
+            addr
+        );
+    } else if acceptor.is_some() {
         log::info!("Binding {} online, accepting TLS connections", addr);
     } else {
         log::info!("Binding {} online, accepting plain-text connections", addr);
@@ -36,9 +116,22 @@ pub async fn listen(
     loop {
         tokio::select! {
             maybe_conn = ln.accept() => match maybe_conn {
-                Ok((conn, peer_addr)) => match acceptor.as_ref() {
-                    Some(a) => handle_tls(conn, peer_addr, shared.clone(), a.clone()),
-                    None => handle_tcp(conn, peer_addr, shared.clone()),
+                Ok((conn, peer_addr)) => {
+                    let conn = match set_socket_options(conn, peer_addr, &options) {
+                        Ok(conn) => conn,
+                        Err(err) => {
+                            log::warn!("Failed to register {} after setting TCP keepalive: {}", peer_addr, err);
+                            continue;
+                        }
+                    };
+                    let options = options.clone();
+                    match acceptor.as_ref() {
+                        Some(a) if options.plaintext => {
+                            handle_any(conn, peer_addr, options, shared.clone(), a.clone());
+                        }
+                        Some(a) => handle_tls(conn, peer_addr, options, shared.clone(), a.clone()),
+                        None => handle_tcp(conn, peer_addr, options, shared.clone()),
+                    }
                 }
                 Err(err) => log::warn!("Binding {} failed to accept a connection: {}", addr, err),
             },
@@ -57,6 +150,9 @@ pub async fn listen(
                     }
                     acceptor = Some(a);
                 }
+                Some(control::Command::SetOptions(new_options)) => {
+                    options = Arc::new(new_options);
+                }
                 None => {
                     log::info!("Binding {} now offline", addr);
                     return;
@@ -66,24 +162,112 @@ pub async fn listen(
     }
 }
 
-fn handle_tcp(conn: net::TcpStream, peer_addr: SocketAddr, shared: State) {
-    tokio::spawn(handle(conn, peer_addr, shared));
+fn handle_tcp(
+    conn: net::TcpStream,
+    peer_addr: SocketAddr,
+    options: Arc<BindingOptions>,
+    shared: State,
+) {
+    tokio::spawn(handle(conn, peer_addr, options, false, None, shared));
 }
 
 #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
-fn handle_tls(conn: net::TcpStream, peer_addr: SocketAddr, shared: State, acceptor: tls::Acceptor) {
+fn handle_tls(
+    conn: net::TcpStream,
+    peer_addr: SocketAddr,
+    options: Arc<BindingOptions>,
+    shared: State,
+    acceptor: tls::Acceptor,
+) {
     #[cfg(feature = "tls")]
     tokio::spawn(async move {
         let tls_handshake_timeout = time::Duration::from_secs(TLS_TIMEOUT_SECS);
         let tls_handshake = time::timeout(tls_handshake_timeout, acceptor.accept(conn));
         match tls_handshake.await {
-            Ok(Ok(tls_conn)) => handle(tls_conn, peer_addr, shared).await,
+            Ok(Ok(tls_conn)) => {
+                let session = tls_conn.get_ref().1;
+                let certfp = session
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .map(|cert| tls::fingerprint(&cert.0));
+                // Dispatch on the protocol negotiated with ALPN.  Clients that don't use ALPN
+                // speak IRC.
+                match session.alpn_protocol() {
+                    None | Some(tls::ALPN_IRC) => {
+                        handle(tls_conn, peer_addr, options, true, certfp, shared).await;
+                    }
+                    Some(tls::ALPN_HTTP) => {
+                        handle_websocket(tls_conn, peer_addr, options, true, certfp, shared).await;
+                    }
+                    Some(protocol) => log::warn!(
+                        "{} negotiated an unsupported protocol: {}",
+                        peer_addr,
+                        String::from_utf8_lossy(protocol)
+                    ),
+                }
+            }
             Ok(Err(err)) => log::warn!("TLS handshake with {} failed: {}", peer_addr, err),
             Err(_) => log::warn!("TLS handshake with {} timed out", peer_addr),
         }
     });
 }
 
+/// Handles a connection on a TLS binding that also accepts plain-text connections.  TLS is used
+/// when the first byte sent by the client starts a handshake record, which plain-text IRC never
+/// does.
+fn handle_any(
+    conn: net::TcpStream,
+    peer_addr: SocketAddr,
+    options: Arc<BindingOptions>,
+    shared: State,
+    acceptor: tls::Acceptor,
+) {
+    tokio::spawn(async move {
+        let mut first = [0];
+        match time::timeout(DETECT_TIMEOUT, conn.peek(&mut first)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(_)) if first[0] == TLS_HANDSHAKE_RECORD => {
+                handle_tls(conn, peer_addr, options, shared, acceptor);
+            }
+            // Clients that send nothing are left to the login timeout.
+            Ok(Ok(_)) | Err(_) => handle_tcp(conn, peer_addr, options, shared),
+            Ok(Err(err)) => log::warn!("Failed to read from {}: {}", peer_addr, err),
+        }
+    });
+}
+
+/// Handles an IRC over WebSocket connection, once its HTTP request is read.
+#[cfg(feature = "tls")]
+async fn handle_websocket(
+    conn: impl io::AsyncRead + io::AsyncWrite + Unpin,
+    peer_addr: SocketAddr,
+    options: Arc<BindingOptions>,
+    tls: bool,
+    certfp: Option<String>,
+    shared: State,
+) {
+    let mut conn = io::BufReader::new(conn);
+    let binary = match time::timeout(DETECT_TIMEOUT, crate::websocket::accept(&mut conn)).await {
+        Ok(Ok(binary)) => binary,
+        Ok(Err(err)) => {
+            log::warn!("WebSocket handshake with {} failed: {}", peer_addr, err);
+            return;
+        }
+        Err(_) => {
+            log::warn!("WebSocket handshake with {} timed out", peer_addr);
+            return;
+        }
+    };
+    let (irc, bridge) = io::duplex(crate::websocket::MAX_MESSAGE_LEN);
+    let irc = handle(irc, peer_addr, options, tls, certfp, shared);
+    let bridge = async {
+        if let Err(err) = crate::websocket::bridge(conn, bridge, binary).await {
+            log::debug!("WebSocket connection with {} failed: {}", peer_addr, err);
+        }
+    };
+    tokio::join!(irc, bridge);
+}
+
 macro_rules! rate_limit {
     ( $rate:expr, $burst:expr, $do:expr ) => {{
         let rate: u32 = $rate;
@@ -102,8 +286,8 @@ macro_rules! rate_limit {
             if burst < used_points {
                 let elapsed = last_round.elapsed();
                 let millis = elapsed.as_millis();
-                let millis = if (std::u32::MAX as u128) < millis {
-                    std::u32::MAX
+                let millis = if (u32::MAX as u128) < millis {
+                    u32::MAX
                 } else {
                     millis as u32
                 };
@@ -124,30 +308,48 @@ macro_rules! rate_limit {
 }
 
 /// Returns a future that handles an IRC connection.
-async fn handle(conn: impl io::AsyncRead + io::AsyncWrite, peer_addr: SocketAddr, shared: State) {
+pub(crate) async fn handle(
+    conn: impl io::AsyncRead + io::AsyncWrite,
+    peer_addr: SocketAddr,
+    options: Arc<BindingOptions>,
+    tls: bool,
+    certfp: Option<String>,
+    shared: State,
+) {
     let (reader, mut writer) = io::split(conn);
-    let mut reader = io::BufReader::new(reader);
+    let codec = IrcCodec::with_message_length(shared.linelen().await);
+    let mut frames = FramedRead::new(reader, codec);
 
     let (msg_queue, mut outgoing_msgs) = sync::mpsc::unbounded_channel();
-    let peer_id = shared.peer_joined(peer_addr, msg_queue).await;
+    let peer_id = shared
+        .peer_joined(peer_addr, &options, tls, certfp, msg_queue)
+        .await;
     tokio::spawn(login_timeout(peer_id, shared.clone()));
 
+    // Milliseconds between `connected_at` and the last line received from the client.
+    let connected_at = time::Instant::now();
+    let last_read = AtomicU64::new(0);
+
     let incoming = async {
-        let mut buf = String::new();
         rate_limit!(125, 32, async {
-            buf.clear();
-            let n = (&mut reader)
-                .take(MAX_MESSAGE_LENGTH)
-                .read_line(&mut buf)
-                .await?;
-            if n == 0 {
-                return Err(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    lines::CONNECTION_RESET,
-                ));
-            }
-            log::trace!("{} >> {}", peer_addr, buf.trim());
-            Ok(handle_buffer(peer_id, &buf, &shared).await)
+            let line = match frames.next().await.transpose()? {
+                Some(Frame::Line(line)) => line,
+                Some(Frame::TooLong) => {
+                    log::debug!("{} >> Line too long", peer_addr);
+                    shared.input_too_long(peer_id).await;
+                    return Ok(3);
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        lines::CONNECTION_RESET,
+                    ));
+                }
+            };
+            log::trace!("{} >> {}", peer_addr, line);
+            let millis = connected_at.elapsed().as_millis();
+            last_read.store(u64::try_from(millis).unwrap_or(u64::MAX), Ordering::Relaxed);
+            Ok(handle_buffer(peer_id, &line, &shared).await)
         })
     };
 
@@ -160,10 +362,54 @@ async fn handle(conn: impl io::AsyncRead + io::AsyncWrite, peer_addr: SocketAddr
         Ok(())
     };
 
+    let pinger = async {
+        let mut timeouts = shared.ping_timeouts().await;
+        let mut pinged = false;
+        loop {
+            let (interval, timeout) = *timeouts.borrow_and_update();
+            let wait = if interval == 0 {
+                None
+            } else {
+                let interval = time::Duration::from_secs(interval);
+                let timeout = time::Duration::from_secs(timeout);
+                let last_read = time::Duration::from_millis(last_read.load(Ordering::Relaxed));
+                let idle = connected_at.elapsed().saturating_sub(last_read);
+                if idle < interval {
+                    pinged = false;
+                    Some(interval - idle)
+                } else if interval + timeout <= idle {
+                    let reason = lines_ping_timeout!(idle.as_secs()).to_string();
+                    return Err::<(), _>(io::Error::new(io::ErrorKind::TimedOut, reason));
+                } else {
+                    if !pinged {
+                        shared.send_ping(peer_id).await;
+                        pinged = true;
+                    }
+                    Some(interval + timeout - idle)
+                }
+            };
+            // Sleep until the next check, or until a rehash changes the timeouts.
+            tokio::select! {
+                () = async {
+                    match wait {
+                        Some(wait) => time::sleep(wait).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+                res = timeouts.changed() => {
+                    if res.is_err() {
+                        std::future::pending::<()>().await;
+                    }
+                }
+            }
+        }
+    };
+
     let res: Option<io::Error>;
     tokio::select! {
         r = incoming => res = r.err(),
         r = outgoing => res = r.err(),
+        r = pinger => res = r.err(),
     }
 
     shared.peer_quit(peer_id, res).await;
@@ -171,8 +417,7 @@ async fn handle(conn: impl io::AsyncRead + io::AsyncWrite, peer_addr: SocketAddr
 
 /// Handle a line from the client.
 ///
-/// Returns `None` if the connection must be closed, `Some(points)` otherwise.  Points are used for
-/// rate limits.
+/// Returns the points used by the line, for rate limits.
 async fn handle_buffer(peer_id: usize, buf: &str, shared: &State) -> u32 {
     if let Some(msg) = Message::parse(buf) {
         return shared.handle_message(peer_id, msg).await;
@@ -185,3 +430,61 @@ async fn login_timeout(peer_id: usize, shared: State) {
     time::sleep(time::Duration::from_millis(timeout)).await;
     shared.remove_if_unregistered(peer_id).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim;
+
+    #[test]
+    fn test_bind_v6only() {
+        sim::run(async {
+            for v6only in [false, true] {
+                let options = SocketOptions {
+                    v6only,
+                    reuseport: false,
+                    backlog: 16,
+                };
+                let ln = match bind(SocketAddr::from(([0u16; 8], 0)), options) {
+                    Ok(ln) => ln,
+                    // The host has no IPv6 support.
+                    Err(_) => return,
+                };
+                let ln = ln.into_std().unwrap();
+                assert_eq!(socket2::SockRef::from(&ln).only_v6().unwrap(), v6only);
+            }
+        });
+    }
+
+    #[test]
+    fn test_set_socket_options() {
+        sim::run(async {
+            let options = SocketOptions {
+                v6only: false,
+                reuseport: false,
+                backlog: 16,
+            };
+            let ln = bind(SocketAddr::from(([127, 0, 0, 1], 0)), options).unwrap();
+            let addr = ln.local_addr().unwrap();
+            for (nodelay, keepalive) in [(false, 0), (true, 30)] {
+                let _client = net::TcpStream::connect(addr).await.unwrap();
+                let (conn, peer_addr) = ln.accept().await.unwrap();
+                let options = BindingOptions {
+                    nodelay,
+                    keepalive,
+                    ..BindingOptions::default()
+                };
+                let conn = set_socket_options(conn, peer_addr, &options).unwrap();
+                assert_eq!(conn.nodelay().unwrap(), nodelay);
+                let conn = conn.into_std().unwrap();
+                let sock = socket2::SockRef::from(&conn);
+                assert_eq!(sock.keepalive().unwrap(), keepalive != 0);
+                #[cfg(target_os = "linux")]
+                if keepalive != 0 {
+                    let time = time::Duration::from_secs(keepalive);
+                    assert_eq!(sock.keepalive_time().unwrap(), time);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,312 @@
+//! Export of traces to an OpenTelemetry collector, over OTLP/HTTP with JSON encoding.
+//!
+//! Each connection is a trace, whose root span lasts from the moment the connection is accepted to
+//! the moment it is closed.  Commands sent by the connection are the children of this span.
+//!
+//! Spans are sent in batches to `<otlp_endpoint>/v1/traces` by a dedicated task, so that the
+//! state is never blocked on the collector.
+
+use crate::util;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::{io, net, time};
+
+/// How often spans are sent to the collector.
+const BATCH_DELAY: Duration = Duration::from_secs(5);
+
+/// Spans are sent before `BATCH_DELAY` when that many are waiting.
+const MAX_BATCH_LEN: usize = 512;
+
+/// How long a request to the collector can take.
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// The address of the collector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Endpoint {
+    /// "host:port", to connect to.
+    addr: String,
+    /// "host", for the Host header.
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parses "http://host[:port][/path]".  The port defaults to 4318.
+    fn parse(endpoint: &str) -> Option<Self> {
+        let rest = endpoint.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+        let has_port = authority
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let addr = if has_port {
+            authority.to_owned()
+        } else {
+            format!("{authority}:4318")
+        };
+        Some(Self {
+            addr,
+            host: authority.to_owned(),
+            path: format!("{}/v1/traces", path.trim_end_matches('/')),
+        })
+    }
+}
+
+struct Span {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: String,
+    name: String,
+    kind: u8,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<Value>,
+}
+
+impl Span {
+    fn to_json(&self) -> Value {
+        json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "parentSpanId": self.parent_span_id,
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": self.attributes,
+        })
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let nanos = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    nanos.to_string()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        other => json!({ "stringValue": other.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// The spans of the connections that are open, and the queue to the exporter task.
+#[derive(Default)]
+pub struct Tracer {
+    queue: Option<mpsc::UnboundedSender<Span>>,
+    endpoint: Option<Endpoint>,
+    connections: HashMap<usize, Span>,
+}
+
+impl Tracer {
+    /// Starts exporting spans to `endpoint`, or stops if it is empty.
+    ///
+    /// Nothing is changed if the endpoint is the same as before.
+    pub fn set_endpoint(&mut self, endpoint: &str) {
+        let endpoint = match endpoint {
+            "" => None,
+            endpoint => match Endpoint::parse(endpoint) {
+                Some(endpoint) => Some(endpoint),
+                None => {
+                    log::warn!("Invalid otlp_endpoint {:?}, traces are disabled", endpoint);
+                    None
+                }
+            },
+        };
+        if endpoint == self.endpoint {
+            return;
+        }
+        self.queue = endpoint.clone().map(|endpoint| {
+            let (queue, spans) = mpsc::unbounded_channel();
+            tokio::spawn(export_spans(endpoint, spans));
+            queue
+        });
+        self.endpoint = endpoint;
+        if self.queue.is_none() {
+            self.connections.clear();
+        }
+    }
+
+    /// Starts the trace of a new connection.
+    pub fn connection_opened(&mut self, id: usize, addr: SocketAddr, tls: bool) {
+        if self.queue.is_none() {
+            return;
+        }
+        let now = SystemTime::now();
+        self.connections.insert(
+            id,
+            Span {
+                trace_id: util::random_hex(16),
+                span_id: util::random_hex(8),
+                parent_span_id: String::new(),
+                name: String::from("connection"),
+                kind: SPAN_KIND_SERVER,
+                start: now,
+                end: now,
+                attributes: vec![
+                    attribute("net.peer.ip", json!(addr.ip().to_string())),
+                    attribute("net.peer.port", json!(addr.port())),
+                    attribute("ellidri.tls", json!(tls)),
+                ],
+            },
+        );
+    }
+
+    /// Records the nickname the connection has registered with.
+    pub fn connection_registered(&mut self, id: usize, nick: &str) {
+        if let Some(span) = self.connections.get_mut(&id) {
+            span.attributes.push(attribute("irc.nick", json!(nick)));
+        }
+    }
+
+    /// Ends the trace of a connection.
+    pub fn connection_closed(&mut self, id: usize, reason: Option<&str>) {
+        let (queue, mut span) = match (&self.queue, self.connections.remove(&id)) {
+            (Some(queue), Some(span)) => (queue, span),
+            _ => return,
+        };
+        span.end = SystemTime::now();
+        if let Some(reason) = reason {
+            span.attributes
+                .push(attribute("ellidri.close_reason", json!(reason)));
+        }
+        let _ = queue.send(span);
+    }
+
+    /// Records the handling of a command sent by the given connection.
+    pub fn command(&self, id: usize, command: &str, start: SystemTime, elapsed: Duration) {
+        let (queue, connection) = match (&self.queue, self.connections.get(&id)) {
+            (Some(queue), Some(connection)) => (queue, connection),
+            _ => return,
+        };
+        let _ = queue.send(Span {
+            trace_id: connection.trace_id.clone(),
+            span_id: util::random_hex(8),
+            parent_span_id: connection.span_id.clone(),
+            name: command.to_owned(),
+            kind: SPAN_KIND_INTERNAL,
+            start,
+            end: start + elapsed,
+            attributes: Vec::new(),
+        });
+    }
+}
+
+/// Sends the spans received from `spans` to the collector, until the channel is closed.
+async fn export_spans(endpoint: Endpoint, mut spans: mpsc::UnboundedReceiver<Span>) {
+    let mut batch = Vec::new();
+    let mut ticker = time::interval(BATCH_DELAY);
+    loop {
+        let closed = tokio::select! {
+            span = spans.recv() => match span {
+                Some(span) => {
+                    batch.push(span.to_json());
+                    if batch.len() < MAX_BATCH_LEN {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = ticker.tick() => false,
+        };
+        if !batch.is_empty() {
+            let n = batch.len();
+            if let Err(err) = post(&endpoint, std::mem::take(&mut batch)).await {
+                log::warn!("Failed to send {} spans to {}: {}", n, endpoint.addr, err);
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+async fn post(endpoint: &Endpoint, spans: Vec<Value>) -> io::Result<()> {
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!("ellidri")),
+                    attribute("service.version", json!(env!("CARGO_PKG_VERSION"))),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "ellidri" },
+                "spans": spans,
+            }],
+        }],
+    })
+    .to_string();
+    let request = async {
+        let mut conn = net::TcpStream::connect(&endpoint.addr).await?;
+        let header = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            endpoint.path,
+            endpoint.host,
+            body.len()
+        );
+        conn.write_all(header.as_bytes()).await?;
+        conn.write_all(body.as_bytes()).await?;
+        let mut status = String::new();
+        io::BufReader::new(conn).read_line(&mut status).await?;
+        // "HTTP/1.1 200 OK"
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "unexpected response {:?}",
+                status.trim()
+            ))),
+        }
+    };
+    time::timeout(POST_TIMEOUT, request)
+        .await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_parse() {
+        let endpoint = |addr: &str, host: &str, path: &str| Endpoint {
+            addr: addr.to_owned(),
+            host: host.to_owned(),
+            path: path.to_owned(),
+        };
+        assert_eq!(
+            Endpoint::parse("http://collector"),
+            Some(endpoint("collector:4318", "collector", "/v1/traces"))
+        );
+        assert_eq!(
+            Endpoint::parse("http://127.0.0.1:4000/otlp/"),
+            Some(endpoint(
+                "127.0.0.1:4000",
+                "127.0.0.1:4000",
+                "/otlp/v1/traces"
+            ))
+        );
+        assert_eq!(
+            Endpoint::parse("http://[::1]"),
+            Some(endpoint("[::1]:4318", "[::1]", "/v1/traces"))
+        );
+        assert_eq!(Endpoint::parse("https://collector"), None);
+        assert_eq!(Endpoint::parse("http:///v1"), None);
+    }
+} // mod tests
@@ -0,0 +1,3306 @@
+//! Deterministic simulation of the server, for tests.
+//!
+//! Connections are in-memory pipes, handled by the same code as TCP connections (rate limiter,
+//! login timeout, pings...).  Tests are given to `run`, which drives them with tokio's paused
+//! clock: time only moves when `time::advance` is called, or when all tasks are waiting on a
+//! timer.  Timeouts and flood limits can then be tested without real sleeps, and with the same
+//! timings on every run.
+
+use crate::config::{self, BindingOptions};
+use crate::{net, State};
+use ellidri_tokens::{Command, Message};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt};
+use tokio::runtime;
+use tokio::sync::Notify;
+
+/// How many bytes can be written to a connection before the writer waits for the reader.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Runs `test` on a single-threaded runtime whose clock is paused.
+pub fn run(test: impl Future<Output = ()> + Send + 'static) {
+    let rt = runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    // The test runs in its own task, because the runtime moves the paused clock forward each time
+    // the future given to `block_on` is woken up.
+    if let Err(err) = rt.block_on(async { tokio::spawn(test).await }) {
+        std::panic::resume_unwind(err.into_panic());
+    }
+}
+
+pub struct Simulation {
+    shared: State,
+    next_port: u16,
+}
+
+impl Simulation {
+    pub async fn new(mut cfg: config::State) -> Self {
+        cfg.domain = String::from("sim.ellidri");
+        cfg.motd_file = String::new();
+        let shared = State::new(cfg, Arc::new(Notify::new())).await;
+        Self {
+            shared,
+            next_port: 1,
+        }
+    }
+
+    /// Reloads the configuration of the server, like REHASH does.
+    pub async fn rehash(&self, mut cfg: config::State) {
+        cfg.domain = String::from("sim.ellidri");
+        cfg.motd_file = String::new();
+        self.shared.rehash(cfg).await;
+    }
+
+    /// Returns the state of the simulated server.
+    pub fn state(&self) -> &State {
+        &self.shared
+    }
+
+    /// Opens a plain-text connection from 127.0.0.1.
+    pub fn connect(&mut self) -> Connection {
+        self.connect_with(BindingOptions::default())
+    }
+
+    /// Opens a plain-text connection from 127.0.0.1, on a binding with the given options.
+    pub fn connect_with(&mut self, options: BindingOptions) -> Connection {
+        self.open(options, false, None)
+    }
+
+    /// Opens a connection from 127.0.0.1 that the server sees as a TLS connection, with the given
+    /// client certificate fingerprint.  Messages are still exchanged in plain text.
+    pub fn connect_tls(&mut self, certfp: Option<&str>) -> Connection {
+        self.open(BindingOptions::default(), true, certfp.map(str::to_owned))
+    }
+
+    fn open(&mut self, options: BindingOptions, tls: bool, certfp: Option<String>) -> Connection {
+        let (server, client) = io::duplex(PIPE_CAPACITY);
+        let addr = SocketAddr::from(([127, 0, 0, 1], self.next_port));
+        self.next_port += 1;
+        tokio::spawn(net::handle(
+            server,
+            addr,
+            Arc::new(options),
+            tls,
+            certfp,
+            self.shared.clone(),
+        ));
+        let (reader, writer) = io::split(client);
+        Connection {
+            reader: io::BufReader::new(reader),
+            writer,
+        }
+    }
+}
+
+/// The client side of a simulated connection.
+pub struct Connection {
+    reader: io::BufReader<io::ReadHalf<io::DuplexStream>>,
+    writer: io::WriteHalf<io::DuplexStream>,
+}
+
+impl Connection {
+    pub async fn send(&mut self, line: &str) {
+        self.writer.write_all(line.as_bytes()).await.unwrap();
+        self.writer.write_all(b"\r\n").await.unwrap();
+    }
+
+    /// Returns the next line sent by the server, or `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end().to_owned()),
+        }
+    }
+
+    /// Skips lines until one with the given command (e.g. "PING" or "001"), and returns it.
+    ///
+    /// Panics if the connection is closed before.
+    pub async fn expect(&mut self, command: &str) -> String {
+        let expected = Command::parse(command).ok_or(command);
+        loop {
+            let line = match self.recv().await {
+                Some(line) => line,
+                None => panic!("connection closed while waiting for {command}"),
+            };
+            if Message::parse(&line).is_some_and(|msg| msg.command == expected) {
+                return line;
+            }
+        }
+    }
+
+    /// Registers with the given nickname, and waits for the welcome burst to be over.
+    pub async fn register(&mut self, nick: &str) {
+        self.send(&format!("NICK {nick}")).await;
+        self.send("USER user 0 * :Simulated user").await;
+        self.expect("422").await; // ERR_NOMOTD
+    }
+
+    /// Logs in to an account with SASL PLAIN, and waits for the server to accept it.
+    pub async fn login(&mut self, account: &str, password: &str) {
+        self.send("CAP REQ sasl").await;
+        self.send("AUTHENTICATE PLAIN").await;
+        self.expect("AUTHENTICATE").await;
+        let plain = format!("{account}\0{account}\0{password}");
+        self.send(&format!("AUTHENTICATE {}", base64::encode(plain)))
+            .await;
+        self.expect("903").await; // RPL_SASLSUCCESS
+    }
+
+    /// Waits for the server to have handled the lines sent so far.
+    pub async fn sync(&mut self) {
+        self.send("PING sync").await;
+        self.expect("PONG").await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::{ClientInfo, Hook, Hooks, Verdict};
+    use ellidri_tokens::ReplyBuffer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::{self, Duration, Instant};
+
+    #[test]
+    fn test_login_timeout() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut conn = sim.connect();
+            let start = Instant::now();
+            conn.send("NICK late").await;
+            conn.expect("ERROR").await;
+            assert_eq!(start.elapsed(), Duration::from_secs(60));
+            assert_eq!(conn.recv().await, None);
+        });
+    }
+
+    #[test]
+    fn test_ping_timeout() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut conn = sim.connect();
+            conn.register("alice").await;
+            let start = Instant::now();
+
+            conn.expect("PING").await;
+            assert_eq!(start.elapsed(), Duration::from_secs(120));
+            conn.send("PONG sim.ellidri").await;
+
+            conn.expect("PING").await;
+            assert_eq!(start.elapsed(), Duration::from_secs(240));
+            while conn.recv().await.is_some() {}
+            assert_eq!(start.elapsed(), Duration::from_secs(300));
+        });
+    }
+
+    #[test]
+    fn test_ping_activity() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut conn = sim.connect();
+            conn.register("alice").await;
+            let start = Instant::now();
+
+            // Any message counts as activity, not only PONG.
+            time::advance(Duration::from_secs(100)).await;
+            conn.sync().await;
+            conn.expect("PING").await;
+            assert_eq!(start.elapsed(), Duration::from_secs(220));
+
+            while conn.recv().await.is_some() {}
+            assert_eq!(start.elapsed(), Duration::from_secs(280));
+        });
+    }
+
+    #[test]
+    fn test_ping_rehash() {
+        run(async {
+            let cfg = config::State {
+                ping_interval: 0,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut conn = sim.connect();
+            conn.register("alice").await;
+            let start = Instant::now();
+
+            // Idle connections are closed as soon as PINGs are enabled by a rehash.
+            time::advance(Duration::from_secs(30)).await;
+            sim.rehash(config::State {
+                ping_interval: 10,
+                ping_timeout: 5,
+                ..config::State::default()
+            })
+            .await;
+            while conn.recv().await.is_some() {}
+            assert_eq!(start.elapsed(), Duration::from_secs(30));
+        });
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut conn = sim.connect();
+            conn.register("alice").await;
+            let start = Instant::now();
+
+            // The burst is 32 points.  The registration costs 10 and TIME costs 2, so the 12th TIME
+            // exceeds the burst, and the next ones are handled every 250ms.
+            for _ in 0..20 {
+                conn.send("TIME").await;
+            }
+            let mut elapsed = Vec::new();
+            for _ in 0..20 {
+                conn.expect("391").await; // RPL_TIME
+                elapsed.push(start.elapsed().as_millis());
+            }
+            assert_eq!(elapsed[..12], [0; 12]);
+            let delayed: Vec<u128> = (1..=8).map(|i| i * 250).collect();
+            assert_eq!(elapsed[12..], delayed[..]);
+        });
+    }
+
+    #[test]
+    fn test_flood_limit() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut op = sim.connect();
+            let mut user = sim.connect();
+            op.register("op").await;
+            user.register("user").await;
+            op.send("JOIN #flood").await;
+            op.expect("366").await; // RPL_ENDOFNAMES
+            op.send("MODE #flood +f 2:10").await;
+            op.expect("MODE").await;
+            user.send("JOIN #flood").await;
+            user.expect("366").await;
+
+            user.send("PRIVMSG #flood :1").await;
+            user.send("PRIVMSG #flood :2").await;
+            user.sync().await;
+            time::advance(Duration::from_secs(10)).await;
+            user.send("PRIVMSG #flood :3").await;
+            user.send("PRIVMSG #flood :4").await;
+            for i in 1..=4 {
+                assert!(op.expect("PRIVMSG").await.ends_with(&format!(":{i}")));
+            }
+
+            user.send("PRIVMSG #flood :5").await;
+            assert!(user.expect("KICK").await.contains("#flood user"));
+            assert!(op.expect("KICK").await.contains("#flood user"));
+
+            // The kicked client is sent the KICK once, and is not a member anymore.
+            user.send("PING sync").await;
+            assert!(user.recv().await.unwrap().contains(" PONG "));
+            op.send("NAMES #flood").await;
+            let names = op.expect("353").await; // RPL_NAMREPLY
+            assert!(names.ends_with(" :@op"), "{names}");
+        });
+    }
+
+    #[test]
+    fn test_list() {
+        run(async {
+            let cfg = config::State {
+                default_chan_mode: String::from("+nt"),
+                chanlimit: 0,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut user = sim.connect();
+            op.register("op").await;
+            user.register("user").await;
+            for i in 0..5 {
+                let channels: Vec<String> = (0..50).map(|j| format!("#c{i}{j:02}")).collect();
+                op.send(&format!("JOIN {}", channels.join(","))).await;
+            }
+            op.sync().await;
+
+            user.send("LIST").await;
+            let mut entries = 0;
+            loop {
+                let line = user.recv().await.unwrap();
+                match Message::parse(&line).and_then(|msg| msg.command.err()) {
+                    Some("322") => entries += 1, // RPL_LIST
+                    Some("323") => break,        // RPL_LISTEND
+                    _ => {}
+                }
+            }
+            assert_eq!(entries, 250);
+
+            user.send("LIST").await;
+            assert!(user.expect("263").await.contains(" LIST :")); // RPL_TRYAGAIN
+            time::advance(Duration::from_secs(10)).await;
+            user.send("LIST").await;
+            user.expect("323").await;
+        });
+    }
+
+    #[test]
+    fn test_ison_userhost() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("Bob").await;
+            bob.send("AWAY :brb").await;
+            bob.expect("306").await; // RPL_NOWAWAY
+
+            alice.send("ISON bob carol :ALICE dave").await;
+            let ison = alice.expect("303").await;
+            assert!(ison.ends_with(" alice :Bob alice"), "{ison}");
+
+            alice.send("USERHOST alice bob carol").await;
+            let userhost = alice.expect("302").await;
+            assert!(
+                userhost.ends_with(" alice :alice=+user@127.0.0.1 Bob=-user@127.0.0.1"),
+                "{userhost}"
+            );
+        });
+    }
+
+    #[test]
+    fn test_nick_index() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            bob.send("NICK Robert").await;
+            bob.expect("NICK").await;
+            alice.send("PRIVMSG BOB :hi").await;
+            alice.expect("401").await; // ERR_NOSUCHNICK
+            alice.send("PRIVMSG rOBERT :hi").await;
+            let privmsg = bob.expect("PRIVMSG").await;
+            assert!(privmsg.starts_with(":alice!"), "{privmsg}");
+
+            bob.send("QUIT").await;
+            while bob.recv().await.is_some() {}
+            let mut carol = sim.connect();
+            carol.register("ROBERT").await;
+        });
+    }
+
+    #[test]
+    fn test_casemapping() {
+        run(async {
+            let cfg = config::State {
+                casemapping: config::CaseMapping::Rfc1459,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.send("NICK al[i]ce").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            let i_support = alice.expect("005").await; // RPL_ISUPPORT
+            assert!(i_support.contains(" CASEMAPPING=rfc1459 "), "{i_support}");
+            alice.expect("422").await; // ERR_NOMOTD
+            bob.register("bob").await;
+
+            bob.send("PRIVMSG AL{I}CE :hi").await;
+            let privmsg = alice.expect("PRIVMSG").await;
+            assert!(privmsg.starts_with(":bob!"), "{privmsg}");
+            bob.send("NICK al{i}ce").await;
+            bob.expect("433").await; // ERR_NICKNAMEINUSE
+
+            sim.rehash(config::State::default()).await;
+            let i_support = alice.expect("005").await;
+            assert!(i_support.contains(" CASEMAPPING=ascii "), "{i_support}");
+            bob.send("NICK al{i}ce").await;
+            let nick = bob.expect("NICK").await;
+            assert!(nick.ends_with(" NICK al{i}ce"), "{nick}");
+
+            // Both nicknames would be the same under rfc1459, so the casemapping is kept.
+            sim.rehash(config::State {
+                casemapping: config::CaseMapping::Rfc1459,
+                ..config::State::default()
+            })
+            .await;
+            alice.send("PRIVMSG al{i}ce :hi").await;
+            let privmsg = bob.expect("PRIVMSG").await;
+            assert!(privmsg.starts_with(":al[i]ce!"), "{privmsg}");
+        });
+    }
+
+    #[test]
+    fn test_info() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut conn = sim.connect();
+            conn.register("alice").await;
+            conn.send("INFO").await;
+            let mut lines = Vec::new();
+            loop {
+                let line = conn.recv().await.unwrap();
+                if line.contains(" 374 ") {
+                    break; // RPL_ENDOFINFO
+                }
+                lines.push(line);
+            }
+            let build = concat!(
+                ":This is ellidri-",
+                env!("CARGO_PKG_VERSION"),
+                ", built on "
+            );
+            assert!(lines.iter().any(|line| line.contains(build)), "{lines:?}");
+            assert!(lines.iter().any(|line| line.contains(":Built with: ")));
+            assert!(lines.iter().any(|line| line.contains(":Administrated by ")));
+        });
+    }
+
+    #[test]
+    fn test_rehash_i_support() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.send("CAP LS 302").await;
+            alice.send("CAP END").await;
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            sim.rehash(config::State {
+                nicklen: 16,
+                sasl_backend: config::SaslBackend::File,
+                ..config::State::default()
+            })
+            .await;
+            let i_support = alice.expect("005").await; // RPL_ISUPPORT
+            assert!(i_support.starts_with(":sim.ellidri 005 alice NICKLEN=16 :"));
+            let cap = alice.expect("CAP").await;
+            assert_eq!(cap, ":sim.ellidri CAP alice NEW :sasl=PLAIN");
+
+            // bob has no cap-notify.
+            bob.expect("005").await;
+            bob.send("PING sync").await;
+            assert!(bob.recv().await.unwrap().contains(" PONG "));
+
+            sim.rehash(config::State {
+                nicklen: 16,
+                ..config::State::default()
+            })
+            .await;
+            let cap = alice.expect("CAP").await;
+            assert_eq!(cap, ":sim.ellidri CAP alice DEL :sasl");
+        });
+    }
+
+    #[test]
+    fn test_dcc_offers() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            alice.send("JOIN #dcc").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            alice.send("MODE #dcc +d").await;
+            alice.expect("MODE").await;
+            bob.send("JOIN #dcc").await;
+            bob.expect("366").await;
+
+            let offer = "\x01DCC SEND free.exe 2130706433 4000 1024\x01";
+            bob.send(&format!("PRIVMSG #dcc :{offer}")).await;
+            assert!(bob.expect("404").await.contains(" #dcc :")); // ERR_CANNOTSENDTOCHAN
+            bob.send(&format!("PRIVMSG alice :{offer}")).await;
+            assert!(alice.expect("PRIVMSG").await.ends_with(offer));
+
+            sim.rehash(config::State {
+                dcc_offers: config::DccAction::Strip,
+                ..config::State::default()
+            })
+            .await;
+            bob.send(&format!("PRIVMSG alice :{offer}")).await;
+            bob.send(&format!("PRIVMSG #dcc :take this{offer}")).await;
+            assert!(alice.expect("PRIVMSG").await.ends_with("#dcc :take this"));
+        });
+    }
+
+    #[test]
+    fn test_ctcp_policy() {
+        run(async {
+            let cfg = config::State {
+                ctcp_limit: String::from("2:10"),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            for _ in 0..3 {
+                bob.send("PRIVMSG alice :\x01VERSION\x01").await;
+            }
+            bob.send("PRIVMSG alice :\x01ACTION waves\x01").await;
+            assert!(bob
+                .expect("NOTICE")
+                .await
+                .starts_with(":sim.ellidri NOTICE bob :"));
+            assert!(alice.expect("PRIVMSG").await.ends_with(":\x01VERSION\x01"));
+            assert!(alice.expect("PRIVMSG").await.ends_with(":\x01VERSION\x01"));
+            assert!(alice
+                .expect("PRIVMSG")
+                .await
+                .ends_with(":\x01ACTION waves\x01"));
+
+            alice.send("MODE alice +T").await;
+            alice.expect("MODE").await;
+            time::advance(Duration::from_secs(10)).await;
+            bob.send("PRIVMSG alice :\x01TIME\x01").await;
+            assert!(bob.expect("404").await.contains(" alice :")); // ERR_CANNOTSENDTOCHAN
+            bob.send("PRIVMSG alice :\x01ACTION waves again\x01").await;
+            assert!(alice
+                .expect("PRIVMSG")
+                .await
+                .ends_with(":\x01ACTION waves again\x01"));
+        });
+    }
+
+    #[test]
+    fn test_registered_only() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            alice.send("MODE alice +R").await;
+            assert!(alice.expect("MODE").await.ends_with(" MODE alice +R"));
+            bob.send("PRIVMSG alice :hi").await;
+            let reply = bob.expect("477").await; // ERR_NEEDREGGEDNICK
+            assert!(reply.contains(" alice :"), "{reply}");
+            bob.send("NOTICE alice :hi").await;
+            bob.sync().await;
+
+            bob.send("OPER op pw").await;
+            bob.expect("381").await; // RPL_YOUREOPER
+            bob.send("PRIVMSG alice :hi again").await;
+            assert!(alice.expect("PRIVMSG").await.ends_with(" :hi again"));
+        });
+    }
+
+    #[test]
+    fn test_deaf() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bot = sim.connect();
+            alice.register("alice").await;
+            bot.register("bot").await;
+            alice.send("JOIN #chan").await;
+            bot.send("MODE bot +D").await;
+            assert!(bot.expect("MODE").await.ends_with(" MODE bot +D"));
+            bot.send("JOIN #chan").await;
+            bot.expect("366").await; // RPL_ENDOFNAMES
+            alice.expect("JOIN").await;
+
+            alice.send("PRIVMSG #chan :anyone there?").await;
+            alice.send("PRIVMSG bot :!help").await;
+            alice.send("PART #chan").await;
+            assert!(bot.expect("PRIVMSG").await.ends_with(" PRIVMSG bot :!help"));
+            bot.expect("PART").await;
+        });
+    }
+
+    #[test]
+    fn test_bot() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bot = sim.connect();
+            alice.send("CAP REQ :message-tags").await;
+            alice.send("NICK alice").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            alice.send("CAP END").await;
+            let isupport = alice.expect("005").await; // RPL_ISUPPORT
+            assert!(isupport.contains(" BOT=B "), "{isupport}");
+            alice.expect("422").await; // ERR_NOMOTD
+            bot.register("bot").await;
+
+            bot.send("MODE bot +B").await;
+            assert!(bot.expect("MODE").await.ends_with(" MODE bot +B"));
+            bot.send("PRIVMSG alice :beep").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.contains(";bot :bot!"), "{msg}");
+            alice.send("WHOIS bot").await;
+            alice.expect("335").await; // RPL_WHOISBOT
+
+            bot.send("MODE bot -B").await;
+            bot.expect("MODE").await;
+            bot.send("PRIVMSG alice :boop").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(!msg.contains(";bot "), "{msg}");
+        });
+    }
+
+    #[test]
+    fn test_chanlimit() {
+        run(async {
+            let cfg = config::State {
+                chanlimit: 2,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            alice.register("alice").await;
+
+            alice.send("JOIN #a,#b,#c").await;
+            let too_many = alice.expect("405").await; // ERR_TOOMANYCHANNELS
+            assert!(too_many.contains(" alice #c :"), "{too_many}");
+
+            alice.send("PART #a").await;
+            alice.expect("PART").await;
+            alice.send("JOIN #c").await;
+            assert!(alice.expect("JOIN").await.ends_with(" JOIN #c"));
+            alice.send("JOIN #d").await;
+            alice.expect("405").await;
+        });
+    }
+
+    #[test]
+    fn test_join_throttle() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            bob.register("bob").await;
+            op.send("JOIN #busy").await;
+            op.expect("366").await; // RPL_ENDOFNAMES
+            op.send("MODE #busy +j 1:10").await;
+            op.expect("MODE").await;
+
+            alice.send("JOIN #busy").await;
+            alice.expect("366").await;
+            bob.send("JOIN #busy").await;
+            assert!(bob.expect("480").await.contains(" bob #busy :")); // ERR_THROTTLE
+
+            time::advance(Duration::from_secs(10)).await;
+            bob.send("JOIN #busy").await;
+            bob.expect("366").await;
+        });
+    }
+
+    #[test]
+    fn test_clones() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let options = BindingOptions {
+                max_clones: 2,
+                clone_action: config::CloneAction::Reject,
+                ..BindingOptions::default()
+            };
+            let mut op = sim.connect_with(options.clone());
+            op.register("op").await;
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            let mut alice = sim.connect_with(options.clone());
+            alice.register("alice").await;
+
+            let mut clone = sim.connect_with(options.clone());
+            clone.expect("ERROR").await;
+            assert_eq!(clone.recv().await, None);
+            let notice = op.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(":Clones (reject): 3 connections from 127.0.0.1"),
+                "{notice}"
+            );
+
+            let options = BindingOptions {
+                clone_action: config::CloneAction::Dline,
+                ..options
+            };
+            let mut clone = sim.connect_with(options);
+            clone.expect("ERROR").await;
+            let mut other = sim.connect();
+            other.expect("ERROR").await;
+            assert_eq!(other.recv().await, None);
+        });
+    }
+
+    #[test]
+    fn test_lockdown() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("JOIN #existing").await;
+            op.expect("366").await; // RPL_ENDOFNAMES
+
+            alice.send("LOCKDOWN 1").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            op.send("LOCKDOWN 4").await;
+            op.expect("NOTICE").await;
+            op.send("LOCKDOWN 2").await;
+            let notice = op.expect("NOTICE").await;
+            assert!(notice.ends_with(" set the lockdown level to 2"), "{notice}");
+            op.expect("NOTICE").await;
+
+            alice.send("PRIVMSG op :hello").await;
+            assert!(alice.expect("477").await.contains(" alice op :")); // ERR_NEEDREGGEDNICK
+            alice.send("JOIN #new").await;
+            assert!(alice.expect("403").await.contains(" alice #new :")); // ERR_NOSUCHCHANNEL
+            alice.send("JOIN #existing").await;
+            alice.expect("366").await;
+
+            op.send("LOCKDOWN 3").await;
+            op.sync().await;
+            let mut bob = sim.connect();
+            bob.expect("ERROR").await;
+            assert_eq!(bob.recv().await, None);
+
+            op.send("LOCKDOWN 0").await;
+            op.sync().await;
+            alice.send("PRIVMSG op :hello").await;
+            assert!(op.expect("PRIVMSG").await.ends_with(" PRIVMSG op :hello"));
+        });
+    }
+
+    #[test]
+    fn test_challenge() {
+        run(async {
+            let cfg = config::State {
+                challenges: vec![config::Challenge {
+                    question: String::from("What is 2 + 2?"),
+                    answer: String::from("Four"),
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let options = BindingOptions {
+                challenge_hosts: vec![String::from("127.0.0.*")],
+                ..BindingOptions::default()
+            };
+            let mut conn = sim.connect_with(options);
+            let question = conn.expect("NOTICE").await;
+            assert!(question.ends_with(": What is 2 + 2?"), "{question}");
+
+            conn.send("NICK alice").await;
+            conn.send("USER user 0 * :Alice").await;
+            conn.expect("NOTICE").await;
+            conn.send("JOIN #channel").await;
+            conn.expect("451").await; // ERR_NOTREGISTERED
+            conn.send("CHALLENGE 5").await;
+            conn.expect("NOTICE").await;
+            conn.send("CHALLENGE four").await;
+            conn.expect("001").await; // RPL_WELCOME
+
+            let mut other = sim.connect();
+            other.register("bob").await;
+        });
+    }
+
+    #[test]
+    fn test_help() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            alice.register("alice").await;
+
+            alice.send("HELP kick").await;
+            let start = alice.expect("704").await; // RPL_HELPSTART
+            assert!(start.ends_with(" KICK :KICK <channel> <nick>[,<nick>...] [reason]"));
+            alice.expect("705").await; // RPL_HELPTXT
+            alice.expect("706").await; // RPL_ENDOFHELP
+
+            alice.send("HELPOP").await;
+            alice.expect("704").await; // RPL_HELPSTART
+            let mut index = String::from(" ");
+            loop {
+                let line = alice.recv().await.unwrap();
+                if line.contains(" 706 ") {
+                    break;
+                }
+                index.push_str(line.rsplit(':').next().unwrap());
+                index.push(' ');
+            }
+            assert!(index.contains(" JOIN "), "{index}");
+            assert!(!index.contains(" REHASH "), "{index}");
+
+            alice.send("HELP REHASH").await;
+            alice.expect("524").await; // ERR_HELPNOTFOUND
+            alice.send("HELP FOO").await;
+            let reply = alice.expect("524").await; // ERR_HELPNOTFOUND
+            assert!(reply.contains(" FOO :"), "{reply}");
+        });
+    }
+
+    #[test]
+    fn test_oper_motd() {
+        run(async {
+            let mut cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg.clone()).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("OPERMOTD").await;
+            op.expect("425").await; // ERR_NOOPERMOTD
+
+            cfg.oper_motd_file = String::from("Escalate to {server} admins");
+            sim.rehash(cfg).await;
+            op.send("OPERMOTD").await;
+            op.expect("720").await; // RPL_OMOTDSTART
+            let line = op.expect("721").await; // RPL_OMOTD
+            assert!(
+                line.ends_with(" :- Escalate to sim.ellidri admins"),
+                "{line}"
+            );
+            op.expect("722").await; // RPL_ENDOFOMOTD
+            alice.send("OPERMOTD").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            alice.send("OPER op pw").await;
+            alice.expect("381").await; // RPL_YOUREOPER
+            alice.expect("721").await; // RPL_OMOTD
+        });
+    }
+
+    #[test]
+    fn test_dump() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            alice.send("JOIN #chan").await;
+            alice.send("MODE #chan +k secret").await;
+            alice.sync().await;
+
+            alice.send("DUMP").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("DUMP").await;
+            let notice = op.expect("NOTICE").await;
+            assert!(notice.ends_with(" (2 clients, 1 channels)"), "{notice}");
+
+            let dump = sim.shared.dump().await;
+            let clients = dump["clients"].as_array().unwrap();
+            assert_eq!(clients.len(), 2);
+            let alice = clients.iter().find(|c| c["nick"] == "alice").unwrap();
+            assert_eq!(alice["state"], "Registered");
+            assert_eq!(alice["channels"], 1);
+            let channel = &dump["channels"][0];
+            assert_eq!(channel["name"], "#chan");
+            assert_eq!(channel["modes"], "+nstk");
+            assert_eq!(channel["members"][0]["prefixes"], "@");
+            assert!(!dump.to_string().contains("secret"));
+            assert!(!dump.to_string().contains("127.0.0.1"));
+        });
+    }
+
+    #[test]
+    fn test_auto_join() {
+        run(async {
+            let cfg = config::State {
+                auto_join: vec![String::from("#welcome"), String::from("#lobby")],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            let join = alice.expect("JOIN").await;
+            assert!(join.ends_with(" JOIN #welcome"), "{join}");
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            let join = alice.expect("JOIN").await;
+            assert!(join.ends_with(" JOIN #lobby"), "{join}");
+            alice.sync().await;
+
+            bob.register("bob").await;
+            let join = alice.expect("JOIN").await;
+            assert!(join.starts_with(":bob!"), "{join}");
+            assert!(join.ends_with(" JOIN #welcome"), "{join}");
+        });
+    }
+
+    #[test]
+    fn test_chathistory() {
+        run(async {
+            let cfg = config::State {
+                channel_history: vec![config::ChannelHistory {
+                    channels: String::from("#small*"),
+                    len: 1,
+                    age: 0,
+                    autoreplay: None,
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            let mut carol = sim.connect();
+            alice.register("alice").await;
+            bob.send("CAP REQ :batch server-time").await;
+            bob.send("NICK bob").await;
+            bob.send("USER user 0 * :Simulated user").await;
+            bob.send("CAP END").await;
+            bob.expect("422").await; // ERR_NOMOTD
+            carol.register("carol").await;
+            alice.send("JOIN #chan,#Small").await;
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            for i in 0..3 {
+                alice.send(&format!("PRIVMSG #chan :hello {i}")).await;
+                alice.send(&format!("PRIVMSG #small :hello {i}")).await;
+            }
+            alice.send("PRIVMSG #chan :\x01VERSION\x01").await;
+            alice.sync().await;
+
+            alice.send("CHATHISTORY LATEST #chan * 2").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.contains(" PRIVMSG #chan :["), "{msg}");
+            assert!(msg.ends_with("] hello 1"), "{msg}");
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.contains(" PRIVMSG #chan :["), "{msg}");
+            assert!(msg.ends_with("] hello 2"), "{msg}");
+            alice.send("CHATHISTORY LATEST #SMALL * 10").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.contains(" PRIVMSG #SMALL :["), "{msg}");
+            assert!(msg.ends_with("] hello 2"), "{msg}");
+            alice.sync().await;
+
+            bob.sync().await;
+            bob.send("CHATHISTORY BEFORE #chan timestamp=2100-01-01T00:00:00.000Z 10")
+                .await;
+            let batch = bob.expect("BATCH").await;
+            assert!(batch.ends_with(" chathistory #chan"), "{batch}");
+            for i in 0..3 {
+                let msg = bob.recv().await.unwrap();
+                assert!(msg.starts_with("@batch=0;msgid="), "{msg}");
+                assert!(
+                    msg.ends_with(&format!(" PRIVMSG #chan :hello {i}")),
+                    "{msg}"
+                );
+            }
+            bob.expect("BATCH").await;
+
+            carol.send("CHATHISTORY LATEST #chan * 10").await;
+            let fail = carol.expect("FAIL").await;
+            assert!(fail.contains(" INVALID_TARGET LATEST #chan :"), "{fail}");
+            carol.send("CHATHISTORY LATEST #chan 10").await;
+            carol.expect("461").await; // ERR_NEEDMOREPARAMS
+            carol.send("CHATHISTORY LATEST #chan msgid= 10").await;
+            let fail = carol.expect("FAIL").await;
+            assert!(fail.contains(" INVALID_PARAMS LATEST :"), "{fail}");
+        });
+    }
+
+    #[test]
+    fn test_tag_limits() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            for client in [&mut alice, &mut bob] {
+                client.send("CAP REQ message-tags").await;
+                client.send("CAP END").await;
+            }
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            let value = "a".repeat(4000);
+            alice.send(&format!("@+big={value} TAGMSG bob")).await;
+            let tagmsg = bob.expect("TAGMSG").await;
+            assert!(tagmsg.starts_with(&format!("@+big={value};")), "{tagmsg}");
+
+            let value = "a".repeat(4095);
+            alice.send(&format!("@+big={value} TAGMSG bob")).await;
+            alice.expect("417").await; // ERR_INPUTTOOLONG
+
+            let text = "a".repeat(9000);
+            alice.send(&format!("PRIVMSG bob :{text}")).await;
+            alice.expect("417").await; // ERR_INPUTTOOLONG
+            alice.send("PRIVMSG bob :short").await;
+            let msg = bob.expect("PRIVMSG").await;
+            assert!(msg.ends_with(" PRIVMSG bob :short"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn test_linelen() {
+        use ellidri_tokens::isupport::{self, Token};
+
+        run(async {
+            let cfg = config::State {
+                linelen: 1024,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.send("NICK alice").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            let mut linelen = None;
+            loop {
+                let line = alice.recv().await.unwrap();
+                let msg = Message::parse(&line).unwrap();
+                if msg.command == Err("422") {
+                    break;
+                }
+                for token in isupport::tokens(&msg) {
+                    if let Token::Limit("LINELEN", len) = token {
+                        linelen = len;
+                    }
+                }
+            }
+            assert_eq!(linelen, Some(1024));
+            bob.register("bob").await;
+
+            // 1024 bytes with CRLF.
+            let text = "a".repeat(1024 - "PRIVMSG bob :\r\n".len());
+            alice.send(&format!("PRIVMSG bob :{text}")).await;
+            let msg = bob.expect("PRIVMSG").await;
+            assert!(msg.ends_with(&text), "{msg}");
+            alice.send(&format!("PRIVMSG bob :{text}a")).await;
+            alice.expect("417").await; // ERR_INPUTTOOLONG
+
+            // Tags don't count.
+            let value = "a".repeat(4000);
+            alice
+                .send(&format!("@+big={value} PRIVMSG bob :{text}"))
+                .await;
+            let msg = bob.expect("PRIVMSG").await;
+            assert!(msg.ends_with(&text), "{msg}");
+        });
+    }
+
+    #[test]
+    fn test_fanout() {
+        run(async {
+            let cfg = config::State {
+                fanout_threshold: 1,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut members = Vec::new();
+            for nick in ["alice", "bob", "carol", "dave"] {
+                let mut member = sim.connect();
+                member.register(nick).await;
+                member.send("JOIN #big").await;
+                member.expect("366").await; // RPL_ENDOFNAMES
+                members.push(member);
+            }
+            for i in 1..=3 {
+                members[0].send(&format!("PRIVMSG #big :{i}")).await;
+            }
+            members[0].send("PART #big").await;
+            for member in &mut members[1..] {
+                for i in 1..=3 {
+                    let msg = member.expect("PRIVMSG").await;
+                    assert!(msg.ends_with(&format!(" PRIVMSG #big :{i}")), "{msg}");
+                }
+                let part = member.expect("PART").await;
+                assert!(part.starts_with(":alice!"), "{part}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_fanout_recipients() {
+        run(async {
+            let cfg = config::State {
+                fanout_threshold: 1,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut members = Vec::new();
+            for nick in ["alice", "bob", "carol"] {
+                let mut member = sim.connect();
+                member.register(nick).await;
+                member.send("JOIN #big").await;
+                member.expect("366").await; // RPL_ENDOFNAMES
+                members.push(member);
+            }
+            members[0].send("PRIVMSG #big :1").await;
+            members[0].sync().await;
+
+            // Recipients are updated when modes and members change.
+            members[2].send("MODE carol +D").await;
+            members[2].expect("MODE").await;
+            let mut dave = sim.connect();
+            dave.register("dave").await;
+            dave.send("JOIN #big").await;
+            dave.expect("366").await; // RPL_ENDOFNAMES
+            members[0].send("PRIVMSG #big :2").await;
+            members[0].send("PART #big").await;
+
+            for i in 1..=2 {
+                let msg = members[1].expect("PRIVMSG").await;
+                assert!(msg.ends_with(&format!(" PRIVMSG #big :{i}")), "{msg}");
+            }
+            loop {
+                let line = members[2].recv().await.unwrap();
+                assert!(!line.contains(" PRIVMSG #big :2"), "{line}");
+                if line.contains(" PART #big") {
+                    break;
+                }
+            }
+            let msg = dave.expect("PRIVMSG").await;
+            assert!(msg.ends_with(" PRIVMSG #big :2"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn test_labeled_response() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice
+                .send("CAP REQ :batch echo-message labeled-response")
+                .await;
+            alice.send("CAP END").await;
+            alice.register("alice").await;
+            alice.sync().await;
+            bob.register("bob").await;
+            bob.send("AWAY :gone").await;
+            bob.sync().await;
+
+            alice.send("@label=a PONG x").await;
+            let ack = alice.recv().await.unwrap();
+            assert!(
+                ack.starts_with("@label=a :") && ack.ends_with(" ACK"),
+                "{ack}"
+            );
+
+            alice.send("@label=b TIME").await;
+            let time = alice.recv().await.unwrap();
+            assert!(
+                time.starts_with("@label=b :") && time.contains(" 391 "),
+                "{time}"
+            );
+
+            alice.send("@label=c PRIVMSG bob :hi").await;
+            let batch = alice.recv().await.unwrap();
+            assert!(batch.starts_with("@label=c :"), "{batch}");
+            assert!(batch.ends_with(" BATCH +0 labeled-response"), "{batch}");
+            let echo = alice.recv().await.unwrap();
+            assert!(echo.starts_with("@batch=0 :alice!"), "{echo}");
+            assert!(echo.ends_with(" PRIVMSG bob :hi"), "{echo}");
+            let away = alice.recv().await.unwrap();
+            assert!(
+                away.starts_with("@batch=0 :") && away.contains(" 301 "),
+                "{away}"
+            );
+            let end = alice.recv().await.unwrap();
+            assert!(end.ends_with(" BATCH -0"), "{end}");
+
+            alice.send("@label=d STATS").await;
+            let batch = alice.recv().await.unwrap();
+            assert!(batch.starts_with("@label=d :"), "{batch}");
+            assert!(batch.ends_with(" BATCH +0 labeled-response"), "{batch}");
+            let stats = alice.recv().await.unwrap();
+            assert!(
+                stats.starts_with("@batch=0 :") && stats.contains(" 219 "),
+                "{stats}"
+            );
+            let end = alice.recv().await.unwrap();
+            assert!(end.ends_with(" BATCH -0"), "{end}");
+        });
+    }
+
+    #[test]
+    fn test_history_mode() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            alice.register("alice").await;
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            for i in 0..3 {
+                alice.send(&format!("PRIVMSG #chan :hello {i}")).await;
+            }
+            alice.send("MODE #chan +H 2:0").await;
+            let mode = alice.expect("MODE").await;
+            assert!(mode.ends_with(" MODE #chan +H 2:0"), "{mode}");
+            alice.send("MODE #chan").await;
+            let modes = alice.expect("324").await; // RPL_CHANNELMODEIS
+            assert!(modes.contains('H') && modes.ends_with(" 2:0"), "{modes}");
+
+            // The MODE event is kept along with the last message.
+            alice.send("CHATHISTORY LATEST #chan * 10").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.ends_with("] hello 2"), "{msg}");
+
+            alice.send("MODE #chan +H 0:0").await;
+            alice.expect("MODE").await;
+            alice.send("PRIVMSG #chan :hello 3").await;
+            alice.send("MODE #chan -H").await;
+            let mode = alice.expect("MODE").await;
+            assert!(mode.ends_with(" MODE #chan -H"), "{mode}");
+            alice.send("PRIVMSG #chan :hello 4").await;
+            alice.send("CHATHISTORY LATEST #chan * 10").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.ends_with("] hello 4"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn test_search() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.send("CAP REQ :batch server-time draft/search").await;
+            bob.send("NICK bob").await;
+            bob.send("USER user 0 * :Simulated user").await;
+            bob.send("CAP END").await;
+            bob.expect("422").await; // ERR_NOMOTD
+            alice.send("JOIN #chan,#other").await;
+            bob.send("JOIN #chan,#other").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            alice.send("PRIVMSG #chan :hello world").await;
+            alice.send("PRIVMSG #other :Hello there").await;
+            alice.send("PRIVMSG #chan :goodbye").await;
+            alice.sync().await;
+            bob.send("PRIVMSG #chan :hello alice").await;
+            bob.sync().await;
+
+            bob.send("SEARCH from=alice;text=HELLO").await;
+            let batch = bob.expect("BATCH").await;
+            assert!(batch.ends_with(" draft/search"), "{batch}");
+            // Both messages may have been sent in the same millisecond.
+            let mut msgs = [bob.recv().await.unwrap(), bob.recv().await.unwrap()];
+            msgs.sort_by_key(|msg| msg.contains("#other"));
+            assert!(msgs[0].ends_with(" PRIVMSG #chan :hello world"), "{msgs:?}");
+            assert!(
+                msgs[1].ends_with(" PRIVMSG #other :Hello there"),
+                "{msgs:?}"
+            );
+            let end = bob.recv().await.unwrap();
+            assert!(end.ends_with(" BATCH -0"), "{end}");
+
+            alice.sync().await;
+            alice.send("SEARCH in=#chan;limit=1").await;
+            let msg = alice.expect("PRIVMSG").await;
+            assert!(msg.ends_with("] hello alice"), "{msg}");
+            alice.send("PART #other").await;
+            alice.send("SEARCH in=#other").await;
+            let fail = alice.expect("FAIL").await;
+            assert!(fail.contains(" SEARCH INVALID_TARGET #other :"), "{fail}");
+            alice.send("SEARCH color=blue").await;
+            let fail = alice.expect("FAIL").await;
+            assert!(
+                fail.contains(" SEARCH INVALID_PARAMS color=blue :"),
+                "{fail}"
+            );
+        });
+    }
+
+    #[test]
+    fn test_autoreplay() {
+        run(async {
+            let cfg = config::State {
+                autoreplay_len: 2,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            let mut carol = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            carol.send("CAP REQ :draft/chathistory").await;
+            carol.send("NICK carol").await;
+            carol.send("USER user 0 * :Simulated user").await;
+            carol.send("CAP END").await;
+            carol.expect("422").await; // ERR_NOMOTD
+            alice.send("JOIN #chan").await;
+            for i in 0..3 {
+                alice.send(&format!("PRIVMSG #chan :hello {i}")).await;
+            }
+            alice.sync().await;
+
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            for i in 1..3 {
+                let msg = bob.expect("PRIVMSG").await;
+                assert!(msg.contains(" PRIVMSG #chan :["), "{msg}");
+                assert!(msg.ends_with(&format!("] hello {i}")), "{msg}");
+            }
+            bob.sync().await;
+
+            bob.send("AUTOREPLAY OFF").await;
+            bob.expect("NOTICE").await;
+            bob.send("PART #chan").await;
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("PING sync").await;
+            assert!(bob.recv().await.unwrap().contains(" PONG "));
+            bob.send("AUTOREPLAY many").await;
+            let fail = bob.expect("FAIL").await;
+            assert!(fail.contains(" AUTOREPLAY INVALID_PARAMS many :"), "{fail}");
+
+            carol.send("JOIN #chan").await;
+            carol.expect("366").await; // RPL_ENDOFNAMES
+            carol.send("PING sync").await;
+            assert!(carol.recv().await.unwrap().contains(" PONG "));
+        });
+    }
+
+    #[test]
+    fn test_event_playback() {
+        run(async {
+            let cfg = config::State {
+                autoreplay_len: 10,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+            alice.send("TOPIC #chan :the topic").await;
+            alice.send("MODE #chan +v bob").await;
+            alice.sync().await;
+            bob.send("PRIVMSG #chan :hello").await;
+            bob.send("PART #chan :bye").await;
+            bob.send("JOIN #chan").await;
+            bob.sync().await;
+            alice.send("KICK #chan bob").await;
+            alice.sync().await;
+
+            let mut carol = sim.connect();
+            carol.send("CAP REQ :batch draft/event-playback").await;
+            carol.send("NICK carol").await;
+            carol.send("USER user 0 * :Simulated user").await;
+            carol.send("CAP END").await;
+            carol.expect("422").await; // ERR_NOMOTD
+            carol.send("JOIN #chan").await;
+            carol.expect("366").await; // RPL_ENDOFNAMES
+            let mut replayed = Vec::new();
+            loop {
+                let line = carol.recv().await.unwrap();
+                if line.contains(" BATCH -") {
+                    break;
+                }
+                if !line.contains(" BATCH +") {
+                    // Remove the batch tag and the prefix.
+                    let (_, line) = line.split_once(' ').unwrap();
+                    replayed.push(line.split_once(' ').unwrap().1.to_owned());
+                }
+            }
+            assert_eq!(replayed.len(), 8, "{replayed:?}");
+            assert_eq!(
+                replayed[..4],
+                [
+                    "JOIN #chan",
+                    "JOIN #chan",
+                    "TOPIC #chan :the topic",
+                    "MODE #chan +v bob",
+                ]
+            );
+            assert!(
+                replayed[4].starts_with("PRIVMSG #chan :["),
+                "{}",
+                replayed[4]
+            );
+            assert!(replayed[4].ends_with("] hello"), "{}", replayed[4]);
+            assert_eq!(
+                replayed[5..],
+                ["PART #chan :bye", "JOIN #chan", "KICK #chan bob"]
+            );
+
+            let mut dave = sim.connect();
+            dave.register("dave").await;
+            dave.send("JOIN #chan").await;
+            dave.expect("366").await; // RPL_ENDOFNAMES
+            let msg = dave.recv().await.unwrap();
+            assert!(msg.ends_with("] hello"), "{msg}");
+            dave.send("PING sync").await;
+            assert!(dave.recv().await.unwrap().contains(" PONG "));
+        });
+    }
+
+    #[test]
+    fn test_vhost() {
+        let path = std::env::temp_dir().join(format!("ellidri-vhost-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("bob", crate::util::hash_password("pw").unwrap());
+        accounts.set_vhost("bob", Some(String::from("staff/bob")));
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.send("CAP REQ chghost").await;
+            alice.send("NICK alice").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            alice.send("CAP END").await;
+            alice.expect("422").await; // ERR_NOMOTD
+            bob.register("bob").await;
+            alice.send("JOIN #chan").await;
+            alice.sync().await;
+            bob.send("JOIN #chan").await;
+            bob.sync().await;
+
+            bob.send("CAP REQ sasl").await;
+            bob.send("AUTHENTICATE PLAIN").await;
+            bob.expect("AUTHENTICATE").await;
+            bob.send(&format!("AUTHENTICATE {}", base64::encode("bob\0bob\0pw")))
+                .await;
+            let line = bob.expect("396").await; // RPL_HOSTHIDDEN
+            assert!(line.contains(" bob staff/bob :"), "{line}");
+            let line = bob.expect("900").await; // RPL_LOGGEDIN
+            assert!(line.contains(" bob!~user@staff/bob bob :"), "{line}");
+            let line = alice.expect("CHGHOST").await;
+            assert_eq!(line, ":bob!~user@127.0.0.1 CHGHOST user staff/bob");
+
+            bob.send("PRIVMSG #chan :hi").await;
+            let line = alice.expect("PRIVMSG").await;
+            assert!(line.contains(":bob!~user@staff/bob PRIVMSG"), "{line}");
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_chghost() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            op.register("op").await;
+            alice.send("CAP REQ chghost").await;
+            alice.send("NICK alice").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            alice.send("CAP END").await;
+            alice.expect("422").await; // ERR_NOMOTD
+            bob.register("bob").await;
+            alice.send("JOIN #chan").await;
+            alice.sync().await;
+            bob.send("JOIN #chan").await;
+            bob.sync().await;
+
+            bob.send("CHGHOST alice nope").await;
+            bob.expect("481").await; // ERR_NOPRIVILEDGES
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("CHGHOST bob :bad host").await;
+            let line = op.expect("FAIL").await;
+            assert!(line.starts_with("FAIL CHGHOST INVALID_HOST :"), "{line}");
+            op.send("CHGHOST nobody event/guest").await;
+            op.expect("401").await; // ERR_NOSUCHNICK
+
+            op.send("CHGHOST bob event/guest").await;
+            let line = op.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" changed the host of bob!~user@127.0.0.1 to event/guest"),
+                "{line}"
+            );
+            let line = bob.expect("396").await; // RPL_HOSTHIDDEN
+            assert!(line.contains(" bob event/guest :"), "{line}");
+            let line = alice.expect("CHGHOST").await;
+            assert_eq!(line, ":bob!~user@127.0.0.1 CHGHOST user event/guest");
+
+            op.send("SETHOST staff/op").await;
+            let line = op.expect("396").await; // RPL_HOSTHIDDEN
+            assert!(line.contains(" op staff/op :"), "{line}");
+            op.send("WHOIS op").await;
+            let line = op.expect("311").await; // RPL_WHOISUSER
+            assert!(line.contains(" op user staff/op * :"), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_nick_enforcement() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-enforce-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        for name in ["alice", "bob"] {
+            accounts.set(name, crate::util::hash_password("pw").unwrap());
+        }
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut carol = sim.connect();
+            let mut dave = sim.connect();
+            carol.send("NICK alice").await;
+            let notice = carol.expect("NOTICE").await;
+            assert!(notice.contains(" within 60 seconds"), "{notice}");
+            carol.register("alice").await;
+            dave.send("NICK bob").await;
+            dave.expect("NOTICE").await;
+            dave.register("bob").await;
+
+            // Logging in to the account of the nickname ends the grace period.
+            dave.login("bob", "pw").await;
+            time::advance(Duration::from_secs(59)).await;
+            sim.shared.rename_expired_nicks().await;
+            carol.send("PING sync").await;
+            let line = carol.recv().await.unwrap();
+            assert!(line.contains(" PONG "), "{line}");
+            time::advance(Duration::from_secs(1)).await;
+            sim.shared.rename_expired_nicks().await;
+            let nick = carol.expect("NICK").await;
+            assert!(
+                nick.starts_with(":alice!") && nick.contains(" NICK Guest"),
+                "{nick}"
+            );
+
+            dave.send("PING sync").await;
+            let line = dave.recv().await.unwrap();
+            assert!(line.contains(" PONG "), "{line}");
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_regain_ghost() {
+        let path = std::env::temp_dir().join(format!("ellidri-regain-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("alice", crate::util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut eve = sim.connect();
+            let mut alice = sim.connect();
+            eve.register("alice").await;
+            eve.send("REGAIN alice").await;
+            let fail = eve.expect("FAIL").await;
+            assert!(
+                fail.starts_with("FAIL REGAIN ACCOUNT_REQUIRED alice :"),
+                "{fail}"
+            );
+
+            // REGAIN against a client that is not logged in renames it.
+            alice.register("al").await;
+            alice.login("alice", "pw").await;
+            alice.send("REGAIN alice").await;
+            let nick = eve.expect("NICK").await;
+            assert!(
+                nick.starts_with(":alice!") && nick.contains(" NICK Guest"),
+                "{nick}"
+            );
+            let nick = alice.expect("NICK").await;
+            assert!(
+                nick.starts_with(":al!") && nick.ends_with(" NICK alice"),
+                "{nick}"
+            );
+
+            alice.send("GHOST alice").await;
+            let fail = alice.expect("FAIL").await;
+            assert!(
+                fail.starts_with("FAIL GHOST CANNOT_GHOST_SELF alice :"),
+                "{fail}"
+            );
+
+            // REGAIN against a stale session of the same account disconnects it.
+            let mut second = sim.connect();
+            second.register("al").await;
+            second.login("alice", "pw").await;
+            second.send("REGAIN alice").await;
+            let nick = second.expect("NICK").await;
+            assert!(
+                nick.starts_with(":al!") && nick.ends_with(" NICK alice"),
+                "{nick}"
+            );
+            let mut ghosted = false;
+            while let Some(line) = alice.recv().await {
+                ghosted |= line.starts_with("ERROR ") && line.contains("Ghosted by al");
+            }
+            assert!(ghosted);
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_always_on() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-always-on-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("alice", crate::util::hash_password("pw").unwrap());
+        accounts.set_always_on("alice", true);
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                always_on_backlog_len: 3,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            alice.login("alice", "pw").await;
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.register("bob").await;
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+
+            // Let the server see the connection closed.
+            drop(alice);
+            time::sleep(Duration::from_secs(1)).await;
+            for i in 1..=4 {
+                let target = if i % 2 == 0 { "alice" } else { "#chan" };
+                bob.send(&format!("PRIVMSG {target} :{i}")).await;
+            }
+            bob.sync().await;
+
+            let mut alice = sim.connect();
+            alice.send("CAP REQ sasl").await;
+            alice.send("NICK alice_").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            alice.send("AUTHENTICATE PLAIN").await;
+            alice.expect("AUTHENTICATE").await;
+            alice
+                .send(&format!(
+                    "AUTHENTICATE {}",
+                    base64::encode("alice\0alice\0pw")
+                ))
+                .await;
+            alice.expect("903").await; // RPL_SASLSUCCESS
+            alice.send("CAP END").await;
+            let nick = alice.expect("NICK").await;
+            assert!(
+                nick.starts_with(":alice_!") && nick.ends_with(" NICK alice"),
+                "{nick}"
+            );
+            let join = alice.expect("JOIN").await;
+            assert!(join.ends_with(" JOIN #chan"), "{join}");
+
+            // Only the last 3 messages are kept, in the order they were sent.
+            for (i, target) in [(2, "alice"), (3, "#chan"), (4, "alice")] {
+                let msg = alice.expect("PRIVMSG").await;
+                assert!(msg.starts_with(":bob!"), "{msg}");
+                assert!(msg.ends_with(&format!(" PRIVMSG {target} :{i}")), "{msg}");
+            }
+            alice.send("PING sync").await;
+            loop {
+                let line = alice.recv().await.unwrap();
+                assert!(!line.contains(" PRIVMSG "), "{line}");
+                if line.contains(" PONG ") {
+                    break;
+                }
+            }
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_offline_messages() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-offline-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("bob", crate::util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                offline_messages_len: 2,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            alice.register("alice").await;
+            for i in 1..=3 {
+                alice.send(&format!("PRIVMSG bob :{i}")).await;
+                let notice = alice.expect("NOTICE").await;
+                assert!(notice.contains(":bob is away from keyboard"), "{notice}");
+            }
+            alice.send("PRIVMSG carol :hi").await;
+            alice.expect("401").await; // ERR_NOSUCHNICK
+
+            // Only the last 2 messages are kept, and they are given in a batch at login.
+            let mut bob = sim.connect();
+            bob.send("CAP REQ :sasl batch server-time").await;
+            bob.send("NICK bob").await;
+            bob.send("USER user 0 * :Simulated user").await;
+            bob.send("AUTHENTICATE PLAIN").await;
+            bob.expect("AUTHENTICATE").await;
+            bob.send(&format!("AUTHENTICATE {}", base64::encode("bob\0bob\0pw")))
+                .await;
+            bob.expect("903").await; // RPL_SASLSUCCESS
+            bob.send("CAP END").await;
+            let start = bob.expect("BATCH").await;
+            assert!(start.ends_with(" ellidri/offline"), "{start}");
+            for i in 2..=3 {
+                let msg = bob.expect("PRIVMSG").await;
+                assert!(msg.starts_with("@batch="), "{msg}");
+                assert!(msg.contains(";time=") && !msg.contains("msgid="), "{msg}");
+                assert!(
+                    msg.ends_with(&format!(" :alice!~user@127.0.0.1 PRIVMSG bob :{i}")),
+                    "{msg}"
+                );
+            }
+            let end = bob.expect("BATCH").await;
+            assert!(end.contains(" BATCH -"), "{end}");
+            drop(bob);
+            time::sleep(Duration::from_secs(1)).await;
+
+            // Without server-time, the time is shown in the message.
+            alice.send("PRIVMSG bob :4").await;
+            alice.expect("NOTICE").await;
+            let mut bob = sim.connect();
+            bob.register("bob").await;
+            bob.login("bob", "pw").await;
+            let msg = bob.expect("PRIVMSG").await;
+            assert!(
+                msg.starts_with(":alice!~user@127.0.0.1 PRIVMSG bob :["),
+                "{msg}"
+            );
+            assert!(msg.ends_with("] 4"), "{msg}");
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_founder() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-founder-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        for name in ["alice", "bob", "carol"] {
+            accounts.set(name, crate::util::hash_password("pw").unwrap());
+        }
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg.clone()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            alice.login("alice", "pw").await;
+            bob.register("bob").await;
+            bob.login("bob", "pw").await;
+            alice.send("JOIN #chan").await;
+            alice.send("FOUNDER #chan REGISTER").await;
+            alice.sync().await;
+
+            bob.send("FOUNDER #chan TRANSFER bob").await;
+            let line = bob.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER NOT_FOUNDER #chan :"),
+                "{line}"
+            );
+            bob.send("FOUNDER #nope").await;
+            let line = bob.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER NOT_REGISTERED #nope :"),
+                "{line}"
+            );
+            alice.send("FOUNDER #chan SUCCESSOR dave").await;
+            let line = alice.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER UNKNOWN_ACCOUNT dave :"),
+                "{line}"
+            );
+
+            alice.send("FOUNDER #chan SUCCESSOR carol").await;
+            let line = alice.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :[#chan] carol will inherit the channel"),
+                "{line}"
+            );
+            bob.send("FOUNDER #chan").await;
+            let line = bob.expect("NOTICE").await;
+            assert!(line.ends_with(" :[#chan] alice is the founder"), "{line}");
+            let line = bob.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :[#chan] carol will inherit the channel"),
+                "{line}"
+            );
+
+            alice.send("FOUNDER #chan TRANSFER bob").await;
+            let line = alice.expect("NOTICE").await;
+            assert!(line.ends_with(" :[#chan] bob is the founder"), "{line}");
+            alice.send("FOUNDER #chan SUCCESSOR").await;
+            alice.expect("FAIL").await;
+            bob.send("FOUNDER #chan SUCCESSOR alice").await;
+            bob.expect("NOTICE").await;
+
+            let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+            accounts.remove("bob");
+            accounts.save().unwrap();
+            sim.rehash(cfg).await;
+            alice.send("FOUNDER #chan").await;
+            let line = alice.expect("NOTICE").await;
+            assert!(line.ends_with(" :[#chan] alice is the founder"), "{line}");
+            let line = alice.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :[#chan] Nobody will inherit this channel"),
+                "{line}"
+            );
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_channel_registration() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-registration-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        for name in ["alice", "bob"] {
+            accounts.set(name, crate::util::hash_password("pw").unwrap());
+        }
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            let mut carol = sim.connect();
+            alice.register("alice").await;
+            alice.login("alice", "pw").await;
+            bob.register("bob").await;
+            bob.login("bob", "pw").await;
+            carol.register("carol").await;
+
+            // Creating a channel while logged in doesn't register it.
+            alice.send("JOIN #chan").await;
+            let line = alice.expect("353").await; // RPL_NAMREPLY
+            assert!(line.ends_with(" :@alice"), "{line}");
+            alice.send("FOUNDER #chan").await;
+            let line = alice.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER NOT_REGISTERED #chan :"),
+                "{line}"
+            );
+
+            // The first user to join an unregistered channel is still given operator status.
+            alice.send("PART #chan").await;
+            alice.sync().await;
+            bob.send("JOIN #chan").await;
+            let line = bob.expect("353").await;
+            assert!(line.ends_with(" :@bob"), "{line}");
+
+            carol.send("JOIN #chan").await;
+            carol.send("FOUNDER #chan REGISTER").await;
+            let line = carol.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER ACCOUNT_REQUIRED #chan :"),
+                "{line}"
+            );
+            alice.send("JOIN #chan").await;
+            alice.send("FOUNDER #chan REGISTER").await;
+            alice.expect("482").await; // ERR_CHANOPRIVSNEEDED
+
+            bob.send("FOUNDER #chan REGISTER").await;
+            let line = bob.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan +O bob");
+            let line = bob.expect("NOTICE").await;
+            assert!(line.ends_with(" :[#chan] bob is the founder"), "{line}");
+            let line = alice.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan +O bob");
+            alice.send("FOUNDER #chan REGISTER").await;
+            let line = alice.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER ALREADY_REGISTERED #chan :"),
+                "{line}"
+            );
+
+            // Founder status is given and taken with MODE +O/-O, by those who have it.
+            bob.send("MODE #chan +o alice").await;
+            bob.expect("MODE").await;
+            alice.expect("MODE").await;
+            alice.send("MODE #chan +O alice").await;
+            alice.expect("482").await;
+            bob.send("MODE #chan +O alice").await;
+            bob.expect("MODE").await;
+            let line = alice.expect("MODE").await;
+            assert!(line.ends_with(" MODE #chan +O alice"), "{line}");
+            alice.send("MODE #chan -O bob").await;
+            let line = bob.expect("MODE").await;
+            assert!(line.ends_with(" MODE #chan -O bob"), "{line}");
+            // The founder can take it back.
+            bob.send("MODE #chan +O bob").await;
+            let line = bob.expect("MODE").await;
+            assert!(line.ends_with(" MODE #chan +O bob"), "{line}");
+
+            // The first user to join a registered channel is not given operator status, and the
+            // founder is given founder status.
+            for conn in [&mut alice, &mut bob, &mut carol] {
+                conn.send("PART #chan").await;
+                conn.sync().await;
+            }
+            alice.send("JOIN #chan").await;
+            let line = alice.expect("353").await;
+            assert!(line.ends_with(" :alice"), "{line}");
+            bob.send("JOIN #chan").await;
+            let line = bob.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan +O bob");
+            let line = alice.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan +O bob");
+
+            alice.send("FOUNDER #chan DROP").await;
+            let line = alice.expect("FAIL").await;
+            assert!(
+                line.starts_with("FAIL FOUNDER NOT_FOUNDER #chan :"),
+                "{line}"
+            );
+            bob.send("FOUNDER #chan DROP").await;
+            let line = bob.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan -O bob");
+            let line = bob.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :[#chan] This channel is no longer registered"),
+                "{line}"
+            );
+            let line = alice.expect("MODE").await;
+            assert_eq!(line, ":sim.ellidri MODE #chan -O bob");
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_sa_commands() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![
+                    config::Oper {
+                        name: String::from("admin"),
+                        password: crate::util::hash_password("pw").unwrap(),
+                        privileges: vec![
+                            config::OperPrivilege::SaJoin,
+                            config::OperPrivilege::SaPart,
+                            config::OperPrivilege::SaMode,
+                            config::OperPrivilege::SaNick,
+                            config::OperPrivilege::SaTopic,
+                        ],
+                        ..config::Oper::default()
+                    },
+                    config::Oper {
+                        name: String::from("helper"),
+                        password: crate::util::hash_password("pw").unwrap(),
+                        ..config::Oper::default()
+                    },
+                ],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut admin = sim.connect();
+            let mut helper = sim.connect();
+            let mut alice = sim.connect();
+            admin.register("admin").await;
+            helper.register("helper").await;
+            alice.register("alice").await;
+            admin.send("OPER admin pw").await;
+            admin.expect("381").await; // RPL_YOUREOPER
+            helper.send("OPER helper pw").await;
+            helper.expect("381").await; // RPL_YOUREOPER
+            admin.expect("NOTICE").await; // helper is now an IRC operator
+            alice.send("JOIN #locked").await;
+            alice.send("MODE #locked +it").await;
+            alice.sync().await;
+
+            alice.send("SAJOIN alice #chan").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            helper.send("SAJOIN alice #chan").await;
+            let line = helper.expect("723").await; // ERR_NOPRIVS
+            assert!(line.contains(" helper sajoin :"), "{line}");
+
+            admin.send("SAJOIN helper #locked").await;
+            let line = admin.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" forced helper!~user@127.0.0.1 to join #locked"),
+                "{line}"
+            );
+            helper.expect("JOIN").await;
+            helper.expect("366").await; // RPL_ENDOFNAMES
+            let line = alice.expect("JOIN").await;
+            assert!(line.starts_with(":helper!"), "{line}");
+
+            admin.send("SATOPIC #locked :Forced topic").await;
+            let line = alice.expect("TOPIC").await;
+            assert!(line.starts_with(":admin!"), "{line}");
+            assert!(line.ends_with(" TOPIC #locked :Forced topic"), "{line}");
+            admin.send("SAMODE #locked +o helper").await;
+            let line = alice.expect("MODE").await;
+            assert!(line.ends_with(" MODE #locked +o helper"), "{line}");
+
+            admin.send("SANICK helper alice").await;
+            admin.expect("433").await; // ERR_NICKNAMEINUSE
+            admin.send("SANICK helper guest").await;
+            let line = helper.expect("NICK").await;
+            assert!(line.starts_with(":helper!"), "{line}");
+            assert!(line.ends_with(" NICK guest"), "{line}");
+            let line = alice.expect("NICK").await;
+            assert!(line.ends_with(" NICK guest"), "{line}");
+
+            admin.send("SAPART guest #locked :bye").await;
+            let line = alice.expect("PART").await;
+            assert!(line.starts_with(":guest!"), "{line}");
+            helper.expect("PART").await;
+
+            admin.send("WHOIS admin").await;
+            let line = admin.expect("311").await; // RPL_WHOISUSER
+            assert!(line.starts_with(":sim.ellidri 311 admin admin "), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_mass_kill() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut bot1 = sim.connect();
+            let mut bot2 = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            bot1.register("bot1").await;
+            bot2.register("bot2").await;
+
+            alice.send("MASSKILL *").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("MASSKILL bot*!*@*").await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.contains(" :2 clients match bot*!*@*, "), "{line}");
+            op.send("MASSKILL 127.0.0.0/8").await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.contains(" :3 clients match 127.0.0.0/8, "), "{line}");
+            op.send("MASSKILL 10.0.0.0/8").await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.contains(" :0 clients match 10.0.0.0/8, "), "{line}");
+
+            op.send("MASSKILL bot*!*@* CONFIRM :Botnet").await;
+            let line = op.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" killed 2 clients matching bot*!*@*: Botnet"),
+                "{line}"
+            );
+            let line = bot1.expect("ERROR").await;
+            assert!(line.contains("Killed: Botnet"), "{line}");
+            bot2.expect("ERROR").await;
+            alice.send("PING alive").await;
+            alice.expect("PONG").await;
+        });
+    }
+
+    #[test]
+    fn test_trace() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut stuck = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            stuck.send("NICK stuck").await;
+
+            alice.send("TRACE").await;
+            let line = alice.expect("205").await; // RPL_TRACEUSER
+            assert!(
+                line.contains(" alice User plain alice!~user@127.0.0.1 127.0.0.1 * "),
+                "{line}"
+            );
+            assert!(line.ends_with(" :registered"), "{line}");
+            alice.expect("262").await; // RPL_TRACEEND
+            alice.send("TRACE op").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            alice.send("TRACE nobody").await;
+            alice.expect("401").await; // ERR_NOSUCHNICK
+
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            op.send("TRACE alice").await;
+            op.expect("205").await;
+            op.expect("262").await;
+            op.send("TRACE").await;
+            let line = op.expect("204").await; // RPL_TRACEOPERATOR
+            assert!(line.contains(" op Oper plain op!~user@"), "{line}");
+            op.expect("205").await;
+            let line = op.expect("203").await; // RPL_TRACEUNKNOWN
+            assert!(line.ends_with(" :nick-given"), "{line}");
+            op.expect("262").await;
+        });
+    }
+
+    #[test]
+    fn test_glob_ops() {
+        run(async {
+            let oper = |name: &str| config::Oper {
+                name: String::from(name),
+                password: crate::util::hash_password("pw").unwrap(),
+                ..config::Oper::default()
+            };
+            let cfg = config::State {
+                opers: vec![oper("op1"), oper("op2")],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op1 = sim.connect();
+            let mut op2 = sim.connect();
+            let mut alice = sim.connect();
+            op1.register("op1").await;
+            op2.register("op2").await;
+            alice.register("alice").await;
+
+            alice.send("GLOBOPS :hello").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+            op1.send("OPER op1 pw").await;
+            op1.expect("381").await; // RPL_YOUREOPER
+            op2.send("OPER op2 pw").await;
+            op2.expect("381").await;
+            op1.expect("NOTICE").await; // op2 is now an operator
+
+            op1.send("GLOBOPS :netsplit incoming").await;
+            let line = op1.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :GLOBOPS from op1: netsplit incoming"),
+                "{line}"
+            );
+            let line = op2.expect("NOTICE").await;
+            assert!(
+                line.ends_with(" :GLOBOPS from op1: netsplit incoming"),
+                "{line}"
+            );
+            alice.send("PING alive").await;
+            let line = alice.recv().await.unwrap();
+            assert!(line.contains(" PONG "), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_announcements() {
+        run(async {
+            let announcement =
+                |name: &str, interval, cron: &str, target: &str| config::Announcement {
+                    name: name.to_owned(),
+                    text: format!("{name} announcement"),
+                    interval,
+                    cron: cron.to_owned(),
+                    target: target.to_owned(),
+                };
+            let cfg = config::State {
+                announcements: vec![
+                    announcement("rules", 3600, "", ""),
+                    announcement("lobby", 0, "* * * * *", "#lobby"),
+                    announcement("ghost", 0, "* * * * *", "#nowhere"),
+                ],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            bob.send("JOIN #lobby").await;
+            bob.expect("366").await; // RPL_ENDOFNAMES
+
+            let now = crate::util::time();
+            sim.shared.send_announcements(now + 3600).await;
+            let line = alice.expect("NOTICE").await;
+            assert_eq!(line, ":sim.ellidri NOTICE alice :rules announcement");
+            let line = bob.expect("NOTICE").await;
+            assert_eq!(line, ":sim.ellidri NOTICE bob :rules announcement");
+            let line = bob.expect("NOTICE").await;
+            assert_eq!(line, ":sim.ellidri NOTICE #lobby :lobby announcement");
+
+            assert!(sim.shared.remove_announcement("lobby").await);
+            assert!(!sim.shared.remove_announcement("lobby").await);
+            let invalid = announcement("invalid", 0, "", "");
+            assert!(!sim.shared.add_announcement(invalid).await);
+            let faster = announcement("rules", 60, "", "#lobby");
+            assert!(sim.shared.add_announcement(faster).await);
+            let names: Vec<String> = (sim.shared.announcements().await)
+                .into_iter()
+                .map(|announcement| announcement.name)
+                .collect();
+            assert_eq!(names, ["ghost", "rules"]);
+
+            sim.shared.send_announcements(now + 60).await;
+            let line = bob.expect("NOTICE").await;
+            assert_eq!(line, ":sim.ellidri NOTICE #lobby :rules announcement");
+            alice.send("PING alive").await;
+            let line = alice.recv().await.unwrap();
+            assert!(line.contains(" PONG "), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_oper_require_tls() {
+        run(async {
+            let oper = |name: &str, require_tls| config::Oper {
+                name: String::from(name),
+                password: crate::util::hash_password("pw").unwrap(),
+                require_tls,
+                ..config::Oper::default()
+            };
+            let mut cfg = config::State {
+                opers: vec![oper("strict", true), oper("lax", false)],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg.clone()).await;
+            let mut plain = sim.connect();
+            let mut secure = sim.connect_tls(None);
+            plain.register("plain").await;
+            secure.register("secure").await;
+
+            plain.send("OPER strict pw").await;
+            let line = plain.expect("491").await; // ERR_NOOPERHOST
+            assert!(line.contains("TLS"), "{line}");
+            plain.send("OPER lax pw").await;
+            plain.expect("381").await; // RPL_YOUREOPER
+            secure.send("OPER strict pw").await;
+            secure.expect("381").await;
+
+            cfg.oper_require_tls = true;
+            sim.rehash(cfg).await;
+            let mut plain = sim.connect();
+            plain.register("plain2").await;
+            plain.send("OPER lax pw").await;
+            plain.expect("491").await;
+        });
+    }
+
+    #[test]
+    fn test_oper_certfp() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![
+                    config::Oper {
+                        name: String::from("both"),
+                        password: crate::util::hash_password("pw").unwrap(),
+                        certfp: String::from("AB:CD:EF"),
+                        ..config::Oper::default()
+                    },
+                    config::Oper {
+                        name: String::from("cert"),
+                        certfp: String::from("abcdef"),
+                        ..config::Oper::default()
+                    },
+                ],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut plain = sim.connect();
+            let mut stranger = sim.connect_tls(Some("123456"));
+            let mut owner = sim.connect_tls(Some("abcdef"));
+            plain.register("plain").await;
+            stranger.register("stranger").await;
+            owner.register("owner").await;
+
+            plain.send("OPER both pw").await;
+            plain.expect("464").await; // ERR_PASSWDMISMATCH
+            plain.send("OPER cert").await;
+            plain.expect("464").await;
+            stranger.send("OPER both pw").await;
+            stranger.expect("464").await;
+            owner.send("OPER both nope").await;
+            owner.expect("464").await;
+            owner.send("OPER both pw").await;
+            owner.expect("381").await; // RPL_YOUREOPER
+
+            let mut owner = sim.connect_tls(Some("ABCDEF"));
+            owner.register("owner2").await;
+            owner.send("OPER cert").await;
+            owner.expect("381").await;
+        });
+    }
+
+    #[test]
+    fn test_rehash_report() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            op.register("op").await;
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+
+            // Changes made without REHASH (e.g. SIGUSR1) are only logged.
+            sim.shared.report_rehash(&[]).await;
+            op.send("REHASH").await;
+            op.expect("382").await; // RPL_REHASHING
+            let changes = [String::from("nicklen: 32 -> 40")];
+            sim.shared.report_rehash(&changes).await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.ends_with(" :Rehash: nicklen: 32 -> 40"), "{line}");
+
+            op.send("REHASH").await;
+            op.expect("382").await;
+            sim.shared.report_rehash(&[]).await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.ends_with(" :Rehash: Nothing changed"), "{line}");
+            sim.shared.report_rehash(&changes).await;
+            op.send("PING done").await;
+            let line = op.recv().await.unwrap();
+            assert!(line.contains(" PONG "), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_multiclient() {
+        let path = std::env::temp_dir().join(format!("ellidri-multi-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("alice", crate::util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                multiclient: true,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut first = sim.connect();
+            let mut second = sim.connect();
+            for (conn, nick) in [(&mut first, "alice"), (&mut second, "alice_")] {
+                conn.login("alice", "pw").await;
+                conn.send("CAP END").await;
+                conn.register(nick).await;
+            }
+            first.sync().await;
+            second.sync().await;
+
+            first.send("AWAY :brb").await;
+            first.expect("306").await; // RPL_NOWAWAY
+            second.send("PING sync").await;
+            let mut now_away = 0;
+            loop {
+                let line = second.recv().await.unwrap();
+                if line.contains(" PONG ") {
+                    break;
+                }
+                if line.contains(" 306 ") {
+                    now_away += 1;
+                }
+            }
+            assert_eq!(now_away, 1);
+
+            first.send("LUSERS").await;
+            let line = first.expect("251").await; // RPL_LUSERCLIENT
+            assert!(line.contains(" 1 senpai(s)"), "{line}");
+            let line = first.expect("265").await; // RPL_LOCALUSERS
+            assert!(line.contains(" alice 1 1 :"), "{line}");
+            let line = first.expect("250").await; // RPL_STATSCONN
+            assert!(line.contains(" 2 connections (1 senpai(s))"), "{line}");
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_lusers_peaks() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            let mut stuck = sim.connect();
+            stuck.send("NICK stuck").await;
+            stuck.sync().await;
+            bob.send("QUIT").await;
+            bob.expect("ERROR").await;
+
+            alice.send("LUSERS").await;
+            let line = alice.expect("265").await; // RPL_LOCALUSERS
+            assert!(line.contains(" alice 1 2 :"), "{line}");
+            let line = alice.expect("266").await; // RPL_GLOBALUSERS
+            assert!(line.contains(" alice 1 2 :"), "{line}");
+            let line = alice.expect("250").await; // RPL_STATSCONN
+            assert!(line.contains(" 3 connections (2 senpai(s))"), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_isupport() {
+        use ellidri_tokens::isupport::{self, Token};
+
+        run(async {
+            let cfg = config::State {
+                chanlimit: 10,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            alice.send("NICK alice").await;
+            alice.send("USER user 0 * :Simulated user").await;
+            let mut lines = Vec::new();
+            loop {
+                let line = alice.recv().await.unwrap();
+                if line.contains(" 422 ") {
+                    break;
+                }
+                lines.push(line);
+            }
+            let mut tokens = Vec::new();
+            for line in &lines {
+                tokens.extend(isupport::tokens(&Message::parse(line).unwrap()));
+            }
+
+            let prefix = [('O', '~'), ('o', '@'), ('h', '%'), ('v', '+')];
+            assert!(tokens.contains(&Token::Prefix(prefix.to_vec())));
+            assert!(tokens.contains(&Token::ChanLimit(vec![("#&", Some(10))])));
+            assert!(tokens.contains(&Token::CaseMapping("ascii")));
+            assert!(tokens.contains(&Token::Limit("NICKLEN", Some(32))));
+            let chanmodes = tokens.iter().find_map(|token| match token {
+                Token::ChanModes(chanmodes) => Some(chanmodes),
+                _ => None,
+            });
+            let chanmodes = chanmodes.unwrap();
+            assert_eq!(chanmodes.takes_param('H', true), Some(true));
+            assert_eq!(chanmodes.takes_param('H', false), Some(false));
+            assert_eq!(chanmodes.takes_param('b', false), Some(true));
+        });
+    }
+
+    #[test]
+    fn test_spamfilter() {
+        run(async {
+            let cfg = config::State {
+                opers: vec![config::Oper {
+                    name: String::from("op"),
+                    password: crate::util::hash_password("pw").unwrap(),
+                    ..config::Oper::default()
+                }],
+                spamfilters: vec![
+                    config::SpamFilter {
+                        pattern: String::from("*buy cheap*"),
+                        action: config::SpamAction::Block,
+                        targets: config::all_spam_targets(),
+                        reason: String::new(),
+                    },
+                    config::SpamFilter {
+                        pattern: String::from("/^spam\\d+$/"),
+                        action: config::SpamAction::Kill,
+                        targets: vec![config::SpamTarget::Privmsg],
+                        reason: String::from("No spam"),
+                    },
+                ],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            op.register("op").await;
+            op.send("OPER op pw").await;
+            op.expect("381").await; // RPL_YOUREOPER
+            alice.register("alice").await;
+            bob.register("bob").await;
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+
+            alice.send("PRIVMSG #chan :Buy cheap stuff").await;
+            let line = alice.expect("NOTICE").await;
+            assert!(line.ends_with(" :Senpai, ellidri won't let this message through!"));
+            let line = op.expect("NOTICE").await;
+            assert!(
+                line.ends_with(
+                    " :Spamfilter *buy cheap* (block) matched PRIVMSG from \
+                     alice!~user@127.0.0.1: Buy cheap stuff"
+                ),
+                "{line}"
+            );
+            alice.send("PART #chan :buy cheap now").await;
+            let line = bob.expect("PART").await;
+            assert!(line.ends_with(" PART #chan"), "{line}");
+            let line = op.expect("NOTICE").await;
+            assert!(line.contains(" matched PART from alice!"), "{line}");
+
+            op.send("SPAMFILTER ADD warn *hello* :Greetings").await;
+            op.expect("NOTICE").await;
+            bob.send("PRIVMSG #chan :hello there").await;
+            bob.sync().await;
+            let line = op.expect("NOTICE").await;
+            assert!(
+                line.contains(" :Spamfilter *hello* (warn) matched "),
+                "{line}"
+            );
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await;
+            alice.send("PRIVMSG #chan :hello bob").await;
+            let line = bob.expect("PRIVMSG").await;
+            assert!(line.ends_with(" PRIVMSG #chan :hello bob"), "{line}");
+            op.expect("NOTICE").await;
+
+            op.send("SPAMFILTER LIST").await;
+            let mut filters = Vec::new();
+            loop {
+                let line = op.expect("NOTICE").await;
+                let (_, filter) = line.split_once(" :").unwrap();
+                if filter == "End of spamfilter list" {
+                    break;
+                }
+                filters.push(filter.to_owned());
+            }
+            assert_eq!(
+                filters,
+                [
+                    "*buy cheap* block",
+                    "/^spam\\d+$/ kill No spam",
+                    "*hello* warn Greetings"
+                ]
+            );
+            op.send("SPAMFILTER DEL *hello*").await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.ends_with(" :Okay! This spamfilter is gone"), "{line}");
+            op.send("SPAMFILTER DEL *hello*").await;
+            let line = op.expect("NOTICE").await;
+            assert!(line.ends_with(" :There is no spamfilter with this pattern, senpai"));
+            alice.send("SPAMFILTER LIST").await;
+            alice.expect("481").await; // ERR_NOPRIVILEDGES
+
+            bob.send("PRIVMSG alice :spam42").await;
+            let line = bob.expect("ERROR").await;
+            assert!(line.contains("Killed: No spam"), "{line}");
+
+            op.send("SPAMFILTER ADD gline *evil* :Evil").await;
+            op.expect("NOTICE").await;
+            alice.send("QUIT :evil plans").await;
+            let line = alice.expect("ERROR").await;
+            assert!(line.contains("not welcome"), "{line}");
+            let mut carol = sim.connect();
+            carol.send("NICK carol").await;
+            carol.send("USER user 0 * :Simulated user").await;
+            let line = carol.expect("ERROR").await;
+            assert!(line.contains("not welcome"), "{line}");
+        });
+    }
+
+    #[test]
+    fn test_motd_vars() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+
+            let motd = "Welcome to {server}\n{users} users, {unknown} {users\n";
+            sim.state().set_motd(Some(String::from(motd))).await;
+            alice.send("MOTD").await;
+            alice.expect("375").await; // RPL_MOTDSTART
+            let welcome = alice.expect("372").await; // RPL_MOTD
+            assert!(welcome.ends_with(" :- Welcome to sim.ellidri"), "{welcome}");
+            let users = alice.expect("372").await;
+            assert!(users.ends_with(" :- 2 users, {unknown} {users"), "{users}");
+            alice.expect("376").await; // RPL_ENDOFMOTD
+
+            sim.state().set_motd(None).await;
+            alice.send("MOTD").await;
+            alice.expect("422").await; // ERR_NOMOTD
+        });
+    }
+
+    #[test]
+    fn test_topic_history() {
+        run(async {
+            let cfg = config::State {
+                topic_history_len: 2,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            op.send("JOIN #chan").await;
+            op.expect("366").await; // RPL_ENDOFNAMES
+            for topic in ["first", "second", "third"] {
+                op.send(&format!("TOPIC #chan :{topic}")).await;
+                op.expect("TOPIC").await;
+            }
+
+            // Only members can see the history.
+            alice.send("TOPICHISTORY #chan").await;
+            alice.expect("442").await; // ERR_NOTONCHANNEL
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await;
+            alice.send("TOPICHISTORY #chan LIST").await;
+            let second = alice.expect("NOTICE").await;
+            assert!(second.contains(" :#chan 1 op "), "{second}");
+            assert!(second.ends_with(": second"), "{second}");
+            let first = alice.expect("NOTICE").await;
+            assert!(first.contains(" :#chan 2 op "), "{first}");
+            assert!(first.ends_with(": first"), "{first}");
+            let end = alice.expect("NOTICE").await;
+            assert!(end.ends_with(" :#chan End of topic history"), "{end}");
+
+            // Restoring a topic is like setting it with TOPIC.
+            alice.send("TOPICHISTORY #chan RESTORE 1").await;
+            alice.expect("482").await; // ERR_CHANOPRIVSNEEDED
+            op.send("TOPICHISTORY #chan RESTORE 3").await;
+            let reply = op.expect("NOTICE").await;
+            assert!(
+                reply.ends_with(" :This topic isn't in the history, senpai"),
+                "{reply}"
+            );
+            op.send("TOPICHISTORY #chan RESTORE 2").await;
+            let topic = alice.expect("TOPIC").await;
+            assert!(topic.ends_with(" TOPIC #chan :first"), "{topic}");
+
+            op.send("TOPICHISTORY #chan").await;
+            let third = op.expect("NOTICE").await;
+            assert!(third.ends_with(": third"), "{third}");
+            let second = op.expect("NOTICE").await;
+            assert!(second.ends_with(": second"), "{second}");
+            let end = op.expect("NOTICE").await;
+            assert!(end.ends_with(" End of topic history"), "{end}");
+        });
+    }
+
+    #[test]
+    fn test_binding_options() {
+        run(async {
+            let cfg = config::State {
+                password: crate::util::hash_password("global").unwrap(),
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let local = BindingOptions {
+                password: crate::util::hash_password("local").unwrap(),
+                ..BindingOptions::default()
+            };
+
+            // The password of the binding replaces the global one.
+            let mut rejected = sim.connect_with(local.clone());
+            rejected.send("PASS global").await;
+            rejected.send("NICK rejected").await;
+            rejected.send("USER user 0 * :Simulated user").await;
+            let error = rejected.expect("ERROR").await;
+            assert_eq!(error, "ERROR :You're not senpai!");
+            let mut alice = sim.connect_with(local);
+            alice.send("PASS local").await;
+            alice.register("alice").await;
+            let mut bob = sim.connect();
+            bob.send("PASS global").await;
+            bob.register("bob").await;
+
+            // The address of clients on Tor bindings is hidden.
+            let tor = BindingOptions {
+                tor: true,
+                ..BindingOptions::default()
+            };
+            let mut carol = sim.connect_with(tor);
+            carol.send("PASS global").await;
+            carol.register("carol").await;
+            alice.send("WHOIS carol").await;
+            let whois = alice.expect("311").await; // RPL_WHOISUSER
+            assert!(whois.contains(" carol user tor.onion "), "{whois}");
+            alice.send("WHOIS bob").await;
+            let whois = alice.expect("311").await;
+            assert!(whois.contains(" bob user 127.0.0.1 "), "{whois}");
+        });
+    }
+
+    #[test]
+    fn test_mute() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut op = sim.connect();
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            op.register("op").await;
+            alice.register("alice").await;
+            bob.register("bob").await;
+            op.send("JOIN #chan").await;
+            op.expect("366").await; // RPL_ENDOFNAMES
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await;
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+
+            alice.send("MUTE #chan bob 10m").await;
+            alice.expect("482").await; // ERR_CHANOPRIVSNEEDED
+            op.send("MUTE #chan bob soon").await;
+            let fail = op.expect("FAIL").await;
+            assert!(
+                fail.starts_with("FAIL MUTE INVALID_DURATION soon :"),
+                "{fail}"
+            );
+            op.send("MUTE #chan carol 10m").await;
+            op.expect("401").await; // ERR_NOSUCHNICK
+
+            let start = crate::util::time();
+            op.send("MUTE #chan bob 10m").await;
+            let notice = op.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :[#chan] op muted bob for 600 seconds"),
+                "{notice}"
+            );
+            let notice = bob.expect("NOTICE").await;
+            assert!(notice.ends_with(" :[#chan] op muted bob for 600 seconds"));
+            bob.send("PRIVMSG #chan :hello?").await;
+            bob.expect("404").await; // ERR_CANNOTSENDTOCHAN
+
+            // Mutes are lifted with UNMUTE...
+            op.send("UNMUTE #chan bob").await;
+            let notice = op.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :[#chan] bob can talk again~"),
+                "{notice}"
+            );
+            bob.expect("NOTICE").await;
+            bob.send("PRIVMSG #chan :hello!").await;
+            assert!(alice.expect("PRIVMSG").await.ends_with(" :hello!"));
+            op.send("UNMUTE #chan bob").await;
+            let fail = op.expect("FAIL").await;
+            assert!(fail.starts_with("FAIL UNMUTE NOT_MUTED bob :"), "{fail}");
+
+            // ... or once they expire.
+            op.send("MUTE #chan bob 10m").await;
+            op.expect("NOTICE").await;
+            bob.expect("NOTICE").await;
+            sim.state().expire_mutes(start + 599).await;
+            bob.send("PRIVMSG #chan :still there?").await;
+            bob.expect("404").await;
+            sim.state().expire_mutes(start + 660).await;
+            let notice = bob.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :[#chan] bob can talk again~"),
+                "{notice}"
+            );
+            let notice = op.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :[#chan] bob can talk again~"),
+                "{notice}"
+            );
+            bob.send("PRIVMSG #chan :back").await;
+            assert!(alice.expect("PRIVMSG").await.ends_with(" :back"));
+        });
+    }
+
+    #[test]
+    fn test_auto_away() {
+        let path =
+            std::env::temp_dir().join(format!("ellidri-auto-away-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_owned();
+        let mut accounts = crate::accounts::Accounts::open(&path).unwrap();
+        accounts.set("bob", crate::util::hash_password("pw").unwrap());
+        accounts.save().unwrap();
+
+        run(async move {
+            let cfg = config::State {
+                sasl_backend: config::SaslBackend::File,
+                account_db: path.clone(),
+                auto_away_delay: 300,
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg.clone()).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.send("CAP REQ away-notify").await;
+            alice.send("CAP END").await;
+            alice.register("alice").await;
+            bob.login("bob", "pw").await;
+            bob.send("CAP END").await;
+            bob.register("bob").await;
+            let start = crate::util::time();
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+
+            alice.send("AUTOAWAY").await;
+            let notice = alice.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" after 300 seconds of silence"),
+                "{notice}"
+            );
+            bob.send("AUTOAWAY soon").await;
+            let fail = bob.expect("FAIL").await;
+            assert!(
+                fail.starts_with("FAIL AUTOAWAY INVALID_DURATION soon :"),
+                "{fail}"
+            );
+            bob.send("AUTOAWAY 10m").await;
+            let notice = bob.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" after 600 seconds of silence"),
+                "{notice}"
+            );
+            sim.rehash(cfg).await; // Waits for the account database to be written.
+            let accounts = crate::accounts::Accounts::open(&path).unwrap();
+            assert_eq!(accounts.auto_away("bob"), Some(600));
+
+            sim.state().set_idle_clients_away(start + 350).await;
+            alice.expect("306").await; // RPL_NOWAWAY
+            bob.send("PING sync").await;
+            assert!(bob.recv().await.unwrap().contains(" PONG "));
+
+            sim.state().set_idle_clients_away(start + 700).await;
+            bob.expect("306").await;
+            let away = alice.expect("AWAY").await;
+            assert!(
+                away.starts_with(":bob!")
+                    && away.ends_with(" AWAY :Senpai fell asleep on the keyboard... zzz"),
+                "{away}"
+            );
+
+            // Automatic away is lifted as soon as the user talks again.
+            bob.send("PRIVMSG #chan :I'm back").await;
+            bob.expect("305").await; // RPL_UNAWAY
+            let away = alice.expect("AWAY").await;
+            assert!(
+                away.starts_with(":bob!") && away.ends_with(" AWAY"),
+                "{away}"
+            );
+
+            bob.send("AUTOAWAY OFF").await;
+            let notice = bob.expect("NOTICE").await;
+            assert!(notice.ends_with(" :ellidri won't mark you away, even if you fall asleep~"));
+            sim.state().set_idle_clients_away(start + 100_000).await;
+            bob.send("PING sync").await;
+            assert!(bob.recv().await.unwrap().contains(" PONG "));
+
+            let _ = std::fs::remove_file(&path);
+        });
+    }
+
+    #[test]
+    fn test_channel_logs() {
+        let dir = std::env::temp_dir().join(format!("ellidri-chanlog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        run(async move {
+            let cfg = config::State {
+                channel_log_dir: dir.to_str().unwrap().to_owned(),
+                logged_channels: vec![String::from("#Logged")],
+                ..config::State::default()
+            };
+            let mut sim = Simulation::new(cfg).await;
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            alice.send("JOIN #logged,#other").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            alice.expect("366").await;
+            bob.send("JOIN #logged").await;
+            bob.expect("366").await;
+            alice.send("PRIVMSG #logged :hello").await;
+            alice.send("PRIVMSG #other :unlogged").await;
+            alice.send("PRIVMSG #logged :\x01ACTION waves\x01").await;
+            alice.send("NOTICE #logged :psst").await;
+            alice.send("TOPIC #logged :Logged channel").await;
+            alice.send("MODE #logged +m").await;
+            alice.sync().await;
+            bob.send("NICK robert").await;
+            bob.send("PART #logged :bye").await;
+            bob.send("JOIN #logged").await;
+            bob.expect("366").await;
+            alice.send("KICK #logged robert :spam").await;
+            alice.expect("KICK").await;
+            alice.send("QUIT :done").await;
+            alice.expect("ERROR").await;
+
+            let expected = [
+                "*** Joins: alice (~user@127.0.0.1)",
+                "*** Joins: bob (~user@127.0.0.1)",
+                "<alice> hello",
+                "* alice waves",
+                "-alice- psst",
+                "*** alice changes topic to 'Logged channel'",
+                "*** alice sets mode: +m",
+                "*** bob is now known as robert",
+                "*** Parts: robert (~user@127.0.0.1) (bye)",
+                "*** Joins: robert (~user@127.0.0.1)",
+                "*** robert was kicked by alice (spam)",
+                "*** Quits: alice (~user@127.0.0.1) (This senpai left a note: done)",
+            ];
+            let channel_dir = dir.join("#logged");
+            let mut lines = Vec::new();
+            for _ in 0..1000 {
+                lines = read_logs(&channel_dir);
+                if lines.len() == expected.len() {
+                    break;
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            let texts: Vec<&str> = lines
+                .iter()
+                .map(|line| line.split_once("] ").unwrap().1)
+                .collect();
+            assert_eq!(texts, expected);
+            assert!(!dir.join("#other").exists());
+
+            let _ = std::fs::remove_dir_all(&dir);
+        });
+    }
+
+    /// Returns the lines of all the log files in `dir`, oldest first.
+    fn read_logs(dir: &std::path::Path) -> Vec<String> {
+        let mut files: Vec<_> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect(),
+            Err(_) => return Vec::new(),
+        };
+        files.sort();
+        files
+            .iter()
+            .flat_map(|file| {
+                let content = std::fs::read_to_string(file).unwrap();
+                content.lines().map(str::to_owned).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// A hook that censors words, and blocks some events to test each callback.
+    struct Censor {
+        /// Number of messages seen by the hook.
+        messages: Arc<AtomicUsize>,
+    }
+
+    impl Hook for Censor {
+        fn on_message(&mut self, _: &ClientInfo<'_>, msg: &Message<'_>) -> Verdict {
+            self.messages.fetch_add(1, Ordering::Relaxed);
+            if msg.command == Ok(Command::Away) {
+                Verdict::Block
+            } else {
+                Verdict::Continue
+            }
+        }
+
+        fn on_register(&mut self, client: &ClientInfo<'_>) -> Verdict {
+            if client.nick == "troll" {
+                Verdict::Block
+            } else {
+                Verdict::Continue
+            }
+        }
+
+        fn on_join(&mut self, _: &ClientInfo<'_>, channel: &str) -> Verdict {
+            if channel == "#secret" {
+                Verdict::Block
+            } else {
+                Verdict::Continue
+            }
+        }
+
+        fn on_privmsg(
+            &mut self,
+            _: &ClientInfo<'_>,
+            _: Command,
+            _: &str,
+            text: &mut String,
+        ) -> Verdict {
+            if text.contains("spam") {
+                return Verdict::Block;
+            }
+            *text = text.replace("darn", "d**n");
+            Verdict::Continue
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let censor_messages = Arc::new(AtomicUsize::new(0));
+            let second_messages = Arc::new(AtomicUsize::new(0));
+            let mut hooks = Hooks::default();
+            hooks.push(Box::new(Censor {
+                messages: censor_messages.clone(),
+            }));
+            hooks.push(Box::new(Censor {
+                messages: second_messages.clone(),
+            }));
+            sim.state().set_hooks(hooks).await;
+
+            let mut troll = sim.connect();
+            troll.send("NICK troll").await;
+            troll.send("USER user 0 * :Simulated user").await;
+            let error = troll.expect("ERROR").await;
+            assert_eq!(error, "ERROR :Sorry senpai, you can't come in...");
+
+            let mut alice = sim.connect();
+            let mut bob = sim.connect();
+            alice.register("alice").await;
+            bob.register("bob").await;
+            alice.send("JOIN #secret").await;
+            alice.expect("474").await; // ERR_BANNEDFROMCHAN
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+
+            // Messages are rewritten, or dropped.
+            alice.send("PRIVMSG #chan :darn it").await;
+            let msg = bob.expect("PRIVMSG").await;
+            assert!(msg.ends_with(" PRIVMSG #chan :d**n it"), "{msg}");
+            alice.send("PRIVMSG bob :buy spam").await;
+            let notice = alice.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :Senpai, ellidri won't let this message through!"),
+                "{notice}"
+            );
+            alice.send("NOTICE bob :more spam").await;
+            alice.send("NOTICE bob :darn").await;
+            let notice = bob.expect("NOTICE").await;
+            assert!(notice.ends_with(" NOTICE bob :d**n"), "{notice}");
+
+            // The first hook that blocks a message stops the others.
+            let seen = second_messages.load(Ordering::Relaxed);
+            alice.send("AWAY :gone").await;
+            alice.send("PING sync").await;
+            assert!(alice.recv().await.unwrap().contains(" PONG "));
+            let censor_seen = censor_messages.load(Ordering::Relaxed);
+            let second_seen = second_messages.load(Ordering::Relaxed);
+            assert_eq!(second_seen, seen + 1); // PING only.
+            assert_eq!(censor_seen, second_seen + 1); // And AWAY.
+        });
+    }
+
+    #[test]
+    fn test_custom_commands() {
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut hooks = Hooks::default();
+            hooks.add_command(
+                "weather",
+                Box::new(
+                    |client: &ClientInfo<'_>, msg: &Message<'_>, rb: &mut ReplyBuffer| {
+                        if msg.num_params == 0 {
+                            return false;
+                        }
+                        let days = if msg.params[0] == "week" { 2 } else { 1 };
+                        if 1 < days {
+                            rb.lr_batch_begin();
+                        }
+                        for day in 1..=days {
+                            rb.reply(Command::Notice).fmt_trailing_param(format_args!(
+                                "{}: day {day}, sunny in {}",
+                                client.nick, msg.params[0]
+                            ));
+                        }
+                        true
+                    },
+                ),
+            );
+            sim.state().set_hooks(hooks).await;
+
+            let mut alice = sim.connect();
+            alice.send("WEATHER Paris").await;
+            alice.expect("451").await; // ERR_NOTREGISTERED
+            alice.send("CAP REQ :batch labeled-response").await;
+            alice.send("CAP END").await;
+            alice.register("alice").await;
+
+            alice.send("Weather Paris").await;
+            let notice = alice.expect("NOTICE").await;
+            assert!(
+                notice.ends_with(" :alice: day 1, sunny in Paris"),
+                "{notice}"
+            );
+            alice.send("WEATHER").await;
+            let unknown = alice.expect("421").await; // ERR_UNKNOWNCOMMAND
+            assert!(unknown.contains(" alice WEATHER :"), "{unknown}");
+
+            alice.send("@label=a WEATHER Paris").await;
+            let notice = alice.recv().await.unwrap();
+            assert!(
+                notice.starts_with("@label=a :") && notice.ends_with(" day 1, sunny in Paris"),
+                "{notice}"
+            );
+            alice.send("@label=b WEATHER week").await;
+            let batch = alice.recv().await.unwrap();
+            assert!(batch.starts_with("@label=b :"), "{batch}");
+            assert!(batch.ends_with(" BATCH +0 labeled-response"), "{batch}");
+            for day in 1..=2 {
+                let notice = alice.recv().await.unwrap();
+                assert!(notice.starts_with("@batch=0 :"), "{notice}");
+                assert!(
+                    notice.ends_with(&format!(" day {day}, sunny in week")),
+                    "{notice}"
+                );
+            }
+            let end = alice.recv().await.unwrap();
+            assert!(end.ends_with(" BATCH -0"), "{end}");
+        });
+    }
+
+    #[test]
+    fn test_events() {
+        use crate::events::Event;
+
+        run(async {
+            let mut sim = Simulation::new(config::State::default()).await;
+            let mut events = sim.state().subscribe().await;
+            let mut alice = sim.connect();
+            alice.register("alice").await;
+            let mut bob = sim.connect();
+            bob.register("bob").await;
+            alice.send("JOIN #chan").await;
+            alice.expect("366").await; // RPL_ENDOFNAMES
+            bob.send("JOIN #chan").await;
+            bob.expect("366").await;
+            alice.send("MODE #chan +m").await;
+            alice.send("PRIVMSG #chan :hello").await;
+            alice.send("NOTICE bob :psst").await;
+            alice.send("KICK #chan bob").await;
+            alice.send("QUIT :bye").await;
+            alice.expect("ERROR").await;
+            bob.sync().await;
+
+            let mut received = Vec::new();
+            while let Ok(event) = events.try_recv() {
+                received.push(event);
+            }
+            assert_eq!(received.len(), 9, "{received:?}");
+            let alice_id = match received[0] {
+                Event::Connected { id, addr } => {
+                    assert_eq!(addr.ip().to_string(), "127.0.0.1");
+                    id
+                }
+                _ => panic!("{received:?}"),
+            };
+            let checks = [
+                matches!(&received[1], Event::Registered { id, nick }
+                    if *id == alice_id && nick == "alice"),
+                matches!(&received[2], Event::Connected { id, .. } if *id != alice_id),
+                matches!(&received[3], Event::Registered { nick, .. } if nick == "bob"),
+                matches!(&received[4], Event::Mode { by, target, modes }
+                    if by == "alice" && target == "#chan" && modes == "+m"),
+                matches!(&received[5], Event::Message { from, command: Command::PrivMsg, target, text: Some(text) }
+                    if from == "alice" && target == "#chan" && text == "hello"),
+                matches!(&received[6], Event::Message { from, command: Command::Notice, target, text: Some(text) }
+                    if from == "alice" && target == "bob" && text == "psst"),
+                matches!(&received[7], Event::Kick { by, channel, kicked, reason: None }
+                    if by == "alice" && channel == "#chan" && kicked == "bob"),
+                matches!(&received[8], Event::Quit { id, nick, reason }
+                    if *id == alice_id && nick == "alice" && reason.ends_with("bye")),
+            ];
+            for (i, check) in checks.into_iter().enumerate() {
+                assert!(check, "{:?}", received[i + 1]);
+            }
+        });
+    }
+} // mod tests
@@ -0,0 +1,125 @@
+//! Server-wide filters on the content of messages.
+
+use crate::config::{self, SpamTarget};
+use crate::util;
+use regex::Regex;
+
+/// A compiled spamfilter pattern.
+pub enum Pattern {
+    /// A lowercase glob, matched against the lowercased text.
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Compiles the given pattern.  Patterns enclosed in slashes are regular expressions, others
+    /// are globs.
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(re) => Ok(Self::Regex(Regex::new(re)?)),
+            None => Ok(Self::Glob(pattern.to_lowercase())),
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Self::Glob(glob) => util::match_mask(glob, &text.to_lowercase()),
+            Self::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+pub struct Filter {
+    pub settings: config::SpamFilter,
+    pattern: Pattern,
+}
+
+/// The list of spamfilters of the server.
+#[derive(Default)]
+pub struct SpamFilters {
+    filters: Vec<Filter>,
+}
+
+impl SpamFilters {
+    /// Compiles the spamfilters of the configuration.  Invalid patterns are skipped.
+    pub fn new(settings: Vec<config::SpamFilter>) -> Self {
+        let mut res = Self::default();
+        for filter in settings {
+            let pattern = filter.pattern.clone();
+            if let Err(err) = res.add(filter) {
+                log::warn!("Invalid spamfilter pattern {:?}: {}", pattern, err);
+            }
+        }
+        res
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &config::SpamFilter> {
+        self.filters.iter().map(|filter| &filter.settings)
+    }
+
+    /// Returns the first filter that applies to `target` and matches `text`.
+    pub fn find(&self, target: SpamTarget, text: &str) -> Option<&config::SpamFilter> {
+        self.filters
+            .iter()
+            .find(|filter| {
+                filter.settings.targets.contains(&target) && filter.pattern.is_match(text)
+            })
+            .map(|filter| &filter.settings)
+    }
+
+    /// Adds a filter, or replaces the filter with the same pattern.
+    pub fn add(&mut self, settings: config::SpamFilter) -> Result<(), regex::Error> {
+        let pattern = Pattern::new(&settings.pattern)?;
+        self.remove(&settings.pattern);
+        self.filters.push(Filter { settings, pattern });
+        Ok(())
+    }
+
+    /// Returns whether a filter has been removed.
+    pub fn remove(&mut self, pattern: &str) -> bool {
+        let len = self.filters.len();
+        self.filters
+            .retain(|filter| filter.settings.pattern != pattern);
+        self.filters.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpamAction;
+
+    fn filter(pattern: &str, targets: Vec<SpamTarget>) -> config::SpamFilter {
+        config::SpamFilter {
+            pattern: pattern.to_owned(),
+            action: SpamAction::Block,
+            targets,
+            reason: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let mut filters = SpamFilters::default();
+        filters
+            .add(filter("*buy cheap*", vec![SpamTarget::Privmsg]))
+            .unwrap();
+        filters
+            .add(filter("/^spam\\d+$/", vec![SpamTarget::Quit]))
+            .unwrap();
+        assert!(filters.add(filter("/(/", vec![SpamTarget::Quit])).is_err());
+
+        assert!(filters
+            .find(SpamTarget::Privmsg, "Come BUY CHEAP stuff")
+            .is_some());
+        assert!(filters
+            .find(SpamTarget::Notice, "come buy cheap stuff")
+            .is_none());
+        assert!(filters.find(SpamTarget::Quit, "spam42").is_some());
+        assert!(filters.find(SpamTarget::Quit, "spam42!").is_none());
+
+        assert!(filters.remove("*buy cheap*"));
+        assert!(!filters.remove("*buy cheap*"));
+        assert!(filters.find(SpamTarget::Privmsg, "buy cheap").is_none());
+    }
+} // mod tests
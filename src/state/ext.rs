@@ -0,0 +1,1776 @@
+//! Handlers for commands that are not part of any specification.
+
+use super::{find_channel, find_member, find_nick, v3, CommandContext, HandlerResult as Result};
+use crate::client::MessageQueueItem;
+use crate::config::{CloneAction, FloodAction, SpamAction, SpamTarget};
+use crate::data::Request;
+use crate::hooks::Verdict;
+use crate::{config, data, help, lines, util};
+use ellidri_tokens::{rpl, Buffer, Command, Message, MessageBuffer, ReplyBuffer};
+use ellidri_unicase::u;
+use std::time::{Duration, UNIX_EPOCH};
+use std::{cmp, fmt, net};
+
+/// The local channel where server notices are sent.  Only IRC operators can join it.
+pub(super) const SERVER_NOTICE_CHANNEL: &str = "&server";
+
+/// Lockdown levels, set with `LOCKDOWN` during spam waves.  Each level adds its restriction to
+/// those of the previous levels.  IRC operators are exempt.
+///
+/// Clients that are not logged in cannot send private messages.
+pub(super) const LOCKDOWN_MESSAGES: u8 = 1;
+/// Only IRC operators can create channels.
+pub(super) const LOCKDOWN_CHANNELS: u8 = 2;
+/// Plain-text connections are refused.
+pub(super) const LOCKDOWN_PLAINTEXT: u8 = 3;
+
+/// Reason given to the clients disconnected by `MASSKILL` when the operator gave none.
+const MASS_KILL_REASON: &str = "Mass kill";
+
+/// Number of commands listed on each line of `HELP` without parameters.
+const HELP_INDEX_WIDTH: usize = 8;
+
+/// Sends a FAIL FOUNDER message with the given code, parameter and line.
+fn founder_fail(rb: &mut ReplyBuffer, code: &str, param: &str, line: &'static str) {
+    rb.message("", "FAIL")
+        .param("FOUNDER")
+        .param(code)
+        .param(param)
+        .trailing_param(&lines::tr(line));
+}
+
+impl super::StateInner {
+    /// Returns `Ok(())` when the client is allowed to manage the channel's persistent data, that
+    /// is when it is an IRC operator or a channel operator.  Otherwise returns `Err(())` and sends
+    /// an error to the client.
+    fn check_channel_manager(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        if self.clients[id].operator {
+            return Ok(());
+        }
+        let channel = find_channel(id, rb, &self.channels, channel_name)?;
+        let modes = find_member(id, rb, channel, channel_name)?;
+        if !modes.is_at_least_op() {
+            log::debug!("{}:     not operator", id);
+            rb.reply(rpl::ERR_CHANOPRIVSNEEDED)
+                .param(channel_name.get())
+                .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Sends the MODE message that gives the member modes `letters` to the client `id`, after
+    /// they have been applied on join from the persistent data of `channel_name`.
+    pub(super) fn send_join_modes(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        channel_name: &str,
+        letters: &str,
+    ) {
+        let nick = self.clients[id].nick();
+        let mut modes = String::with_capacity(letters.len() + 1);
+        modes.push('+');
+        modes.push_str(letters);
+
+        let msg = rb
+            .prefixed_message(Command::Mode)
+            .param(channel_name)
+            .param(&modes);
+        letters.chars().fold(msg, |msg, _| msg.param(nick));
+
+        let mut mode_notice = Buffer::with_capacity(128);
+        {
+            let msg = mode_notice
+                .message(&self.domain, Command::Mode)
+                .param(channel_name)
+                .param(&modes);
+            letters.chars().fold(msg, |msg, _| msg.param(nick));
+        }
+        let mode_notice = MessageQueueItem::from(mode_notice);
+
+        let channel = &self.channels[ellidri_unicase::u(channel_name)];
+        for member in channel.members.keys().filter(|m| **m != id) {
+            self.clients[*member].send(mode_notice.clone());
+        }
+    }
+
+    /// Gives founder status (`~`) to the members of `channel_name` logged in to the account `new`,
+    /// and takes it from those logged in to `old`, after the founder of the channel has changed.
+    /// The members of the channel are told with a MODE message from the server.
+    pub(super) fn move_founder_status(
+        &mut self,
+        channel_name: &str,
+        old: Option<&str>,
+        new: Option<&str>,
+    ) {
+        let channel = match self.channels.get_mut(ellidri_unicase::u(channel_name)) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let is = |account: Option<&str>, name: Option<&str>| {
+            account
+                .zip(name)
+                .is_some_and(|(account, name)| account.eq_ignore_ascii_case(name))
+        };
+
+        let mut mode_notice = Buffer::with_capacity(128);
+        for (member, modes) in &mut channel.members {
+            let client = &self.clients[*member];
+            let founder = if is(client.account(), new) {
+                true
+            } else if is(client.account(), old) {
+                false
+            } else {
+                continue;
+            };
+            if modes.founder == founder {
+                continue;
+            }
+            modes.founder = founder;
+            mode_notice
+                .message(&self.domain, Command::Mode)
+                .param(channel_name)
+                .param(if founder { "+O" } else { "-O" })
+                .param(client.nick());
+        }
+        if mode_notice.is_empty() {
+            return;
+        }
+
+        let mode_notice = MessageQueueItem::from(mode_notice);
+        for member in channel.members.keys() {
+            self.clients[*member].send(mode_notice.clone());
+        }
+    }
+
+    /// Tells the operators and halfops of `channel_name` that the client `id` has joined thanks to
+    /// an invitation from `invited_by`.
+    pub(super) fn send_invite_used(&self, id: usize, channel_name: &str, invited_by: &str) {
+        let channel = &self.channels[ellidri_unicase::u(channel_name)];
+        let nick = self.clients[id].nick();
+        for (member, _) in channel
+            .members
+            .iter()
+            .filter(|(m, modes)| **m != id && modes.is_at_least_halfop())
+        {
+            let member = &self.clients[*member];
+            let mut notice = Buffer::with_capacity(512);
+            notice
+                .message(&self.domain, Command::Notice)
+                .param(member.nick())
+                .fmt_trailing_param(lines_invite_used!(channel_name, nick, invited_by));
+            member.send(notice);
+        }
+    }
+
+    /// Applies the configured flood action to the client `id`, which has exceeded the flood limit
+    /// of `channel_name`.
+    pub(super) fn punish_flood(&mut self, id: usize, rb: &mut ReplyBuffer, channel_name: &str) {
+        let client = &self.clients[id];
+        let mask = format!("*!*@{}", client.host());
+        let channel = self
+            .channels
+            .get_mut(ellidri_unicase::u(channel_name))
+            .unwrap();
+
+        let added_mask = match self.flood_action {
+            FloodAction::Mute => channel.quiet_mask.insert(&mask).then_some("+q"),
+            FloodAction::Kickban if channel.ban_mask.len() < self.maxlist => {
+                channel.ban_mask.insert(&mask).then_some("+b")
+            }
+            FloodAction::Kickban | FloodAction::Kick => None,
+        };
+        let kick = self.flood_action != FloodAction::Mute;
+        if added_mask.is_none() && !kick {
+            return;
+        }
+
+        rb.lr_batch_begin();
+        if let Some(modes) = added_mask {
+            rb.message(&self.domain, Command::Mode)
+                .param(channel_name)
+                .param(modes)
+                .param(&mask);
+            let mut notice = Buffer::with_capacity(512);
+            notice
+                .message(&self.domain, Command::Mode)
+                .param(channel_name)
+                .param(modes)
+                .param(&mask);
+            let notice = MessageQueueItem::from(notice);
+            for member in channel.members.keys().filter(|m| **m != id) {
+                self.clients[*member].send(notice.clone());
+            }
+        }
+
+        if kick {
+            let reason = lines::tr(lines::FLOODING);
+            self.kick_member(id, rb, channel_name, None, id, Some(&reason));
+        }
+    }
+
+    /// Closes the new connection `id` if its IP address is D-lined, or if it has more clones than
+    /// allowed by its binding.
+    pub(super) fn check_clones(
+        &mut self,
+        id: usize,
+        ip: net::IpAddr,
+        options: &config::BindingOptions,
+    ) {
+        let now = util::time();
+        self.dlines.retain(|_, until| now < *until);
+        if self.dlines.contains_key(&ip) {
+            log::debug!("{}: D-lined", id);
+            self.remove_client(id, lines::tr(lines::DLINED), lines::DLINED);
+            return;
+        }
+        if options.max_clones == 0 {
+            return;
+        }
+
+        let host = self.clients[id].host();
+        let clones = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.host() == host && !client.is_detached())
+            .count();
+        if clones <= options.max_clones {
+            return;
+        }
+
+        log::debug!("{}: {} connections from {}", id, clones, ip);
+        self.send_server_notice(format_args!(
+            "Clones ({}): {} connections from {}",
+            options.clone_action.name(),
+            clones,
+            ip,
+        ));
+        match options.clone_action {
+            CloneAction::Notice => {}
+            CloneAction::Reject => {
+                self.remove_client(
+                    id,
+                    lines::tr(lines::TOO_MANY_CLONES),
+                    lines::TOO_MANY_CLONES,
+                );
+            }
+            CloneAction::Dline => {
+                self.dlines.insert(ip, now + self.dline_duration);
+                self.remove_client(id, lines::tr(lines::DLINED), lines::DLINED);
+            }
+        }
+    }
+
+    /// Gives a random challenge to the new connection `id`, if its binding requires one.
+    pub(super) fn set_challenge(&mut self, id: usize, options: &config::BindingOptions) {
+        if self.challenges.is_empty() {
+            return;
+        }
+        let client = match self.clients.get_mut(id) {
+            Some(client) => client,
+            None => return, // closed by `check_clones`
+        };
+        let host = client.host();
+        if !options
+            .challenge_hosts
+            .iter()
+            .any(|mask| util::match_mask(mask, host))
+        {
+            return;
+        }
+        let i = util::random_below(self.challenges.len());
+        client.challenge = Some(self.challenges[i].clone());
+        self.send_challenge(id);
+    }
+
+    /// Sends the question of its challenge to the client `id`.
+    pub(super) fn send_challenge(&self, id: usize) {
+        let client = &self.clients[id];
+        let challenge = match &client.challenge {
+            Some(challenge) => challenge,
+            None => return,
+        };
+        let mut notice = Buffer::with_capacity(512);
+        notice
+            .message(&self.domain, Command::Notice)
+            .param("*")
+            .fmt_trailing_param(lines_challenge!(challenge.question));
+        client.send(notice);
+    }
+
+    /// Applies spamfilters to the request of the client `id`.  Returns the request to handle, or
+    /// `None` if it must be dropped.
+    pub(super) fn filter_spam<'a>(
+        &mut self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        req: Request<'a>,
+    ) -> Option<Request<'a>> {
+        let (target, text) = match req {
+            Request::MessageAll(data::req::MessageAll {
+                command, content, ..
+            })
+            | Request::MessageChannel(data::req::MessageChannel {
+                command, content, ..
+            })
+            | Request::MessageUser(data::req::MessageUser {
+                command, content, ..
+            }) => match (command, content) {
+                (Command::PrivMsg, Some(text)) => (SpamTarget::Privmsg, text),
+                (Command::Notice, Some(text)) => (SpamTarget::Notice, text),
+                _ => return Some(req),
+            },
+            Request::Part(data::req::Part {
+                reason: Some(text), ..
+            }) => (SpamTarget::Part, text),
+            Request::Quit(Some(text)) => (SpamTarget::Quit, text),
+            _ => return Some(req),
+        };
+        let filter = match self.spamfilters.find(target, text) {
+            Some(filter) => filter.clone(),
+            None => return Some(req),
+        };
+
+        log::debug!("{}:     matched spamfilter {:?}", id, filter.pattern);
+        self.send_spamfilter_notice(id, &filter, target, text);
+        match filter.action {
+            SpamAction::Block => match req {
+                Request::Part(args) => Some(Request::Part(data::req::Part {
+                    reason: None,
+                    ..args
+                })),
+                Request::Quit(_) => Some(Request::Quit(None)),
+                _ => {
+                    if target == SpamTarget::Privmsg {
+                        rb.reply(Command::Notice)
+                            .trailing_param(&lines::tr(lines::MESSAGE_BLOCKED));
+                    }
+                    None
+                }
+            },
+            SpamAction::Warn => Some(req),
+            SpamAction::Kill => {
+                self.remove_client(id, format_args!("Killed: {}", filter.reason), "Killed");
+                None
+            }
+            SpamAction::Gline => {
+                let mask = format!("*!*@{}", self.clients[id].host());
+                self.glines.insert(&mask);
+                self.remove_client(id, lines::tr(lines::GLINED), lines::GLINED);
+                None
+            }
+        }
+    }
+
+    /// Runs the `on_privmsg` hooks on the request of the client `id`.  Returns the request to
+    /// handle, with the text given by the hooks, or `None` if it must be dropped.
+    ///
+    /// `text` holds the new text of the message.
+    pub(super) fn apply_hooks<'a>(
+        &mut self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        req: Request<'a>,
+        text: &'a mut String,
+    ) -> Option<Request<'a>> {
+        if self.hooks.is_empty() {
+            return Some(req);
+        }
+        let (command, target, content) = match &req {
+            Request::MessageChannel(data::req::MessageChannel {
+                command,
+                to,
+                content,
+                ..
+            }) => (*command, to.get(), *content),
+            Request::MessageUser(data::req::MessageUser {
+                command,
+                to,
+                content,
+                ..
+            }) => (*command, to.get(), *content),
+            _ => return Some(req),
+        };
+        let content = match (command, content) {
+            (Command::PrivMsg | Command::Notice, Some(content)) => content,
+            _ => return Some(req),
+        };
+
+        text.push_str(content);
+        let verdict = self
+            .hooks
+            .privmsg(id, &self.clients[id], command, target, text);
+        if verdict == Verdict::Block {
+            log::debug!("{}:     blocked by a hook", id);
+            if command == Command::PrivMsg {
+                rb.reply(Command::Notice)
+                    .trailing_param(&lines::tr(lines::MESSAGE_BLOCKED));
+            }
+            return None;
+        }
+
+        let content = Some(text.as_str());
+        Some(match req {
+            Request::MessageChannel(args) => {
+                Request::MessageChannel(data::req::MessageChannel { content, ..args })
+            }
+            Request::MessageUser(args) => {
+                Request::MessageUser(data::req::MessageUser { content, ..args })
+            }
+            req => req,
+        })
+    }
+
+    /// Tells IRC operators that the client `id` has sent a message that matched `filter`.
+    fn send_spamfilter_notice(
+        &self,
+        id: usize,
+        filter: &config::SpamFilter,
+        target: SpamTarget,
+        text: &str,
+    ) {
+        self.send_server_notice(format_args!(
+            "Spamfilter {} ({}) matched {} from {}: {}",
+            filter.pattern,
+            filter.action.name(),
+            target.name(),
+            self.clients[id].full_name(),
+            text,
+        ));
+    }
+
+    /// Sends a notice to the members of `SERVER_NOTICE_CHANNEL`, and privately to the IRC
+    /// operators that are not in it.
+    pub(super) fn send_server_notice(&self, text: fmt::Arguments<'_>) {
+        let text = text.to_string();
+        let channel = self.channels.get(u(SERVER_NOTICE_CHANNEL));
+
+        let mut notice = Buffer::with_capacity(512);
+        notice
+            .message(&self.domain, Command::Notice)
+            .param(SERVER_NOTICE_CHANNEL)
+            .trailing_param(&text);
+        let channel_notice = MessageQueueItem::from(notice);
+
+        for (id, client) in self.clients.iter().filter(|(_, c)| c.session.is_none()) {
+            if channel.is_some_and(|channel| channel.members.contains_key(&id)) {
+                client.send(channel_notice.clone());
+            } else if client.operator {
+                let mut notice = Buffer::with_capacity(512);
+                notice
+                    .message(&self.domain, Command::Notice)
+                    .param(client.nick())
+                    .trailing_param(&text);
+                client.send(MessageQueueItem::from(notice));
+            }
+        }
+    }
+
+    /// Writes an action of an IRC operator to the log, and tells the other IRC operators.
+    fn audit(&self, text: fmt::Arguments<'_>) {
+        log::info!("{}", text);
+        self.send_server_notice(text);
+    }
+
+    /// Returns `Ok(())` when the client is an IRC operator with the given privilege.  Otherwise
+    /// returns `Err(())` and sends an error to the client.
+    fn check_privilege(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        privilege: config::OperPrivilege,
+    ) -> Result {
+        self.check_operator(id, rb)?;
+        if !self.clients[id].oper_privileges.contains(&privilege) {
+            log::debug!("{}:     missing privilege {}", id, privilege.name());
+            rb.reply(rpl::ERR_NOPRIVS)
+                .param(privilege.name())
+                .trailing_param(&lines::tr(lines::NO_PRIVS));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Handles a command as if the client `id` had sent it, on behalf of the client `issuer`.
+    /// The replies are sent to `id`.
+    pub(super) fn run_as(
+        &mut self,
+        issuer: usize,
+        id: usize,
+        f: impl FnOnce(&mut Self, CommandContext<'_>) -> Result,
+    ) -> Result {
+        let mut rb = self.clients[id].reply("");
+        let ctx = CommandContext {
+            id,
+            conn: id,
+            rb: &mut rb,
+            client_tags: "",
+        };
+        let res = f(self, ctx);
+        if !rb.is_empty() {
+            self.clients[id].send(rb);
+        }
+        // Replies to the issuer must be addressed to it again.
+        ReplyBuffer::set_nick(self.clients[issuer].nick());
+        res
+    }
+
+    /// Returns `Ok(())` when the client is an IRC operator.  Otherwise returns `Err(())` and
+    /// sends an error to the client.
+    fn check_operator(&self, id: usize, rb: &mut ReplyBuffer) -> Result {
+        if !self.clients[id].operator {
+            rb.reply(rpl::ERR_NOPRIVILEDGES)
+                .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
+            return Err(());
+        }
+        Ok(())
+    }
+
+    // ACCESS
+
+    pub fn cmd_access_list(
+        &self,
+        ctx: CommandContext<'_>,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        self.check_channel_manager(ctx.id, ctx.rb, channel_name)?;
+
+        ctx.rb.lr_batch_begin();
+        for entry in self.store.access(channel_name.get()) {
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(format_args!(
+                    "{} {} {}",
+                    channel_name.get(),
+                    entry.level.letter(),
+                    entry.mask
+                ));
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(format_args!(
+                "{} {}",
+                channel_name.get(),
+                lines::tr(lines::END_OF_ACCESS_LIST)
+            ));
+
+        Ok(())
+    }
+
+    pub fn cmd_access_add(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::AccessAdd<'_>,
+    ) -> Result {
+        self.check_channel_manager(ctx.id, ctx.rb, args.channel)?;
+
+        self.store
+            .add_access(args.channel.get(), args.mask, args.level);
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::ACCESS_ADDED));
+
+        Ok(())
+    }
+
+    pub fn cmd_access_del(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::AccessDel<'_>,
+    ) -> Result {
+        self.check_channel_manager(ctx.id, ctx.rb, args.channel)?;
+
+        if !self.store.remove_access(args.channel.get(), args.mask) {
+            log::debug!("{}:     not on access list", ctx.id);
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::ACCESS_NOT_FOUND));
+            return Err(());
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::ACCESS_DELETED));
+
+        Ok(())
+    }
+
+    // FOUNDER
+
+    /// Gives the channels whose founder's account has been dropped to their successor.
+    pub(super) fn inherit_founders(&mut self) {
+        if self.sasl_backend != config::SaslBackend::File || self.accounts.is_empty() {
+            // Accounts are unknown, they can't be told apart from dropped ones.
+            return;
+        }
+        let accounts = &self.accounts;
+        for (channel, old, new) in self.store.inherit_founders(|name| accounts.contains(name)) {
+            log::info!("{} inherited {}", new, channel);
+            self.move_founder_status(&channel, Some(&old), Some(&new));
+        }
+    }
+
+    /// Returns `Ok(())` when the client is logged in to the account of the founder of the
+    /// channel, or is an IRC operator.  Otherwise returns `Err(())` and sends an error.
+    fn check_founder(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        if self.store.founder(channel_name.get()).is_none() {
+            log::debug!("{}:     not registered", id);
+            founder_fail(
+                rb,
+                "NOT_REGISTERED",
+                channel_name.get(),
+                lines::CHANNEL_NOT_REGISTERED,
+            );
+            return Err(());
+        }
+        let client = &self.clients[id];
+        let is_founder = client
+            .account()
+            .is_some_and(|account| self.store.is_founder(channel_name.get(), account));
+        if !is_founder && !client.operator {
+            log::debug!("{}:     not founder", id);
+            founder_fail(rb, "NOT_FOUNDER", channel_name.get(), lines::NOT_FOUNDER);
+            return Err(());
+        }
+        Ok(())
+    }
+
+    /// Returns `Ok(())` when `account` exists.  Otherwise returns `Err(())` and sends an error.
+    fn check_account(&self, id: usize, rb: &mut ReplyBuffer, account: &str) -> Result {
+        if !self.accounts.contains(account) {
+            log::debug!("{}:     unknown account", id);
+            founder_fail(rb, "UNKNOWN_ACCOUNT", account, lines::NO_SUCH_ACCOUNT);
+            return Err(());
+        }
+        Ok(())
+    }
+
+    pub fn cmd_founder_get(
+        &self,
+        ctx: CommandContext<'_>,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        let channel = channel_name.get();
+        let founder = match self.store.founder(channel) {
+            Some(founder) => founder,
+            None => {
+                founder_fail(
+                    ctx.rb,
+                    "NOT_REGISTERED",
+                    channel,
+                    lines::CHANNEL_NOT_REGISTERED,
+                );
+                return Err(());
+            }
+        };
+
+        ctx.rb.lr_batch_begin();
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_founder!(channel, founder));
+        match self.store.successor(channel) {
+            Some(successor) => ctx
+                .rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(lines_successor!(channel, successor)),
+            None => ctx
+                .rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(format_args!(
+                    "[{}] {}",
+                    channel,
+                    lines::tr(lines::NO_SUCCESSOR)
+                )),
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_founder_register(
+        &mut self,
+        ctx: CommandContext<'_>,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        let account = match self.clients[ctx.id].account() {
+            Some(account) => account.to_owned(),
+            None => {
+                log::debug!("{}:     not logged in", ctx.id);
+                founder_fail(
+                    ctx.rb,
+                    "ACCOUNT_REQUIRED",
+                    channel_name.get(),
+                    lines::NOT_LOGGED_IN,
+                );
+                return Err(());
+            }
+        };
+        if self.store.founder(channel_name.get()).is_some() {
+            log::debug!("{}:     already registered", ctx.id);
+            founder_fail(
+                ctx.rb,
+                "ALREADY_REGISTERED",
+                channel_name.get(),
+                lines::CHANNEL_ALREADY_REGISTERED,
+            );
+            return Err(());
+        }
+        self.check_channel_manager(ctx.id, ctx.rb, channel_name)?;
+
+        self.store.set_founder(channel_name.get(), &account);
+        self.move_founder_status(channel_name.get(), None, Some(&account));
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_founder!(channel_name.get(), account));
+
+        Ok(())
+    }
+
+    pub fn cmd_founder_drop(
+        &mut self,
+        ctx: CommandContext<'_>,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        self.check_founder(ctx.id, ctx.rb, channel_name)?;
+
+        let old = self.store.unregister(channel_name.get());
+        self.move_founder_status(channel_name.get(), old.as_deref(), None);
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(format_args!(
+                "[{}] {}",
+                channel_name.get(),
+                lines::tr(lines::CHANNEL_DROPPED)
+            ));
+
+        Ok(())
+    }
+
+    pub fn cmd_founder_transfer(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::FounderTransfer<'_>,
+    ) -> Result {
+        self.check_founder(ctx.id, ctx.rb, args.channel)?;
+        self.check_account(ctx.id, ctx.rb, args.account)?;
+
+        let old = self.store.founder(args.channel.get()).map(str::to_owned);
+        self.store.set_founder(args.channel.get(), args.account);
+        self.move_founder_status(args.channel.get(), old.as_deref(), Some(args.account));
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_founder!(args.channel.get(), args.account));
+
+        Ok(())
+    }
+
+    pub fn cmd_founder_successor(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::FounderSuccessor<'_>,
+    ) -> Result {
+        self.check_founder(ctx.id, ctx.rb, args.channel)?;
+        if let Some(account) = args.account {
+            self.check_account(ctx.id, ctx.rb, account)?;
+        }
+
+        self.store.set_successor(args.channel.get(), args.account);
+        match args.account {
+            Some(account) => ctx
+                .rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(lines_successor!(args.channel.get(), account)),
+            None => ctx
+                .rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(format_args!(
+                    "[{}] {}",
+                    args.channel.get(),
+                    lines::tr(lines::NO_SUCCESSOR)
+                )),
+        }
+
+        Ok(())
+    }
+
+    // AUTOAWAY
+
+    pub fn cmd_auto_away(&mut self, ctx: CommandContext<'_>, delay: Option<&str>) -> Result {
+        let client = &mut self.clients[ctx.id];
+        if let Some(delay) = delay {
+            let delay = if delay.eq_ignore_ascii_case("OFF") {
+                0
+            } else if let Some(delay) = util::parse_duration(delay) {
+                delay
+            } else {
+                ctx.rb
+                    .message("", "FAIL")
+                    .param("AUTOAWAY")
+                    .param("INVALID_DURATION")
+                    .param(delay)
+                    .trailing_param(&lines::tr(lines::INVALID_DURATION));
+                return Err(());
+            };
+            client.auto_away_delay = Some(delay);
+            if let Some(account) = client.account() {
+                self.accounts.set_auto_away(account, delay);
+            }
+        }
+
+        let delay = client.auto_away_delay.unwrap_or(self.auto_away_delay);
+        if delay == 0 {
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::AUTO_AWAY_OFF));
+        } else {
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(lines_auto_away!(delay));
+        }
+
+        Ok(())
+    }
+
+    // AUTOREPLAY
+
+    pub fn cmd_autoreplay(&mut self, ctx: CommandContext<'_>, len: Option<&str>) -> Result {
+        let client = &mut self.clients[ctx.id];
+        if let Some(len) = len {
+            let len = if len.eq_ignore_ascii_case("OFF") {
+                0
+            } else if let Ok(len) = len.parse::<usize>() {
+                cmp::min(len, v3::CHATHISTORY_LIMIT)
+            } else {
+                ctx.rb
+                    .message("", "FAIL")
+                    .param("AUTOREPLAY")
+                    .param("INVALID_PARAMS")
+                    .param(len)
+                    .trailing_param(&lines::tr(lines::INVALID_AUTOREPLAY));
+                return Err(());
+            };
+            client.autoreplay = Some(len);
+            if let Some(account) = client.account() {
+                self.accounts.set_autoreplay(account, len);
+            }
+        }
+
+        let len = client.autoreplay.unwrap_or(self.autoreplay_len);
+        if len == 0 {
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::AUTOREPLAY_OFF));
+        } else {
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(lines_autoreplay!(len));
+        }
+
+        Ok(())
+    }
+
+    // HELP
+
+    pub fn cmd_help(&self, ctx: CommandContext<'_>, subject: &str) -> Result {
+        let operator = self.clients[ctx.id].operator;
+        if subject.is_empty() || subject.eq_ignore_ascii_case("index") {
+            ctx.rb.lr_batch_begin();
+            ctx.rb
+                .reply(rpl::HELPSTART)
+                .param("index")
+                .trailing_param(&lines::tr(lines::HELP_INDEX));
+            let topics: Vec<_> = help::TOPICS
+                .iter()
+                .filter(|topic| operator || !topic.oper)
+                .collect();
+            for chunk in topics.chunks(HELP_INDEX_WIDTH) {
+                let mut msg = ctx.rb.reply(rpl::HELPTXT).param("index");
+                let trailing = msg.raw_trailing_param();
+                for topic in chunk {
+                    trailing.push_str(topic.command.as_str());
+                    trailing.push(' ');
+                }
+                trailing.pop();
+            }
+            ctx.rb
+                .reply(rpl::ENDOFHELP)
+                .param("index")
+                .trailing_param(&lines::tr(lines::END_OF_HELP));
+            return Ok(());
+        }
+
+        let topic = match help::find(subject) {
+            Some(topic) if operator || !topic.oper => topic,
+            _ => {
+                log::debug!("{}:     no help for {:?}", ctx.id, subject);
+                ctx.rb
+                    .reply(rpl::ERR_HELPNOTFOUND)
+                    .param(subject)
+                    .trailing_param(&lines::tr(lines::HELP_NOT_FOUND));
+                return Err(());
+            }
+        };
+        let name = topic.command.as_str();
+        ctx.rb.lr_batch_begin();
+        ctx.rb
+            .reply(rpl::HELPSTART)
+            .param(name)
+            .fmt_trailing_param(format_args!("{} {}", name, topic.usage));
+        for line in topic.text {
+            ctx.rb.reply(rpl::HELPTXT).param(name).trailing_param(line);
+        }
+        ctx.rb
+            .reply(rpl::ENDOFHELP)
+            .param(name)
+            .trailing_param(&lines::tr(lines::END_OF_HELP));
+
+        Ok(())
+    }
+
+    // LANGUAGE
+
+    pub fn cmd_language(&mut self, ctx: CommandContext<'_>, language: &str) -> Result {
+        let language = language.to_ascii_lowercase();
+        if language != "en" && !self.locales.contains_key(&language) {
+            ctx.rb
+                .message("", "FAIL")
+                .param("LANGUAGE")
+                .param("INVALID_LANGUAGE")
+                .param(&language)
+                .trailing_param(&lines::tr(lines::INVALID_LANGUAGE));
+            return Err(());
+        }
+        self.clients[ctx.id].language = Some(language);
+
+        let _catalog = lines::use_catalog(self.catalog(ctx.id));
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::LANGUAGE_SET));
+
+        Ok(())
+    }
+
+    // CHALLENGE
+
+    pub fn cmd_challenge(&mut self, ctx: CommandContext<'_>, answer: &str) -> Result {
+        let client = &mut self.clients[ctx.id];
+        let challenge = match &client.challenge {
+            Some(challenge) => challenge,
+            None => return Ok(()),
+        };
+        if !challenge.answer.trim().eq_ignore_ascii_case(answer.trim()) {
+            log::debug!("{}:     wrong answer", ctx.id);
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::CHALLENGE_FAILED));
+            return Err(());
+        }
+        client.challenge = None;
+
+        Ok(())
+    }
+
+    // GHOST
+    // REGAIN
+
+    /// Returns `Ok(())` when the client is logged in to the account named `nick`.  Otherwise
+    /// returns `Err(())` and sends an error to the client.
+    fn check_nick_owner(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        command: &str,
+        nick: data::Nickname<'_>,
+    ) -> Result {
+        let client = &self.clients[id];
+        if client
+            .account()
+            .is_some_and(|account| account.eq_ignore_ascii_case(nick.get()))
+        {
+            return Ok(());
+        }
+        log::debug!("{}:     not the owner of {:?}", id, nick.get());
+        rb.message("", "FAIL")
+            .param(command)
+            .param("ACCOUNT_REQUIRED")
+            .param(nick.get())
+            .trailing_param(&lines::tr(lines::NOT_NICK_OWNER));
+        Err(())
+    }
+
+    pub fn cmd_ghost(&mut self, ctx: CommandContext<'_>, nick: data::Nickname<'_>) -> Result {
+        self.check_nick_owner(ctx.id, ctx.rb, "GHOST", nick)?;
+        let (target_id, _) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, nick)?;
+        if target_id == ctx.id {
+            ctx.rb
+                .message("", "FAIL")
+                .param("GHOST")
+                .param("CANNOT_GHOST_SELF")
+                .param(nick.get())
+                .trailing_param(&lines::tr(lines::GHOST_SELF));
+            return Err(());
+        }
+
+        let ghost = lines_ghosted!(self.clients[ctx.id].nick()).to_string();
+        self.remove_client(target_id, &ghost, &ghost);
+
+        Ok(())
+    }
+
+    pub fn cmd_regain(&mut self, ctx: CommandContext<'_>, nick: data::Nickname<'_>) -> Result {
+        self.check_nick_owner(ctx.id, ctx.rb, "REGAIN", nick)?;
+
+        if let Some(target_id) = self.nicks.get(nick.get()) {
+            if target_id == ctx.id {
+                return Ok(());
+            }
+            let target = &self.clients[target_id];
+            if target
+                .account()
+                .is_some_and(|account| account.eq_ignore_ascii_case(nick.get()))
+            {
+                // A stale session of the same account.
+                let ghost = lines_ghosted!(self.clients[ctx.id].nick()).to_string();
+                self.remove_client(target_id, &ghost, &ghost);
+            } else {
+                let guest = self.guest_nick(target_id);
+                self.force_nick(target_id, &guest);
+            }
+        }
+
+        self.cmd_nick(ctx, nick)
+    }
+
+    // LOCKDOWN
+
+    pub fn cmd_lockdown(&mut self, ctx: CommandContext<'_>, level: Option<&str>) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        if let Some(level) = level {
+            let level = match level.parse() {
+                Ok(level) if level <= LOCKDOWN_PLAINTEXT => level,
+                _ => {
+                    log::debug!("{}:     invalid level", ctx.id);
+                    ctx.rb
+                        .reply(Command::Notice)
+                        .trailing_param(&lines::tr(lines::LOCKDOWN_INVALID));
+                    return Err(());
+                }
+            };
+            if level != self.lockdown {
+                self.lockdown = level;
+                self.send_server_notice(format_args!(
+                    "{} set the lockdown level to {}",
+                    self.clients[ctx.id].full_name(),
+                    level,
+                ));
+            }
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_lockdown!(self.lockdown));
+
+        Ok(())
+    }
+
+    // CHGHOST
+    // SETHOST
+
+    /// Changes the host of the client `target_id` on behalf of the IRC operator `ctx.id`, and
+    /// tells the other IRC operators.
+    fn oper_change_host(
+        &mut self,
+        ctx: CommandContext<'_>,
+        command: &str,
+        target_id: usize,
+        host: &str,
+    ) -> Result {
+        if !util::is_valid_host(host) {
+            log::debug!("{}:     invalid host", ctx.id);
+            ctx.rb
+                .message("", "FAIL")
+                .param(command)
+                .param("INVALID_HOST")
+                .trailing_param(&lines::tr(lines::INVALID_HOST));
+            return Err(());
+        }
+
+        self.audit(format_args!(
+            "{} changed the host of {} to {}",
+            self.clients[ctx.id].full_name(),
+            self.clients[target_id].full_name(),
+            host,
+        ));
+        self.change_host(target_id, host);
+
+        Ok(())
+    }
+
+    pub fn cmd_chghost(&mut self, ctx: CommandContext<'_>, args: data::req::ChgHost<'_>) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+        let (target_id, _) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        self.oper_change_host(ctx, "CHGHOST", target_id, args.host)
+    }
+
+    pub fn cmd_sethost(&mut self, ctx: CommandContext<'_>, host: &str) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+        let id = ctx.id;
+        self.oper_change_host(ctx, "SETHOST", id, host)
+    }
+
+    // SAJOIN
+    // SAMODE
+    // SANICK
+    // SAPART
+    // SATOPIC
+
+    pub fn cmd_sajoin(&mut self, ctx: CommandContext<'_>, args: data::req::SaJoin<'_>) -> Result {
+        self.check_privilege(ctx.id, ctx.rb, config::OperPrivilege::SaJoin)?;
+        let (target_id, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        self.audit(format_args!(
+            "{} forced {} to join {}",
+            self.clients[ctx.id].full_name(),
+            target.full_name(),
+            args.channels,
+        ));
+        let list = data::JoinList::new(args.channels, "");
+        self.run_as(ctx.id, target_id, |state, ctx| state.join(ctx, list, true))
+    }
+
+    pub fn cmd_samode(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::ModeChannelSet<'_>,
+    ) -> Result {
+        self.check_privilege(ctx.id, ctx.rb, config::OperPrivilege::SaMode)?;
+        find_channel(ctx.id, ctx.rb, &self.channels, args.channel)?;
+        self.audit(format_args!(
+            "{} forced the modes {} on {}",
+            self.clients[ctx.id].full_name(),
+            args.modes,
+            args.channel.get(),
+        ));
+        self.mode_channel_set(ctx, args, true)
+    }
+
+    pub fn cmd_sanick(&mut self, ctx: CommandContext<'_>, args: data::req::SaNick<'_>) -> Result {
+        self.check_privilege(ctx.id, ctx.rb, config::OperPrivilege::SaNick)?;
+        let (target_id, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        let taken = self
+            .nicks
+            .get(args.nick.get())
+            .is_some_and(|id| id != target_id);
+        if taken || self.is_service_nick(args.nick.get()) {
+            log::debug!("{}:     Already in use", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_NICKNAMEINUSE)
+                .param(args.nick.get())
+                .trailing_param(&lines::tr(lines::NICKNAME_IN_USE));
+            return Err(());
+        }
+        self.audit(format_args!(
+            "{} forced {} to change their nickname to {}",
+            self.clients[ctx.id].full_name(),
+            target.full_name(),
+            args.nick.get(),
+        ));
+        self.run_as(ctx.id, target_id, |state, ctx| {
+            state.cmd_nick(ctx, args.nick)
+        })
+    }
+
+    pub fn cmd_sapart(&mut self, ctx: CommandContext<'_>, args: data::req::SaPart<'_>) -> Result {
+        self.check_privilege(ctx.id, ctx.rb, config::OperPrivilege::SaPart)?;
+        let (target_id, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        let channels: Vec<String> = args
+            .part
+            .from
+            .iter()
+            .map(|name| name.get().to_owned())
+            .collect();
+        self.audit(format_args!(
+            "{} forced {} to part {}",
+            self.clients[ctx.id].full_name(),
+            target.full_name(),
+            channels.join(","),
+        ));
+        self.run_as(ctx.id, target_id, |state, ctx| {
+            state.cmd_part(ctx, args.part)
+        })
+    }
+
+    pub fn cmd_satopic(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::TopicSet<'_>,
+    ) -> Result {
+        self.check_privilege(ctx.id, ctx.rb, config::OperPrivilege::SaTopic)?;
+        find_channel(ctx.id, ctx.rb, &self.channels, args.channel)?;
+        self.audit(format_args!(
+            "{} forced the topic of {}",
+            self.clients[ctx.id].full_name(),
+            args.channel.get(),
+        ));
+        self.topic_set(ctx, args, true)
+    }
+
+    // MASSKILL
+
+    /// Returns the clients that match `mask`, either a `nick!user@host` mask or a range of IP
+    /// addresses.  IRC operators are left out.
+    fn mass_kill_targets(&self, mask: &str) -> Vec<usize> {
+        let cidr = util::Cidr::parse(mask);
+        self.clients
+            .iter()
+            .filter(|(_, client)| !client.operator && client.session.is_none())
+            .filter(|(_, client)| match cidr {
+                Some(cidr) => client.ip.is_some_and(|ip| cidr.contains(ip)),
+                None => util::match_mask(mask, client.full_name()),
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    pub fn cmd_mass_kill(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::MassKill<'_>,
+    ) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        let targets = self.mass_kill_targets(args.mask);
+        if !args.confirm {
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(lines_mass_kill!(targets.len(), args.mask));
+            return Ok(());
+        }
+
+        let reason = args.reason.unwrap_or(MASS_KILL_REASON);
+        self.audit(format_args!(
+            "{} killed {} clients matching {}: {}",
+            self.clients[ctx.id].full_name(),
+            targets.len(),
+            args.mask,
+            reason,
+        ));
+        for id in targets {
+            self.remove_client(id, format_args!("Killed: {reason}"), "Killed");
+        }
+
+        Ok(())
+    }
+
+    // GLOBOPS
+
+    pub fn cmd_glob_ops(&self, ctx: CommandContext<'_>, text: &str) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+        self.send_server_notice(format_args!(
+            "GLOBOPS from {}: {}",
+            self.clients[ctx.id].nick(),
+            text
+        ));
+        Ok(())
+    }
+
+    // OPERMOTD
+
+    pub fn cmd_oper_motd(&self, ctx: CommandContext<'_>) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+        if self.oper_motd.is_none() {
+            ctx.rb
+                .reply(rpl::ERR_NOOPERMOTD)
+                .trailing_param(&lines::tr(lines::NO_OPER_MOTD));
+            return Err(());
+        }
+        ctx.rb.lr_batch_begin();
+        self.send_oper_motd(ctx.rb);
+        Ok(())
+    }
+
+    // DUMP
+
+    /// Returns a snapshot of the clients and channels, for debugging.  Hosts, real names, channel
+    /// keys, topics and messages are left out.
+    pub fn dump(&self) -> serde_json::Value {
+        let clients: Vec<_> = self
+            .clients
+            .iter()
+            .map(|(id, client)| {
+                serde_json::json!({
+                    "id": id,
+                    "state": format!("{:?}", client.state()),
+                    "nick": client.nick(),
+                    "user": client.user(),
+                    "account": client.account(),
+                    "modes": mode_string(|out| client.write_modes(out)),
+                    "tls": client.tls,
+                    "signon": client.signon_time(),
+                    "idle": client.idle_time(),
+                    "lag_ms": client.lag.map(|lag| lag.as_millis() as u64),
+                    "channels": client.joined_channels,
+                    "connections": client.connection_ids().collect::<Vec<_>>(),
+                    "detached": client.is_detached(),
+                    "backlog": client.backlog_len(),
+                    "pending_list": client.pending_list.is_some(),
+                })
+            })
+            .collect();
+        let channels: Vec<_> = self
+            .channels
+            .iter()
+            .map(|(name, channel)| {
+                let members: Vec<_> = channel
+                    .members
+                    .iter()
+                    .map(|(id, modes)| {
+                        let mut prefixes = String::new();
+                        modes.all_symbols(&mut prefixes);
+                        serde_json::json!({ "id": id, "prefixes": prefixes })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "name": name.get(),
+                    "modes": mode_string(|out| channel.modes(out, false)),
+                    "members": members,
+                    "bans": channel.ban_mask.len(),
+                    "exceptions": channel.exception_mask.len(),
+                    "invitations": channel.invex_mask.len(),
+                    "quiets": channel.quiet_mask.len(),
+                    "mutes": channel.mutes.len(),
+                    "history": channel.history.len(),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "domain": self.domain.as_ref(),
+            "time": util::time(),
+            "lockdown": self.lockdown,
+            "clients": clients,
+            "channels": channels,
+        })
+    }
+
+    pub fn cmd_dump(&self, ctx: CommandContext<'_>) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        log::info!(
+            "State dump requested by {}: {}",
+            self.clients[ctx.id].full_name(),
+            self.dump(),
+        );
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_state_dumped!(self.clients.len(), self.channels.len()));
+
+        Ok(())
+    }
+
+    // MUTE
+    // UNMUTE
+
+    /// Returns the ID of `nick` if the client `id` can mute it on the channel, that is when the
+    /// client is at least halfop and `nick` is a member.  Otherwise sends an error to the client.
+    fn check_mute_target(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        channel_name: data::ChannelName<'_>,
+        nick: data::Nickname<'_>,
+    ) -> std::result::Result<usize, ()> {
+        let channel = find_channel(id, rb, &self.channels, channel_name)?;
+        let modes = find_member(id, rb, channel, channel_name)?;
+        if !modes.is_at_least_halfop() {
+            log::debug!("{}:     not halfop", id);
+            rb.reply(rpl::ERR_CHANOPRIVSNEEDED)
+                .param(channel_name.get())
+                .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
+            return Err(());
+        }
+        let (target_id, _) = find_nick(id, rb, &self.clients, &self.nicks, nick)?;
+        if !channel.members.contains_key(&target_id) {
+            log::debug!("{}:     {:?} not on channel", id, nick.get());
+            rb.reply(rpl::ERR_USERNOTINCHANNEL)
+                .param(nick.get())
+                .param(channel_name.get())
+                .trailing_param(&lines::tr(lines::USER_NOT_IN_CHANNEL));
+            return Err(());
+        }
+        Ok(target_id)
+    }
+
+    /// Sends `line` to the member `who` and to the halfops and above of `channel_name`, except
+    /// `except`.
+    fn send_mute_notice(&self, channel_name: &str, who: usize, except: usize, line: &str) {
+        let channel = match self.channels.get(u(channel_name)) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let mut notified: Vec<usize> = channel
+            .members
+            .iter()
+            .filter(|(id, modes)| **id != who && modes.is_at_least_halfop())
+            .map(|(id, _)| *id)
+            .collect();
+        notified.push(who);
+        for id in notified.into_iter().filter(|id| *id != except) {
+            if let Some(client) = self.clients.get(id) {
+                let mut notice = Buffer::new();
+                notice
+                    .message(&self.domain, Command::Notice)
+                    .param(client.nick())
+                    .trailing_param(line);
+                client.send(notice);
+            }
+        }
+    }
+
+    pub fn cmd_mute(&mut self, ctx: CommandContext<'_>, args: data::req::Mute<'_>) -> Result {
+        let duration = match util::parse_duration(args.duration) {
+            Some(duration) => duration,
+            None => {
+                ctx.rb
+                    .message("", "FAIL")
+                    .param("MUTE")
+                    .param("INVALID_DURATION")
+                    .param(args.duration)
+                    .trailing_param(&lines::tr(lines::INVALID_DURATION));
+                return Err(());
+            }
+        };
+        let target_id = self.check_mute_target(ctx.id, ctx.rb, args.channel, args.who)?;
+
+        let channel = self.channels.get_mut(args.channel.u()).unwrap();
+        channel
+            .mutes
+            .insert(target_id, util::time().saturating_add(duration));
+
+        let line = lines_muted!(
+            args.channel.get(),
+            self.clients[ctx.id].nick(),
+            args.who.get(),
+            duration
+        )
+        .to_string();
+        ctx.rb.reply(Command::Notice).trailing_param(&line);
+        self.send_mute_notice(args.channel.get(), target_id, ctx.id, &line);
+
+        Ok(())
+    }
+
+    pub fn cmd_unmute(&mut self, ctx: CommandContext<'_>, args: data::req::Unmute<'_>) -> Result {
+        let target_id = self.check_mute_target(ctx.id, ctx.rb, args.channel, args.who)?;
+
+        let channel = self.channels.get_mut(args.channel.u()).unwrap();
+        if channel.mutes.remove(&target_id).is_none() {
+            ctx.rb
+                .message("", "FAIL")
+                .param("UNMUTE")
+                .param("NOT_MUTED")
+                .param(args.who.get())
+                .trailing_param(&lines::tr(lines::NOT_MUTED));
+            return Err(());
+        }
+
+        let line = lines_unmuted!(args.channel.get(), args.who.get()).to_string();
+        ctx.rb.reply(Command::Notice).trailing_param(&line);
+        self.send_mute_notice(args.channel.get(), target_id, ctx.id, &line);
+
+        Ok(())
+    }
+
+    /// Lifts the mutes that have expired at `now`, a unix time, and notifies the members they
+    /// applied to.
+    pub(super) fn expire_mutes(&mut self, now: u64) {
+        let mut expired = Vec::new();
+        for (name, channel) in &mut self.channels {
+            for id in channel.expire_mutes(now) {
+                expired.push((name.get().to_owned(), id));
+            }
+        }
+        for (channel_name, id) in expired {
+            let line = lines_unmuted!(channel_name, self.clients[id].nick()).to_string();
+            self.send_mute_notice(&channel_name, id, usize::MAX, &line);
+        }
+    }
+
+    // Announcements
+
+    pub(super) fn send_announcements(&mut self, now: u64) {
+        for announcement in self.announcements.due(now) {
+            let target = &announcement.target;
+            if target.is_empty() {
+                let users = self
+                    .clients
+                    .iter()
+                    .filter(|(_, client)| client.is_registered() && client.session.is_none());
+                for (_, client) in users {
+                    let mut notice = Buffer::with_capacity(512);
+                    notice
+                        .message(&self.domain, Command::Notice)
+                        .param(client.nick())
+                        .trailing_param(&announcement.text);
+                    client.send(MessageQueueItem::from(notice));
+                }
+            } else if let Some(channel) = self.channels.get(u(target)) {
+                let mut notice = Buffer::with_capacity(512);
+                notice
+                    .message(&self.domain, Command::Notice)
+                    .param(target)
+                    .trailing_param(&announcement.text);
+                let notice = MessageQueueItem::from(notice);
+                for member in channel.members.keys() {
+                    self.clients[*member].send(notice.clone());
+                }
+            } else {
+                log::debug!(
+                    "Announcement {:?}: channel {} doesn't exist",
+                    announcement.name,
+                    target
+                );
+            }
+        }
+    }
+
+    // Services aliases
+
+    /// Returns whether `nick` is the nickname of a services alias.  These nicknames cannot be
+    /// used by clients.
+    pub(super) fn is_service_nick(&self, nick: &str) -> bool {
+        self.services
+            .iter()
+            .any(|service| service.nick.eq_ignore_ascii_case(nick))
+    }
+
+    /// If `msg` is sent with the command of a services alias (e.g. `NS GHOST nick`), or to its
+    /// nickname (e.g. `PRIVMSG NickServ :GHOST nick`), returns the index of the alias and the
+    /// arguments given to it.
+    pub(super) fn service_alias<'a>(&self, msg: &Message<'a>) -> Option<(usize, Vec<&'a str>)> {
+        let params = &msg.params[..msg.num_params];
+        match msg.command {
+            Ok(Command::PrivMsg) if params.len() == 2 => {
+                let service = self
+                    .services
+                    .iter()
+                    .position(|service| service.nick.eq_ignore_ascii_case(params[0]))?;
+                Some((service, params[1].split_whitespace().collect()))
+            }
+            Err(command) => {
+                let service = self
+                    .services
+                    .iter()
+                    .position(|service| service.command.eq_ignore_ascii_case(command))?;
+                Some((service, params.to_vec()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Rewrites the arguments given to a services alias into an ellidri command, and handles it.
+    /// Unknown subcommands are answered with the list of known ones, in a notice sent from the
+    /// nickname of the alias.
+    pub(super) fn handle_service_command(
+        &mut self,
+        id: usize,
+        msg: &Message<'_>,
+        label: &str,
+        service: usize,
+        args: &[&str],
+    ) -> u32 {
+        let service = &self.services[service];
+        let subcommand = args.first().copied().unwrap_or("");
+        let template = service
+            .subcommands
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(subcommand))
+            .map(|(_, template)| template);
+
+        if let Some(template) = template {
+            let mut line = String::new();
+            if !msg.tags.is_empty() {
+                line.push('@');
+                line.push_str(msg.tags);
+                line.push(' ');
+            }
+            line.push_str(&util::expand_args(template, &args[1..]));
+            log::debug!("{}: {} -> {}", id, service.nick, line);
+            if let Some(msg) = Message::parse(&line) {
+                if self.service_alias(&msg).is_none() {
+                    return self.handle_message(id, msg);
+                }
+            }
+        }
+
+        let client = &self.clients[id];
+        let prefix = format!("{0}!{0}@{1}", service.nick, self.domain);
+        let mut rb = client.reply(label);
+        rb.lr_batch_begin();
+        if !subcommand.is_empty() && !subcommand.eq_ignore_ascii_case("HELP") {
+            rb.message(&prefix, Command::Notice)
+                .param(client.nick())
+                .trailing_param(&lines::tr(lines::UNKNOWN_COMMAND));
+        }
+        let names: Vec<&str> = service.subcommands.keys().map(String::as_str).collect();
+        rb.message(&prefix, Command::Notice)
+            .param(client.nick())
+            .fmt_trailing_param(lines_service_help!(service.command, names.join(" ")));
+        rb.lr_end();
+        client.send(rb);
+
+        4
+    }
+
+    // SPAMFILTER
+
+    pub fn cmd_spamfilter_list(&self, ctx: CommandContext<'_>) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        ctx.rb.lr_batch_begin();
+        for filter in self.spamfilters.iter() {
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(format_args!(
+                    "{} {} {}",
+                    filter.pattern,
+                    filter.action.name(),
+                    filter.reason
+                ));
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::END_OF_SPAMFILTER_LIST));
+
+        Ok(())
+    }
+
+    pub fn cmd_spamfilter_add(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::SpamFilterAdd<'_>,
+    ) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        let filter = config::SpamFilter {
+            pattern: args.pattern.to_owned(),
+            action: args.action,
+            targets: config::all_spam_targets(),
+            reason: args.reason.to_owned(),
+        };
+        if let Err(err) = self.spamfilters.add(filter) {
+            log::debug!("{}:     invalid pattern: {}", ctx.id, err);
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::SPAMFILTER_INVALID));
+            return Err(());
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::SPAMFILTER_ADDED));
+
+        Ok(())
+    }
+
+    pub fn cmd_spamfilter_del(&mut self, ctx: CommandContext<'_>, pattern: &str) -> Result {
+        self.check_operator(ctx.id, ctx.rb)?;
+
+        if !self.spamfilters.remove(pattern) {
+            log::debug!("{}:     no such spamfilter", ctx.id);
+            ctx.rb
+                .reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::SPAMFILTER_NOT_FOUND));
+            return Err(());
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .trailing_param(&lines::tr(lines::SPAMFILTER_DELETED));
+
+        Ok(())
+    }
+
+    // TOPICHISTORY
+
+    pub fn cmd_topic_history(
+        &self,
+        ctx: CommandContext<'_>,
+        channel_name: data::ChannelName<'_>,
+    ) -> Result {
+        let channel = find_channel(ctx.id, ctx.rb, &self.channels, channel_name)?;
+        find_member(ctx.id, ctx.rb, channel, channel_name)?;
+
+        ctx.rb.lr_batch_begin();
+        for (i, topic) in channel.topic_history.iter().enumerate() {
+            let time = UNIX_EPOCH + Duration::from_secs(topic.time);
+            ctx.rb
+                .reply(Command::Notice)
+                .fmt_trailing_param(format_args!(
+                    "{} {} {} {}: {}",
+                    channel_name.get(),
+                    i + 1,
+                    topic.who,
+                    humantime::format_rfc3339_seconds(time),
+                    topic.content
+                ));
+        }
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(format_args!(
+                "{} {}",
+                channel_name.get(),
+                lines::tr(lines::END_OF_TOPIC_HISTORY)
+            ));
+
+        Ok(())
+    }
+
+    pub fn cmd_topic_restore(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::TopicRestore<'_>,
+    ) -> Result {
+        let channel = find_channel(ctx.id, ctx.rb, &self.channels, args.channel)?;
+        let topic = args
+            .index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| channel.topic_history.get(i.checked_sub(1)?));
+        let topic = match topic {
+            Some(topic) => topic.content.clone(),
+            None => {
+                log::debug!("{}:     no such topic", ctx.id);
+                ctx.rb
+                    .reply(Command::Notice)
+                    .trailing_param(&lines::tr(lines::NO_SUCH_TOPIC));
+                return Err(());
+            }
+        };
+
+        self.cmd_topic_set(
+            ctx,
+            data::req::TopicSet {
+                channel: args.channel,
+                topic: &topic,
+            },
+        )
+    }
+}
+
+/// Returns the modes written by `write` (`Client::write_modes` or `Channel::modes`), e.g. "+int".
+fn mode_string(write: impl FnOnce(MessageBuffer<'_>)) -> String {
+    let mut buf = Buffer::new();
+    write(buf.message("", Command::Mode));
+    let buf = buf.build();
+    buf.trim_end()
+        .rsplit(' ')
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
@@ -2,37 +2,116 @@
 
 #![allow(clippy::needless_pass_by_value)]
 
-use crate::client::{MessageQueue, MessageQueueItem};
+use crate::channel::FloodLimit;
+use crate::client::{Broadcast, MessageQueue, MessageQueueItem, Outbox, Recipient, Recipients};
 use crate::data::Request;
-use crate::{config, data, lines, util, Channel, Client};
-use ellidri_tokens::{mode, rpl, Buffer, Command, Message, ReplyBuffer};
+use crate::{
+    accounts, announce, chanlog, config, data, events, history, hooks, lines, logging, otlp,
+    spamfilter, store, util, Channel, Client,
+};
+use ellidri_tokens::{mode, rpl, Buffer, Command, Message, ReplyBuffer, CLIENT_TAGS_LENGTH};
 use ellidri_unicase::{u, UniCase};
 use slab::Slab;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use std::{fmt, fs, net};
-use tokio::sync::{Mutex, Notify};
+use tokio::sync::{broadcast, watch, Mutex, Notify};
 
+mod ext;
 mod v1;
 mod v3;
 
 const SERVER_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION"));
 
+/// When ellidri has been built, in RFC 3339 format (see `build.rs`).
+const SERVER_BUILD_DATE: &str = env!("ELLIDRI_BUILD_DATE");
+
+/// The optional features ellidri has been built with.
+const SERVER_FEATURES: &[&str] = &[
+    #[cfg(feature = "tls")]
+    "tls (rustls)",
+    #[cfg(feature = "metrics")]
+    "metrics",
+];
+
 /// Information about ellidri from an IRC client perspective.
 ///
 /// Sent to client with the INFO command.
 const SERVER_INFO: &str = include_str!("info.txt");
 
-const MAX_TAG_DATA_LENGTH: usize = 4094;
+/// Maximum number of tokens in a RPL_ISUPPORT reply, as recommended by the specification.
+const I_SUPPORT_TOKENS_PER_REPLY: usize = 13;
+
 const MAX_LABEL_LENGTH: usize = 64;
 
+/// The host given to clients that connect through a Tor hidden service.
+const TOR_HOST: &str = "tor.onion";
+
 type ChannelMap = HashMap<UniCase<String>, Channel>;
 type ClientMap = Slab<Client>;
-type NicksMap = HashMap<UniCase<String>, usize>;
 type HandlerResult = Result<(), ()>;
 
+/// Index of the clients by nickname, casefolded with the configured casemapping.
+#[derive(Default)]
+struct NicksMap {
+    casemapping: config::CaseMapping,
+    ids: HashMap<String, usize>,
+}
+
+impl NicksMap {
+    fn new(casemapping: config::CaseMapping) -> Self {
+        Self {
+            casemapping,
+            ids: HashMap::new(),
+        }
+    }
+
+    fn get(&self, nick: &str) -> Option<usize> {
+        self.ids.get(&self.casemapping.fold(nick)).copied()
+    }
+
+    fn contains(&self, nick: &str) -> bool {
+        self.ids.contains_key(&self.casemapping.fold(nick))
+    }
+
+    fn insert(&mut self, nick: &str, id: usize) {
+        self.ids.insert(self.casemapping.fold(nick), id);
+    }
+
+    fn remove(&mut self, nick: &str) {
+        self.ids.remove(&self.casemapping.fold(nick));
+    }
+
+    fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ids.values().copied()
+    }
+
+    /// Casefolds the nicknames of `clients` again with `casemapping`.  Does nothing and returns
+    /// false if two of them would then be the same.
+    fn set_casemapping(&mut self, casemapping: config::CaseMapping, clients: &ClientMap) -> bool {
+        let mut ids = HashMap::with_capacity(self.ids.len());
+        for &id in self.ids.values() {
+            if ids
+                .insert(casemapping.fold(clients[id].nick()), id)
+                .is_some()
+            {
+                return false;
+            }
+        }
+        self.casemapping = casemapping;
+        self.ids = ids;
+        true
+    }
+}
+
 pub struct CommandContext<'a> {
     id: usize,
+
+    /// The connection that issued the command.  This is `id` unless the client is an additional
+    /// connection of a multiclient session.
+    conn: usize,
+
     rb: &'a mut ReplyBuffer,
     client_tags: &'a str,
 }
@@ -63,11 +142,42 @@ impl State {
 
     /// Reload state configuration.
     ///
-    /// `cfg.motd_file` must be the contents of the MOTD file instead of its path.
+    /// `cfg.motd_file` and `cfg.oper_motd_file` must be the contents of the MOTD files instead of
+    /// their path.
     pub async fn rehash(&self, cfg: config::State) {
         self.0.lock().await.rehash(cfg);
     }
 
+    /// Sends the changes made by the last rehash to the operator who asked for it with REHASH.
+    pub async fn report_rehash(&self, changes: &[String]) {
+        self.0.lock().await.report_rehash(changes);
+    }
+
+    /// Replaces the hooks and the command handlers of the state.
+    pub async fn set_hooks(&self, hooks: hooks::Hooks) {
+        self.0.lock().await.hooks = hooks;
+    }
+
+    /// Returns a receiver of the events of the server.
+    pub async fn subscribe(&self) -> broadcast::Receiver<events::Event> {
+        self.0.lock().await.events.subscribe()
+    }
+
+    /// Returns a snapshot of the clients and channels, for debugging.
+    pub async fn dump(&self) -> serde_json::Value {
+        self.0.lock().await.dump()
+    }
+
+    /// Disconnects all clients, before the server stops.
+    pub async fn shutdown(&self) {
+        self.0.lock().await.shutdown();
+    }
+
+    /// Replaces the MOTD with the given contents.
+    pub async fn set_motd(&self, motd: Option<String>) {
+        self.0.lock().await.motd = motd;
+    }
+
     /// Adds a new connection to the state.
     ///
     /// The given `addr`ess is used to build the client's host, and the given `queue` is used to
@@ -75,8 +185,18 @@ impl State {
     ///
     /// Each connection is identified by an integer.  This function returns the identifier for this
     /// connection, which must be used to handle messages from this client.
-    pub async fn peer_joined(&self, addr: net::SocketAddr, queue: MessageQueue) -> usize {
-        self.0.lock().await.peer_joined(addr, queue)
+    pub async fn peer_joined(
+        &self,
+        addr: net::SocketAddr,
+        options: &config::BindingOptions,
+        tls: bool,
+        certfp: Option<String>,
+        queue: MessageQueue,
+    ) -> usize {
+        self.0
+            .lock()
+            .await
+            .peer_joined(addr, options, tls, certfp, queue)
     }
 
     /// Removes the given connection from the state, with an optional error.
@@ -87,19 +207,129 @@ impl State {
         self.0.lock().await.peer_quit(id, err);
     }
 
-    /// Updates the state according to the given message from the given client.
+    /// Updates the state according to the given message from the given client, and records the
+    /// time it took.
     pub async fn handle_message(&self, id: usize, msg: Message<'_>) -> u32 {
-        self.0.lock().await.handle_message(id, msg)
+        let mut state = self.0.lock().await;
+        let command = msg.command.ok();
+        let start = SystemTime::now();
+        let timer = Instant::now();
+        let points = state.handle_message(id, msg);
+        if let Some(command) = command {
+            state.command_handled(id, command, start, timer.elapsed());
+        }
+        let listing = state
+            .clients
+            .get(id)
+            .is_some_and(|c| c.pending_list.is_some());
+        drop(state);
+
+        // Long LIST replies are sent in chunks, and other clients are handled in between.
+        if listing {
+            while self.0.lock().await.send_list_chunk(id) {
+                let () = tokio::task::yield_now().await;
+            }
+        }
+        points
+    }
+
+    /// Tells the client `id` that the line it sent was too long to be read.
+    pub async fn input_too_long(&self, id: usize) {
+        self.0.lock().await.input_too_long(id);
     }
 
     pub async fn remove_if_unregistered(&self, id: usize) {
         self.0.lock().await.remove_if_unregistered(id);
     }
 
+    /// Renames the clients that use the nickname of an account they are not logged in to, once
+    /// their grace period is over.
+    pub async fn rename_expired_nicks(&self) {
+        self.0.lock().await.rename_expired_nicks();
+    }
+
+    /// Marks the clients that have been idle for too long at `now`, a unix time, as away.
+    pub async fn set_idle_clients_away(&self, now: u64) {
+        self.0.lock().await.set_idle_clients_away(now);
+    }
+
+    /// Lifts the channel mutes that have expired at `now`, a unix time.
+    pub async fn expire_mutes(&self, now: u64) {
+        self.0.lock().await.expire_mutes(now);
+    }
+
+    /// Removes the channel messages that are past their history limits at `now`, a unix time.
+    pub async fn purge_history(&self, now: u64) {
+        self.0.lock().await.purge_history(now);
+    }
+
+    /// Writes the peaks shown by LUSERS to the channel database, if they have changed.
+    pub async fn save_peaks(&self) {
+        self.0.lock().await.store.save_peaks();
+    }
+
+    /// Sends the announcements that are due at `now`, a unix time.
+    pub async fn send_announcements(&self, now: u64) {
+        self.0.lock().await.send_announcements(now);
+    }
+
+    pub async fn announcements(&self) -> Vec<config::Announcement> {
+        self.0.lock().await.announcements.iter().cloned().collect()
+    }
+
+    /// Adds an announcement, or replaces the one with the same name, until the next rehash.
+    /// Returns false if its schedule is invalid.
+    pub async fn add_announcement(&self, announcement: config::Announcement) -> bool {
+        let mut state = self.0.lock().await;
+        state.announcements.add(announcement, util::time())
+    }
+
+    /// Removes an announcement until the next rehash.  Returns false if there was none with
+    /// this name.
+    pub async fn remove_announcement(&self, name: &str) -> bool {
+        self.0.lock().await.announcements.remove(name)
+    }
+
+    /// Adds the given client to a channel, without sending anything to anyone.  Used to set up
+    /// large channels quickly, in benchmarks.
+    pub async fn add_member(&self, id: usize, channel: &str) {
+        let mut state = self.0.lock().await;
+        let modes = state.default_chan_mode.clone();
+        state
+            .channels
+            .entry(UniCase::new(channel.to_owned()))
+            .or_insert_with(|| Channel::new(&modes))
+            .add_member(id, false);
+    }
+
+    /// Sends a PING to the given connection, and records when, so that its lag can be measured
+    /// when it answers.
+    pub async fn send_ping(&self, id: usize) {
+        let mut state = self.0.lock().await;
+        let domain = state.domain.clone();
+        if let Some(client) = state.clients.get_mut(id) {
+            let mut ping = Buffer::new();
+            ping.message("", Command::Ping).trailing_param(&domain);
+            client.send_to_own_connection(ping);
+            client.ping_sent = Some(tokio::time::Instant::now());
+        }
+    }
+
+    /// Returns the maximum length of the lines read from clients, without tags.
+    pub async fn linelen(&self) -> usize {
+        self.0.lock().await.linelen
+    }
+
     /// Returns the timeout for registration, in milliseconds.
     pub async fn login_timeout(&self) -> u64 {
         self.0.lock().await.login_timeout
     }
+
+    /// Returns how long a connection can be idle before a PING is sent to it, and how long after
+    /// that it is closed, in seconds.  The receiver is updated on rehash.
+    pub async fn ping_timeouts(&self) -> watch::Receiver<(u64, u64)> {
+        self.0.lock().await.ping_timeouts.subscribe()
+    }
 }
 
 /// The actual shared data (state) of the IRC server.
@@ -119,6 +349,8 @@ pub(crate) struct StateInner {
     /// Map that associates a socket address to each client.
     clients: ClientMap,
 
+    /// Index of the clients by nickname, under `casemapping`.  Kept in sync with `clients` on NICK,
+    /// QUIT and registration, so that nickname lookups don't need to go through every client.
     nicks: NicksMap,
 
     /// HashMap to associate the name of each channel with their metadata.
@@ -128,33 +360,225 @@ pub(crate) struct StateInner {
     /// register (in a "003 RPL_CREATED" reply).
     created_at: String,
 
-    /// The message of the day.
+    /// The UNIX time when this instance is created, used for the MOTD's `{uptime}` variable.
+    started_at: u64,
+
+    /// The message of the day.  May contain variables, see `motd_var`.
     motd: Option<String>,
 
+    /// The message of the day sent to IRC operators, with `OPERMOTD` and on `OPER`.
+    oper_motd: Option<String>,
+
     /// The global password. Clients need to issue a PASS command with this password to register.
     password: String,
 
     /// Modes applied at the creation of new channels.
     default_chan_mode: String,
 
+    /// Channels joined by clients when they register.
+    auto_join: Vec<String>,
+
     /// A list of (name, password) that are valid OPER parameters.
     opers: Vec<config::Oper>,
 
+    /// Whether OPER is refused over plain-text connections, for all oper blocks.
+    oper_require_tls: bool,
+
+    /// Persistent channel data.
+    store: store::Store,
+
+    /// Where SASL accounts are stored, and the accounts themselves.
+    sasl_backend: config::SaslBackend,
+    accounts: accounts::Accounts,
+
+    /// Action taken against members who exceed the flood limit of a channel.
+    flood_action: config::FloodAction,
+
+    /// What happens to PRIVMSGs that contain DCC offers.
+    dcc_offers: config::DccAction,
+
+    /// How many CTCP requests (other than ACTION) clients can send, if limited.
+    ctcp_limit: Option<FloodLimit>,
+
+    /// Filters on the content of messages.
+    spamfilters: spamfilter::SpamFilters,
+
+    /// Notices sent periodically to all clients or to a channel.
+    announcements: announce::Announcements,
+
+    /// Masks of clients that cannot connect anymore, added by the `gline` spamfilter action.
+    glines: util::MaskSet,
+
+    /// IP addresses that cannot connect until the given UNIX time, added by the `dline` clone
+    /// action.
+    dlines: HashMap<net::IpAddr, u64>,
+
+    /// Questions asked to the clients of some bindings before they register.
+    challenges: Vec<config::Challenge>,
+
+    /// The lockdown level set with `LOCKDOWN`, see `ext::LOCKDOWN_MESSAGES` and following.
+    lockdown: u8,
+
+    /// Logs of the channels listed in the configuration.
+    chanlog: chanlog::ChannelLogs,
+
+    /// Per-command counters and latency histograms.
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
+
+    /// Traces of connections and commands, exported to `otlp_endpoint`.
+    tracer: otlp::Tracer,
+
+    /// Translations of the lines sent to clients, by language, and the language of clients that
+    /// haven't chosen one.
+    locales: HashMap<String, Arc<lines::Catalog>>,
+    default_language: String,
+
+    /// Extensions given by programs that embed the server.
+    hooks: hooks::Hooks,
+    events: events::Events,
+
+    /// Masks of the clients that are exempt from rate limits, along with IRC operators.
+    trusted_hosts: util::MaskSet,
+
+    /// Maximum number of channels a client can join, or 0 for no limit.
+    chanlimit: usize,
+
     /// Limits in number of characters for user input.
     awaylen: usize,
     channellen: usize,
     keylen: usize,
     kicklen: usize,
     namelen: usize,
+
+    /// Maximum length of the lines read from clients, without tags.
+    linelen: usize,
+
+    /// Maximum number of masks in each of the ban, exception, invite and quiet lists of a
+    /// channel.
+    maxlist: usize,
     nicklen: usize,
     topiclen: usize,
     userlen: usize,
 
+    /// Number of previous topics kept for each channel.
+    topic_history_len: usize,
+
+    /// Number of messages kept for each channel and for how long, unless overridden by
+    /// `channel_history`.
+    history_limit: history::Limit,
+    channel_history: Vec<config::ChannelHistory>,
+
+    /// Number of messages replayed to clients when they join a channel, unless they set their own
+    /// with `AUTOREPLAY` or the channel has its own in `channel_history`.
+    autoreplay_len: usize,
+
+    /// Metadata limits: number of keys per user or channel, number of subscriptions per client
+    /// and length of values.
+    metadata_max_keys: usize,
+    metadata_max_subs: usize,
+    metadata_max_value_len: usize,
+
+    /// Metadata keys that can only be seen by their owner and IRC operators.
+    metadata_private_keys: Vec<String>,
+
+    /// Maximum number of messages kept for each offline account.  0 disables offline messages.
+    offline_messages_len: usize,
+
+    /// Number of messages kept for always-on clients while they are detached.
+    always_on_backlog_len: usize,
+
+    /// Seconds given to clients that use the nickname of an account to log in to it, before their
+    /// nickname is changed.  0 disables nickname enforcement.
+    nick_enforcement_delay: u64,
+
+    /// Whether several connections can share the client of an account.
+    multiclient: bool,
+
+    /// Seconds of inactivity after which clients are marked as away.  0 disables automatic away,
+    /// unless clients enable it themselves with `AUTOAWAY`.
+    auto_away_delay: u64,
+
+    /// Services aliases, that rewrite `NS ...` and `PRIVMSG NickServ :...` into ellidri commands.
+    services: Vec<config::Service>,
+
+    /// Private messages sent to offline accounts, indexed by their lowercase name.
+    offline_messages: HashMap<String, VecDeque<accounts::OfflineMessage>>,
+
     /// Registration timeout, in milliseconds.
     login_timeout: u64,
 
+    /// Seconds of inactivity after which a PING is sent to connections, and seconds after which
+    /// they are closed if they still don't send anything.  An interval of 0 disables PINGs.
+    ping_timeouts: watch::Sender<(u64, u64)>,
+
+    /// Time after which invitations expire, in seconds.  0 means invitations never expire.
+    invite_expiry: u64,
+
+    /// Time during which D-lines added by the `dline` clone action last, in seconds.
+    dline_duration: u64,
+
+    /// Number of members above which channel messages are delivered by worker tasks, and number
+    /// of members each task delivers to.  0 disables worker tasks.
+    fanout_threshold: usize,
+
+    /// Where the messages sent to clients go through, to keep them in order with those
+    /// delivered by worker tasks.
+    outbox: Outbox,
+
     /// Channel to send rehash notifications
     rehash: Arc<Notify>,
+
+    /// The operator who sent the last REHASH, to whom its changes are reported.
+    rehash_by: Option<usize>,
+}
+
+fn trusted_hosts(masks: &[String]) -> util::MaskSet {
+    let mut set = util::MaskSet::new();
+    for mask in masks {
+        set.insert(mask);
+    }
+    set
+}
+
+/// Reads the translations of `<dir>/<language>.yml`, by language.
+fn load_locales(dir: &str) -> HashMap<String, Arc<lines::Catalog>> {
+    let mut locales = HashMap::new();
+    if dir.is_empty() {
+        return locales;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::warn!("Failed to read {:?}: {}", dir, err);
+            return locales;
+        }
+    };
+    for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+        let language = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(language) if path.extension().is_some_and(|ext| ext == "yml") => language,
+            _ => continue,
+        };
+        let catalog = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                lines::Catalog::from_yaml(&contents).map_err(|err| err.to_string())
+            });
+        match catalog {
+            Ok(catalog) => {
+                locales.insert(language.to_ascii_lowercase(), Arc::new(catalog));
+            }
+            Err(err) => log::warn!("Failed to read {:?}: {}", path, err),
+        }
+    }
+    locales
+}
+
+fn load_accounts(backend: config::SaslBackend, account_db: &str) -> accounts::Accounts {
+    match backend {
+        config::SaslBackend::None => accounts::Accounts::default(),
+        config::SaslBackend::File => accounts::Accounts::load(account_db),
+    }
 }
 
 impl StateInner {
@@ -167,33 +591,104 @@ impl StateInner {
                 None
             }
         };
-        Self {
+        let oper_motd = if config.oper_motd_file.is_empty() {
+            None
+        } else {
+            log::info!("Loading OPERMOTD from {:?}", config.oper_motd_file);
+            match fs::read_to_string(&config.oper_motd_file) {
+                Ok(motd) => Some(motd),
+                Err(err) => {
+                    log::warn!("Failed to read {:?}: {}", config.oper_motd_file, err);
+                    None
+                }
+            }
+        };
+        let mut tracer = otlp::Tracer::default();
+        tracer.set_endpoint(&config.otlp_endpoint);
+        let mut state = Self {
             domain: Arc::from(config.domain),
             org_name: config.org_name,
             org_location: config.org_location,
             org_mail: config.org_mail,
             clients: Slab::new(),
-            nicks: HashMap::new(),
+            nicks: NicksMap::new(config.casemapping),
             channels: HashMap::new(),
             created_at: util::time_str(),
+            started_at: util::time(),
             motd,
+            oper_motd,
             password: config.password,
             default_chan_mode: config.default_chan_mode,
+            auto_join: config.auto_join,
             opers: config.opers,
+            oper_require_tls: config.oper_require_tls,
+            store: store::Store::load(&config.channel_db),
+            sasl_backend: config.sasl_backend,
+            accounts: load_accounts(config.sasl_backend, &config.account_db),
+            flood_action: config.flood_action,
+            dcc_offers: config.dcc_offers,
+            ctcp_limit: config.ctcp_limit.parse().ok(),
+            spamfilters: spamfilter::SpamFilters::new(config.spamfilters),
+            announcements: announce::Announcements::new(config.announcements, util::time()),
+            glines: util::MaskSet::new(),
+            dlines: HashMap::new(),
+            lockdown: 0,
+            challenges: config.challenges,
+            chanlog: chanlog::ChannelLogs::new(&config.channel_log_dir, &config.logged_channels),
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::Metrics::default(),
+            tracer,
+            locales: load_locales(&config.locale_dir),
+            default_language: config.default_language.to_ascii_lowercase(),
+            hooks: hooks::Hooks::default(),
+            events: events::Events::default(),
+            trusted_hosts: trusted_hosts(&config.trusted_hosts),
             awaylen: config.awaylen,
+            chanlimit: config.chanlimit,
             channellen: config.channellen,
             keylen: config.keylen,
             kicklen: config.kicklen,
             namelen: config.namelen,
+            linelen: config.linelen,
+            maxlist: config.maxlist,
             nicklen: config.nicklen,
             topiclen: config.topiclen,
+            topic_history_len: config.topic_history_len,
+            history_limit: history::Limit {
+                len: config.history_len,
+                age: config.history_age,
+            },
+            channel_history: config.channel_history,
+            autoreplay_len: config.autoreplay_len,
+            metadata_max_keys: config.metadata_max_keys,
+            metadata_max_subs: config.metadata_max_subs,
+            metadata_max_value_len: config.metadata_max_value_len,
+            metadata_private_keys: config.metadata_private_keys,
+            offline_messages_len: config.offline_messages_len,
+            offline_messages: HashMap::new(),
+            multiclient: config.multiclient,
+            nick_enforcement_delay: config.nick_enforcement_delay,
+            auto_away_delay: config.auto_away_delay,
+            services: config.services,
+            always_on_backlog_len: config.always_on_backlog_len,
             userlen: config.userlen,
             login_timeout: config.login_timeout,
+            ping_timeouts: watch::channel((config.ping_interval, config.ping_timeout)).0,
+            invite_expiry: config.invite_expiry,
+            dline_duration: config.dline_duration,
+            fanout_threshold: config.fanout_threshold,
+            outbox: Outbox::default(),
             rehash,
-        }
+            rehash_by: None,
+        };
+        state.inherit_founders();
+        state
     }
 
     pub fn rehash(&mut self, config: config::State) {
+        let old_i_support = self.i_support_tokens();
+        let old_caps = self.config_caps();
+
         self.domain = Arc::from(config.domain);
         self.org_name = config.org_name;
         self.org_location = config.org_location;
@@ -203,28 +698,250 @@ impl StateInner {
         } else {
             Some(config.motd_file)
         };
+        self.oper_motd = if config.oper_motd_file.is_empty() {
+            None
+        } else {
+            Some(config.oper_motd_file)
+        };
         self.password = config.password;
         self.default_chan_mode = config.default_chan_mode;
+        self.auto_join = config.auto_join;
         self.opers = config.opers;
+        self.oper_require_tls = config.oper_require_tls;
+        if self.store.path() != config.channel_db {
+            self.store.save_peaks();
+            self.store = store::Store::load(&config.channel_db);
+        }
+        // Always reload accounts, since they are managed outside of the server.
+        self.sasl_backend = config.sasl_backend;
+        self.accounts.sync();
+        self.accounts = load_accounts(config.sasl_backend, &config.account_db);
+        self.inherit_founders();
+        let detached: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.is_detached())
+            .filter(|(_, client)| {
+                !client
+                    .account()
+                    .is_some_and(|a| self.accounts.is_always_on(a))
+            })
+            .map(|(id, _)| id)
+            .collect();
+        for id in detached {
+            self.remove_client(id, lines::CLOSING_LINK, lines::CONNECTION_RESET);
+        }
+        if !self
+            .nicks
+            .set_casemapping(config.casemapping, &self.clients)
+        {
+            log::warn!(
+                "casemapping not changed to {}: some nicknames would be the same",
+                config.casemapping.name()
+            );
+        }
+        self.flood_action = config.flood_action;
+        self.dcc_offers = config.dcc_offers;
+        self.ctcp_limit = config.ctcp_limit.parse().ok();
+        self.spamfilters = spamfilter::SpamFilters::new(config.spamfilters);
+        self.announcements = announce::Announcements::new(config.announcements, util::time());
         self.awaylen = config.awaylen;
+        self.chanlimit = config.chanlimit;
         self.channellen = config.channellen;
         self.keylen = config.keylen;
         self.kicklen = config.kicklen;
         self.namelen = config.namelen;
+        self.linelen = config.linelen;
+        self.maxlist = config.maxlist;
         self.topiclen = config.topiclen;
+        self.topic_history_len = config.topic_history_len;
+        self.history_limit = history::Limit {
+            len: config.history_len,
+            age: config.history_age,
+        };
+        self.channel_history = config.channel_history;
+        self.autoreplay_len = config.autoreplay_len;
+        self.metadata_max_keys = config.metadata_max_keys;
+        self.metadata_max_subs = config.metadata_max_subs;
+        self.metadata_max_value_len = config.metadata_max_value_len;
+        self.trusted_hosts = trusted_hosts(&config.trusted_hosts);
+        self.challenges = config.challenges;
+        self.tracer.set_endpoint(&config.otlp_endpoint);
+        self.locales = load_locales(&config.locale_dir);
+        self.default_language = config.default_language.to_ascii_lowercase();
+        self.chanlog = chanlog::ChannelLogs::new(&config.channel_log_dir, &config.logged_channels);
+        self.metadata_private_keys = config.metadata_private_keys;
+        self.offline_messages_len = config.offline_messages_len;
+        self.always_on_backlog_len = config.always_on_backlog_len;
+        self.multiclient = config.multiclient;
+        self.nick_enforcement_delay = config.nick_enforcement_delay;
+        self.auto_away_delay = config.auto_away_delay;
+        self.services = config.services;
         self.userlen = config.userlen;
         self.login_timeout = config.login_timeout;
+        self.ping_timeouts
+            .send_replace((config.ping_interval, config.ping_timeout));
+        self.invite_expiry = config.invite_expiry;
+        self.dline_duration = config.dline_duration;
+        self.fanout_threshold = config.fanout_threshold;
+        self.nicklen = config.nicklen;
+
+        self.send_rehash_changes(&old_i_support, &old_caps);
     }
 
-    pub fn peer_joined(&mut self, addr: net::SocketAddr, queue: MessageQueue) -> usize {
+    fn report_rehash(&mut self, changes: &[String]) {
+        let Some(id) = self.rehash_by.take() else {
+            return;
+        };
+        let Some(client) = self.clients.get(id).filter(|client| client.operator) else {
+            return;
+        };
+        let nothing = [String::from("Nothing changed")];
+        let lines = if changes.is_empty() {
+            &nothing[..]
+        } else {
+            changes
+        };
+        for line in lines {
+            let mut notice = Buffer::new();
+            notice
+                .message(&self.domain, Command::Notice)
+                .param(client.nick())
+                .trailing_param(&format!("Rehash: {line}"));
+            client.send(MessageQueueItem::from(notice));
+        }
+    }
+
+    /// Tells the connected clients about the ISUPPORT tokens and the capabilities that changed
+    /// with a rehash.
+    fn send_rehash_changes(&mut self, old_i_support: &[String], old_caps: &v3::ConfigCaps) {
+        let i_support = self.i_support_tokens();
+        let caps = self.config_caps();
+
+        let token_name = |token: &str| token.split('=').next().unwrap_or_default().to_owned();
+        let mut changed: Vec<String> = i_support
+            .iter()
+            .filter(|token| !old_i_support.contains(token))
+            .cloned()
+            .collect();
+        changed.extend(
+            old_i_support
+                .iter()
+                .map(|old| token_name(old))
+                .filter(|old| !i_support.iter().any(|token| token_name(token) == *old))
+                .map(|old| format!("-{old}")),
+        );
+        if changed.is_empty() && caps == *old_caps {
+            return;
+        }
+
+        for (id, client) in &self.clients {
+            if !client.is_registered() || client.is_detached() {
+                continue;
+            }
+            let session = client.session.unwrap_or(id);
+            let _catalog = lines::use_catalog(self.catalog(session));
+            let mut rb = self.clients[session].reply("");
+            if !changed.is_empty() {
+                send_i_support_tokens(&mut rb, &changed);
+            }
+            self.write_cap_changes(id, &mut rb, old_caps, &caps);
+            if !rb.is_empty() {
+                client.send_to_own_connection(Buffer::from(rb.build()));
+            }
+        }
+
+        // Capabilities that are not available anymore are disabled.
+        let sasl = caps.iter().find(|(cap, _)| *cap == data::cap::SASL);
+        if sasl.is_some_and(|(_, value)| value.is_none()) {
+            let ids: Vec<usize> = self
+                .clients
+                .iter()
+                .filter(|(_, client)| client.cap_enabled.sasl)
+                .map(|(id, _)| id)
+                .collect();
+            for id in ids {
+                self.clients[id].cap_enabled.sasl = false;
+                if let Some(session) = self.clients[id].session {
+                    let cap_enabled = self.clients[id].cap_enabled;
+                    self.clients[session].set_connection_caps(id, cap_enabled);
+                }
+            }
+        }
+    }
+
+    pub fn peer_joined(
+        &mut self,
+        addr: net::SocketAddr,
+        options: &config::BindingOptions,
+        tls: bool,
+        certfp: Option<String>,
+        queue: MessageQueue,
+    ) -> usize {
         log::debug!("{}: Connected", addr);
-        let client = Client::new(self.domain.clone(), queue, addr.ip().to_string());
-        self.clients.insert(client)
+        let host = if options.tor {
+            String::from(TOR_HOST)
+        } else {
+            addr.ip().to_string()
+        };
+        let mut client = Client::new(self.domain.clone(), queue, self.outbox.clone(), host);
+        if !options.password.is_empty() {
+            client.password = Some(options.password.clone());
+        }
+        client.tls = tls;
+        client.ip = (!options.tor).then(|| addr.ip());
+        client.certfp = certfp;
+        let id = self.clients.insert(client);
+        self.record_peaks();
+        self.tracer.connection_opened(id, addr, tls);
+        self.events.emit(|| events::Event::Connected { id, addr });
+        if !tls && ext::LOCKDOWN_PLAINTEXT <= self.lockdown {
+            log::debug!("{}: Plain-text connection refused by the lockdown", id);
+            self.remove_client(
+                id,
+                lines::tr(lines::LOCKDOWN_PLAINTEXT),
+                lines::LOCKDOWN_PLAINTEXT,
+            );
+        } else {
+            if !options.tor {
+                self.check_clones(id, addr.ip(), options);
+            }
+            self.set_challenge(id, options);
+        }
+        id
+    }
+
+    /// The hash of the password the client `id` must give to register, or an empty string.
+    fn required_password(&self, id: usize) -> &str {
+        self.clients[id]
+            .password
+            .as_deref()
+            .unwrap_or(&self.password)
     }
 
     pub fn peer_quit(&mut self, id: usize, err: Option<impl fmt::Display>) {
         log::debug!("{}: Disconnected", id);
 
+        let reason = err.as_ref().map(ToString::to_string);
+        self.tracer.connection_closed(id, reason.as_deref());
+
+        let client = match self.clients.get(id) {
+            Some(client) => client,
+            None => return,
+        };
+        if client.is_detached() {
+            // The client has already been detached by QUIT.
+            return;
+        }
+        if client.session.is_some() || client.has_connections() {
+            self.close_connection(id, lines::CLOSING_LINK);
+            return;
+        }
+        if self.is_always_on(id) {
+            self.detach_client(id, lines::CLOSING_LINK);
+            return;
+        }
+
         if let Some(err) = err {
             self.remove_client(id, format_args!("{err}"), format_args!("{err}"));
         } else {
@@ -232,6 +949,227 @@ impl StateInner {
         }
     }
 
+    /// Whether the client stays on the network when its connection is closed.
+    fn is_always_on(&self, id: usize) -> bool {
+        self.clients.get(id).is_some_and(|client| {
+            client.is_registered()
+                && client
+                    .account()
+                    .is_some_and(|account| self.accounts.is_always_on(account))
+        })
+    }
+
+    /// Closes the connection of an always-on client, but keeps it in channels.  Messages sent to
+    /// it are kept until the user logs in again.
+    fn detach_client(&mut self, id: usize, msg_to_client: impl fmt::Display) {
+        log::debug!("{}: Detached", id);
+        let client = &mut self.clients[id];
+
+        let mut error = Buffer::new();
+        error.message("", "ERROR").fmt_trailing_param(msg_to_client);
+        client.send(error);
+
+        client.detach(self.always_on_backlog_len);
+        self.drop_recipients(id);
+    }
+
+    /// Closes one connection of a multiclient session, and keeps the others.
+    fn close_connection(&mut self, id: usize, msg_to_client: impl fmt::Display) {
+        log::debug!("{}: Connection closed", id);
+        let client = &self.clients[id];
+
+        let mut error = Buffer::new();
+        error.message("", "ERROR").fmt_trailing_param(msg_to_client);
+        client.send_to_own_connection(error);
+
+        if let Some(session) = client.session {
+            self.clients.remove(id);
+            if let Some(client) = self.clients.get_mut(session) {
+                client.remove_connection(id);
+                self.drop_recipients(session);
+            }
+        } else {
+            // The session is held by this client, give it to another connection.
+            let to = client.connection_ids().next().unwrap();
+            self.move_client(id, to);
+        }
+    }
+
+    /// Moves the client `from` (its nickname, channels, other connections...) to the connection
+    /// of the client `to`, and removes `from`.  Returns the messages sent to `from` while it was
+    /// detached.
+    fn move_client(&mut self, from: usize, to: usize) -> VecDeque<MessageQueueItem> {
+        let mut conn = self.clients.remove(from);
+        std::mem::swap(&mut conn, &mut self.clients[to]);
+        if conn.session.is_none() {
+            self.nicks.remove(conn.nick());
+        }
+        let backlog = self.clients[to].attach(to, conn);
+
+        let client = &self.clients[to];
+        self.nicks.insert(client.nick(), to);
+        for channel in self.channels.values_mut() {
+            if let Some(modes) = channel.remove_member(from) {
+                channel.members.insert(to, modes);
+            }
+            if let Some(until) = channel.mutes.remove(&from) {
+                channel.mutes.insert(to, until);
+            }
+        }
+        let others: Vec<usize> = client.connection_ids().collect();
+        for other in others {
+            self.clients[other].session = Some(to);
+        }
+
+        backlog
+    }
+
+    /// Gives the newly registered client `id` the identity of the detached always-on client of the
+    /// same account, if any: its nickname, channels and the messages it received while detached.
+    ///
+    /// With multiclient, the client is instead added to the connections of the client already
+    /// logged in to the same account, if any.
+    fn attach_client(&mut self, id: usize) {
+        let account = match self.clients[id].account() {
+            Some(account) => account,
+            None => return,
+        };
+        let session = self.clients.iter().find(|(other, client)| {
+            *other != id
+                && client.session.is_none()
+                && client.is_registered()
+                && (client.is_detached() || self.multiclient)
+                && client
+                    .account()
+                    .is_some_and(|a| a.eq_ignore_ascii_case(account))
+        });
+        let (session, detached) = match session {
+            Some((session, client)) => (session, client.is_detached()),
+            None => return,
+        };
+        let old_full_name = self.clients[id].full_name().to_owned();
+
+        if detached {
+            log::debug!("{}: Attached to {}", id, session);
+            let backlog = self.move_client(session, id);
+            let client = &self.clients[id];
+            client.send(self.session_burst(id, &old_full_name));
+            for msg in backlog {
+                client.send(msg);
+            }
+        } else {
+            log::debug!("{}: Joined the session of {}", id, session);
+            self.nicks.remove(self.clients[id].nick());
+            let (client, conn) = self.clients.get2_mut(session, id).unwrap();
+            client.add_connection(id, conn);
+            conn.session = Some(session);
+            self.drop_recipients(session);
+            self.clients[id].send(self.session_burst(session, &old_full_name));
+        }
+    }
+
+    /// Joins the client `id`, which just registered, to the `auto_join` channels.
+    ///
+    /// Clients that resumed a session with channels are left alone.
+    fn auto_join(&mut self, id: usize) {
+        let client = &self.clients[id];
+        if self.auto_join.is_empty() || client.session.is_some() || client.joined_channels != 0 {
+            return;
+        }
+        log::debug!("{}: Auto-joining {:?}", id, self.auto_join);
+        let channels = self.auto_join.join(",");
+        let _ = self.run_as(id, id, |state, ctx| {
+            state.cmd_join(ctx, data::JoinList::new(&channels, ""))
+        });
+    }
+
+    /// Returns the messages that tell a new connection of the client `id` its nickname and its
+    /// channels.
+    fn session_burst(&self, id: usize, old_full_name: &str) -> ReplyBuffer {
+        let client = &self.clients[id];
+        let mut rb = client.reply("");
+
+        if old_full_name != client.full_name() {
+            rb.message(old_full_name, Command::Nick)
+                .param(client.nick());
+        }
+        for (name, channel) in &self.channels {
+            if !channel.members.contains_key(&id) {
+                continue;
+            }
+            let channel_name = data::ChannelName::try_from(name.get().as_str()).unwrap();
+            rb.message(client.full_name(), Command::Join)
+                .param(name.get());
+            self.send_topic(&mut rb, channel_name, false);
+            self.send_names(id, &mut rb, channel_name);
+        }
+
+        rb
+    }
+
+    /// Starts the grace period of a client that uses the nickname of an account it is not logged in
+    /// to.  Its nickname is changed when the grace period is over.
+    fn enforce_nick(&mut self, id: usize, rb: &mut ReplyBuffer) {
+        let client = &mut self.clients[id];
+        let nick = client.nick();
+        let owned = self.nick_enforcement_delay != 0
+            && self.accounts.contains(nick)
+            && !client
+                .account()
+                .is_some_and(|account| account.eq_ignore_ascii_case(nick));
+        if !owned {
+            client.nick_deadline = None;
+            return;
+        }
+        let delay = Duration::from_secs(self.nick_enforcement_delay);
+        client.nick_deadline = Some(tokio::time::Instant::now() + delay);
+        rb.reply(Command::Notice)
+            .fmt_trailing_param(lines_nick_owned!(self.nick_enforcement_delay));
+    }
+
+    fn rename_expired_nicks(&mut self) {
+        let now = tokio::time::Instant::now();
+        let expired: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.is_registered() && client.session.is_none())
+            .filter(|(_, client)| client.nick_deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| id)
+            .collect();
+        for id in expired {
+            let guest = self.guest_nick(id);
+            log::debug!("{}: Nickname enforced, renamed to {}", id, guest);
+            self.force_nick(id, &guest);
+        }
+    }
+
+    /// Returns an unused nickname for the client `id`.
+    fn guest_nick(&self, id: usize) -> String {
+        (id..)
+            .map(|n| format!("Guest{n}"))
+            .find(|nick| !self.nicks.contains(nick))
+            .unwrap()
+    }
+
+    /// Changes the nickname of the registered client `id` without it asking, and tells it and the
+    /// clients that share a channel with it.
+    fn force_nick(&mut self, id: usize, nick: &str) {
+        let client = &mut self.clients[id];
+        self.nicks.remove(client.nick());
+        self.nicks.insert(nick, id);
+
+        let mut response = Buffer::with_capacity(128);
+        response
+            .message(client.full_name(), Command::Nick)
+            .param(nick);
+        let response = MessageQueueItem::from(response);
+
+        client.set_nick(nick);
+        client.nick_deadline = None;
+        client.send(response.clone());
+        self.send_notification(id, response, |_, _| true);
+    }
+
     /// This function is called by `peer_quit` and `cmd_quit` to do the various cleanup needed when
     /// a client disconnects:
     ///
@@ -250,20 +1188,37 @@ impl StateInner {
         }
 
         let client = self.clients.remove(id);
-        self.nicks.remove(u(client.nick()));
+        self.nicks.remove(client.nick());
+        for conn in client.connection_ids() {
+            if self.clients.contains(conn) {
+                self.clients.remove(conn);
+            }
+        }
+
+        let reason = msg_to_others.to_string();
+        self.tracer.connection_closed(id, Some(&reason));
+        self.events.emit(|| events::Event::Quit {
+            id,
+            nick: client.nick().to_owned(),
+            reason: reason.clone(),
+        });
 
         if client.is_registered() {
             let mut quit_notice = Buffer::new();
             quit_notice
                 .message(client.full_name(), Command::Quit)
-                .fmt_trailing_param(msg_to_others);
+                .trailing_param(&reason);
 
             let quit_notice = MessageQueueItem::from(quit_notice);
             client.send(quit_notice.clone());
             self.send_notification(id, quit_notice, |_, _| true);
 
-            self.channels.retain(|_, channel| {
-                channel.members.remove(&id);
+            let chanlog = &self.chanlog;
+            self.channels.retain(|channel_name, channel| {
+                if channel.remove_member(id).is_some() {
+                    chanlog.quit(channel_name.get(), &client, &reason);
+                }
+                channel.mutes.remove(&id);
                 !channel.members.is_empty()
             });
         }
@@ -273,17 +1228,62 @@ impl StateInner {
         client.send(error);
     }
 
+    /// Marks the registered clients that have been idle at `now` for longer than their automatic
+    /// away delay as away.
+    fn set_idle_clients_away(&mut self, now: u64) {
+        let idle: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                let delay = client.auto_away_delay.unwrap_or(self.auto_away_delay);
+                client.is_registered()
+                    && client.session.is_none()
+                    && client.away_message.is_none()
+                    && delay != 0
+                    && delay <= client.idle_time_at(now)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        for id in idle {
+            log::debug!("{}: Idle, marked as away", id);
+            let _catalog = lines::use_catalog(self.catalog(id));
+            let client = &mut self.clients[id];
+            client.away_message = Some(lines::AUTO_AWAY.to_owned());
+            client.auto_away = true;
+            let mut rb = client.reply("");
+            rb.reply(rpl::NOWAWAY)
+                .trailing_param(&lines::tr(lines::NOW_AWAY));
+            client.send(rb);
+            self.send_away_notify(id);
+        }
+    }
+
+    fn input_too_long(&self, id: usize) {
+        let client = match self.clients.get(id) {
+            Some(client) if !client.is_detached() => client,
+            _ => return,
+        };
+        let _catalog = lines::use_catalog(self.catalog(client.session.unwrap_or(id)));
+        let mut rb = client.reply("");
+        rb.reply(rpl::ERR_INPUTTOOLONG)
+            .trailing_param(&lines::tr(lines::INPUT_TOO_LONG));
+        client.send(rb);
+    }
+
     pub fn handle_message(&mut self, id: usize, msg: Message<'_>) -> u32 {
         let client = match self.clients.get(id) {
-            Some(client) => client,
-            None => return 999_999,
+            Some(client) if !client.is_detached() => client,
+            _ => return 999_999,
         };
 
-        if MAX_TAG_DATA_LENGTH < msg.tags.len() {
+        let _catalog = lines::use_catalog(self.catalog(client.session.unwrap_or(id)));
+        let _log_context = logging::set_context(id, client.nick(), client.host());
+
+        if CLIENT_TAGS_LENGTH < msg.tags.len() {
             let mut rb = client.reply("");
             rb.reply(rpl::ERR_INPUTTOOLONG)
-                .trailing_param(lines::INPUT_TOO_LONG);
-            client.send(rb);
+                .trailing_param(&lines::tr(lines::INPUT_TOO_LONG));
+            client.send_to_own_connection(rb);
             return 3;
         }
 
@@ -294,79 +1294,102 @@ impl StateInner {
             .filter(|label| label.len() <= MAX_LABEL_LENGTH)
             .unwrap_or("");
 
-        let mut rb = client.reply(label);
-        let is_operator = client.operator;
+        if self.hooks.message(id, client, &msg) == hooks::Verdict::Block {
+            log::debug!("{}: Blocked by a hook", id);
+            return 2;
+        }
+
+        if let Some((service, args)) = self.service_alias(&msg) {
+            return self.handle_service_command(id, &msg, label, service, &args);
+        }
+
+        let mut rb = self.clients[client.session.unwrap_or(id)].reply(label);
 
         let req = match Request::new(&msg) {
             Ok(req) => req,
             Err(data::Error::ErroneousNickname(name)) => {
                 rb.reply(rpl::ERR_ERRONEUSNICKNAME)
                     .param(name)
-                    .trailing_param(lines::ERRONEOUS_NICKNAME);
-                client.send(rb);
+                    .trailing_param(&lines::tr(lines::ERRONEOUS_NICKNAME));
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::InvalidCap) => {
                 rb.reply(Command::Cap)
                     .param("NAK")
                     .trailing_param(msg.params[1]);
-                client.send(rb);
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::InvalidCapCmd(cmd)) => {
                 rb.reply(rpl::ERR_INVALIDCAPCMD)
                     .param(cmd)
-                    .trailing_param(lines::UNKNOWN_COMMAND);
-                client.send(rb);
+                    .trailing_param(&lines::tr(lines::UNKNOWN_COMMAND));
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::NoSuchChannel(name)) => {
                 rb.reply(rpl::ERR_NOSUCHCHANNEL)
                     .param(name)
-                    .trailing_param(lines::NO_SUCH_CHANNEL);
-                client.send(rb);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::NoSuchNick(name)) => {
                 rb.reply(rpl::ERR_NOSUCHNICK)
                     .param(name)
-                    .trailing_param(lines::NO_SUCH_NICK);
-                client.send(rb);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_NICK));
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::NeedMoreParams(command, n)) => {
                 match command {
                     Command::Nick | Command::WhoIs => {
                         rb.reply(rpl::ERR_NONICKNAMEGIVEN)
-                            .trailing_param(lines::NEED_MORE_PARAMS);
+                            .trailing_param(&lines::tr(lines::NEED_MORE_PARAMS));
                     }
                     Command::PrivMsg | Command::Notice | Command::TagMsg if n == 0 => {
                         rb.reply(rpl::ERR_NORECIPIENT)
-                            .trailing_param(lines::NEED_MORE_PARAMS);
+                            .trailing_param(&lines::tr(lines::NEED_MORE_PARAMS));
                     }
                     Command::PrivMsg | Command::Notice if n == 1 => {
                         rb.reply(rpl::ERR_NOTEXTTOSEND)
-                            .trailing_param(lines::NEED_MORE_PARAMS);
+                            .trailing_param(&lines::tr(lines::NEED_MORE_PARAMS));
                     }
                     _ => {
                         rb.reply(rpl::ERR_NEEDMOREPARAMS)
                             .param(command.as_str())
-                            .trailing_param(lines::NEED_MORE_PARAMS);
+                            .trailing_param(&lines::tr(lines::NEED_MORE_PARAMS));
                     }
                 }
-                client.send(rb);
+                client.send_to_own_connection(rb);
                 return 6;
             }
             Err(data::Error::UnknownCommand(unknown)) => {
+                let session = client.session.unwrap_or(id);
+                if client.is_registered()
+                    && self.hooks.command(session, client, unknown, &msg, &mut rb)
+                {
+                    rb.lr_end();
+                    client.send_to_own_connection(rb);
+                    return 4;
+                }
                 if client.is_registered() {
                     rb.reply(rpl::ERR_UNKNOWNCOMMAND)
                         .param(unknown)
-                        .trailing_param(lines::UNKNOWN_COMMAND);
+                        .trailing_param(&lines::tr(lines::UNKNOWN_COMMAND));
                 } else {
                     rb.reply(rpl::ERR_NOTREGISTERED)
-                        .trailing_param(lines::NOT_REGISTERED);
+                        .trailing_param(&lines::tr(lines::NOT_REGISTERED));
                 }
-                client.send(rb);
+                client.send_to_own_connection(rb);
+                return 6;
+            }
+            Err(data::Error::UnknownMode(mode)) => {
+                rb.reply(rpl::ERR_UNKNOWNMODE)
+                    .param(mode)
+                    .trailing_param(&lines::tr(lines::UNKNOWN_MODE));
+                client.send_to_own_connection(rb);
                 return 6;
             }
         };
@@ -374,18 +1397,51 @@ impl StateInner {
         if !client.can_issue_request(&req) {
             if client.is_registered() {
                 rb.reply(rpl::ERR_ALREADYREGISTRED)
-                    .trailing_param(lines::ALREADY_REGISTERED);
+                    .trailing_param(&lines::tr(lines::ALREADY_REGISTERED));
             } else {
                 rb.reply(rpl::ERR_NOTREGISTERED)
-                    .trailing_param(lines::NOT_REGISTERED);
+                    .trailing_param(&lines::tr(lines::NOT_REGISTERED));
             }
-            client.send(rb);
+            client.send_to_own_connection(rb);
             return 2;
         }
 
+        // Additional connections of a multiclient session act on behalf of the session, except for
+        // capability negotiation and QUIT, which only concern the connection.
+        let conn = id;
+        let id = match client.session {
+            Some(session) if !req.is_connection_specific() => session,
+            _ => conn,
+        };
+        let is_operator = self.clients[id].operator;
+        let forward = req.changes_client_state();
+
         let points = req.points();
+        let req = if is_operator {
+            req
+        } else {
+            match self.filter_spam(id, &mut rb, req) {
+                Some(req) => req,
+                None if self.clients.contains(id) => {
+                    rb.lr_end();
+                    self.clients[conn].send_to_own_connection(rb);
+                    return points.saturating_mul(2);
+                }
+                None => return 999_999,
+            }
+        };
+        let mut text = String::new();
+        let req = match self.apply_hooks(id, &mut rb, req, &mut text) {
+            Some(req) => req,
+            None => {
+                rb.lr_end();
+                self.clients[conn].send_to_own_connection(rb);
+                return points;
+            }
+        };
         let ctx = CommandContext {
             id,
+            conn,
             rb: &mut rb,
             client_tags: msg.tags,
         };
@@ -394,21 +1450,41 @@ impl StateInner {
         let res = match req.clone() {
             // Requests about general server info.
             Request::Admin => self.cmd_admin(ctx),
+            Request::Help(subject) => self.cmd_help(ctx, subject),
             Request::Info => self.cmd_info(ctx),
             Request::LUsers => self.cmd_lusers(ctx),
             Request::Motd => self.cmd_motd(ctx),
             Request::Time => self.cmd_time(ctx),
+            Request::Trace(target) => self.cmd_trace(ctx, target),
             Request::Version => self.cmd_version(ctx),
             Request::WhoChannel(args) => self.cmd_who_channel(ctx, args),
             Request::WhoMask(args) => self.cmd_who_mask(ctx, args),
             Request::WhoUser(args) => self.cmd_who_user(ctx, args),
             Request::WhoAll(args) => self.cmd_who_all(ctx, args),
             Request::WhoIs(args) => self.cmd_whois(ctx, args),
+            Request::IsOn(args) => self.cmd_ison(ctx, args),
+            Request::UserHost(args) => self.cmd_userhost(ctx, args),
 
             // IRCop restricted requests.
+            Request::ChgHost(args) => self.cmd_chghost(ctx, args),
+            Request::GlobOps(text) => self.cmd_glob_ops(ctx, text),
             Request::Kill(args) => self.cmd_kill(ctx, args),
+            Request::Dump => self.cmd_dump(ctx),
+            Request::Lockdown(args) => self.cmd_lockdown(ctx, args),
+            Request::MassKill(args) => self.cmd_mass_kill(ctx, args),
             Request::Oper(args) => self.cmd_oper(ctx, args),
+            Request::OperMotd => self.cmd_oper_motd(ctx),
             Request::Rehash => self.cmd_rehash(ctx),
+            Request::SaJoin(args) => self.cmd_sajoin(ctx, args),
+            Request::SaMode(args) => self.cmd_samode(ctx, args),
+            Request::SaNick(args) => self.cmd_sanick(ctx, args),
+            Request::SaPart(args) => self.cmd_sapart(ctx, args),
+            Request::SaTopic(args) => self.cmd_satopic(ctx, args),
+            Request::SetHost(host) => self.cmd_sethost(ctx, host),
+            Request::SpamFilterList => self.cmd_spamfilter_list(ctx),
+            Request::SpamFilterAdd(args) => self.cmd_spamfilter_add(ctx, args),
+            Request::SpamFilterDel(args) => self.cmd_spamfilter_del(ctx, args),
+            Request::Stats(args) => self.cmd_stats(ctx, args),
 
             // Requests about channel info.
             Request::List(args) => self.cmd_list(ctx, args),
@@ -417,12 +1493,16 @@ impl StateInner {
             Request::NamesAll => self.cmd_names_all(ctx),
             Request::TopicGet(args) => self.cmd_topic_get(ctx, args),
             Request::TopicSet(args) => self.cmd_topic_set(ctx, args),
+            Request::TopicHistory(args) => self.cmd_topic_history(ctx, args),
+            Request::TopicRestore(args) => self.cmd_topic_restore(ctx, args),
 
             // Client session related requests.
             Request::CapLs(args) => self.cmd_cap_ls(ctx, args),
             Request::CapList => self.cmd_cap_list(ctx),
             Request::CapReq(args) => self.cmd_cap_req(ctx, args),
             Request::CapEnd => self.cmd_cap_end(ctx),
+            Request::Authenticate(args) => self.cmd_authenticate(ctx, args),
+            Request::Challenge(args) => self.cmd_challenge(ctx, args),
             Request::Pass(args) => self.cmd_pass(ctx, args),
             Request::Ping(args) => self.cmd_ping(ctx, args),
             Request::Pong(args) => self.cmd_pong(ctx, args),
@@ -431,12 +1511,37 @@ impl StateInner {
 
             // Client info related requests.
             Request::Away(args) => self.cmd_away(ctx, args),
+            Request::AutoAway(args) => self.cmd_auto_away(ctx, args),
+            Request::AutoReplay(args) => self.cmd_autoreplay(ctx, args),
+            Request::Language(args) => self.cmd_language(ctx, args),
             Request::ModeUserGet(args) => self.cmd_mode_user_get(ctx, args),
             Request::ModeUserSet(args) => self.cmd_mode_user_set(ctx, args),
             Request::Nick(args) => self.cmd_nick(ctx, args),
             Request::SetName(args) => self.cmd_setname(ctx, args),
+            Request::Ghost(args) => self.cmd_ghost(ctx, args),
+            Request::Regain(args) => self.cmd_regain(ctx, args),
+            Request::MetadataGet(args) => self.cmd_metadata_get(ctx, args),
+            Request::MetadataList(args) => self.cmd_metadata_list(ctx, args),
+            Request::MetadataSet(args) => self.cmd_metadata_set(ctx, args),
+            Request::MetadataClear(args) => self.cmd_metadata_clear(ctx, args),
+            Request::MetadataSub(args) => self.cmd_metadata_sub(ctx, args),
+            Request::MetadataUnsub(args) => self.cmd_metadata_unsub(ctx, args),
+            Request::MetadataSubs => self.cmd_metadata_subs(ctx),
+            Request::MetadataSync(args) => self.cmd_metadata_sync(ctx, args),
+
+            // History requests.
+            Request::ChatHistory(args) => self.cmd_chathistory(ctx, args),
+            Request::Search(attributes) => self.cmd_search(ctx, attributes),
 
             // Channel management requests.
+            Request::AccessList(args) => self.cmd_access_list(ctx, args),
+            Request::AccessAdd(args) => self.cmd_access_add(ctx, args),
+            Request::AccessDel(args) => self.cmd_access_del(ctx, args),
+            Request::FounderGet(args) => self.cmd_founder_get(ctx, args),
+            Request::FounderRegister(args) => self.cmd_founder_register(ctx, args),
+            Request::FounderDrop(args) => self.cmd_founder_drop(ctx, args),
+            Request::FounderTransfer(args) => self.cmd_founder_transfer(ctx, args),
+            Request::FounderSuccessor(args) => self.cmd_founder_successor(ctx, args),
             Request::Invite(args) => self.cmd_invite(ctx, args),
             Request::Join(args) => self.cmd_join(ctx, args),
             Request::Kick(args) => self.cmd_kick(ctx, args),
@@ -445,21 +1550,43 @@ impl StateInner {
             Request::MessageUser(args) => self.cmd_message_user(ctx, args),
             Request::ModeChannelGet(args) => self.cmd_mode_channel_get(ctx, args),
             Request::ModeChannelSet(args) => self.cmd_mode_channel_set(ctx, args),
+            Request::Mute(args) => self.cmd_mute(ctx, args),
+            Request::Unmute(args) => self.cmd_unmute(ctx, args),
             Request::Part(args) => self.cmd_part(ctx, args),
             Request::PartAll => self.cmd_part_all(ctx),
         };
 
-        if !self.clients.contains(id) {
-            // Command handler removed the client from the network state.
+        if self.clients.get(id).is_none_or(Client::is_detached) {
+            // Command handler removed or detached the client from the network state.
             return 999_999;
         }
 
+        let mut just_registered = false;
         let used_points = if res.is_ok() {
             let client = self.clients.get_mut(id).unwrap();
             let old_state = client.state();
             let new_state = client.apply_request(&req);
 
-            if new_state.is_registered() && !old_state.is_registered() {
+            if new_state.is_registered() && !old_state.is_registered() && client.challenge.is_some()
+            {
+                log::debug!("{}: Waiting for the challenge answer", id);
+                client.wait_for_challenge();
+                self.send_challenge(id);
+            } else if new_state.is_registered() && !old_state.is_registered() {
+                if self.glines.is_match(client.full_name()) {
+                    log::debug!("{}: G-lined", id);
+                    self.remove_client(id, lines::tr(lines::GLINED), lines::GLINED);
+                    return 999_999;
+                }
+                if self.hooks.register(id, client) == hooks::Verdict::Block {
+                    log::debug!("{}: Registration blocked by a hook", id);
+                    self.remove_client(
+                        id,
+                        lines::tr(lines::REGISTRATION_REFUSED),
+                        lines::REGISTRATION_REFUSED,
+                    );
+                    return 999_999;
+                }
                 log::debug!(
                     "{}: {:?} + {:?} == {:?}",
                     id,
@@ -467,7 +1594,14 @@ impl StateInner {
                     msg.command,
                     new_state
                 );
+                self.tracer.connection_registered(conn, client.nick());
+                self.events.emit(|| events::Event::Registered {
+                    id,
+                    nick: client.nick().to_owned(),
+                });
                 self.send_welcome(id, &mut rb);
+                self.send_offline_messages(id, &mut rb);
+                just_registered = true;
             } else if !old_state.is_registered() {
                 log::debug!(
                     "{}: {:?} + {:?} == {:?}",
@@ -483,22 +1617,81 @@ impl StateInner {
             points.saturating_mul(2)
         };
 
+        if let Some(session) = self.clients[conn].session {
+            let cap_enabled = self.clients[conn].cap_enabled;
+            self.clients[session].set_connection_caps(conn, cap_enabled);
+        }
+
         rb.lr_end();
         if !rb.is_empty() {
-            self.clients[id].send(rb);
+            let replies = rb.build();
+            if forward && self.clients[id].has_connections() {
+                let copy = Buffer::from(util::untagged_replies(&replies));
+                self.clients[id].send_except(id, conn, copy);
+            }
+            self.clients[conn].send_to_own_connection(Buffer::from(replies));
+        }
+        if just_registered {
+            self.attach_client(id);
+            self.record_peaks();
+            self.auto_join(id);
         }
 
-        if is_operator {
+        // IRC operators and trusted hosts (bridges, bots...) are mostly exempt from rate limits.
+        let is_trusted = self
+            .clients
+            .get(id)
+            .is_some_and(|client| self.trusted_hosts.is_match(client.full_name()));
+        if is_operator || is_trusted {
             1
         } else {
             used_points
         }
     }
 
+    /// Records the time spent handling a command sent by the connection `id`.
+    fn command_handled(
+        &mut self,
+        id: usize,
+        command: Command,
+        start: SystemTime,
+        elapsed: Duration,
+    ) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record(command.as_str(), elapsed);
+        self.tracer.command(id, command.as_str(), start, elapsed);
+    }
+
+    pub fn shutdown(&mut self) {
+        let sessions: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.session.is_none())
+            .map(|(id, _)| id)
+            .collect();
+        for id in sessions {
+            self.remove_client(id, lines::SERVER_SHUTDOWN, lines::SERVER_SHUTDOWN);
+        }
+        self.store.save_peaks();
+        self.store.sync();
+        self.accounts.sync();
+    }
+
+    /// The translations of the lines sent to the client `id`, or `None` for English.
+    fn catalog(&self, id: usize) -> Option<Arc<lines::Catalog>> {
+        let language = self
+            .clients
+            .get(id)
+            .and_then(|client| client.language.as_deref())
+            .unwrap_or(&self.default_language);
+        self.locales.get(language).cloned()
+    }
+
     pub fn remove_if_unregistered(&mut self, id: usize) {
         if let Some(client) = self.clients.get(id) {
             if !client.is_registered() {
-                self.remove_client(id, lines::REGISTRATION_TIMEOUT, "");
+                let _catalog = lines::use_catalog(self.catalog(id));
+                self.remove_client(id, lines::tr(lines::REGISTRATION_TIMEOUT), "");
             }
         }
     }
@@ -532,7 +1725,7 @@ fn find_channel<'a>(
         Err(()) => {
             rb.reply(rpl::ERR_NOSUCHCHANNEL)
                 .param(channel_name.get())
-                .trailing_param(lines::NO_SUCH_CHANNEL);
+                .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
             Err(())
         }
     }
@@ -554,12 +1747,40 @@ fn find_member(
             log::debug!("{}:         not on {:?}", id, channel_name.get());
             rb.reply(rpl::ERR_NOTONCHANNEL)
                 .param(channel_name.get())
-                .trailing_param(lines::NOT_ON_CHANNEL);
+                .trailing_param(&lines::tr(lines::NOT_ON_CHANNEL));
             Err(())
         }
     }
 }
 
+/// Sends `tokens` in as many RPL_ISUPPORT replies as needed.
+fn send_i_support_tokens(rb: &mut ReplyBuffer, tokens: &[String]) {
+    for tokens in tokens.chunks(I_SUPPORT_TOKENS_PER_REPLY) {
+        let mut msg = rb.reply(rpl::ISUPPORT);
+        for token in tokens {
+            msg = msg.param(token);
+        }
+        msg.trailing_param(&lines::tr(lines::I_SUPPORT));
+    }
+}
+
+/// Returns `Ok((address, client))` when the client identified by the nickname `nick` is connected
+/// and registered.  Otherwise returns `Err(())`.
+fn find_nick_quiet<'a>(
+    id: usize,
+    clients: &'a ClientMap,
+    nicks: &'a NicksMap,
+    nick: data::Nickname<'_>,
+) -> Result<(usize, &'a Client), ()> {
+    nicks
+        .get(nick.get())
+        .map(|id| (id, &clients[id]))
+        .filter(|(_, c)| c.is_registered())
+        .ok_or_else(|| {
+            log::debug!("{}:         nick doesn't exist", id);
+        })
+}
+
 /// Returns `Ok((address, client))` when the client identified by the nickname `nick` is connected
 /// and registered.  Otherwise returns `Err(())` and send an error to the client.
 fn find_nick<'a>(
@@ -570,19 +1791,69 @@ fn find_nick<'a>(
     nick: data::Nickname<'_>,
 ) -> Result<(usize, &'a Client), ()> {
     nicks
-        .get(nick.u())
-        .map(|id| (*id, &clients[*id]))
+        .get(nick.get())
+        .map(|id| (id, &clients[id]))
         .filter(|(_, c)| c.is_registered())
         .ok_or_else(|| {
             log::debug!("{}:         nick doesn't exist", id);
             rb.reply(rpl::ERR_NOSUCHNICK)
                 .param(nick.get())
-                .trailing_param(lines::NO_SUCH_NICK);
+                .trailing_param(&lines::tr(lines::NO_SUCH_NICK));
         })
 }
 
 // Send utilities
 impl StateInner {
+    /// Sends `msg` to the members of `channel_name` that match `filter`.  When they are more
+    /// than `fanout_threshold`, it is delivered by worker tasks.
+    fn fan_out(
+        &mut self,
+        channel_name: &str,
+        msg: MessageQueueItem,
+        filter: impl Fn(&Recipient) -> bool + Send + Sync + 'static,
+    ) {
+        let channel = self.channels.get_mut(u(channel_name)).unwrap();
+        let clients = &self.clients;
+        let recipients = channel
+            .recipients
+            .get_or_insert_with(|| {
+                let mut recipients = Recipients::default();
+                for &id in channel.members.keys() {
+                    recipients.push(clients[id].recipient(id));
+                }
+                Arc::new(recipients)
+            })
+            .clone();
+
+        for target in recipients.detached.iter().filter(|target| filter(target)) {
+            self.clients[target.id].send(msg.clone());
+        }
+        let threshold = self.fanout_threshold;
+        if threshold == 0 || recipients.connected.len() <= threshold {
+            for target in recipients.connected.iter().filter(|target| filter(target)) {
+                self.clients[target.id].send(msg.clone());
+            }
+            return;
+        }
+        self.outbox.broadcast(Broadcast {
+            recipients,
+            msg,
+            filter: Arc::new(filter),
+            chunk: threshold,
+        });
+    }
+
+    /// Drops the recipients of the channels of `id`, after the connections of the client, or the
+    /// modes and capabilities channel messages depend on, have changed.
+    fn drop_recipients(&mut self, id: usize) {
+        let id = self.clients[id].session.unwrap_or(id);
+        for channel in self.channels.values_mut() {
+            if channel.members.contains_key(&id) {
+                channel.recipients = None;
+            }
+        }
+    }
+
     fn send_notification(
         &self,
         issuer: usize,
@@ -612,56 +1883,75 @@ impl StateInner {
         }
     }
 
+    /// The tokens of the RPL_ISUPPORT replies.
+    fn i_support_tokens(&self) -> Vec<String> {
+        let mut tokens: Vec<String> = [
+            "BOT=B",
+            "CHANTYPES=#&",
+            mode::CHANMODES,
+            "EXCEPTS",
+            "HOSTLEN=39", // max size of an IPv6 address
+            "INVEX",
+            "MODES",
+            "PREFIX=(Oohv)~@%+",
+            "SAFELIST",
+            "TARGMAX=JOIN:,KICK:,LIST:,NAMES:,NOTICE:1,PART:,PRIVMSG:1,WHOIS:1",
+        ]
+        .iter()
+        .map(|&token| token.to_owned())
+        .collect();
+        tokens.push(format!("AWAYLEN={}", self.awaylen));
+        tokens.push(format!("CASEMAPPING={}", self.nicks.casemapping.name()));
+        if self.chanlimit == 0 {
+            tokens.push("CHANLIMIT=#&:".to_owned());
+        } else {
+            tokens.push(format!("CHANLIMIT=#&:{}", self.chanlimit));
+        }
+        tokens.push(format!("CHANNELLEN={}", self.channellen));
+        tokens.push(format!("CHATHISTORY={}", v3::CHATHISTORY_LIMIT));
+        tokens.push(format!("KEYLEN={}", self.keylen));
+        tokens.push(format!("KICKLEN={}", self.kicklen));
+        tokens.push(format!("LINELEN={}", self.linelen));
+        tokens.push(format!("MAXLIST=b:{0},e:{0},I:{0},q:{0}", self.maxlist));
+        tokens.push("MSGREFTYPES=timestamp,msgid".to_owned());
+        tokens.push(format!("NAMELEN={}", self.namelen));
+        tokens.push(format!("NICKLEN={}", self.nicklen));
+        tokens.push(format!("TOPICLEN={}", self.topiclen));
+        tokens
+    }
+
     fn send_i_support(&self, rb: &mut ReplyBuffer) {
-        rb.reply(rpl::ISUPPORT)
-            .param("CASEMAPPING=ascii")
-            .param("CHANLIMIT=#&:")
-            .param("CHANTYPES=#&")
-            .param(mode::CHANMODES)
-            .param("EXCEPTS")
-            .param("HOSTLEN=39") // max size of an IPv6 address
-            .param("INVEX")
-            .param("MODES")
-            .param("PREFIX=(ohv)@%+")
-            .param("SAFELIST")
-            .param("TARGMAX=JOIN:,KICK:,LIST:,NAMES:,NOTICE:1,PART:,PRIVMSG:1,WHOIS:1")
-            .fmt_param(format_args!("AWAYLEN={}", self.awaylen))
-            .fmt_param(format_args!("CHANNELLEN={}", self.channellen))
-            .trailing_param(lines::I_SUPPORT);
-        rb.reply(rpl::ISUPPORT)
-            .fmt_param(format_args!("KEYLEN={}", self.keylen))
-            .fmt_param(format_args!("KICKLEN={}", self.kicklen))
-            .fmt_param(format_args!("NAMELEN={}", self.namelen))
-            .fmt_param(format_args!("NICKLEN={}", self.nicklen))
-            .fmt_param(format_args!("TOPICLEN={}", self.topiclen))
-            .trailing_param(lines::I_SUPPORT);
+        send_i_support_tokens(rb, &self.i_support_tokens());
     }
 
     fn send_lusers(&self, id: usize, rb: &mut ReplyBuffer) {
+        // The additional connections of multiclient sessions are not counted as users.
+        let (users, op, unknown) =
+            self.clients
+                .iter()
+                .fold((0, 0, 0), |(users, op, unknown), (_, client)| {
+                    if !client.is_registered() {
+                        (users, op, unknown + 1)
+                    } else if client.session.is_some() {
+                        (users, op, unknown)
+                    } else if client.operator {
+                        (users + 1, op + 1, unknown)
+                    } else {
+                        (users + 1, op, unknown)
+                    }
+                });
         rb.reply(rpl::LUSERCLIENT)
-            .fmt_trailing_param(lines_luser_client!(self.clients.len()));
+            .fmt_trailing_param(lines_luser_client!(users + unknown));
 
-        let (op, unknown) = self
-            .clients
-            .iter()
-            .fold((0, 0), |(op, unknown), (_, client)| {
-                if !client.is_registered() {
-                    (op, unknown + 1)
-                } else if client.operator {
-                    (op + 1, unknown)
-                } else {
-                    (op, unknown)
-                }
-            });
         if 0 < op {
             rb.reply(rpl::LUSEROP)
                 .fmt_param(op)
-                .trailing_param(lines::LUSER_OP);
+                .trailing_param(&lines::tr(lines::LUSER_OP));
         }
         if 0 < unknown {
             rb.reply(rpl::LUSERUNKNOWN)
                 .fmt_param(unknown)
-                .trailing_param(lines::LUSER_UNKNOWN);
+                .trailing_param(&lines::tr(lines::LUSER_UNKNOWN));
         }
 
         let channels = self
@@ -672,11 +1962,59 @@ impl StateInner {
         if 0 < channels {
             rb.reply(rpl::LUSERCHANNELS)
                 .fmt_param(channels)
-                .trailing_param(lines::LUSER_CHANNELS);
+                .trailing_param(&lines::tr(lines::LUSER_CHANNELS));
         }
 
         rb.reply(rpl::LUSERME)
-            .fmt_trailing_param(lines_luser_me!(self.clients.len()));
+            .fmt_trailing_param(lines_luser_me!(users + unknown));
+
+        // The client that is registering is not counted in the peaks yet.
+        let mut peaks = self.store.peaks();
+        peaks.users = peaks.users.max(users);
+        peaks.connections = peaks.connections.max(self.clients.len());
+        rb.reply(rpl::LOCALUSERS)
+            .fmt_param(users)
+            .fmt_param(peaks.users)
+            .fmt_trailing_param(lines_local_users!(users, peaks.users));
+        rb.reply(rpl::GLOBALUSERS)
+            .fmt_param(users)
+            .fmt_param(peaks.users)
+            .fmt_trailing_param(lines_global_users!(users, peaks.users));
+        rb.reply(rpl::STATSCONN)
+            .fmt_trailing_param(lines_highest_connections!(peaks.connections, peaks.users));
+    }
+
+    /// Raises the peaks shown by LUSERS to the current numbers of users and connections.
+    fn record_peaks(&mut self) {
+        let connections = self.clients.len();
+        let peaks = self.store.peaks();
+        // There cannot be more users than connections.
+        if connections <= peaks.users && connections <= peaks.connections {
+            return;
+        }
+        let users = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.is_registered() && client.session.is_none())
+            .count();
+        self.store.record_peaks(users, connections);
+    }
+
+    /// Returns the value of the given MOTD variable.
+    fn motd_var(&self, name: &str) -> Option<String> {
+        match name {
+            "server" => Some(self.domain.to_string()),
+            "uptime" => {
+                let uptime = util::time().saturating_sub(self.started_at);
+                Some(humantime::format_duration(Duration::from_secs(uptime)).to_string())
+            }
+            "users" => {
+                let users = self.clients.iter().filter(|(_, c)| c.session.is_none());
+                Some(users.count().to_string())
+            }
+            "date" => Some(util::time_str()),
+            _ => None,
+        }
     }
 
     fn send_motd(&self, rb: &mut ReplyBuffer) {
@@ -685,14 +2023,34 @@ impl StateInner {
                 .fmt_trailing_param(lines_motd_start!(&self.domain));
 
             for line in motd.lines() {
+                let line = util::expand_vars(line, |name| self.motd_var(name));
                 rb.reply(rpl::MOTD)
                     .fmt_trailing_param(format_args!("- {line}"));
             }
 
-            rb.reply(rpl::ENDOFMOTD).trailing_param(lines::END_OF_MOTD);
+            rb.reply(rpl::ENDOFMOTD)
+                .trailing_param(&lines::tr(lines::END_OF_MOTD));
         } else {
-            rb.reply(rpl::ERR_NOMOTD).trailing_param(lines::NO_MOTD);
+            rb.reply(rpl::ERR_NOMOTD)
+                .trailing_param(&lines::tr(lines::NO_MOTD));
+        }
+    }
+
+    /// Sends the message of the day of IRC operators, if any.
+    fn send_oper_motd(&self, rb: &mut ReplyBuffer) {
+        let motd = match self.oper_motd {
+            Some(ref motd) => motd,
+            None => return,
+        };
+        rb.reply(rpl::OMOTDSTART)
+            .fmt_trailing_param(lines_oper_motd_start!(&self.domain));
+        for line in motd.lines() {
+            let line = util::expand_vars(line, |name| self.motd_var(name));
+            rb.reply(rpl::OMOTD)
+                .fmt_trailing_param(format_args!("- {line}"));
         }
+        rb.reply(rpl::ENDOFOMOTD)
+            .trailing_param(&lines::tr(lines::END_OF_OPER_MOTD));
     }
 
     /// Sends the list of nicknames in the channel `channel_name` to the given client.
@@ -701,7 +2059,7 @@ impl StateInner {
             Some(channel) => channel,
             None => return,
         };
-        if channel.secret && !channel.members.contains_key(&id) {
+        if channel.hides_members() && !channel.members.contains_key(&id) {
             return;
         }
 
@@ -735,7 +2093,7 @@ impl StateInner {
 
         rb.reply(rpl::ENDOFNAMES)
             .param(channel_name.get())
-            .trailing_param(lines::END_OF_NAMES);
+            .trailing_param(&lines::tr(lines::END_OF_NAMES));
     }
 
     /// Sends the topic of the channel `channel_name` to the given client.
@@ -758,7 +2116,7 @@ impl StateInner {
         } else if send_error {
             rb.reply(rpl::NOTOPIC)
                 .param(channel_name.get())
-                .trailing_param(lines::NO_TOPIC);
+                .trailing_param(&lines::tr(lines::NO_TOPIC));
         }
     }
 
@@ -783,4 +2141,50 @@ impl StateInner {
         self.send_lusers(id, rb);
         self.send_motd(rb);
     }
+
+    /// Sends the messages received by the account of the client while it was offline, in an
+    /// `ellidri/offline` batch.
+    fn send_offline_messages(&mut self, id: usize, rb: &mut ReplyBuffer) {
+        let client = &self.clients[id];
+        let account = match client.account() {
+            Some(account) => account.to_ascii_lowercase(),
+            None => return,
+        };
+        let messages = match self.offline_messages.remove(&account) {
+            Some(messages) => messages,
+            None => return,
+        };
+
+        let caps = client.cap_enabled;
+        if caps.batch {
+            rb.batch_begin("ellidri/offline");
+        }
+        for message in messages {
+            let mut msg = rb.tagged_message("");
+            if caps.message_tags {
+                msg = msg.tag("msgid", Some(&message.msgid));
+            }
+            if caps.server_time {
+                msg = msg.tag("time", Some(&message.time));
+            }
+            if caps.account_tag || caps.message_tags {
+                if let Some(ref account) = message.account {
+                    msg = msg.tag("account", Some(account));
+                }
+            }
+            let msg = msg
+                .prefixed_command(&message.from, Command::PrivMsg)
+                .param(client.nick());
+            if caps.server_time || message.content.starts_with('\x01') {
+                msg.trailing_param(&message.content);
+            } else {
+                // Without server-time, show when the message was sent like bouncers do.
+                let time = message.time.get(..19).unwrap_or_default().replace('T', " ");
+                msg.fmt_trailing_param(format_args!("[{}] {}", time, message.content));
+            }
+        }
+        if client.cap_enabled.batch {
+            rb.batch_end();
+        }
+    }
 }
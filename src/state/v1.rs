@@ -3,15 +3,35 @@
 //! <https://tools.ietf.org/html/rfc2812.html>
 //! <https://modern.ircdocs.horse/>
 
+use super::ext::{LOCKDOWN_CHANNELS, LOCKDOWN_MESSAGES, SERVER_NOTICE_CHANNEL};
 use super::{
-    find_channel, find_channel_quiet, find_member, find_nick, CommandContext,
+    find_channel, find_channel_quiet, find_member, find_nick, find_nick_quiet, CommandContext,
     HandlerResult as Result,
 };
 use crate::channel::{MemberModes, Topic};
-use crate::client::MessageQueueItem;
-use crate::{data, lines, util, Channel, Client};
+use crate::client::{Invite, MessageQueueItem, PendingList};
+use crate::events::Event;
+use crate::hooks::Verdict;
+use crate::{accounts, config, data, history, lines, util, Channel, Client};
 use ellidri_tokens::{mode, rpl, Buffer, Command, ReplyBuffer};
 use ellidri_unicase::{u, UniCase};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Maximum length of the channel list in a `RPL_WHOISCHANNELS` reply.
+const WHOIS_CHANNELS_LENGTH: usize = 400;
+
+/// Maximum number of nicknames a USERHOST request can ask about.
+const USERHOST_MAX_NICKS: usize = 5;
+
+/// How many channels are listed at once.  Longer LIST replies are sent in several chunks, and the
+/// state is unlocked between them.
+const LIST_CHUNK_LEN: usize = 100;
+
+/// How long clients must wait before listing all channels again.  IRC operators are exempt.
+const LIST_DELAY: Duration = Duration::from_secs(10);
 
 // Command handlers
 impl super::StateInner {
@@ -22,7 +42,7 @@ impl super::StateInner {
         ctx.rb
             .reply(rpl::ADMINME)
             .param(&self.domain)
-            .trailing_param(lines::ADMIN_ME);
+            .trailing_param(&lines::tr(lines::ADMIN_ME));
         ctx.rb
             .reply(rpl::ADMINLOC1)
             .trailing_param(&self.org_location);
@@ -36,20 +56,33 @@ impl super::StateInner {
     pub fn cmd_away(&mut self, ctx: CommandContext<'_>, reason: Option<&str>) -> Result {
         let client = &mut self.clients[ctx.id];
 
-        if client.away_message().is_some() == reason.is_some() {
+        if client.away_message().is_some() == reason.is_some() && !client.auto_away {
             log::debug!("{}:     useless away", ctx.id);
             return Err(());
         }
 
         let awaylen = self.awaylen;
         client.away_message = reason.map(|r| r[..r.len().min(awaylen)].to_owned());
+        client.auto_away = false;
 
         if reason.is_some() {
-            ctx.rb.reply(rpl::NOWAWAY).trailing_param(lines::NOW_AWAY);
+            ctx.rb
+                .reply(rpl::NOWAWAY)
+                .trailing_param(&lines::tr(lines::NOW_AWAY));
         } else {
-            ctx.rb.reply(rpl::UNAWAY).trailing_param(lines::UN_AWAY);
+            ctx.rb
+                .reply(rpl::UNAWAY)
+                .trailing_param(&lines::tr(lines::UN_AWAY));
         }
 
+        self.send_away_notify(ctx.id);
+        Ok(())
+    }
+
+    /// Sends the away status of the client `id` to the clients that share a channel with it and
+    /// have enabled away-notify.
+    pub(super) fn send_away_notify(&self, id: usize) {
+        let client = &self.clients[id];
         let mut away_notify = Buffer::with_capacity(512);
         {
             let msg = away_notify.message(client.full_name(), Command::Away);
@@ -57,10 +90,22 @@ impl super::StateInner {
                 msg.trailing_param(away_message);
             }
         }
-        self.send_notification(ctx.id, away_notify, |_, client| {
-            client.cap_enabled.away_notify
-        });
-        Ok(())
+        self.send_notification(id, away_notify, |_, client| client.cap_enabled.away_notify);
+    }
+
+    /// Resets the idle time of the client `id`, and removes its away status if it has been set
+    /// automatically.
+    fn set_active(&mut self, id: usize, rb: &mut ReplyBuffer) {
+        let client = &mut self.clients[id];
+        client.update_idle_time();
+        if !client.auto_away {
+            return;
+        }
+        client.auto_away = false;
+        client.away_message = None;
+        rb.reply(rpl::UNAWAY)
+            .trailing_param(&lines::tr(lines::UN_AWAY));
+        self.send_away_notify(id);
     }
 
     // INFO
@@ -70,9 +115,26 @@ impl super::StateInner {
         for line in super::SERVER_INFO.lines() {
             ctx.rb.reply(rpl::INFO).trailing_param(line);
         }
+        let features = if super::SERVER_FEATURES.is_empty() {
+            Cow::Borrowed("no optional features")
+        } else {
+            Cow::Owned(super::SERVER_FEATURES.join(", "))
+        };
+        ctx.rb
+            .reply(rpl::INFO)
+            .fmt_trailing_param(lines_info_build!(
+                super::SERVER_VERSION,
+                super::SERVER_BUILD_DATE
+            ));
+        ctx.rb
+            .reply(rpl::INFO)
+            .fmt_trailing_param(lines_info_features!(features));
+        ctx.rb
+            .reply(rpl::INFO)
+            .fmt_trailing_param(lines_info_admin!(self.org_name, self.org_mail));
         ctx.rb
             .reply(rpl::ENDOFINFO)
-            .trailing_param(lines::END_OF_INFO);
+            .trailing_param(&lines::tr(lines::END_OF_INFO));
         Ok(())
     }
 
@@ -81,14 +143,14 @@ impl super::StateInner {
     pub fn cmd_invite(&mut self, ctx: CommandContext<'_>, args: data::req::Invite<'_>) -> Result {
         let (who_id, who_data) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
 
-        let channel = match self.channels.get_mut(args.to.u()) {
+        let channel = match self.channels.get(args.to.u()) {
             Some(channel) => channel,
             None => {
                 log::debug!("{}:     no such channel", ctx.id);
                 ctx.rb
                     .reply(rpl::ERR_NOSUCHCHANNEL)
                     .param(args.to.get())
-                    .trailing_param(lines::NO_SUCH_CHANNEL);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
                 return Err(());
             }
         };
@@ -97,7 +159,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_CHANOPRIVSNEEDED)
                 .param(args.to.get())
-                .trailing_param(lines::CHAN_O_PRIVS_NEEDED);
+                .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
             return Err(());
         }
         if channel.members.contains_key(&who_id) {
@@ -106,11 +168,7 @@ impl super::StateInner {
                 .reply(rpl::ERR_USERONCHANNEL)
                 .param(args.who.get())
                 .param(args.to.get())
-                .trailing_param(lines::USER_ON_CHANNEL);
-            return Err(());
-        }
-
-        if who_data.invites.contains(args.to.u()) {
+                .trailing_param(&lines::tr(lines::USER_ON_CHANNEL));
             return Err(());
         }
 
@@ -126,9 +184,19 @@ impl super::StateInner {
                 .trailing_param(away_msg);
         }
 
-        self.clients[who_id]
-            .invites
-            .insert(UniCase::new(args.to.get().to_owned()));
+        let expires = if self.invite_expiry == 0 {
+            u64::MAX
+        } else {
+            util::time().saturating_add(self.invite_expiry)
+        };
+        let inviter = self.clients[ctx.id].nick().to_owned();
+        self.clients[who_id].invites.insert(
+            UniCase::new(args.to.get().to_owned()),
+            Invite {
+                by: inviter,
+                expires,
+            },
+        );
 
         let mut invite = Buffer::with_capacity(512);
         invite
@@ -139,16 +207,48 @@ impl super::StateInner {
 
         self.clients[who_id].send(invite.clone());
 
-        for member in channel.members.keys().filter(|a| **a != ctx.id) {
+        for (member, modes) in channel.members.iter().filter(|(a, _)| **a != ctx.id) {
             let c = &self.clients[*member];
             if c.cap_enabled.invite_notify && channel.can_invite(*member) {
                 c.send(invite.clone());
+            } else if modes.is_at_least_halfop() {
+                let mut notice = Buffer::with_capacity(512);
+                notice
+                    .message(&self.domain, Command::Notice)
+                    .param(c.nick())
+                    .fmt_trailing_param(lines_invite_sent!(
+                        args.to.get(),
+                        self.clients[ctx.id].nick(),
+                        args.who.get()
+                    ));
+                c.send(notice);
             }
         }
 
         Ok(())
     }
 
+    // ISON
+
+    pub fn cmd_ison(&self, ctx: CommandContext<'_>, params: &[&str]) -> Result {
+        // Nicknames can be given as several parameters, or space-separated in the last one.
+        let mut online = String::new();
+        for nick in params.iter().flat_map(|param| param.split_whitespace()) {
+            let nick = match data::Nickname::try_from(nick) {
+                Ok(nick) => nick,
+                Err(_) => continue,
+            };
+            if let Ok((_, client)) = find_nick_quiet(ctx.id, &self.clients, &self.nicks, nick) {
+                if !online.is_empty() {
+                    online.push(' ');
+                }
+                online.push_str(client.nick());
+            }
+        }
+        ctx.rb.reply(rpl::ISON).trailing_param(&online);
+        Ok(())
+    }
+
     // JOIN
 
     fn check_join(
@@ -167,26 +267,34 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_BADCHANKEY)
                 .param(channel_name)
-                .trailing_param(lines::BAD_CHAN_KEY);
+                .trailing_param(&lines::tr(lines::BAD_CHAN_KEY));
             return Err(());
         }
         if channel
             .user_limit
-            .map_or(false, |user_limit| user_limit <= channel.members.len())
+            .is_some_and(|user_limit| user_limit <= channel.members.len())
         {
             log::debug!("{}:     user limit reached", ctx.id);
             ctx.rb
                 .reply(rpl::ERR_CHANNELISFULL)
                 .param(channel_name)
-                .trailing_param(lines::CHANNEL_IS_FULL);
+                .trailing_param(&lines::tr(lines::CHANNEL_IS_FULL));
+            return Err(());
+        }
+        if !client.is_invited_to(channel_name) && channel.is_join_throttled(Instant::now()) {
+            log::debug!("{}:     join limit reached", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_THROTTLE)
+                .param(channel_name)
+                .trailing_param(&lines::tr(lines::JOIN_THROTTLED));
             return Err(());
         }
-        if !channel.is_invited(client.nick()) && !client.invites.contains(u(channel_name)) {
+        if !channel.is_invited(client.nick()) && !client.is_invited_to(channel_name) {
             log::debug!("{}:     not invited", ctx.id);
             ctx.rb
                 .reply(rpl::ERR_INVITEONLYCHAN)
                 .param(channel_name)
-                .trailing_param(lines::INVITE_ONLY_CHAN);
+                .trailing_param(&lines::tr(lines::INVITE_ONLY_CHAN));
             return Err(());
         }
         if channel.is_banned(client.nick()) || channel.is_banned(client.full_name()) {
@@ -194,7 +302,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_BANNEDFROMCHAN)
                 .param(channel_name)
-                .trailing_param(lines::BANNED_FROM_CHAN);
+                .trailing_param(&lines::tr(lines::BANNED_FROM_CHAN));
             return Err(());
         }
         Ok(())
@@ -243,12 +351,32 @@ impl super::StateInner {
         }
     }
 
-    pub fn cmd_join(&mut self, mut ctx: CommandContext<'_>, list: data::JoinList<'_>) -> Result {
+    pub fn cmd_join(&mut self, ctx: CommandContext<'_>, list: data::JoinList<'_>) -> Result {
+        self.join(ctx, list, false)
+    }
+
+    /// Joins the client to the channels of `list`.  When `force` is true, keys, limits,
+    /// invitations and bans are ignored.
+    pub(super) fn join(
+        &mut self,
+        mut ctx: CommandContext<'_>,
+        list: data::JoinList<'_>,
+        force: bool,
+    ) -> Result {
         let client = &self.clients[ctx.id];
+        let mut joined_channels = client.joined_channels;
 
         let mut joined = false;
         for (channel_name, key) in list.iter() {
+            if channel_name.u() == u(SERVER_NOTICE_CHANNEL) && !client.operator {
+                log::debug!("{}:     not operator", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_NOPRIVILEDGES)
+                    .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
+                continue;
+            }
             let can_join = match self.channels.get(channel_name.u()) {
+                Some(channel) if force => !channel.members.contains_key(&ctx.id),
                 Some(channel) => Self::check_join(
                     client,
                     channel,
@@ -257,27 +385,101 @@ impl super::StateInner {
                     &mut ctx,
                 )
                 .is_ok(),
+                None if LOCKDOWN_CHANNELS <= self.lockdown && !client.operator && !force => {
+                    log::debug!("{}:     no channel creation during lockdown", ctx.id);
+                    ctx.rb
+                        .reply(rpl::ERR_NOSUCHCHANNEL)
+                        .param(channel_name.get())
+                        .trailing_param(&lines::tr(lines::LOCKDOWN_CHANNELS));
+                    false
+                }
                 None => true,
             };
+            let too_many = self.chanlimit != 0 && self.chanlimit <= joined_channels;
+            if can_join && too_many && !client.operator && !force {
+                log::debug!("{}:     too many channels", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_TOOMANYCHANNELS)
+                    .param(channel_name.get())
+                    .trailing_param(&lines::tr(lines::TOO_MANY_CHANNELS));
+                continue;
+            }
+            let can_join = can_join && {
+                let verdict = self.hooks.join(ctx.id, client, channel_name.get());
+                if verdict == Verdict::Block {
+                    log::debug!("{}:     blocked by a hook", ctx.id);
+                    ctx.rb
+                        .reply(rpl::ERR_BANNEDFROMCHAN)
+                        .param(channel_name.get())
+                        .trailing_param(&lines::tr(lines::BANNED_FROM_CHAN));
+                }
+                verdict == Verdict::Continue
+            };
 
             if can_join {
+                let invited_by = self
+                    .channels
+                    .get(channel_name.u())
+                    .filter(|channel| !channel.is_invited(client.nick()))
+                    .and_then(|_| client.invites.get(channel_name.u()))
+                    .map(|invite| invite.by.clone());
+                let registered = self.store.founder(channel_name.get()).is_some();
+                let access = self.store.access_level(
+                    channel_name.get(),
+                    client.full_name(),
+                    client.account(),
+                );
+
                 let default_chan_mode = &self.default_chan_mode;
                 let channel = self
                     .channels
                     .entry(UniCase::new(channel_name.get().to_owned()))
                     .or_insert_with(|| Channel::new(default_chan_mode));
-                channel.add_member(ctx.id);
+                channel.add_member(ctx.id, registered);
+                channel.count_join(Instant::now());
+                joined_channels += 1;
+
+                let modes = channel.members.get_mut(&ctx.id).unwrap();
+                let mut given_modes = String::new();
+                if let Some(account) = client.account() {
+                    if self.store.is_founder(channel_name.get(), account) {
+                        modes.founder = true;
+                        given_modes.push('O');
+                    }
+                }
+                if let Some(level) = access.filter(|level| level.apply(modes)) {
+                    given_modes.push(level.letter());
+                }
 
                 ctx.rb.lr_batch_begin();
                 self.send_join(ctx.id, ctx.rb, channel_name.get(), client);
+                self.chanlog.join(channel_name.get(), client);
+                if !given_modes.is_empty() {
+                    self.send_join_modes(ctx.id, ctx.rb, channel_name.get(), &given_modes);
+                }
                 self.send_topic(ctx.rb, channel_name, false);
                 self.send_names(ctx.id, ctx.rb, channel_name);
+                self.send_join_metadata(ctx.id, ctx.rb, channel_name);
+                self.send_autoreplay(ctx.id, ctx.rb, channel_name.get());
+                let event = history::Entry::event(
+                    Command::Join,
+                    client.full_name(),
+                    client.account(),
+                    String::new(),
+                );
+                let limit = self.channel_history_limit(channel_name.get());
+                let channel = self.channels.get_mut(channel_name.u()).unwrap();
+                channel.history.push(event, limit);
+                if let Some(invited_by) = invited_by {
+                    self.send_invite_used(ctx.id, channel_name.get(), &invited_by);
+                }
                 joined = true;
             }
         }
         if joined {
+            self.set_active(ctx.id, ctx.rb);
             let client = &mut self.clients[ctx.id];
-            client.update_idle_time();
+            client.joined_channels = joined_channels;
             for (channel_name, _) in list.iter() {
                 client.invites.remove(channel_name.u());
             }
@@ -288,20 +490,49 @@ impl super::StateInner {
 
     // KICK
 
-    fn send_kick(
+    /// Removes the client `kicked_id` from `channel_name`, and tells the members.  The KICK is from
+    /// the client `by`, or from the server if `None`.  The client `id` is sent the KICK through
+    /// `rb`.
+    ///
+    /// Returns false if `kicked_id` is not on the channel.
+    pub(super) fn kick_member(
+        &mut self,
         id: usize,
         rb: &mut ReplyBuffer,
-        clients: &super::ClientMap,
-        channel: &Channel,
         channel_name: &str,
+        by: Option<usize>,
         kicked_id: usize,
-        kicked_nick: &str,
         reason: Option<&str>,
-    ) {
-        let client = &clients[id];
+    ) -> bool {
+        let history_limit = self.channel_history_limit(channel_name);
+        let channel = match self.channels.get_mut(u(channel_name)) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        if channel.remove_member(kicked_id).is_none() {
+            return false;
+        }
+        channel.mutes.remove(&kicked_id);
+
+        let (by_name, by_nick, by_account) = match by {
+            Some(by) => {
+                let client = &self.clients[by];
+                (client.full_name(), client.nick(), client.account())
+            }
+            None => (&*self.domain, &*self.domain, None),
+        };
+        let kicked_nick = self.clients[kicked_id].nick();
+        self.chanlog
+            .kick(channel_name, kicked_nick, by_nick, reason);
+        self.events.emit(|| Event::Kick {
+            by: by_nick.to_owned(),
+            channel: channel_name.to_owned(),
+            kicked: kicked_nick.to_owned(),
+            reason: reason.map(str::to_owned),
+        });
 
         let msg = rb
-            .message(client.full_name(), Command::Kick)
+            .message(by_name, Command::Kick)
             .param(channel_name)
             .param(kicked_nick);
         if let Some(reason) = reason {
@@ -311,7 +542,7 @@ impl super::StateInner {
         let mut kick_response = Buffer::with_capacity(512);
         {
             let msg = kick_response
-                .message(client.full_name(), Command::Kick)
+                .message(by_name, Command::Kick)
                 .param(channel_name)
                 .param(kicked_nick);
             if let Some(reason) = reason {
@@ -321,20 +552,35 @@ impl super::StateInner {
         let msg = MessageQueueItem::from(kick_response);
 
         for member in channel.members.keys().filter(|m| **m != id) {
-            clients[*member].send(msg.clone());
+            self.clients[*member].send(msg.clone());
+        }
+        if kicked_id != id {
+            self.clients[kicked_id].send(msg);
         }
-        clients[kicked_id].send(msg);
+
+        let event = history::Entry::event(
+            Command::Kick,
+            by_name,
+            by_account,
+            match reason {
+                Some(reason) => format!("{kicked_nick} {reason}"),
+                None => kicked_nick.to_owned(),
+            },
+        );
+        channel.history.push(event, history_limit);
+        self.clients[kicked_id].left_channel();
+        true
     }
 
     pub fn cmd_kick(&mut self, ctx: CommandContext<'_>, args: data::req::Kick<'_>) -> Result {
-        let channel = match self.channels.get_mut(args.from.u()) {
+        let channel = match self.channels.get(args.from.u()) {
             Some(channel) => channel,
             None => {
                 log::debug!("{}:         no such channel", ctx.id);
                 ctx.rb
                     .reply(rpl::ERR_NOSUCHCHANNEL)
                     .param(args.from.get())
-                    .trailing_param(lines::NO_SUCH_CHANNEL);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
                 return Err(());
             }
         };
@@ -346,7 +592,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_CHANOPRIVSNEEDED)
                 .param(args.from.get())
-                .trailing_param(lines::CHAN_O_PRIVS_NEEDED);
+                .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
             return Err(());
         }
 
@@ -356,27 +602,26 @@ impl super::StateInner {
             .map(|reason| &reason[..reason.len().min(kicklen)]);
 
         for kicked_nick in args.who.iter() {
-            let kicked_id = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, kicked_nick)
+            let kicked = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, kicked_nick)
                 .ok()
-                .and_then(|(id, _)| channel.members.remove(&id).map(|_| id));
-            if let Some(kicked_id) = kicked_id {
-                Self::send_kick(
+                .map(|(id, _)| id);
+            let kicked = kicked.is_some_and(|kicked_id| {
+                self.kick_member(
                     ctx.id,
                     ctx.rb,
-                    &self.clients,
-                    channel,
                     args.from.get(),
+                    Some(ctx.id),
                     kicked_id,
-                    kicked_nick.get(),
                     reason,
-                );
-            } else {
+                )
+            });
+            if !kicked {
                 log::debug!("{}:     {:?} not on channel", ctx.id, kicked_nick.get());
                 ctx.rb
                     .reply(rpl::ERR_USERNOTINCHANNEL)
                     .param(kicked_nick.get())
                     .param(args.from.get())
-                    .trailing_param(lines::USER_NOT_IN_CHANNEL);
+                    .trailing_param(&lines::tr(lines::USER_NOT_IN_CHANNEL));
             }
         }
 
@@ -390,35 +635,130 @@ impl super::StateInner {
         if !client.operator {
             ctx.rb
                 .reply(rpl::ERR_NOPRIVILEDGES)
-                .trailing_param(lines::NO_PRIVILEDGES);
+                .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
             return Err(());
         }
-        let (target_id, _) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        let (target_id, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.who)?;
+        self.send_server_notice(format_args!(
+            "{} killed {}: {}",
+            client.nick(),
+            target.full_name(),
+            args.reason
+        ));
         self.remove_client(target_id, format_args!("Killed: {}", args.reason), "Killed");
         Ok(())
     }
 
     // LIST
 
-    pub fn cmd_list_all(&self, ctx: CommandContext<'_>) -> Result {
+    pub fn cmd_list_all(&mut self, ctx: CommandContext<'_>) -> Result {
         let client = &self.clients[ctx.id];
-        ctx.rb.lr_batch_begin();
+        let now = Instant::now();
+        let too_soon = client
+            .last_list
+            .is_some_and(|last| now.duration_since(last) < LIST_DELAY);
+        if self.clients[ctx.conn].pending_list.is_some() || (too_soon && !client.operator) {
+            log::debug!("{}:     listing too often", ctx.id);
+            ctx.rb
+                .reply(rpl::TRYAGAIN)
+                .param("LIST")
+                .trailing_param(&lines::tr(lines::LIST_TOO_OFTEN));
+            return Err(());
+        }
 
-        for (name, channel) in &self.channels {
-            if channel.secret && !client.operator && !channel.members.contains_key(&ctx.id) {
-                continue;
+        let visible = |(name, channel): (&UniCase<String>, &Channel)| {
+            let visible =
+                !channel.secret || client.operator || channel.members.contains_key(&ctx.id);
+            visible.then(|| name.get().to_owned())
+        };
+        let channels: VecDeque<String> = self.channels.iter().filter_map(visible).collect();
+        self.clients[ctx.id].last_list = Some(now);
+
+        if channels.len() <= LIST_CHUNK_LEN {
+            ctx.rb.lr_batch_begin();
+            for name in &channels {
+                let msg = ctx.rb.reply(rpl::LIST).param(name);
+                self.channels[u(name)].list_entry(msg);
             }
-            let msg = ctx.rb.reply(rpl::LIST).param(name.get());
-            channel.list_entry(msg);
+            ctx.rb
+                .reply(rpl::LISTEND)
+                .trailing_param(&lines::tr(lines::END_OF_LIST));
+            return Ok(());
         }
 
-        ctx.rb
-            .reply(rpl::LISTEND)
-            .trailing_param(lines::END_OF_LIST);
+        // The replies are sent by `send_list_chunk`, in their own batch, since other replies can
+        // be sent in between.
+        let labeled = ctx.rb.has_label();
+        if labeled {
+            ctx.rb
+                .prefixed_message("BATCH")
+                .param("+list")
+                .param("labeled-response");
+        }
+        self.clients[ctx.conn].pending_list = Some(PendingList {
+            id: ctx.id,
+            channels,
+            labeled,
+        });
 
         Ok(())
     }
 
+    /// Sends the next channels of the LIST reply pending on the connection `conn`.  Returns
+    /// whether there are channels left to send.
+    pub(super) fn send_list_chunk(&mut self, conn: usize) -> bool {
+        let _catalog = lines::use_catalog(self.catalog(conn));
+        let mut pending = match self
+            .clients
+            .get_mut(conn)
+            .and_then(|c| c.pending_list.take())
+        {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let operator = self.clients.get(pending.id).is_some_and(|c| c.operator);
+        let client = &self.clients[conn];
+        let batch = pending.labeled.then_some("list");
+
+        let mut buf = Buffer::new();
+        let n = pending.channels.len().min(LIST_CHUNK_LEN);
+        for name in pending.channels.drain(..n) {
+            let channel = match self.channels.get(u(&name)) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            if channel.secret && !operator && !channel.members.contains_key(&pending.id) {
+                continue;
+            }
+            let msg = buf
+                .tagged_message("")
+                .tag("batch", batch)
+                .prefixed_command(&client.domain, rpl::LIST)
+                .param(client.nick())
+                .param(&name);
+            channel.list_entry(msg);
+        }
+
+        let done = pending.channels.is_empty();
+        if done {
+            buf.tagged_message("")
+                .tag("batch", batch)
+                .prefixed_command(&client.domain, rpl::LISTEND)
+                .param(client.nick())
+                .trailing_param(&lines::tr(lines::END_OF_LIST));
+            if pending.labeled {
+                buf.message(&client.domain, "BATCH").param("-list");
+            }
+        }
+        if !buf.is_empty() {
+            client.send(buf);
+        }
+        if !done {
+            self.clients[conn].pending_list = Some(pending);
+        }
+        !done
+    }
+
     pub fn cmd_list(
         &self,
         ctx: CommandContext<'_>,
@@ -439,7 +779,7 @@ impl super::StateInner {
 
         ctx.rb
             .reply(rpl::LISTEND)
-            .trailing_param(lines::END_OF_LIST);
+            .trailing_param(&lines::tr(lines::END_OF_LIST));
 
         Ok(())
     }
@@ -473,6 +813,18 @@ impl super::StateInner {
         ctx: CommandContext<'_>,
         args: data::req::ModeChannelSet<'_>,
     ) -> Result {
+        self.mode_channel_set(ctx, args, false)
+    }
+
+    /// Changes the modes of a channel.  When `force` is true, the client doesn't need to be a
+    /// member of the channel, nor to be allowed to change its modes.
+    pub(super) fn mode_channel_set(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::ModeChannelSet<'_>,
+        force: bool,
+    ) -> Result {
+        let history_limit = self.channel_history_limit(args.channel.get());
         let channel = match self.channels.get_mut(args.channel.u()) {
             Some(channel) => channel,
             None => {
@@ -480,33 +832,41 @@ impl super::StateInner {
                 ctx.rb
                     .reply(rpl::ERR_NOSUCHCHANNEL)
                     .param(args.channel.get())
-                    .trailing_param(lines::NO_SUCH_CHANNEL);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
                 return Err(());
             }
         };
 
         let issuer = &self.clients[ctx.id];
-        let issuer_modes = find_member(ctx.id, ctx.rb, channel, args.channel)?;
-
-        if !issuer.operator && !issuer_modes.can_change(args.modes) {
-            log::debug!("{}:     not operator", ctx.id);
-            ctx.rb
-                .reply(rpl::ERR_CHANOPRIVSNEEDED)
-                .param(args.channel.get())
-                .trailing_param(lines::CHAN_O_PRIVS_NEEDED);
-            return Err(());
+        if !force {
+            let mut issuer_modes = find_member(ctx.id, ctx.rb, channel, args.channel)?;
+            // The founder of the channel can give and take founder status even without it.
+            issuer_modes.founder |= issuer
+                .account()
+                .is_some_and(|account| self.store.is_founder(args.channel.get(), account));
+            if !issuer.operator && !issuer_modes.can_change(args.modes) {
+                log::debug!("{}:     not operator", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_CHANOPRIVSNEEDED)
+                    .param(args.channel.get())
+                    .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
+                return Err(());
+            }
         }
 
-        let reply_list = |rb: &mut ReplyBuffer, item, end, line: &str, it: util::Masks<'_>| {
-            for i in it {
-                rb.reply(item).param(args.channel.get()).param(i);
-            }
-            rb.reply(end).param(args.channel.get()).trailing_param(line);
-        };
+        let reply_list =
+            |rb: &mut ReplyBuffer, item, end, line: &'static str, it: util::Masks<'_>| {
+                for i in it {
+                    rb.reply(item).param(args.channel.get()).param(i);
+                }
+                rb.reply(end)
+                    .param(args.channel.get())
+                    .trailing_param(&lines::tr(line));
+            };
 
         ctx.rb.lr_batch_begin();
 
-        let clients = &self.clients;
+        let nicks = &self.nicks;
         let mut applied_modes = String::new();
         let mut applied_modeparams = Vec::new();
         let mut last_applied_value = true;
@@ -539,8 +899,24 @@ impl super::StateInner {
                         channel.exception_mask.masks(),
                     );
                 }
+                Ok(mode::ChannelChange::GetQuiets) => {
+                    for mask in channel.quiet_mask.masks() {
+                        ctx.rb
+                            .reply(rpl::QUIETLIST)
+                            .param(args.channel.get())
+                            .param("q")
+                            .param(mask);
+                    }
+                    ctx.rb
+                        .reply(rpl::ENDOFQUIETLIST)
+                        .param(args.channel.get())
+                        .param("q")
+                        .trailing_param(&lines::tr(lines::END_OF_QUIET_LIST));
+                }
                 Ok(change) => {
-                    match channel.apply_mode_change(change, self.keylen, |a| clients[a].nick()) {
+                    match channel.apply_mode_change(change, self.keylen, self.maxlist, |nick| {
+                        nicks.get(nick)
+                    }) {
                         Ok(true) => {
                             log::debug!("    - Applied {:?}", change);
                             let change_value = change.value();
@@ -559,13 +935,19 @@ impl super::StateInner {
                             ctx.rb
                                 .reply(rpl::ERR_USERNOTINCHANNEL)
                                 .param(change)
-                                .trailing_param(lines::USER_NOT_IN_CHANNEL);
+                                .trailing_param(&lines::tr(lines::USER_NOT_IN_CHANNEL));
+                        }
+                        Err(rpl::ERR_BANLISTFULL) => {
+                            let mut msg =
+                                ctx.rb.reply(rpl::ERR_BANLISTFULL).param(args.channel.get());
+                            msg.raw_param().push(change.symbol());
+                            msg.trailing_param(&lines::tr(lines::BAN_LIST_FULL));
                         }
                         Err(rpl::ERR_KEYSET) => {
                             ctx.rb
                                 .reply(rpl::ERR_KEYSET)
                                 .param(args.channel.get())
-                                .trailing_param(lines::KEY_SET);
+                                .trailing_param(&lines::tr(lines::KEY_SET));
                         }
                         Err(_) => {
                             unreachable!();
@@ -575,7 +957,7 @@ impl super::StateInner {
                 Err(mode::Error::Unknown(mode, _)) => {
                     let mut msg = ctx.rb.reply(rpl::ERR_UNKNOWNMODE);
                     msg.raw_param().push(mode);
-                    msg.trailing_param(lines::UNKNOWN_MODE);
+                    msg.trailing_param(&lines::tr(lines::UNKNOWN_MODE));
                 }
                 Err(_) => {}
             }
@@ -592,6 +974,21 @@ impl super::StateInner {
             }
             let mode_change = MessageQueueItem::from(mode_notice);
 
+            let modes = || {
+                applied_modeparams
+                    .iter()
+                    .fold(applied_modes.clone(), |modes, mp| modes + " " + mp)
+            };
+            if self.chanlog.is_logged(args.channel.get()) {
+                self.chanlog
+                    .mode(args.channel.get(), issuer.nick(), &modes());
+            }
+            self.events.emit(|| Event::Mode {
+                by: issuer.nick().to_owned(),
+                target: args.channel.get().to_owned(),
+                modes: modes(),
+            });
+
             for member in channel.members.keys().filter(|m| **m != ctx.id) {
                 self.clients[*member].send(mode_change.clone());
             }
@@ -602,6 +999,16 @@ impl super::StateInner {
                 .param(args.channel.get())
                 .param(&applied_modes);
             applied_modeparams.iter().fold(msg, |msg, mp| msg.param(mp));
+
+            let event =
+                history::Entry::event(Command::Mode, issuer.full_name(), issuer.account(), modes());
+            channel.history.push(event, history_limit);
+        }
+
+        if applied_modes.contains('H') {
+            let limit = self.channel_history_limit(args.channel.get());
+            let channel = self.channels.get_mut(args.channel.u()).unwrap();
+            channel.history.expire(limit, util::time());
         }
 
         Ok(())
@@ -619,7 +1026,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_USERSDONTMATCH)
                 .param(args.user.get())
-                .trailing_param(lines::USERS_DONT_MATCH);
+                .trailing_param(&lines::tr(lines::USERS_DONT_MATCH));
             return Err(());
         }
 
@@ -636,7 +1043,7 @@ impl super::StateInner {
                 Err(mode::Error::Unknown(mode, _)) => {
                     let mut msg = ctx.rb.reply(rpl::ERR_UMODEUNKNOWNFLAG);
                     msg.raw_param().push(mode);
-                    msg.trailing_param(lines::UNKNOWN_MODE);
+                    msg.trailing_param(&lines::tr(lines::UNKNOWN_MODE));
                 }
                 Err(_) => {}
             }
@@ -646,6 +1053,12 @@ impl super::StateInner {
                 .message(client.full_name(), Command::Mode)
                 .param(args.user.get())
                 .param(&applied_modes);
+            self.events.emit(|| Event::Mode {
+                by: client.nick().to_owned(),
+                target: client.nick().to_owned(),
+                modes: applied_modes,
+            });
+            self.drop_recipients(ctx.id);
         }
 
         Ok(())
@@ -663,7 +1076,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ERR_USERSDONTMATCH)
                 .param(nickname.get())
-                .trailing_param(lines::USERS_DONT_MATCH);
+                .trailing_param(&lines::tr(lines::USERS_DONT_MATCH));
             return Err(());
         }
 
@@ -686,7 +1099,7 @@ impl super::StateInner {
         ctx.rb
             .reply(rpl::ENDOFNAMES)
             .param("*")
-            .trailing_param(lines::END_OF_NAMES);
+            .trailing_param(&lines::tr(lines::END_OF_NAMES));
         Ok(())
     }
 
@@ -707,15 +1120,24 @@ impl super::StateInner {
     // NICK
 
     pub fn cmd_nick(&mut self, ctx: CommandContext<'_>, nick: data::Nickname<'_>) -> Result {
+        if self.is_service_nick(nick.get()) {
+            log::debug!("{}:     Reserved for services", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_NICKNAMEINUSE)
+                .param(nick.get())
+                .trailing_param(&lines::tr(lines::NICKNAME_IN_USE));
+            return Err(());
+        }
+
         let issuer = &mut self.clients[ctx.id];
 
-        if let Some(&id) = self.nicks.get(nick.u()) {
+        if let Some(id) = self.nicks.get(nick.get()) {
             if id != ctx.id {
                 log::debug!("{}:     Already in use", ctx.id);
                 ctx.rb
                     .reply(rpl::ERR_NICKNAMEINUSE)
                     .param(nick.get())
-                    .trailing_param(lines::NICKNAME_IN_USE);
+                    .trailing_param(&lines::tr(lines::NICKNAME_IN_USE));
                 return Err(());
             } else if issuer.nick() == nick.get() {
                 // Return Ok when the client NICK to the exact same nickname, change the nickname
@@ -724,14 +1146,14 @@ impl super::StateInner {
             }
         }
 
-        self.nicks.remove(u(issuer.nick()));
-        self.nicks
-            .insert(UniCase::new(nick.get().to_owned()), ctx.id);
+        self.nicks.remove(issuer.nick());
+        self.nicks.insert(nick.get(), ctx.id);
 
         if !issuer.is_registered() {
             log::debug!("{}:     Is not registered", ctx.id);
             issuer.set_nick(nick.get());
             ReplyBuffer::set_nick(nick.get());
+            self.enforce_nick(ctx.id, ctx.rb);
             return Ok(());
         }
 
@@ -743,10 +1165,18 @@ impl super::StateInner {
             .message(issuer.full_name(), Command::Nick)
             .param(nick.get());
 
+        for (channel_name, channel) in &self.channels {
+            if channel.members.contains_key(&ctx.id) {
+                self.chanlog
+                    .nick(channel_name.get(), issuer.nick(), nick.get());
+            }
+        }
+
         issuer.set_nick(nick.get());
         ReplyBuffer::set_nick(nick.get());
 
         self.send_notification(ctx.id, nick_response, |_, _| true);
+        self.enforce_nick(ctx.id, ctx.rb);
 
         Ok(())
     }
@@ -754,20 +1184,40 @@ impl super::StateInner {
     // OPER
 
     pub fn cmd_oper(&mut self, ctx: CommandContext<'_>, args: data::req::Oper<'_>) -> Result {
-        if !self
-            .opers
-            .iter()
-            .any(|o| o.name == args.name && o.password == args.password)
-        {
-            log::debug!("{}:     Password mismatch", ctx.id);
+        let certfp = self.clients[ctx.id].certfp.as_deref();
+        let oper = match self.opers.iter().find(|o| {
+            o.name == args.name
+                && o.accepts_certfp(certfp)
+                && if o.password.is_empty() {
+                    !o.certfp.is_empty()
+                } else {
+                    crate::util::verify_password_hash(&o.password, args.password).is_ok()
+                }
+        }) {
+            Some(oper) => oper,
+            None => {
+                log::debug!("{}:     Password or certificate mismatch", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_PASSWDMISMATCH)
+                    .trailing_param(&lines::tr(lines::PASSWORD_MISMATCH));
+                return Err(());
+            }
+        };
+        if (self.oper_require_tls || oper.require_tls) && !self.clients[ctx.id].tls {
+            log::info!(
+                "{}: OPER {} refused over a plain-text connection",
+                ctx.id,
+                oper.name
+            );
             ctx.rb
-                .reply(rpl::ERR_PASSWDMISMATCH)
-                .trailing_param(lines::PASSWORD_MISMATCH);
+                .reply(rpl::ERR_NOOPERHOST)
+                .trailing_param(&lines::tr(lines::OPER_NEEDS_TLS));
             return Err(());
         }
 
         let client = &mut self.clients[ctx.id];
         client.operator = true;
+        client.oper_privileges = oper.privileges.clone();
 
         ctx.rb.lr_batch_begin();
         ctx.rb
@@ -776,7 +1226,14 @@ impl super::StateInner {
             .param("+o");
         ctx.rb
             .reply(rpl::YOUREOPER)
-            .trailing_param(lines::YOURE_OPER);
+            .trailing_param(&lines::tr(lines::YOURE_OPER));
+        self.send_oper_motd(ctx.rb);
+
+        let client = &self.clients[ctx.id];
+        self.send_server_notice(format_args!(
+            "{} is now an IRC operator",
+            client.full_name()
+        ));
 
         Ok(())
     }
@@ -787,10 +1244,12 @@ impl super::StateInner {
         let issuer = &self.clients[ctx.id];
 
         let mut res = Ok(());
+        let mut parted = 0;
 
         for channel_name in args.from.iter() {
             ctx.rb.lr_batch_begin();
 
+            let history_limit = self.channel_history_limit(channel_name.get());
             let channel = match self.channels.get_mut(channel_name.u()) {
                 Some(channel) => channel,
                 None => {
@@ -798,21 +1257,23 @@ impl super::StateInner {
                     ctx.rb
                         .reply(rpl::ERR_NOTONCHANNEL)
                         .param(channel_name.get())
-                        .trailing_param(lines::NOT_ON_CHANNEL);
+                        .trailing_param(&lines::tr(lines::NOT_ON_CHANNEL));
                     res = Err(());
                     continue;
                 }
             };
 
-            if channel.members.remove(&ctx.id).is_none() {
+            if channel.remove_member(ctx.id).is_none() {
                 log::debug!("{}:         not on {:?}", ctx.id, channel_name.get());
                 ctx.rb
                     .reply(rpl::ERR_NOTONCHANNEL)
                     .param(channel_name.get())
-                    .trailing_param(lines::NOT_ON_CHANNEL);
+                    .trailing_param(&lines::tr(lines::NOT_ON_CHANNEL));
                 res = Err(());
                 continue;
             }
+            parted += 1;
+            self.chanlog.part(channel_name.get(), issuer, args.reason);
 
             if channel.members.is_empty() {
                 self.channels.remove(channel_name.u());
@@ -831,6 +1292,14 @@ impl super::StateInner {
                 for member in channel.members.keys() {
                     self.clients[*member].send(part_notice.clone());
                 }
+
+                let event = history::Entry::event(
+                    Command::Part,
+                    issuer.full_name(),
+                    issuer.account(),
+                    args.reason.unwrap_or_default().to_owned(),
+                );
+                channel.history.push(event, history_limit);
             }
 
             let msg = ctx
@@ -841,24 +1310,34 @@ impl super::StateInner {
                 msg.trailing_param(reason);
             }
         }
+        let issuer = &mut self.clients[ctx.id];
+        issuer.joined_channels = issuer.joined_channels.saturating_sub(parted);
 
         res
     }
 
     pub fn cmd_part_all(&mut self, ctx: CommandContext<'_>) -> Result {
+        let history_limits: HashMap<String, history::Limit> = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.members.contains_key(&ctx.id))
+            .map(|(name, _)| (name.get().clone(), self.channel_history_limit(name.get())))
+            .collect();
         let clients = &self.clients;
         let issuer = &clients[ctx.id];
+        let chanlog = &self.chanlog;
 
         self.channels.retain(|channel_name, channel| {
-            if channel.members.remove(&ctx.id).is_none() {
+            if channel.remove_member(ctx.id).is_none() {
                 return true;
             }
+            chanlog.part(channel_name.get(), issuer, Some(lines::PART_ALL));
 
             ctx.rb.lr_batch_begin();
             ctx.rb
                 .message(issuer.full_name(), Command::Part)
                 .param(channel_name.get())
-                .trailing_param(lines::PART_ALL);
+                .trailing_param(&lines::tr(lines::PART_ALL));
 
             let is_not_empty = !channel.members.is_empty();
             if is_not_empty {
@@ -867,17 +1346,28 @@ impl super::StateInner {
                 part_notice
                     .message(issuer.full_name(), Command::Part)
                     .param(channel_name.get())
-                    .trailing_param(lines::PART_ALL);
+                    .trailing_param(&lines::tr(lines::PART_ALL));
 
                 let part_notice = MessageQueueItem::from(part_notice);
 
                 for member in channel.members.keys() {
                     clients[*member].send(part_notice.clone());
                 }
+
+                let event = history::Entry::event(
+                    Command::Part,
+                    issuer.full_name(),
+                    issuer.account(),
+                    lines::tr(lines::PART_ALL).into_owned(),
+                );
+                channel
+                    .history
+                    .push(event, history_limits[channel_name.get()]);
             }
 
             is_not_empty
         });
+        self.clients[ctx.id].joined_channels = 0;
 
         Ok(())
     }
@@ -885,7 +1375,7 @@ impl super::StateInner {
     // PASS
 
     pub fn cmd_pass(&mut self, ctx: CommandContext<'_>, password: &str) -> Result {
-        if crate::util::verify_password_hash(&self.password, password).is_ok() {
+        if crate::util::verify_password_hash(self.required_password(ctx.id), password).is_ok() {
             self.clients[ctx.id].has_given_password = true;
         }
 
@@ -903,37 +1393,107 @@ impl super::StateInner {
 
     // PONG
 
-    pub fn cmd_pong(&mut self, _: CommandContext<'_>, _: &str) -> Result {
+    pub fn cmd_pong(&mut self, ctx: CommandContext<'_>, _: &str) -> Result {
+        let client = &mut self.clients[ctx.id];
+        if let Some(sent) = client.ping_sent.take() {
+            client.lag = Some(sent.elapsed());
+        }
         Ok(())
     }
 
     // QUIT
 
     pub fn cmd_quit(&mut self, ctx: CommandContext<'_>, reason: Option<&str>) -> Result {
+        let client = &self.clients[ctx.id];
+        if client.session.is_some() || client.has_connections() {
+            self.close_connection(ctx.id, lines::tr(lines::CLOSING_LINK));
+            return Ok(());
+        }
+        if self.is_always_on(ctx.id) {
+            self.detach_client(ctx.id, lines::tr(lines::CLOSING_LINK));
+            return Ok(());
+        }
         lines::quit(reason, |quit| {
-            self.remove_client(ctx.id, lines::CLOSING_LINK, quit)
+            self.remove_client(ctx.id, lines::tr(lines::CLOSING_LINK), quit)
         });
         Ok(())
     }
 
     // REHASH
 
-    pub fn cmd_rehash(&self, ctx: CommandContext<'_>) -> Result {
+    pub fn cmd_rehash(&mut self, ctx: CommandContext<'_>) -> Result {
         if self.clients[ctx.id].operator {
             ctx.rb
                 .reply(rpl::REHASHING)
                 .param("--")
-                .trailing_param(lines::REHASHING);
+                .trailing_param(&lines::tr(lines::REHASHING));
+            self.rehash_by = Some(ctx.id);
             self.rehash.notify_one();
+            self.send_server_notice(format_args!(
+                "{} is reloading the configuration",
+                self.clients[ctx.id].nick()
+            ));
             Ok(())
         } else {
             ctx.rb
                 .reply(rpl::ERR_NOPRIVILEDGES)
-                .trailing_param(lines::NO_PRIVILEDGES);
+                .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
             Err(())
         }
     }
 
+    // STATS
+
+    pub fn cmd_stats(&self, ctx: CommandContext<'_>, query: &str) -> Result {
+        let query = if query.is_empty() { "*" } else { query };
+        ctx.rb.lr_batch_begin();
+        match query {
+            "l" | "L" | "m" | "M" if !self.clients[ctx.id].operator => {
+                ctx.rb
+                    .reply(rpl::ERR_NOPRIVILEDGES)
+                    .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
+                return Err(());
+            }
+            "l" | "L" => self.send_stats_links(ctx.rb),
+            #[cfg(feature = "metrics")]
+            "m" | "M" => self.send_stats_commands(ctx.rb),
+            _ => {}
+        }
+        ctx.rb
+            .reply(rpl::ENDOFSTATS)
+            .param(query)
+            .trailing_param(&lines::tr(lines::END_OF_STATS));
+        Ok(())
+    }
+
+    fn send_stats_links(&self, rb: &mut ReplyBuffer) {
+        let now = util::time();
+        for (_, client) in self.clients.iter().filter(|(_, c)| c.is_registered()) {
+            // ellidri doesn't keep track of queues and traffic, so the "sendq", "sent" and
+            // "received" fields are replaced by the lag of the connection, in milliseconds.
+            let msg = rb.reply(rpl::STATSLINKINFO).param(client.full_name());
+            let msg = match client.lag {
+                Some(lag) => msg.fmt_param(lag.as_millis()),
+                None => msg.param("*"),
+            };
+            msg.fmt_param(now.saturating_sub(client.signon_time()));
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn send_stats_commands(&self, rb: &mut ReplyBuffer) {
+        for (command, stats) in self.metrics.iter() {
+            // The "byte count" and "remote count" fields are replaced by the mean, 90th and 99th
+            // percentile of the time spent handling the command, in microseconds.
+            rb.reply(rpl::STATSCOMMANDS)
+                .param(command)
+                .fmt_param(stats.count())
+                .fmt_param(stats.mean())
+                .fmt_param(stats.quantile(0.9))
+                .fmt_param(stats.quantile(0.99));
+        }
+    }
+
     // TIME
 
     pub fn cmd_time(&self, ctx: CommandContext<'_>) -> Result {
@@ -958,6 +1518,7 @@ impl super::StateInner {
             find_member(ctx.id, ctx.rb, channel, channel_name)?;
         }
 
+        ctx.rb.lr_batch_begin();
         self.send_topic(ctx.rb, channel_name, true);
 
         Ok(())
@@ -968,6 +1529,18 @@ impl super::StateInner {
         ctx: CommandContext<'_>,
         args: data::req::TopicSet<'_>,
     ) -> Result {
+        self.topic_set(ctx, args, false)
+    }
+
+    /// Changes the topic of a channel.  When `force` is true, the client doesn't need to be a
+    /// member of the channel, nor to be allowed to change its topic.
+    pub(super) fn topic_set(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::TopicSet<'_>,
+        force: bool,
+    ) -> Result {
+        let history_limit = self.channel_history_limit(args.channel.get());
         let channel = match self.channels.get_mut(args.channel.u()) {
             Some(channel) => channel,
             None => {
@@ -975,26 +1548,27 @@ impl super::StateInner {
                 ctx.rb
                     .reply(rpl::ERR_NOSUCHCHANNEL)
                     .param(args.channel.get())
-                    .trailing_param(lines::NO_SUCH_CHANNEL);
+                    .trailing_param(&lines::tr(lines::NO_SUCH_CHANNEL));
                 return Err(());
             }
         };
 
-        let member_modes = find_member(ctx.id, ctx.rb, channel, args.channel)?;
-
-        if !member_modes.operator && channel.topic_restricted {
-            log::debug!("{}:     not operator", ctx.id);
-            ctx.rb
-                .reply(rpl::ERR_CHANOPRIVSNEEDED)
-                .param(args.channel.get())
-                .trailing_param(lines::CHAN_O_PRIVS_NEEDED);
-            return Err(());
+        if !force {
+            let member_modes = find_member(ctx.id, ctx.rb, channel, args.channel)?;
+            if !member_modes.operator && channel.topic_restricted {
+                log::debug!("{}:     not operator", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_CHANOPRIVSNEEDED)
+                    .param(args.channel.get())
+                    .trailing_param(&lines::tr(lines::CHAN_O_PRIVS_NEEDED));
+                return Err(());
+            }
         }
 
         let client = &self.clients[ctx.id];
         let topic = &args.topic[..args.topic.len().min(self.topiclen)];
 
-        channel.topic = if topic.is_empty() {
+        let new_topic = if topic.is_empty() {
             None
         } else {
             Some(Topic {
@@ -1003,6 +1577,8 @@ impl super::StateInner {
                 time: util::time(),
             })
         };
+        channel.set_topic(new_topic, self.topic_history_len);
+        self.chanlog.topic(args.channel.get(), client.nick(), topic);
 
         let mut topic_notice = Buffer::with_capacity(512);
         topic_notice
@@ -1020,29 +1596,140 @@ impl super::StateInner {
             .param(args.channel.get())
             .trailing_param(topic);
 
+        let event = history::Entry::event(
+            Command::Topic,
+            client.full_name(),
+            client.account(),
+            topic.to_owned(),
+        );
+        channel.history.push(event, history_limit);
+
         Ok(())
     }
 
+    // TRACE
+
+    pub fn cmd_trace(&self, ctx: CommandContext<'_>, target: &str) -> Result {
+        let operator = self.clients[ctx.id].operator;
+        if target.is_empty() || target.eq_ignore_ascii_case(&self.domain) {
+            ctx.rb.lr_batch_begin();
+            if operator {
+                for (_, client) in &self.clients {
+                    self.send_trace_line(ctx.rb, client);
+                }
+            } else {
+                self.send_trace_line(ctx.rb, &self.clients[ctx.id]);
+            }
+        } else {
+            let nick = data::Nickname::try_from(target).map_err(|_| {
+                ctx.rb
+                    .reply(rpl::ERR_NOSUCHNICK)
+                    .param(target)
+                    .trailing_param(&lines::tr(lines::NO_SUCH_NICK));
+            })?;
+            let (target_id, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, nick)?;
+            if target_id != ctx.id && !operator {
+                ctx.rb
+                    .reply(rpl::ERR_NOPRIVILEDGES)
+                    .trailing_param(&lines::tr(lines::NO_PRIVILEDGES));
+                return Err(());
+            }
+            ctx.rb.lr_batch_begin();
+            self.send_trace_line(ctx.rb, target);
+        }
+        ctx.rb
+            .reply(rpl::TRACEEND)
+            .param(&self.domain)
+            .param(super::SERVER_VERSION)
+            .trailing_param(&lines::tr(lines::END_OF_TRACE));
+        Ok(())
+    }
+
+    fn send_trace_line(&self, rb: &mut ReplyBuffer, client: &Client) {
+        // ellidri has no connection classes, nor links to other servers, so the class is the
+        // kind of connection and the link information is its address, lag and age.
+        let class = if client.tls { "tls" } else { "plain" };
+        let msg = if !client.is_registered() {
+            rb.reply(rpl::TRACEUNKNOWN)
+                .param("????")
+                .param(class)
+                .param(client.host())
+        } else if client.operator {
+            rb.reply(rpl::TRACEOPERATOR)
+                .param("Oper")
+                .param(class)
+                .param(client.full_name())
+        } else {
+            rb.reply(rpl::TRACEUSER)
+                .param("User")
+                .param(class)
+                .param(client.full_name())
+        };
+        let msg = match client.ip {
+            Some(ip) => msg.fmt_param(ip),
+            None => msg.param("*"),
+        };
+        let msg = match client.lag {
+            Some(lag) => msg.fmt_param(lag.as_millis()),
+            None => msg.param("*"),
+        };
+        msg.fmt_param(util::time().saturating_sub(client.signon_time()))
+            .trailing_param(client.state().name());
+    }
+
     // USER
 
     pub fn cmd_user(&mut self, ctx: CommandContext<'_>, args: data::req::User<'_>) -> Result {
-        let client = &mut self.clients[ctx.id];
-
-        if !self.password.is_empty() && !client.has_given_password {
+        if !self.required_password(ctx.id).is_empty() && !self.clients[ctx.id].has_given_password {
             log::debug!("{}:     Password mismatch", ctx.id);
             ctx.rb
                 .reply(rpl::ERR_PASSWDMISMATCH)
-                .trailing_param(lines::PASSWORD_MISMATCH);
-            self.remove_client(ctx.id, lines::BAD_PASSWORD, "");
+                .trailing_param(&lines::tr(lines::PASSWORD_MISMATCH));
+            self.remove_client(ctx.id, lines::tr(lines::BAD_PASSWORD), "");
             return Err(());
         }
 
+        let client = &mut self.clients[ctx.id];
         client.set_user(&args.username[..args.username.len().min(self.userlen)]);
         client.set_real(&args.realname[..args.realname.len().min(self.namelen)]);
 
         Ok(())
     }
 
+    // USERHOST
+
+    pub fn cmd_userhost(&self, ctx: CommandContext<'_>, params: &[&str]) -> Result {
+        let mut replies = String::new();
+        for &nick in params.iter().take(USERHOST_MAX_NICKS) {
+            let nick = match data::Nickname::try_from(nick) {
+                Ok(nick) => nick,
+                Err(_) => continue,
+            };
+            let client = match find_nick_quiet(ctx.id, &self.clients, &self.nicks, nick) {
+                Ok((_, client)) => client,
+                Err(()) => continue,
+            };
+            if !replies.is_empty() {
+                replies.push(' ');
+            }
+            replies.push_str(client.nick());
+            if client.operator {
+                replies.push('*');
+            }
+            replies.push('=');
+            replies.push(if client.away_message().is_some() {
+                '-'
+            } else {
+                '+'
+            });
+            replies.push_str(client.user());
+            replies.push('@');
+            replies.push_str(client.host());
+        }
+        ctx.rb.reply(rpl::USERHOST).trailing_param(&replies);
+        Ok(())
+    }
+
     // VERSION
 
     pub fn cmd_version(&self, ctx: CommandContext<'_>) -> Result {
@@ -1079,6 +1766,9 @@ impl super::StateInner {
         } else {
             'H'
         });
+        if target.bot {
+            param.push('B');
+        }
         if issuer.cap_enabled.multi_prefix {
             modes.all_symbols(param);
         } else if let Some(symbol) = modes.symbol() {
@@ -1114,15 +1804,15 @@ impl super::StateInner {
                 None => continue,
             };
             if !issuer.operator
-                && (target.invisible || channel.secret)
+                && (target.invisible || channel.hides_members())
                 && !channel.members.contains_key(&issuer_id)
             {
                 // issuer cannot see that target is in the channel, because it is not in the
                 // channel and either target is invisible (and issuer must have a channel in
                 // common to see it), or the channel is secret (thus it must not know its
-                // existence).  If issuer is in the channel, then it is fine for ellidri to
-                // show it with the line because issuer knows the existence of both the channel
-                // and target.
+                // existence) or private (thus it must not know its members).  If issuer is in
+                // the channel, then it is fine for ellidri to show it with the line because
+                // issuer knows the existence of both the channel and target.
                 // IRCops bypass these restrictions, they can see secret channels and invisible
                 // users.
                 continue;
@@ -1147,20 +1837,20 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ENDOFWHO)
                 .param("*")
-                .trailing_param(lines::END_OF_WHO);
+                .trailing_param(&lines::tr(lines::END_OF_WHO));
             return Err(());
         }
 
         ctx.rb.lr_batch_begin();
 
-        for target_id in self.nicks.values() {
-            self.who_user(ctx.id, ctx.rb, issuer, *target_id, filter);
+        for target_id in self.nicks.ids() {
+            self.who_user(ctx.id, ctx.rb, issuer, target_id, filter);
         }
 
         ctx.rb
             .reply(rpl::ENDOFWHO)
             .param("*")
-            .trailing_param(lines::END_OF_WHO);
+            .trailing_param(&lines::tr(lines::END_OF_WHO));
 
         Ok(())
     }
@@ -1174,13 +1864,14 @@ impl super::StateInner {
         // `for` can be used instead of `if let Some(_) = _`!!!  This way `break` (or `continue`,
         // but let's just use `break`) can be used to jump outside the non-loop and avoid some
         // indentations.
+        #[allow(for_loops_over_fallibles)]
         for channel in self.channels.get(args.mask.u()) {
             ctx.rb.lr_batch_begin();
 
             let issuer = &self.clients[ctx.id];
 
             let in_channel = channel.members.contains_key(&ctx.id);
-            if channel.secret && !in_channel && !issuer.operator {
+            if channel.hides_members() && !in_channel && !issuer.operator {
                 break;
             }
 
@@ -1201,7 +1892,7 @@ impl super::StateInner {
         ctx.rb
             .reply(rpl::ENDOFWHO)
             .param(args.mask.get())
-            .trailing_param(lines::END_OF_WHO);
+            .trailing_param(&lines::tr(lines::END_OF_WHO));
 
         Ok(())
     }
@@ -1212,7 +1903,7 @@ impl super::StateInner {
             ctx.rb
                 .reply(rpl::ENDOFWHO)
                 .param(args.mask.get())
-                .trailing_param(lines::END_OF_WHO);
+                .trailing_param(&lines::tr(lines::END_OF_WHO));
             return Err(());
         }
 
@@ -1232,30 +1923,30 @@ impl super::StateInner {
                     self.who_line(ctx.rb, issuer, target, name.get(), *modes)
                 });
         } else {
-            for (nick, id) in &self.nicks {
-                if !args.mask.is_match(nick.get()) {
+            for id in self.nicks.ids() {
+                if !args.mask.is_match(self.clients[id].nick()) {
                     continue;
                 }
-                self.who_user(ctx.id, ctx.rb, issuer, *id, args.filter);
+                self.who_user(ctx.id, ctx.rb, issuer, id, args.filter);
             }
         }
 
         ctx.rb
             .reply(rpl::ENDOFWHO)
             .param(args.mask.get())
-            .trailing_param(lines::END_OF_WHO);
+            .trailing_param(&lines::tr(lines::END_OF_WHO));
 
         Ok(())
     }
 
     pub fn cmd_who_user(&self, ctx: CommandContext<'_>, args: data::req::WhoUser<'_>) -> Result {
-        if let Some(target_id) = self.nicks.get(args.mask.u()) {
+        if let Some(target_id) = self.nicks.get(args.mask.get()) {
             ctx.rb.lr_batch_begin();
             self.who_user(
                 ctx.id,
                 ctx.rb,
                 &self.clients[ctx.id],
-                *target_id,
+                target_id,
                 args.filter,
             );
         }
@@ -1263,15 +1954,60 @@ impl super::StateInner {
         ctx.rb
             .reply(rpl::ENDOFWHO)
             .param(args.mask.get())
-            .trailing_param(lines::END_OF_WHO);
+            .trailing_param(&lines::tr(lines::END_OF_WHO));
 
         Ok(())
     }
 
     // WHOIS
 
+    /// Sends the channels of `target_id` as `RPL_WHOISCHANNELS` replies.  Secret and private
+    /// channels are only shown to their members and IRC operators.
+    fn send_whois_channels(&self, id: usize, rb: &mut ReplyBuffer, target_id: usize) {
+        let issuer = &self.clients[id];
+        let target = &self.clients[target_id];
+        let mut channels = Vec::new();
+        for (name, channel) in &self.channels {
+            let modes = match channel.members.get(&target_id) {
+                Some(modes) => modes,
+                None => continue,
+            };
+            if channel.hides_members()
+                && id != target_id
+                && !issuer.operator
+                && !channel.members.contains_key(&id)
+            {
+                continue;
+            }
+            let mut entry = String::new();
+            if issuer.cap_enabled.multi_prefix {
+                modes.all_symbols(&mut entry);
+            } else if let Some(s) = modes.symbol() {
+                entry.push(s);
+            }
+            entry.push_str(name.get());
+            channels.push(entry);
+        }
+
+        let mut start = 0;
+        while start < channels.len() {
+            let mut len = 0;
+            let mut end = start;
+            while end < channels.len() && len + channels[end].len() < WHOIS_CHANNELS_LENGTH {
+                len += channels[end].len() + 1;
+                end += 1;
+            }
+            end = end.max(start + 1);
+            rb.reply(rpl::WHOISCHANNELS)
+                .param(target.nick())
+                .trailing_param(&channels[start..end].join(" "));
+            start = end;
+        }
+    }
+
     pub fn cmd_whois(&self, ctx: CommandContext<'_>, nick: data::Nickname<'_>) -> Result {
-        let (_, target_client) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, nick)?;
+        let (target_id, target_client) =
+            find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, nick)?;
 
         ctx.rb.lr_batch_begin();
         ctx.rb
@@ -1281,17 +2017,51 @@ impl super::StateInner {
             .param(target_client.host())
             .param("*")
             .trailing_param(target_client.real());
+        self.send_whois_channels(ctx.id, ctx.rb, target_id);
         ctx.rb
             .reply(rpl::WHOISSERVER)
             .param(target_client.nick())
             .param(&self.domain)
             .trailing_param(&self.org_name);
+        if target_client.operator {
+            ctx.rb
+                .reply(rpl::WHOISOPERATOR)
+                .param(target_client.nick())
+                .trailing_param(&lines::tr(lines::WHOIS_OPERATOR));
+        }
+        if target_client.bot {
+            ctx.rb
+                .reply(rpl::WHOISBOT)
+                .param(target_client.nick())
+                .trailing_param(&lines::tr(lines::WHOIS_BOT));
+        }
+        if target_client.tls {
+            ctx.rb
+                .reply(rpl::WHOISSECURE)
+                .param(target_client.nick())
+                .trailing_param(&lines::tr(lines::WHOIS_SECURE));
+        }
+        if let Some(certfp) = &target_client.certfp {
+            if target_id == ctx.id || self.clients[ctx.id].operator {
+                ctx.rb
+                    .reply(rpl::WHOISCERTFP)
+                    .param(target_client.nick())
+                    .fmt_trailing_param(lines_whois_certfp!(certfp));
+            }
+        }
+        if let Some(account) = target_client.account() {
+            ctx.rb
+                .reply(rpl::WHOISACCOUNT)
+                .param(target_client.nick())
+                .param(account)
+                .trailing_param(&lines::tr(lines::WHOIS_ACCOUNT));
+        }
         ctx.rb
             .reply(rpl::WHOISIDLE)
             .param(target_client.nick())
             .fmt_param(target_client.idle_time())
             .fmt_param(target_client.signon_time())
-            .trailing_param(lines::WHOIS_IDLE);
+            .trailing_param(&lines::tr(lines::WHOIS_IDLE));
 
         if let Some(away_msg) = target_client.away_message() {
             ctx.rb
@@ -1303,7 +2073,7 @@ impl super::StateInner {
         ctx.rb
             .reply(rpl::ENDOFWHOIS)
             .param(target_client.nick())
-            .trailing_param(lines::END_OF_WHOIS);
+            .trailing_param(&lines::tr(lines::END_OF_WHOIS));
 
         Ok(())
     }
@@ -1319,23 +2089,25 @@ impl super::StateInner {
         command: Command,
         target: &str,
         content: Option<&str>,
+        msgid: &str,
+        time: &str,
     ) -> MessageQueueItem {
         let issuer = &self.clients[ctx.id];
 
-        let msgid = util::new_message_id();
-        let time = util::time_precise();
-
         if issuer.cap_enabled.echo_message {
             if issuer.cap_enabled.has_message_tags() {
                 let mut msg = ctx
                     .rb
                     .tagged_message(ctx.client_tags)
-                    .tag("msgid", Some(&msgid))
-                    .tag("time", Some(&time));
+                    .tag("msgid", Some(msgid))
+                    .tag("time", Some(time));
 
                 if let Some(account) = issuer.account() {
                     msg = msg.tag("account", Some(account));
                 }
+                if issuer.bot {
+                    msg = msg.tag("bot", None::<&str>);
+                }
 
                 let msg = msg
                     .prefixed_command(issuer.full_name(), command)
@@ -1357,12 +2129,15 @@ impl super::StateInner {
         {
             let mut msg = buf
                 .tagged_message(ctx.client_tags)
-                .tag("msgid", Some(&msgid))
-                .tag("time", Some(&time));
+                .tag("msgid", Some(msgid))
+                .tag("time", Some(time));
 
             if let Some(account) = issuer.account() {
                 msg = msg.tag("account", Some(account));
             }
+            if issuer.bot {
+                msg = msg.tag("bot", None::<&str>);
+            }
 
             let msg = msg
                 .save_tag_len(&mut tag_len)
@@ -1376,9 +2151,72 @@ impl super::StateInner {
 
         let mut msg = MessageQueueItem::from(buf);
         msg.start = tag_len;
+        if issuer.has_connections() {
+            // Let the other connections of a multiclient session know about the message.
+            issuer.send_except(ctx.id, ctx.conn, msg.clone());
+        }
         msg
     }
 
+    /// Applies the CTCP policy to `content`, the text of a PRIVMSG sent by `id`: CTCP requests
+    /// over `ctcp_limit` are dropped.  Returns whether `content` is a CTCP request, ACTIONs
+    /// excluded.
+    fn check_ctcp(
+        &mut self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        content: &str,
+    ) -> std::result::Result<bool, ()> {
+        let is_request = ellidri_tokens::ctcp_command(content)
+            .is_some_and(|command| !command.eq_ignore_ascii_case("ACTION"));
+        if !is_request {
+            return Ok(false);
+        }
+        let client = &mut self.clients[id];
+        let limit = match self.ctcp_limit {
+            Some(limit) if !client.operator => limit,
+            _ => return Ok(true),
+        };
+        if client.is_ctcp_flooding(limit, Instant::now()) {
+            log::debug!("{}:     CTCP flooding", id);
+            rb.reply(Command::Notice)
+                .trailing_param(&lines::tr(lines::CTCP_FLOODING));
+            return Err(());
+        }
+        Ok(true)
+    }
+
+    /// Applies `action` to the DCC offers of `content`, the text of a PRIVMSG sent to `target`.
+    /// Returns the text to send, or `Err(())` if the message must be dropped.
+    fn filter_dcc<'a>(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        target: &str,
+        action: config::DccAction,
+        content: &'a str,
+    ) -> std::result::Result<Cow<'a, str>, ()> {
+        match action {
+            config::DccAction::Allow => Ok(Cow::Borrowed(content)),
+            config::DccAction::Block if ellidri_tokens::is_dcc_offer(content) => {
+                log::debug!("{}:     DCC offers not allowed", id);
+                rb.reply(rpl::ERR_CANNOTSENDTOCHAN)
+                    .param(target)
+                    .trailing_param(&lines::tr(lines::CANNOT_SEND_DCC));
+                Err(())
+            }
+            config::DccAction::Block => Ok(Cow::Borrowed(content)),
+            config::DccAction::Strip => {
+                let stripped = ellidri_tokens::strip_dcc_offers(content);
+                if matches!(stripped, Cow::Owned(_)) && stripped.trim().is_empty() {
+                    log::debug!("{}:     only DCC offers, dropped", id);
+                    return Err(());
+                }
+                Ok(stripped)
+            }
+        }
+    }
+
     pub fn cmd_message_all(
         &self,
         _ctx: CommandContext<'_>,
@@ -1405,38 +2243,116 @@ impl super::StateInner {
                 ctx.rb
                     .reply(rpl::ERR_CANNOTSENDTOCHAN)
                     .param(args.to.get())
-                    .trailing_param(lines::BANNED_FROM_CHAN);
+                    .trailing_param(&lines::tr(lines::BANNED_FROM_CHAN));
             }
             return Err(());
         }
-        if !channel.can_talk(ctx.id) {
+        if !channel.can_talk(ctx.id, self.clients[ctx.id].full_name()) {
             log::debug!("{}:     can't send to channel", ctx.id);
             if args.feedback {
                 ctx.rb
                     .reply(rpl::ERR_CANNOTSENDTOCHAN)
                     .param(args.to.get())
-                    .trailing_param(lines::CANNOT_SEND_TO_CHAN);
+                    .trailing_param(&lines::tr(lines::CANNOT_SEND_TO_CHAN));
+            }
+            return Err(());
+        }
+        if channel.no_colors && args.content.is_some_and(ellidri_tokens::is_formatted) {
+            log::debug!("{}:     colors not allowed", ctx.id);
+            if args.feedback {
+                ctx.rb
+                    .reply(rpl::ERR_CANNOTSENDTOCHAN)
+                    .param(args.to.get())
+                    .trailing_param(&lines::tr(lines::CANNOT_SEND_COLORS));
             }
             return Err(());
         }
 
-        let msg = self.message_build(&mut ctx, args.command, args.to.get(), args.content);
+        let dcc_action = match self.dcc_offers {
+            config::DccAction::Allow if channel.no_dcc => config::DccAction::Block,
+            action => action,
+        };
+        let content = match args.content {
+            Some(content) if args.command == Command::PrivMsg => {
+                Some(self.filter_dcc(ctx.id, ctx.rb, args.to.get(), dcc_action, content)?)
+            }
+            content => content.map(Cow::Borrowed),
+        };
 
-        for target_id in channel.members.keys() {
-            if *target_id == ctx.id {
-                continue;
+        let flooding = self
+            .channels
+            .get_mut(args.to.u())
+            .unwrap()
+            .is_flooding(ctx.id, Instant::now());
+        if flooding {
+            log::debug!("{}:     flooding", ctx.id);
+            self.punish_flood(ctx.id, ctx.rb, args.to.get());
+            return Err(());
+        }
+        let is_ctcp = match content {
+            Some(ref content) if args.command == Command::PrivMsg => {
+                self.check_ctcp(ctx.id, ctx.rb, content)?
             }
-            let target = match self.clients.get(*target_id) {
-                Some(target) => target,
-                None => continue,
-            };
-            if !target.cap_enabled.is_capable_of(args.command) {
-                continue;
+            _ => false,
+        };
+        let channel = &self.channels[args.to.u()];
+
+        let content = match content {
+            Some(content) if channel.strip_colors && ellidri_tokens::is_formatted(&content) => {
+                Some(Cow::Owned(
+                    ellidri_tokens::strip_formatting(&content).into_owned(),
+                ))
             }
-            target.send(msg.clone());
+            content => content,
+        };
+        let msgid = util::new_message_id();
+        let time = util::time_precise();
+        let msg = self.message_build(
+            &mut ctx,
+            args.command,
+            args.to.get(),
+            content.as_deref(),
+            &msgid,
+            &time,
+        );
+        let nick = self.clients[ctx.id].nick();
+        if let Some(ref content) = content {
+            self.chanlog
+                .message(args.to.get(), args.command, nick, content);
+        }
+        self.events.emit(|| Event::Message {
+            from: nick.to_owned(),
+            command: args.command,
+            target: args.to.get().to_owned(),
+            text: content.as_deref().map(str::to_owned),
+        });
+
+        let from = ctx.id;
+        let command = args.command;
+        self.fan_out(args.to.get(), msg, move |target| {
+            target.id != from
+                && target.cap_enabled.is_capable_of(command)
+                && !(is_ctcp && target.no_ctcp)
+                && !target.deaf
+        });
+
+        if let Some(content) = content.filter(|_| !is_ctcp) {
+            let issuer = &self.clients[ctx.id];
+            let entry = history::Entry {
+                command: args.command,
+                from: issuer.full_name().to_owned(),
+                account: issuer.account().map(str::to_owned),
+                msgid,
+                time,
+                unix_time: util::time(),
+                content: content.into_owned(),
+            };
+            let limit = self.channel_history_limit(args.to.get());
+            let channel = self.channels.get_mut(args.to.u()).unwrap();
+            channel.history.push(entry, limit);
         }
 
-        self.clients.get_mut(ctx.id).unwrap().update_idle_time();
+        self.set_active(ctx.id, ctx.rb);
 
         Ok(())
     }
@@ -1446,15 +2362,76 @@ impl super::StateInner {
         mut ctx: CommandContext<'_>,
         args: data::req::MessageUser<'_>,
     ) -> Result {
+        let issuer = &self.clients[ctx.id];
+        if LOCKDOWN_MESSAGES <= self.lockdown && issuer.account().is_none() && !issuer.operator {
+            log::debug!("{}:     not logged in during lockdown", ctx.id);
+            if args.feedback {
+                ctx.rb
+                    .reply(rpl::ERR_NEEDREGGEDNICK)
+                    .param(args.to.get())
+                    .trailing_param(&lines::tr(lines::LOCKDOWN_MESSAGES));
+            }
+            return Err(());
+        }
+        if args.command == Command::PrivMsg && !self.nicks.contains(args.to.get()) {
+            return self.store_offline_message(ctx, args);
+        }
+        let is_ctcp = match args.content {
+            Some(content) if args.command == Command::PrivMsg => {
+                self.check_ctcp(ctx.id, ctx.rb, content)?
+            }
+            _ => false,
+        };
+
         let (_, target) = find_nick(ctx.id, ctx.rb, &self.clients, &self.nicks, args.to)?;
 
         if !target.cap_enabled.is_capable_of(args.command) {
             return Err(());
         }
+        if is_ctcp && target.no_ctcp {
+            log::debug!("{}:     target refuses CTCP", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_CANNOTSENDTOCHAN)
+                .param(args.to.get())
+                .trailing_param(&lines::tr(lines::CTCP_BLOCKED));
+            return Err(());
+        }
+        let issuer = &self.clients[ctx.id];
+        if target.registered_only && issuer.account().is_none() && !issuer.operator {
+            log::debug!("{}:     target only accepts logged in users", ctx.id);
+            if args.feedback {
+                ctx.rb
+                    .reply(rpl::ERR_NEEDREGGEDNICK)
+                    .param(args.to.get())
+                    .trailing_param(&lines::tr(lines::REGISTERED_ONLY));
+            }
+            return Err(());
+        }
+
+        let content = match args.content {
+            Some(content) if args.command == Command::PrivMsg => {
+                let action = self.dcc_offers;
+                Some(self.filter_dcc(ctx.id, ctx.rb, args.to.get(), action, content)?)
+            }
+            content => content.map(Cow::Borrowed),
+        };
 
-        let msg = self.message_build(&mut ctx, args.command, args.to.get(), args.content);
+        let msg = self.message_build(
+            &mut ctx,
+            args.command,
+            args.to.get(),
+            content.as_deref(),
+            &util::new_message_id(),
+            &util::time_precise(),
+        );
 
         target.send(msg);
+        self.events.emit(|| Event::Message {
+            from: self.clients[ctx.id].nick().to_owned(),
+            command: args.command,
+            target: target.nick().to_owned(),
+            text: content.as_deref().map(str::to_owned),
+        });
 
         if let Some(ref away_message) = target.away_message {
             ctx.rb
@@ -1463,7 +2440,56 @@ impl super::StateInner {
                 .trailing_param(away_message);
         }
 
-        self.clients.get_mut(ctx.id).unwrap().update_idle_time();
+        self.set_active(ctx.id, ctx.rb);
+
+        Ok(())
+    }
+
+    /// Keeps a private message sent to a registered account that is offline, to deliver it when
+    /// the account logs in.
+    fn store_offline_message(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::MessageUser<'_>,
+    ) -> Result {
+        let content = match args.content {
+            Some(content)
+                if self.offline_messages_len != 0 && self.accounts.contains(args.to.get()) =>
+            {
+                content
+            }
+            _ => {
+                ctx.rb
+                    .reply(rpl::ERR_NOSUCHNICK)
+                    .param(args.to.get())
+                    .trailing_param(&lines::tr(lines::NO_SUCH_NICK));
+                return Err(());
+            }
+        };
+        let issuer = &self.clients[ctx.id];
+
+        log::debug!("{}:     storing offline message", ctx.id);
+        let message = accounts::OfflineMessage {
+            from: issuer.full_name().to_owned(),
+            account: issuer.account().map(str::to_owned),
+            msgid: util::new_message_id(),
+            time: util::time_precise(),
+            content: content.to_owned(),
+        };
+        let messages = self
+            .offline_messages
+            .entry(args.to.get().to_ascii_lowercase())
+            .or_default();
+        if self.offline_messages_len <= messages.len() {
+            messages.pop_front();
+        }
+        messages.push_back(message);
+
+        ctx.rb
+            .reply(Command::Notice)
+            .fmt_trailing_param(lines_offline_message_stored!(args.to.get()));
+
+        self.set_active(ctx.id, ctx.rb);
 
         Ok(())
     }
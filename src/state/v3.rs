@@ -2,9 +2,36 @@
 //!
 //! <https://ircv3.net/irc/>
 
-use super::{CommandContext, HandlerResult as Result};
-use crate::{data, lines};
-use ellidri_tokens::{Buffer, Command};
+use super::{find_nick_quiet, CommandContext, HandlerResult as Result};
+use crate::client::MessageQueueItem;
+use crate::metadata::{self, Metadata};
+use crate::{config, data, history, lines, util};
+use ellidri_tokens::cap::{self, Capability};
+use ellidri_tokens::{rpl, Buffer, Command, ReplyBuffer};
+use ellidri_unicase::u;
+use std::cmp;
+use std::convert::TryFrom;
+
+/// The SASL mechanisms supported by ellidri.
+const SASL_MECHANISMS: &str = "PLAIN";
+
+/// Size of AUTHENTICATE chunks.  A chunk of this size means more data is coming.
+const SASL_CHUNK_LEN: usize = 400;
+
+/// Maximum size of the base64-encoded SASL PLAIN payload.
+const SASL_MAX_LEN: usize = 4 * SASL_CHUNK_LEN;
+
+/// The capabilities that depend on the configuration, with their CAP 302 value, or `None` if they
+/// are not available.
+pub type ConfigCaps = [(&'static str, Option<String>); 2];
+
+/// How long capability lists are split for clients of the given CAP version.
+fn cap_split(version: data::cap::Version) -> cap::Split {
+    match version {
+        data::cap::Version::V300 => cap::Split::Never,
+        data::cap::Version::V302 => cap::Split::Continued,
+    }
+}
 
 /// Handler for the CAP command.
 ///
@@ -12,9 +39,9 @@ use ellidri_tokens::{Buffer, Command};
 impl super::StateInner {
     pub fn cmd_cap_list(&self, ctx: CommandContext<'_>) -> Result {
         let client = &self.clients[ctx.id];
+        let split = cap_split(client.cap_version);
 
-        let mut msg = ctx.rb.reply(Command::Cap).param("LIST");
-        client.cap_enabled.write(msg.raw_trailing_param());
+        cap::write_replies(ctx.rb, "LIST", client.cap_enabled.names(), split);
 
         Ok(())
     }
@@ -26,18 +53,85 @@ impl super::StateInner {
             client.cap_version = version;
         }
 
-        let mut msg = ctx.rb.reply(Command::Cap).param("LS");
-
-        let trailing = msg.raw_trailing_param();
-        trailing.push_str(data::cap::ls_common());
+        let v302 = version == data::cap::Version::V302;
+        let config_caps = self.config_caps();
+        let mut caps: Vec<_> = data::cap::COMMON
+            .iter()
+            .map(|&name| Capability { name, value: None })
+            .collect();
+        for (name, value) in &config_caps {
+            if let Some(value) = value {
+                let value = v302.then_some(value.as_str());
+                caps.push(Capability { name, value });
+            }
+        }
+        cap::write_replies(ctx.rb, "LS", caps, cap_split(version));
 
         Ok(())
     }
 
+    pub(super) fn config_caps(&self) -> ConfigCaps {
+        let metadata = format!(
+            "max-subs={},max-keys={},max-value-bytes={}",
+            self.metadata_max_subs, self.metadata_max_keys, self.metadata_max_value_len,
+        );
+        let sasl =
+            (self.sasl_backend != config::SaslBackend::None).then(|| SASL_MECHANISMS.to_owned());
+        [
+            (data::cap::METADATA, Some(metadata)),
+            (data::cap::SASL, sasl),
+        ]
+    }
+
+    /// Writes CAP NEW and CAP DEL replies for the capabilities that changed between `old` and
+    /// `new`, if the connection `id` has cap-notify.
+    ///
+    /// Per the capability negotiation specification, a capability whose value changed is sent
+    /// again with CAP NEW.
+    pub(super) fn write_cap_changes(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        old: &ConfigCaps,
+        new: &ConfigCaps,
+    ) {
+        let client = &self.clients[id];
+        let v302 = client.cap_version == data::cap::Version::V302;
+        if !v302 && !client.cap_enabled.cap_notify {
+            return;
+        }
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for ((name, old), (_, new)) in old.iter().zip(new) {
+            let value = new.as_deref().filter(|_| v302);
+            match (old, new) {
+                (Some(_), None) => removed.push(Capability { name, value: None }),
+                (None, Some(_)) => added.push(Capability { name, value }),
+                (Some(old), Some(new)) if v302 && old != new => {
+                    added.push(Capability { name, value });
+                }
+                _ => {}
+            }
+        }
+        if !removed.is_empty() {
+            cap::write_replies(rb, "DEL", removed, cap::Split::Separate);
+        }
+        if !added.is_empty() {
+            cap::write_replies(rb, "NEW", added, cap::Split::Separate);
+        }
+    }
+
     pub fn cmd_cap_req(&mut self, ctx: CommandContext<'_>, req: data::cap::Diff) -> Result {
         let client = &mut self.clients[ctx.id];
 
+        if req.sasl == Some(true) && self.sasl_backend == config::SaslBackend::None {
+            let mut msg = ctx.rb.reply(Command::Cap).param("NAK");
+            req.write(msg.raw_trailing_param());
+            return Err(());
+        }
+
         client.cap_enabled.update(req);
+        self.drop_recipients(ctx.id);
 
         let mut msg = ctx.rb.reply(Command::Cap).param("ACK");
         req.write(msg.raw_trailing_param());
@@ -50,6 +144,141 @@ impl super::StateInner {
     }
 }
 
+/// Handler for the AUTHENTICATE command.
+///
+/// Only the PLAIN mechanism is supported.  Link to the specification:
+/// <https://ircv3.net/specs/extensions/sasl-3.1>
+impl super::StateInner {
+    pub fn cmd_authenticate(&mut self, ctx: CommandContext<'_>, payload: &str) -> Result {
+        let client = &mut self.clients[ctx.id];
+
+        if self.sasl_backend == config::SaslBackend::None || !client.cap_enabled.sasl {
+            log::debug!("{}:     SASL is not enabled", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_SASLFAIL)
+                .trailing_param(&lines::tr(lines::SASL_FAILED));
+            return Err(());
+        }
+        if client.account().is_some() {
+            log::debug!("{}:     Already logged in", ctx.id);
+            ctx.rb
+                .reply(rpl::ERR_SASLALREADY)
+                .trailing_param(&lines::tr(lines::SASL_ALREADY));
+            return Err(());
+        }
+        if payload == "*" {
+            client.sasl_buffer = None;
+            ctx.rb
+                .reply(rpl::ERR_SASLABORTED)
+                .trailing_param(&lines::tr(lines::SASL_ABORTED));
+            return Err(());
+        }
+
+        let buffer = match &mut client.sasl_buffer {
+            Some(buffer) => buffer,
+            None if payload.eq_ignore_ascii_case("PLAIN") => {
+                client.sasl_buffer = Some(String::new());
+                ctx.rb.message("", Command::Authenticate).param("+");
+                return Ok(());
+            }
+            None => {
+                log::debug!("{}:     Unknown mechanism", ctx.id);
+                ctx.rb.lr_batch_begin();
+                ctx.rb
+                    .reply(rpl::SASLMECHS)
+                    .param(SASL_MECHANISMS)
+                    .trailing_param(&lines::tr(lines::SASL_MECHANISMS));
+                ctx.rb
+                    .reply(rpl::ERR_SASLFAIL)
+                    .trailing_param(&lines::tr(lines::SASL_FAILED));
+                return Err(());
+            }
+        };
+
+        if payload != "+" {
+            buffer.push_str(payload);
+        }
+        if SASL_MAX_LEN < buffer.len() {
+            client.sasl_buffer = None;
+            ctx.rb
+                .reply(rpl::ERR_SASLTOOLONG)
+                .trailing_param(&lines::tr(lines::SASL_TOO_LONG));
+            return Err(());
+        }
+        if payload.len() == SASL_CHUNK_LEN {
+            return Ok(());
+        }
+
+        let buffer = client.sasl_buffer.take().unwrap_or_default();
+        let account = base64::decode(buffer)
+            .ok()
+            .and_then(|plain| String::from_utf8(plain).ok())
+            .and_then(|plain| {
+                // authzid \0 authcid \0 password
+                let mut parts = plain.splitn(3, '\0');
+                let authzid = parts.next()?;
+                let authcid = parts.next()?;
+                let password = parts.next()?;
+                if !authzid.is_empty() && authzid != authcid {
+                    return None;
+                }
+                self.accounts
+                    .authenticate(authcid, password)
+                    .map(str::to_owned)
+            });
+        let account = match account {
+            Some(account) => account,
+            None => {
+                log::debug!("{}:     Authentication failed", ctx.id);
+                ctx.rb
+                    .reply(rpl::ERR_SASLFAIL)
+                    .trailing_param(&lines::tr(lines::SASL_FAILED));
+                return Err(());
+            }
+        };
+
+        let client = &mut self.clients[ctx.id];
+        client.set_account(&account);
+        if account.eq_ignore_ascii_case(client.nick()) {
+            client.nick_deadline = None;
+        }
+        if let Some(delay) = self.accounts.auto_away(&account) {
+            client.auto_away_delay = Some(delay);
+        }
+        if let Some(len) = self.accounts.autoreplay(&account) {
+            client.autoreplay = Some(len);
+        }
+        if let Some(metadata) = self.accounts.metadata(&account) {
+            client
+                .metadata
+                .extend(metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        if let Some(vhost) = self.accounts.vhost(&account).map(str::to_owned) {
+            self.change_host(ctx.id, &vhost);
+        }
+        let client = &self.clients[ctx.id];
+        let full_name = if client.is_registered() {
+            client.full_name()
+        } else {
+            "*"
+        };
+        ctx.rb.lr_batch_begin();
+        ctx.rb
+            .reply(rpl::LOGGEDIN)
+            .param(full_name)
+            .param(&account)
+            .fmt_trailing_param(lines_logged_in!(account));
+        ctx.rb
+            .reply(rpl::SASLSUCCESS)
+            .trailing_param(&lines::tr(lines::SASL_SUCCESSFUL));
+        if self.clients[ctx.id].is_registered() {
+            self.send_offline_messages(ctx.id, ctx.rb);
+        }
+
+        Ok(())
+    }
+}
+
 /// Handlers for commands related to the setname specification.
 impl super::StateInner {
     pub fn cmd_setname(&mut self, ctx: CommandContext<'_>, realname: &str) -> Result {
@@ -61,7 +290,7 @@ impl super::StateInner {
                 .message("", "FAIL")
                 .param("SETNAME")
                 .param("INVALID_REALNAME")
-                .trailing_param(lines::INVALID_REALNAME);
+                .trailing_param(&lines::tr(lines::INVALID_REALNAME));
             return Err(());
         }
 
@@ -81,3 +310,872 @@ impl super::StateInner {
         Ok(())
     }
 }
+
+/// Helpers for the chghost specification.
+impl super::StateInner {
+    /// Changes the displayed host of the client `id`, and tells it and the users that share a
+    /// channel with it.
+    pub(super) fn change_host(&mut self, id: usize, host: &str) {
+        let client = &mut self.clients[id];
+        if client.host() == host {
+            return;
+        }
+
+        let mut response = Buffer::new();
+        response
+            .message(client.full_name(), "CHGHOST")
+            .param(client.user())
+            .param(host);
+        client.set_host(host);
+        if !client.is_registered() {
+            return;
+        }
+
+        let response = MessageQueueItem::from(response);
+        if client.cap_enabled.chghost {
+            client.send(response.clone());
+        } else {
+            let mut reply = Buffer::new();
+            reply
+                .message(&self.domain, rpl::HOSTHIDDEN)
+                .param(client.nick())
+                .param(host)
+                .trailing_param(&lines::tr(lines::HOST_HIDDEN));
+            client.send(reply);
+        }
+
+        self.send_notification(id, response, |_, client| client.cap_enabled.chghost);
+    }
+}
+
+/// The user or channel a METADATA command is about.
+#[derive(Clone, Copy)]
+enum MetadataTarget<'a> {
+    User(usize),
+    Channel(data::ChannelName<'a>),
+}
+
+fn metadata_fail(rb: &mut ReplyBuffer, code: &str, params: &[&str], line: &'static str) {
+    let mut msg = rb.message("", "FAIL").param("METADATA").param(code);
+    for param in params {
+        msg = msg.param(param);
+    }
+    msg.trailing_param(&lines::tr(line));
+}
+
+/// Helpers for the METADATA command.
+impl super::StateInner {
+    /// Returns the target of a METADATA command, or sends an error if it doesn't exist or is not
+    /// visible to the client.
+    fn metadata_target<'a>(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        target: &'a str,
+    ) -> std::result::Result<MetadataTarget<'a>, ()> {
+        if target == "*" {
+            return Ok(MetadataTarget::User(id));
+        }
+        if let Ok(name) = data::ChannelName::try_from(target) {
+            if let Some(channel) = self.channels.get(name.u()) {
+                if !channel.secret || channel.members.contains_key(&id) || self.clients[id].operator
+                {
+                    return Ok(MetadataTarget::Channel(name));
+                }
+            }
+        } else if let Ok(nick) = data::Nickname::try_from(target) {
+            if let Ok((target_id, _)) = find_nick_quiet(id, &self.clients, &self.nicks, nick) {
+                return Ok(MetadataTarget::User(target_id));
+            }
+        }
+        log::debug!("{}:     Invalid metadata target", id);
+        metadata_fail(
+            rb,
+            "INVALID_TARGET",
+            &[target],
+            lines::METADATA_INVALID_TARGET,
+        );
+        Err(())
+    }
+
+    fn metadata_target_name<'a>(&'a self, target: &'a MetadataTarget<'_>) -> &'a str {
+        match target {
+            MetadataTarget::User(id) => self.clients[*id].nick(),
+            MetadataTarget::Channel(name) => name.get(),
+        }
+    }
+
+    fn metadata(&self, target: MetadataTarget<'_>) -> &Metadata {
+        match target {
+            MetadataTarget::User(id) => &self.clients[id].metadata,
+            MetadataTarget::Channel(name) => &self.channels[name.u()].metadata,
+        }
+    }
+
+    fn metadata_mut(&mut self, target: MetadataTarget<'_>) -> &mut Metadata {
+        match target {
+            MetadataTarget::User(id) => &mut self.clients[id].metadata,
+            MetadataTarget::Channel(name) => &mut self.channels.get_mut(name.u()).unwrap().metadata,
+        }
+    }
+
+    /// Whether the client can change the metadata of `target`, and see its private keys.
+    ///
+    /// Users own their metadata, channel operators own the metadata of their channel.  IRC
+    /// operators own everything.
+    fn is_metadata_owner(&self, id: usize, target: MetadataTarget<'_>) -> bool {
+        self.clients[id].operator
+            || match target {
+                MetadataTarget::User(target_id) => target_id == id,
+                MetadataTarget::Channel(name) => self.channels[name.u()]
+                    .members
+                    .get(&id)
+                    .is_some_and(|modes| modes.is_at_least_op()),
+            }
+    }
+
+    fn metadata_visibility(&self, key: &str) -> &'static str {
+        if self.metadata_private_keys.iter().any(|k| k == key) {
+            "private"
+        } else {
+            "*"
+        }
+    }
+
+    fn can_read_metadata(&self, id: usize, target: MetadataTarget<'_>, key: &str) -> bool {
+        self.metadata_visibility(key) == "*" || self.is_metadata_owner(id, target)
+    }
+
+    /// Sends the keys of `target` that the client is subscribed to and can read.
+    fn send_subscribed_metadata(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        target: MetadataTarget<'_>,
+    ) {
+        let client = &self.clients[id];
+        let name = self.metadata_target_name(&target);
+        for (key, value) in self.metadata(target) {
+            if client.metadata_subs.contains(key) && self.can_read_metadata(id, target, key) {
+                rb.prefixed_message(Command::Metadata)
+                    .param(name)
+                    .param(key)
+                    .param(self.metadata_visibility(key))
+                    .trailing_param(value);
+            }
+        }
+    }
+
+    /// Saves the metadata of accounts, and notifies subscribed clients of a change of `key`.
+    fn metadata_changed(
+        &mut self,
+        issuer: usize,
+        target: MetadataTarget<'_>,
+        key: &str,
+        value: Option<&str>,
+    ) {
+        if let MetadataTarget::User(target_id) = target {
+            let client = &self.clients[target_id];
+            if let Some(account) = client.account() {
+                self.accounts.set_metadata(account, client.metadata.clone());
+            }
+        }
+
+        let mut notification = Buffer::new();
+        {
+            let msg = notification
+                .message(self.clients[issuer].full_name(), Command::Metadata)
+                .param(self.metadata_target_name(&target))
+                .param(key)
+                .param(self.metadata_visibility(key));
+            if let Some(value) = value {
+                msg.trailing_param(value);
+            }
+        }
+        let notification = MessageQueueItem::from(notification);
+        let notified = |id: usize| {
+            let client = &self.clients[id];
+            id != issuer
+                && client.cap_enabled.metadata
+                && client.metadata_subs.contains(key)
+                && self.can_read_metadata(id, target, key)
+        };
+
+        match target {
+            MetadataTarget::User(target_id) => {
+                if notified(target_id) {
+                    self.clients[target_id].send(notification.clone());
+                }
+                self.send_notification(target_id, notification, |id, _| notified(id));
+            }
+            MetadataTarget::Channel(name) => {
+                for &member in self.channels[name.u()].members.keys() {
+                    if notified(member) {
+                        self.clients[member].send(notification.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Exchanges the metadata of a client that joined a channel with the other members.
+    pub(super) fn send_join_metadata(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        channel_name: data::ChannelName<'_>,
+    ) {
+        let channel = &self.channels[channel_name.u()];
+        let client = &self.clients[id];
+
+        if client.cap_enabled.metadata && !client.metadata_subs.is_empty() {
+            self.send_subscribed_metadata(id, rb, MetadataTarget::Channel(channel_name));
+            for &member in channel.members.keys().filter(|&&member| member != id) {
+                self.send_subscribed_metadata(id, rb, MetadataTarget::User(member));
+            }
+        }
+
+        if client.metadata.is_empty() {
+            return;
+        }
+        for &member_id in channel.members.keys().filter(|&&member| member != id) {
+            let member = &self.clients[member_id];
+            if !member.cap_enabled.metadata {
+                continue;
+            }
+            let mut msg = Buffer::new();
+            for (key, value) in &client.metadata {
+                if member.metadata_subs.contains(key)
+                    && self.can_read_metadata(member_id, MetadataTarget::User(id), key)
+                {
+                    msg.message(&self.domain, Command::Metadata)
+                        .param(client.nick())
+                        .param(key)
+                        .param(self.metadata_visibility(key))
+                        .trailing_param(value);
+                }
+            }
+            if !msg.is_empty() {
+                member.send(msg);
+            }
+        }
+    }
+}
+
+/// Handlers for the METADATA command.
+///
+/// Link to the specification: <https://ircv3.net/specs/extensions/metadata>
+impl super::StateInner {
+    pub fn cmd_metadata_get(
+        &self,
+        ctx: CommandContext<'_>,
+        args: data::req::MetadataGet<'_>,
+    ) -> Result {
+        let target = self.metadata_target(ctx.id, ctx.rb, args.target)?;
+        let name = self.metadata_target_name(&target);
+        let metadata = self.metadata(target);
+
+        ctx.rb.lr_batch_begin();
+        for &key in args.keys {
+            if !metadata::is_valid_key(key) {
+                metadata_fail(ctx.rb, "KEY_INVALID", &[key], lines::METADATA_KEY_INVALID);
+            } else if !self.can_read_metadata(ctx.id, target, key) {
+                metadata_fail(
+                    ctx.rb,
+                    "KEY_NO_PERMISSION",
+                    &[name, key],
+                    lines::METADATA_KEY_NO_PERMISSION,
+                );
+            } else if let Some(value) = metadata.get(key) {
+                ctx.rb
+                    .reply(rpl::KEYVALUE)
+                    .param(name)
+                    .param(key)
+                    .param(self.metadata_visibility(key))
+                    .trailing_param(value);
+            } else {
+                ctx.rb
+                    .reply(rpl::KEYNOTSET)
+                    .param(name)
+                    .param(key)
+                    .trailing_param(&lines::tr(lines::METADATA_KEY_NOT_SET));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_list(&self, ctx: CommandContext<'_>, target: &str) -> Result {
+        let target = self.metadata_target(ctx.id, ctx.rb, target)?;
+        let name = self.metadata_target_name(&target);
+
+        ctx.rb.lr_batch_begin();
+        for (key, value) in self.metadata(target) {
+            if self.can_read_metadata(ctx.id, target, key) {
+                ctx.rb
+                    .reply(rpl::KEYVALUE)
+                    .param(name)
+                    .param(key)
+                    .param(self.metadata_visibility(key))
+                    .trailing_param(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_set(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::MetadataSet<'_>,
+    ) -> Result {
+        let target = self.metadata_target(ctx.id, ctx.rb, args.target)?;
+        let name = self.metadata_target_name(&target).to_owned();
+        let key = args.key;
+
+        if !metadata::is_valid_key(key) {
+            metadata_fail(ctx.rb, "KEY_INVALID", &[key], lines::METADATA_KEY_INVALID);
+            return Err(());
+        }
+        if !self.is_metadata_owner(ctx.id, target) {
+            metadata_fail(
+                ctx.rb,
+                "KEY_NO_PERMISSION",
+                &[&name, key],
+                lines::METADATA_KEY_NO_PERMISSION,
+            );
+            return Err(());
+        }
+        if args
+            .value
+            .is_some_and(|value| self.metadata_max_value_len < value.len())
+        {
+            metadata_fail(ctx.rb, "VALUE_INVALID", &[], lines::METADATA_VALUE_INVALID);
+            return Err(());
+        }
+
+        let max_keys = self.metadata_max_keys;
+        let metadata = self.metadata_mut(target);
+        match args.value {
+            Some(value) => {
+                if !metadata.contains_key(key) && max_keys <= metadata.len() {
+                    metadata_fail(
+                        ctx.rb,
+                        "LIMIT_REACHED",
+                        &[&name],
+                        lines::METADATA_LIMIT_REACHED,
+                    );
+                    return Err(());
+                }
+                metadata.insert(key.to_owned(), value.to_owned());
+                ctx.rb
+                    .reply(rpl::KEYVALUE)
+                    .param(&name)
+                    .param(key)
+                    .param(self.metadata_visibility(key))
+                    .trailing_param(value);
+            }
+            None => {
+                metadata.remove(key);
+                ctx.rb
+                    .reply(rpl::KEYNOTSET)
+                    .param(&name)
+                    .param(key)
+                    .trailing_param(&lines::tr(lines::METADATA_KEY_NOT_SET));
+            }
+        }
+        self.metadata_changed(ctx.id, target, key, args.value);
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_clear(&mut self, ctx: CommandContext<'_>, target: &str) -> Result {
+        let target = self.metadata_target(ctx.id, ctx.rb, target)?;
+        let name = self.metadata_target_name(&target).to_owned();
+
+        if !self.is_metadata_owner(ctx.id, target) {
+            metadata_fail(
+                ctx.rb,
+                "KEY_NO_PERMISSION",
+                &[&name, "*"],
+                lines::METADATA_KEY_NO_PERMISSION,
+            );
+            return Err(());
+        }
+
+        let cleared = std::mem::take(self.metadata_mut(target));
+        ctx.rb.lr_batch_begin();
+        for key in cleared.keys() {
+            ctx.rb
+                .reply(rpl::KEYNOTSET)
+                .param(&name)
+                .param(key)
+                .trailing_param(&lines::tr(lines::METADATA_KEY_NOT_SET));
+            self.metadata_changed(ctx.id, target, key, None);
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_sub(&mut self, ctx: CommandContext<'_>, keys: &[&str]) -> Result {
+        let max_subs = self.metadata_max_subs;
+        let client = &mut self.clients[ctx.id];
+
+        let mut subscribed = Vec::new();
+        for &key in keys {
+            if !metadata::is_valid_key(key) {
+                metadata_fail(ctx.rb, "KEY_INVALID", &[key], lines::METADATA_KEY_INVALID);
+            } else if client.metadata_subs.contains(key) || client.metadata_subs.len() < max_subs {
+                client.metadata_subs.insert(key.to_owned());
+                subscribed.push(key);
+            } else {
+                metadata_fail(
+                    ctx.rb,
+                    "TOO_MANY_SUBS",
+                    &[key],
+                    lines::METADATA_TOO_MANY_SUBS,
+                );
+            }
+        }
+
+        if !subscribed.is_empty() {
+            let mut msg = ctx.rb.reply(rpl::METADATASUBOK);
+            for key in subscribed {
+                msg = msg.param(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_unsub(&mut self, ctx: CommandContext<'_>, keys: &[&str]) -> Result {
+        let client = &mut self.clients[ctx.id];
+
+        let mut msg = ctx.rb.reply(rpl::METADATAUNSUBOK);
+        for &key in keys {
+            client.metadata_subs.remove(key);
+            msg = msg.param(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_subs(&self, ctx: CommandContext<'_>) -> Result {
+        let client = &self.clients[ctx.id];
+
+        ctx.rb.lr_batch_begin();
+        for key in &client.metadata_subs {
+            ctx.rb.reply(rpl::METADATASUBS).param(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_metadata_sync(&self, ctx: CommandContext<'_>, target: &str) -> Result {
+        let target = self.metadata_target(ctx.id, ctx.rb, target)?;
+
+        ctx.rb.lr_batch_begin();
+        self.send_subscribed_metadata(ctx.id, ctx.rb, target);
+        if let MetadataTarget::Channel(name) = target {
+            for &member in self.channels[name.u()].members.keys() {
+                self.send_subscribed_metadata(ctx.id, ctx.rb, MetadataTarget::User(member));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maximum number of messages sent in reply to a CHATHISTORY command.
+pub const CHATHISTORY_LIMIT: usize = 100;
+
+/// Sends a FAIL reply to a CHATHISTORY or SEARCH command.
+fn history_fail(
+    rb: &mut ReplyBuffer,
+    command: &str,
+    code: &str,
+    params: &[&str],
+    line: &'static str,
+) {
+    let mut msg = rb.message("", "FAIL").param(command).param(code);
+    for param in params {
+        msg = msg.param(param);
+    }
+    msg.trailing_param(&lines::tr(line));
+}
+
+/// Returns the target, the query and the limit of a CHATHISTORY subcommand other than TARGETS.
+fn chathistory_query<'a>(
+    subcommand: &str,
+    params: &[&'a str],
+) -> Option<(&'a str, history::Query<'a>, &'a str)> {
+    use history::Query;
+    let reference = history::Reference::parse;
+    Some(match (subcommand, params) {
+        ("LATEST", [target, "*", limit, ..]) => (target, Query::Latest(None), limit),
+        ("LATEST", [target, after, limit, ..]) => {
+            (target, Query::Latest(Some(reference(after)?)), limit)
+        }
+        ("BEFORE", [target, before, limit, ..]) => {
+            (target, Query::Before(reference(before)?), limit)
+        }
+        ("AFTER", [target, after, limit, ..]) => (target, Query::After(reference(after)?), limit),
+        ("AROUND", [target, around, limit, ..]) => {
+            (target, Query::Around(reference(around)?), limit)
+        }
+        ("BETWEEN", [target, from, to, limit, ..]) => {
+            let query = Query::Between(reference(from)?, reference(to)?);
+            (target, query, limit)
+        }
+        _ => return None,
+    })
+}
+
+/// Parses the limit of a CHATHISTORY command, capped to `CHATHISTORY_LIMIT`.
+fn chathistory_limit(limit: &str) -> Option<usize> {
+    match limit.parse() {
+        Ok(0) | Err(_) => None,
+        Ok(limit) => Some(cmp::min(limit, CHATHISTORY_LIMIT)),
+    }
+}
+
+/// Sends `entry`, a message or an event sent to `target`, as it was sent.
+fn history_message(rb: &mut ReplyBuffer, has_tags: bool, target: &str, entry: &history::Entry) {
+    let mut msg = rb.tagged_message("");
+    if has_tags {
+        msg = msg
+            .tag("msgid", Some(&entry.msgid))
+            .tag("time", Some(&entry.time));
+        if let Some(ref account) = entry.account {
+            msg = msg.tag("account", Some(account));
+        }
+    }
+    let msg = msg
+        .prefixed_command(&entry.from, entry.command)
+        .param(target);
+    match entry.command {
+        Command::PrivMsg | Command::Notice => {}
+        Command::Mode => {
+            entry
+                .content
+                .split(' ')
+                .fold(msg, |msg, param| msg.param(param));
+            return;
+        }
+        Command::Kick => {
+            let (kicked, reason) = entry
+                .content
+                .split_once(' ')
+                .unwrap_or((&entry.content, ""));
+            let msg = msg.param(kicked);
+            if !reason.is_empty() {
+                msg.trailing_param(reason);
+            }
+            return;
+        }
+        Command::Part if entry.content.is_empty() => return,
+        Command::Join => return,
+        _ => {
+            msg.trailing_param(&entry.content);
+            return;
+        }
+    }
+    if has_tags || entry.content.starts_with('\x01') {
+        msg.trailing_param(&entry.content);
+    } else {
+        // Without server-time, show when the message was sent like bouncers do.
+        let time = entry.time.get(11..19).unwrap_or_default();
+        msg.fmt_trailing_param(format_args!("[{}] {}", time, entry.content));
+    }
+}
+
+/// Handlers for the CHATHISTORY command.
+///
+/// Link to the specification: <https://ircv3.net/specs/extensions/chathistory>
+impl super::StateInner {
+    /// Returns the first `channel_history` matching the channel `name`.
+    fn channel_history(&self, name: &str) -> Option<&config::ChannelHistory> {
+        let name = name.to_ascii_lowercase();
+        self.channel_history
+            .iter()
+            .find(|h| util::match_mask(&h.channels.to_ascii_lowercase(), &name))
+    }
+
+    /// Returns the history limits of the channel `name`: those of the first matching
+    /// `channel_history`, or the global ones, restricted by the history mode (+H) of the channel.
+    pub(super) fn channel_history_limit(&self, name: &str) -> history::Limit {
+        let limit = self
+            .channel_history(name)
+            .map_or(self.history_limit, |h| history::Limit {
+                len: h.len,
+                age: h.age,
+            });
+        self.channels
+            .get(u(name))
+            .and_then(|channel| channel.history_limit)
+            .map_or(limit, |channel_limit| limit.min(channel_limit))
+    }
+
+    /// Removes the messages that don't fit in the history limits of their channel anymore.
+    pub(super) fn purge_history(&mut self, now: u64) {
+        let limits: Vec<_> = self
+            .channels
+            .keys()
+            .map(|name| (name.get().clone(), self.channel_history_limit(name.get())))
+            .collect();
+        for (name, limit) in limits {
+            if let Some(channel) = self.channels.get_mut(u(&name)) {
+                channel.history.expire(limit, now);
+            }
+        }
+    }
+
+    /// Sends the last messages of `channel_name` to the client `id`, which just joined it.
+    ///
+    /// Clients that support `CHATHISTORY` fetch history themselves and get nothing.
+    pub(super) fn send_autoreplay(&self, id: usize, rb: &mut ReplyBuffer, channel_name: &str) {
+        let client = &self.clients[id];
+        if client.cap_enabled.chathistory {
+            return;
+        }
+        let len = client.autoreplay.unwrap_or_else(|| {
+            self.channel_history(channel_name)
+                .and_then(|h| h.autoreplay)
+                .unwrap_or(self.autoreplay_len)
+        });
+        if len == 0 {
+            return;
+        }
+
+        let channel = match self.channels.get(u(channel_name)) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let limit = self.channel_history_limit(channel_name);
+        let now = util::time();
+        let events = client.cap_enabled.event_playback;
+        let mut entries = channel
+            .history
+            .get(history::Query::Latest(None), len, events)
+            .skip_while(|entry| limit.is_expired(entry.unix_time, now))
+            .peekable();
+        if entries.peek().is_some() {
+            log::debug!("{}:     replaying history", id);
+            self.send_history(id, rb, channel_name, entries);
+        }
+    }
+
+    /// Sends `entries`, messages sent to `target`, in a `chathistory` batch.
+    pub(super) fn send_history<'a>(
+        &self,
+        id: usize,
+        rb: &mut ReplyBuffer,
+        target: &str,
+        entries: impl Iterator<Item = &'a history::Entry>,
+    ) {
+        let client = &self.clients[id];
+        let has_tags = client.cap_enabled.has_message_tags();
+        if client.cap_enabled.batch {
+            rb.batch_begin_with("chathistory", &[target]);
+        }
+        for entry in entries {
+            history_message(rb, has_tags, target, entry);
+        }
+        if client.cap_enabled.batch {
+            rb.batch_end();
+        }
+    }
+
+    pub fn cmd_chathistory(
+        &mut self,
+        ctx: CommandContext<'_>,
+        args: data::req::ChatHistory<'_>,
+    ) -> Result {
+        let subcommand = args.subcommand.to_ascii_uppercase();
+        if subcommand == "TARGETS" {
+            return self.cmd_chathistory_targets(ctx, args.params);
+        }
+
+        let query = chathistory_query(&subcommand, args.params)
+            .and_then(|(target, query, limit)| Some((target, query, chathistory_limit(limit)?)));
+        let (target, query, limit) = match query {
+            Some(query) => query,
+            None => {
+                log::debug!("{}:     invalid parameters", ctx.id);
+                let line = lines::CHATHISTORY_INVALID_PARAMS;
+                history_fail(
+                    ctx.rb,
+                    "CHATHISTORY",
+                    "INVALID_PARAMS",
+                    &[args.subcommand],
+                    line,
+                );
+                return Err(());
+            }
+        };
+
+        let history_limit = self.channel_history_limit(target);
+        let channel = match self.channels.get_mut(u(target)) {
+            Some(channel) if channel.members.contains_key(&ctx.id) => channel,
+            _ => {
+                log::debug!("{}:     not a member of {}", ctx.id, target);
+                let line = lines::CHATHISTORY_INVALID_TARGET;
+                history_fail(
+                    ctx.rb,
+                    "CHATHISTORY",
+                    "INVALID_TARGET",
+                    &[&subcommand, target],
+                    line,
+                );
+                return Err(());
+            }
+        };
+        channel.history.expire(history_limit, util::time());
+
+        ctx.rb.lr_batch_begin();
+        let events = self.clients[ctx.id].cap_enabled.event_playback;
+        let entries = self.channels[u(target)].history.get(query, limit, events);
+        self.send_history(ctx.id, ctx.rb, target, entries);
+
+        Ok(())
+    }
+
+    /// Lists the channels of the client that have messages between two timestamps, by time of
+    /// their last message.
+    fn cmd_chathistory_targets(&self, ctx: CommandContext<'_>, params: &[&str]) -> Result {
+        use history::Reference::Timestamp;
+        let reference = history::Reference::parse;
+        let query = match params {
+            [from, to, limit, ..] => (reference(from), reference(to), chathistory_limit(limit)),
+            _ => (None, None, None),
+        };
+        let (from, to, limit) = match query {
+            (Some(Timestamp(from)), Some(Timestamp(to)), Some(limit)) => {
+                (cmp::min(from, to), cmp::max(from, to), limit)
+            }
+            _ => {
+                log::debug!("{}:     invalid parameters", ctx.id);
+                let line = lines::CHATHISTORY_INVALID_PARAMS;
+                history_fail(ctx.rb, "CHATHISTORY", "INVALID_PARAMS", &["TARGETS"], line);
+                return Err(());
+            }
+        };
+
+        let now = util::time();
+        let mut targets: Vec<(&str, &str)> = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.members.contains_key(&ctx.id))
+            .filter_map(|(name, channel)| {
+                let last = channel.history.last()?;
+                let limit = self.channel_history_limit(name.get());
+                let in_range = from < last.time.as_str() && last.time.as_str() < to;
+                (in_range && !limit.is_expired(last.unix_time, now))
+                    .then(|| (name.get().as_str(), last.time.as_str()))
+            })
+            .collect();
+        targets.sort_unstable_by_key(|&(_, time)| time);
+        targets.truncate(limit);
+
+        ctx.rb.lr_batch_begin();
+        let batch = self.clients[ctx.id].cap_enabled.batch;
+        if batch {
+            ctx.rb.batch_begin("draft/chathistory-targets");
+        }
+        for (name, time) in targets {
+            ctx.rb
+                .prefixed_message(Command::ChatHistory)
+                .param("TARGETS")
+                .param(name)
+                .param(time);
+        }
+        if batch {
+            ctx.rb.batch_end();
+        }
+
+        Ok(())
+    }
+}
+
+/// Handlers for the SEARCH command.
+///
+/// Link to the specification: <https://github.com/ircv3/ircv3-specifications/pull/437>
+impl super::StateInner {
+    pub fn cmd_search(&self, ctx: CommandContext<'_>, attributes: &str) -> Result {
+        let mut values = Vec::new();
+        for attribute in ellidri_tokens::tags(attributes) {
+            values.push((attribute.key, attribute.unescape_value()));
+        }
+        let mut search = history::Search::default();
+        let mut target = None;
+        let mut limit = Some(CHATHISTORY_LIMIT);
+        let mut valid = true;
+        for (key, value) in &values {
+            let value = Some(value.as_str()).filter(|value| !value.is_empty());
+            match *key {
+                "in" => target = value,
+                "from" => search.from = value,
+                "after" => search.after = value,
+                "before" => search.before = value,
+                "text" => search.text = value,
+                "limit" => limit = value.and_then(chathistory_limit),
+                _ => valid = false,
+            }
+        }
+        let limit = match limit {
+            Some(limit) if valid => limit,
+            _ => {
+                log::debug!("{}:     invalid parameters", ctx.id);
+                let line = lines::SEARCH_INVALID_PARAMS;
+                history_fail(ctx.rb, "SEARCH", "INVALID_PARAMS", &[attributes], line);
+                return Err(());
+            }
+        };
+        if let Some(target) = target {
+            let is_member = self
+                .channels
+                .get(u(target))
+                .is_some_and(|channel| channel.members.contains_key(&ctx.id));
+            if !is_member {
+                log::debug!("{}:     not a member of {}", ctx.id, target);
+                let line = lines::CHATHISTORY_INVALID_TARGET;
+                history_fail(ctx.rb, "SEARCH", "INVALID_TARGET", &[target], line);
+                return Err(());
+            }
+        }
+
+        let now = util::time();
+        let mut results: Vec<(&str, &history::Entry)> = Vec::new();
+        for (name, channel) in &self.channels {
+            let name = name.get().as_str();
+            let searched = target.is_none_or(|target| u(target) == u(name));
+            if !searched || !channel.members.contains_key(&ctx.id) {
+                continue;
+            }
+            let history_limit = self.channel_history_limit(name);
+            let entries = channel.history.search(&search, limit).into_iter();
+            results.extend(
+                entries
+                    .filter(|entry| !history_limit.is_expired(entry.unix_time, now))
+                    .map(|entry| (name, entry)),
+            );
+        }
+        results.sort_by(|(_, a), (_, b)| a.time.cmp(&b.time));
+        let results = &results[results.len().saturating_sub(limit)..];
+
+        ctx.rb.lr_batch_begin();
+        let client = &self.clients[ctx.id];
+        let has_tags = client.cap_enabled.has_message_tags();
+        if client.cap_enabled.batch {
+            ctx.rb.batch_begin("draft/search");
+        }
+        for (name, entry) in results {
+            history_message(ctx.rb, has_tags, name, entry);
+        }
+        if client.cap_enabled.batch {
+            ctx.rb.batch_end();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+//! Persistent channel data.
+//!
+//! Channels are removed from the state as soon as they are empty, so everything that must outlive
+//! them (e.g. access lists) is kept in the `Store`, along with the highest user counts shown by
+//! LUSERS.  When a file is configured (`channel_db`), the store is loaded from it at startup and
+//! written back to it on each change, away from the state.
+
+use crate::channel::AccessLevel;
+use crate::util;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::{fs, io};
+
+/// An entry of a channel access list.
+///
+/// Users whose full name (`nick!user@host`) matches `mask` are given `level` when they join the
+/// channel.  A mask of the form `$a:name` matches users logged in to the account `name` instead.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct AccessEntry {
+    pub mask: String,
+    pub level: AccessLevel,
+}
+
+impl AccessEntry {
+    pub fn is_match(&self, full_name: &str, account: Option<&str>) -> bool {
+        match self.mask.strip_prefix("$a:") {
+            Some(name) => account.is_some_and(|account| account.eq_ignore_ascii_case(name)),
+            None => util::match_mask(&self.mask, full_name),
+        }
+    }
+}
+
+/// The persistent data of a channel.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ChannelRecord {
+    /// The account of the founder of the channel.
+    ///
+    /// The founder is the user that registered the channel with `FOUNDER <channel> REGISTER`.  It
+    /// is given the founder status (`~`) each time it joins the channel.
+    pub founder: Option<String>,
+
+    /// The account that becomes founder when the account of the founder is dropped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub successor: Option<String>,
+
+    pub access: Vec<AccessEntry>,
+}
+
+impl ChannelRecord {
+    fn is_empty(&self) -> bool {
+        self.founder.is_none() && self.successor.is_none() && self.access.is_empty()
+    }
+}
+
+/// The highest numbers of registered users and of connections the server has had.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Peaks {
+    pub users: usize,
+    pub connections: usize,
+}
+
+impl Peaks {
+    fn is_empty(&self) -> bool {
+        self.users == 0 && self.connections == 0
+    }
+}
+
+/// The contents of the store file.
+///
+/// Channel records are keys of the top-level map, next to `peaks`, which cannot be mistaken for a
+/// channel name since it has no channel prefix.  Files written before peaks were recorded are
+/// read as is.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoreFile<'a> {
+    #[serde(default, skip_serializing_if = "Peaks::is_empty")]
+    peaks: Peaks,
+    #[serde(flatten)]
+    channels: Cow<'a, BTreeMap<String, ChannelRecord>>,
+}
+
+#[derive(Default)]
+pub struct Store {
+    /// Path to the file the store is saved to, or empty if the store is not persisted.
+    path: String,
+
+    /// Writes the file, if `path` is not empty.
+    writer: Option<util::FileWriter>,
+
+    /// Channel records, indexed by the lowercase channel name (ellidri uses the `ascii`
+    /// casemapping).
+    channels: BTreeMap<String, ChannelRecord>,
+
+    peaks: Peaks,
+
+    /// Whether `peaks` has changed since the store was last written.
+    peaks_changed: bool,
+}
+
+impl Store {
+    /// Loads the store from the file at `path`.
+    ///
+    /// If `path` is empty, the store is kept in memory.  If the file does not exist, it will be
+    /// created on the first change.  If it cannot be read, the store is kept in memory as well, so
+    /// that the file is not replaced by an empty store.
+    pub fn load(path: &str) -> Self {
+        let mut store = Self::default();
+        if path.is_empty() {
+            return store;
+        }
+
+        log::info!("Loading channel data from {:?}", path);
+        match fs::read_to_string(path) {
+            Ok(contents) => match serde_yaml::from_str::<StoreFile<'_>>(&contents) {
+                Ok(file) => {
+                    store.channels = file.channels.into_owned();
+                    store.peaks = file.peaks;
+                }
+                Err(err) => {
+                    log::error!(
+                        "Failed to parse {:?}, it will not be written to: {}",
+                        path,
+                        err
+                    );
+                    return store;
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                log::error!(
+                    "Failed to read {:?}, it will not be written to: {}",
+                    path,
+                    err
+                );
+                return store;
+            }
+        }
+        store.path = path.to_owned();
+        store.writer = Some(util::FileWriter::spawn());
+        store
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&ChannelRecord> {
+        self.channels.get(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the account of the founder of the given channel.
+    pub fn founder(&self, channel: &str) -> Option<&str> {
+        self.channel(channel)?.founder.as_deref()
+    }
+
+    pub fn is_founder(&self, channel: &str, account: &str) -> bool {
+        self.founder(channel)
+            .is_some_and(|founder| founder.eq_ignore_ascii_case(account))
+    }
+
+    /// Registers `channel` to `account`.
+    ///
+    /// If `account` was the successor of the channel, the channel is left without successor.
+    pub fn set_founder(&mut self, channel: &str, account: &str) {
+        let record = self
+            .channels
+            .entry(channel.to_ascii_lowercase())
+            .or_default();
+        if record
+            .successor
+            .as_ref()
+            .is_some_and(|successor| successor.eq_ignore_ascii_case(account))
+        {
+            record.successor = None;
+        }
+        record.founder = Some(account.to_owned());
+        self.save();
+    }
+
+    /// Unregisters `channel`, and returns the account of its founder if it was registered.
+    ///
+    /// The successor of the channel is removed as well.
+    pub fn unregister(&mut self, channel: &str) -> Option<String> {
+        let key = channel.to_ascii_lowercase();
+        let record = self.channels.get_mut(&key)?;
+        let founder = record.founder.take()?;
+        record.successor = None;
+        if record.is_empty() {
+            self.channels.remove(&key);
+        }
+        self.save();
+        Some(founder)
+    }
+
+    /// Returns the account that inherits the given channel when its founder's account is dropped.
+    pub fn successor(&self, channel: &str) -> Option<&str> {
+        self.channel(channel)?.successor.as_deref()
+    }
+
+    /// Changes the successor of `channel`, or removes it if `account` is `None`.
+    pub fn set_successor(&mut self, channel: &str, account: Option<&str>) {
+        let record = self
+            .channels
+            .entry(channel.to_ascii_lowercase())
+            .or_default();
+        record.successor = account.map(str::to_owned);
+        self.save();
+    }
+
+    /// Gives the channels whose founder's account no longer exists to their successor, if its
+    /// account exists.
+    ///
+    /// Returns the names of these channels with their old and new founders.
+    pub fn inherit_founders(
+        &mut self,
+        exists: impl Fn(&str) -> bool,
+    ) -> Vec<(String, String, String)> {
+        let mut inherited = Vec::new();
+        for (name, record) in &mut self.channels {
+            let dropped = record
+                .founder
+                .as_deref()
+                .is_some_and(|founder| !exists(founder));
+            if !dropped || !record.successor.as_deref().is_some_and(&exists) {
+                continue;
+            }
+            let successor = record.successor.take().unwrap();
+            let founder = record.founder.replace(successor.clone()).unwrap();
+            inherited.push((name.clone(), founder, successor));
+        }
+        if !inherited.is_empty() {
+            self.save();
+        }
+        inherited
+    }
+
+    /// Returns the access list of the given channel.
+    pub fn access(&self, channel: &str) -> &[AccessEntry] {
+        self.channel(channel).map_or(&[], |record| &record.access)
+    }
+
+    /// Returns the highest level given to the user in the channel's access list.
+    pub fn access_level(
+        &self,
+        channel: &str,
+        full_name: &str,
+        account: Option<&str>,
+    ) -> Option<AccessLevel> {
+        self.access(channel)
+            .iter()
+            .filter(|entry| entry.is_match(full_name, account))
+            .map(|entry| entry.level)
+            .max()
+    }
+
+    /// Adds `mask` to the access list of `channel`, or changes its level if it is already there.
+    ///
+    /// Returns whether the access list has changed.
+    pub fn add_access(&mut self, channel: &str, mask: &str, level: AccessLevel) -> bool {
+        let record = self
+            .channels
+            .entry(channel.to_ascii_lowercase())
+            .or_default();
+        match record.access.iter_mut().find(|entry| entry.mask == mask) {
+            Some(entry) if entry.level == level => return false,
+            Some(entry) => entry.level = level,
+            None => record.access.push(AccessEntry {
+                mask: mask.to_owned(),
+                level,
+            }),
+        }
+        self.save();
+        true
+    }
+
+    /// Removes `mask` from the access list of `channel`.
+    ///
+    /// Returns whether the access list has changed.
+    pub fn remove_access(&mut self, channel: &str, mask: &str) -> bool {
+        let key = channel.to_ascii_lowercase();
+        let record = match self.channels.get_mut(&key) {
+            Some(record) => record,
+            None => return false,
+        };
+        let len = record.access.len();
+        record.access.retain(|entry| entry.mask != mask);
+        if record.access.len() == len {
+            return false;
+        }
+        if record.is_empty() {
+            self.channels.remove(&key);
+        }
+        self.save();
+        true
+    }
+
+    pub fn peaks(&self) -> Peaks {
+        self.peaks
+    }
+
+    /// Raises the recorded peaks to the given numbers of users and connections, if they are
+    /// higher.
+    ///
+    /// Peaks change with each new connection, so they are only written by `save_peaks`.
+    pub fn record_peaks(&mut self, users: usize, connections: usize) {
+        let peaks = Peaks {
+            users: self.peaks.users.max(users),
+            connections: self.peaks.connections.max(connections),
+        };
+        if peaks != self.peaks {
+            self.peaks = peaks;
+            self.peaks_changed = true;
+        }
+    }
+
+    /// Writes the store to its file, if the peaks have changed since it was last written.
+    pub fn save_peaks(&mut self) {
+        if self.peaks_changed {
+            self.save();
+        }
+    }
+
+    /// Writes the store to its file, if any.
+    fn save(&mut self) {
+        self.peaks_changed = false;
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let file = StoreFile {
+            peaks: self.peaks,
+            channels: Cow::Borrowed(&self.channels),
+        };
+        let contents = match serde_yaml::to_string(&file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::error!("Failed to serialize channel data: {}", err);
+                return;
+            }
+        };
+        let path = self.path.clone();
+        writer.run(move || {
+            if let Err(err) = util::write_atomic(&path, &contents) {
+                log::error!("Failed to write {:?}: {}", path, err);
+            }
+        });
+    }
+
+    /// Waits for the changes made so far to be written to the file.
+    pub fn sync(&self) {
+        if let Some(writer) = &self.writer {
+            writer.sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_level() {
+        let mut store = Store::default();
+        assert!(store.add_access("#Chan", "*!*@trusted", AccessLevel::Voice));
+        assert!(store.add_access("#chan", "$a:admin", AccessLevel::Operator));
+        assert!(!store.add_access("#chan", "*!*@trusted", AccessLevel::Voice));
+
+        let level = |full_name, account| store.access_level("#CHAN", full_name, account);
+        assert_eq!(level("a!~a@trusted", None), Some(AccessLevel::Voice));
+        assert_eq!(
+            level("a!~a@trusted", Some("Admin")),
+            Some(AccessLevel::Operator)
+        );
+        assert_eq!(level("a!~a@untrusted", None), None);
+
+        assert!(store.remove_access("#chan", "$a:admin"));
+        assert!(store.remove_access("#chan", "*!*@trusted"));
+        assert!(store.channel("#chan").is_none());
+    }
+
+    #[test]
+    fn test_founder() {
+        let mut store = Store::default();
+        assert_eq!(store.founder("#chan"), None);
+        store.set_founder("#Chan", "Admin");
+        assert!(store.is_founder("#chan", "admin"));
+        assert!(!store.is_founder("#chan", "someone"));
+
+        store.add_access("#chan", "*!*@*", AccessLevel::Voice);
+        store.remove_access("#chan", "*!*@*");
+        assert_eq!(store.founder("#chan"), Some("Admin"));
+
+        store.set_successor("#chan", Some("someone"));
+        assert_eq!(store.unregister("#CHAN").as_deref(), Some("Admin"));
+        assert!(store.channel("#chan").is_none());
+        assert_eq!(store.unregister("#chan"), None);
+    }
+
+    #[test]
+    fn test_successor() {
+        let mut store = Store::default();
+        store.set_founder("#a", "alice");
+        store.set_successor("#a", Some("bob"));
+        store.set_founder("#b", "alice");
+        store.set_successor("#b", Some("carol"));
+        store.set_founder("#c", "bob");
+        store.set_successor("#c", Some("alice"));
+        store.set_founder("#d", "alice");
+
+        let exists = |account: &str| account != "alice" && account != "carol";
+        let inherited = store.inherit_founders(exists);
+        let expected = (
+            String::from("#a"),
+            String::from("alice"),
+            String::from("bob"),
+        );
+        assert_eq!(inherited, [expected]);
+        assert_eq!(store.founder("#a"), Some("bob"));
+        assert_eq!(store.successor("#a"), None);
+        assert_eq!(store.founder("#b"), Some("alice"));
+        assert_eq!(store.founder("#c"), Some("bob"));
+        assert_eq!(store.founder("#d"), Some("alice"));
+
+        store.set_successor("#c", Some("dave"));
+        store.set_founder("#c", "Dave");
+        assert_eq!(store.successor("#c"), None);
+    }
+
+    #[test]
+    fn test_peaks() {
+        let path = std::env::temp_dir().join(format!("ellidri-peaks-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap();
+        // A file written before peaks were recorded.
+        fs::write(path, "\"#chan\":\n  founder: alice\n  access: []\n").unwrap();
+
+        let mut store = Store::load(path);
+        assert_eq!(store.peaks(), Peaks::default());
+        assert_eq!(store.founder("#chan"), Some("alice"));
+        store.record_peaks(3, 5);
+        store.record_peaks(4, 2);
+        store.sync();
+        assert_eq!(Store::load(path).peaks(), Peaks::default());
+        store.save_peaks();
+        store.sync();
+        assert_eq!(
+            store.peaks(),
+            Peaks {
+                users: 4,
+                connections: 5
+            }
+        );
+
+        let store = Store::load(path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(store.peaks().users, 4);
+        assert_eq!(store.peaks().connections, 5);
+        assert_eq!(store.founder("#chan"), Some("alice"));
+    }
+
+    #[test]
+    fn test_unreadable_file_is_kept() {
+        let path = std::env::temp_dir().join(format!("ellidri-store-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "\"#chan\": [not a record").unwrap();
+
+        let mut store = Store::load(path);
+        assert_eq!(store.path(), "");
+        store.set_founder("#other", "alice");
+        store.sync();
+
+        let contents = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(contents, "\"#chan\": [not a record");
+    }
+} // mod tests
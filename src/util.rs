@@ -4,84 +4,181 @@ use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use rand_chacha::rand_core::{RngCore, SeedableRng};
 use rand_chacha::ChaChaRng;
 use rand_core::OsRng;
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::time;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::mpsc;
+use std::{fmt, fs, io, net, thread, time};
 
 thread_local! {
     static RNG: RefCell<ChaChaRng> = RefCell::new(ChaChaRng::seed_from_u64(time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs()));
 }
 
-pub type Masks<'a> = std::str::Split<'a, char>;
+pub type Masks<'a> = std::iter::Map<std::slice::Iter<'a, String>, fn(&String) -> &str>;
 
+/// A mask that contains wildcards, split into chunks once and for all.
+struct Pattern {
+    mask: String,
+    chunks: Vec<(bool, String)>,
+}
+
+impl Pattern {
+    fn new(mask: &str) -> Self {
+        let mut rest = mask;
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let (star, chunk) = scan_chunk(&mut rest);
+            chunks.push((star, chunk.to_owned()));
+        }
+        Self {
+            mask: mask.to_owned(),
+            chunks,
+        }
+    }
+
+    fn is_match(&self, s: &str) -> bool {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|(star, chunk)| (*star, chunk.as_str()));
+        match_chunks(chunks, s)
+    }
+}
+
+/// A set of masks, such as the ban list of a channel.
+///
+/// Masks without wildcards are matched with a hash lookup, and the others are parsed when they
+/// are inserted, so that matching does not have to parse them again.
+#[derive(Default)]
 pub struct MaskSet {
-    raw: String,
+    /// All masks, in insertion order.
+    masks: Vec<String>,
+    exact: HashSet<String>,
+    patterns: Vec<Pattern>,
 }
 
 impl MaskSet {
     pub fn new() -> Self {
-        MaskSet { raw: String::new() }
+        Self::default()
+    }
+
+    /// The number of masks in the set.
+    pub fn len(&self) -> usize {
+        self.masks.len()
     }
 
     pub fn is_match(&self, s: &str) -> bool {
-        self.raw.split(',').any(|mask| match_mask(mask, s))
+        self.exact.contains(s) || self.patterns.iter().any(|pattern| pattern.is_match(s))
+    }
+
+    /// Whether `mask` is in the set, as is.
+    pub fn contains(&self, mask: &str) -> bool {
+        self.masks.iter().any(|m| m == mask)
     }
 
     /// Returns whether mask has been inserted.
     pub fn insert(&mut self, mask: &str) -> bool {
-        if self.raw.split(',').any(|m| m == mask) {
+        if self.contains(mask) {
             return false;
         }
 
-        if !self.raw.is_empty() {
-            self.raw.push(',');
+        if mask.contains(['*', '?']) {
+            self.patterns.push(Pattern::new(mask));
+        } else {
+            self.exact.insert(mask.to_owned());
         }
-        self.raw.push_str(mask);
+        self.masks.push(mask.to_owned());
 
         true
     }
 
     /// Returns whether mask has been removed.
     pub fn remove(&mut self, mask: &str) -> bool {
-        if let Some(removed) = self.raw.split(',').find(|m| *m == mask) {
-            let start = removed.as_ptr() as usize - self.raw.as_ptr() as usize;
-
-            let mut end = start + removed.len();
-            if end < self.raw.len() {
-                end += 1;
-            }
-
-            self.raw.replace_range(start..end, "");
+        let i = match self.masks.iter().position(|m| m == mask) {
+            Some(i) => i,
+            None => return false,
+        };
 
-            return true;
+        self.masks.remove(i);
+        if !self.exact.remove(mask) {
+            self.patterns.retain(|pattern| pattern.mask != mask);
         }
 
-        false
+        true
     }
 
     pub fn masks(&self) -> Masks<'_> {
-        self.raw.split(',')
+        self.masks.iter().map(String::as_str)
+    }
+}
+
+/// Matches `s` against a glob mask, where `*` matches any sequence of characters and `?` any
+/// character.
+pub fn match_mask(mut mask: &str, s: &str) -> bool {
+    let chunks = std::iter::from_fn(|| (!mask.is_empty()).then(|| scan_chunk(&mut mask)));
+    match_chunks(chunks, s)
+}
+
+/// A range of IP addresses, written `address/prefix-length` (e.g. `192.0.2.0/24`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: net::IpAddr,
+    len: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, len) = s.split_once('/')?;
+        let addr: net::IpAddr = addr.parse().ok()?;
+        let len: u8 = len.parse().ok()?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        (len <= max_len).then_some(Self { addr, len })
+    }
+
+    pub fn contains(&self, ip: net::IpAddr) -> bool {
+        match (self.addr, ip) {
+            (net::IpAddr::V4(addr), net::IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.len)).unwrap_or(0);
+                u32::from(addr) & mask == u32::from(ip) & mask
+            }
+            (net::IpAddr::V6(addr), net::IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.len))
+                    .unwrap_or(0);
+                u128::from(addr) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
     }
 }
 
 // Taken from <https://golang.org/src/path/match.go?s=1084:1142#L28>
-pub fn match_mask(mut mask: &str, mut s: &str) -> bool {
-    'pattern: while !mask.is_empty() {
-        let (star, chunk) = scan_chunk(&mut mask);
+fn match_chunks<'a>(chunks: impl Iterator<Item = (bool, &'a str)>, mut s: &str) -> bool {
+    let mut chunks = chunks.peekable();
+    'pattern: while let Some((star, chunk)) = chunks.next() {
+        let is_last = chunks.peek().is_none();
         if star && chunk.is_empty() {
             return true;
         }
 
         let (rest, ok) = match_chunk(chunk, s);
-        if ok && (rest.is_empty() || !mask.is_empty()) {
+        if ok && (rest.is_empty() || !is_last) {
             s = rest;
             continue;
         }
 
         if star {
-            for i in 0..s.len() {
-                let (rest, ok) = match_chunk(chunk, &s[i + 1..]);
+            for (i, c) in s.char_indices() {
+                let (rest, ok) = match_chunk(chunk, &s[i + c.len_utf8()..]);
                 if ok {
-                    if mask.is_empty() && !rest.is_empty() {
+                    if is_last && !rest.is_empty() {
                         continue;
                     }
                     s = rest;
@@ -124,6 +221,20 @@ fn match_chunk<'a>(chunk: &str, mut s: &'a str) -> (&'a str, bool) {
     (s, true)
 }
 
+/// Returns `len` random bytes, hex-encoded.
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0x0; len];
+    RNG.with(|rng| {
+        rng.borrow_mut().fill_bytes(&mut bytes);
+    });
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns a random number below `n`, which must not be zero.
+pub fn random_below(n: usize) -> usize {
+    RNG.with(|rng| rng.borrow_mut().next_u64() as usize % n)
+}
+
 pub fn new_message_id() -> String {
     let mut bytes = [0x0; 24];
     RNG.with(|rng| {
@@ -158,6 +269,112 @@ pub fn time() -> u64 {
     }
 }
 
+/// Replaces the `{name}` variables in `s` with `var(name)`.  Unknown variables are left as-is.
+///
+/// Does not allocate if `s` has no known variables.
+pub fn expand_vars(s: &str, var: impl Fn(&str) -> Option<String>) -> Cow<'_, str> {
+    let mut res = String::new();
+    let mut rest = s;
+    let mut done = 0;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        if let Some(value) = var(&rest[start + 1..end]) {
+            let offset = s.len() - rest.len();
+            res.push_str(&s[done..offset + start]);
+            res.push_str(&value);
+            done = offset + end + 1;
+        }
+        rest = &rest[end + 1..];
+    }
+    if done == 0 {
+        return Cow::Borrowed(s);
+    }
+    res.push_str(&s[done..]);
+    Cow::Owned(res)
+}
+
+/// Parses a duration in seconds, optionally followed by a unit: `s`, `m` (minutes), `h` (hours)
+/// or `d` (days).  Returns `None` if `s` is not a valid duration or if it is zero.
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let factor = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(factor))
+        .filter(|&n| n != 0)
+}
+
+/// Whether `host` can be displayed as the host of a client: at most 63 letters, digits, dots,
+/// dashes, slashes and colons, not starting with a colon.
+pub fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 63
+        && !host.starts_with(':')
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-./:".contains(&b))
+}
+
+/// Replaces `$N` in `template` with the Nth argument and `$N-` with the Nth and following
+/// arguments separated by spaces.  Missing arguments are replaced by empty strings.
+pub fn expand_args(template: &str, args: &[&str]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n = match rest[..digits].parse::<usize>() {
+            Ok(n) if n != 0 => n,
+            _ => {
+                res.push('$');
+                continue;
+            }
+        };
+        rest = &rest[digits..];
+        if let Some(after) = rest.strip_prefix('-') {
+            res.push_str(&args.get(n - 1..).unwrap_or_default().join(" "));
+            rest = after;
+        } else {
+            res.push_str(args.get(n - 1).unwrap_or(&""));
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Removes message tags and BATCH messages from the replies to a command, so that they can be sent
+/// to other connections than the one that issued the command.
+pub fn untagged_replies(replies: &str) -> String {
+    let mut res = String::with_capacity(replies.len());
+    for line in replies.split_inclusive("\r\n") {
+        let line = match line.strip_prefix('@') {
+            Some(tagged) => tagged.split_once(' ').map_or("", |(_, rest)| rest),
+            None => line,
+        };
+        let mut words = line.split(' ').skip_while(|word| word.starts_with(':'));
+        if words.next() != Some("BATCH") {
+            res.push_str(line);
+        }
+    }
+    res
+}
+
 pub fn hash_password(password: &str) -> anyhow::Result<String> {
     use argon2::PasswordHasher;
     let salt = SaltString::generate(&mut OsRng);
@@ -177,6 +394,48 @@ pub fn verify_password_hash(password_hash: &str, password: &str) -> anyhow::Resu
     Ok(())
 }
 
+/// Writes `contents` to a temporary file next to `path`, then renames it over `path`, so that the
+/// file is never left half-written.
+pub fn write_atomic(path: &str, contents: &str) -> io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs file writes in order on a thread of its own, so that they do not block the state.
+pub struct FileWriter(mpsc::Sender<Job>);
+
+impl FileWriter {
+    pub fn spawn() -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            for job in receiver {
+                job();
+            }
+        });
+        Self(jobs)
+    }
+
+    pub fn run(&self, job: impl FnOnce() + Send + 'static) {
+        if let Err(mpsc::SendError(job)) = self.0.send(Box::new(job)) {
+            job();
+        }
+    }
+
+    /// Waits for the jobs given so far to be done.
+    pub fn sync(&self) {
+        let (done, wait) = mpsc::channel();
+        self.run(move || {
+            let _ = done.send(());
+        });
+        let _ = wait.recv();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,6 +448,156 @@ mod tests {
         assert!(verify_password_hash(&hashed_password_two, "foo bar").is_ok());
         assert!(verify_password_hash(&hashed_password_two, "hello world").is_err());
     }
+    #[test]
+    fn test_mask_set_len() {
+        let mut set = MaskSet::new();
+        assert_eq!(set.len(), 0);
+        assert!(set.insert("a!*@*"));
+        assert!(set.insert("b!*@*"));
+        assert!(!set.insert("a!*@*"));
+        assert_eq!(set.len(), 2);
+        assert!(set.remove("a!*@*"));
+        assert!(set.remove("b!*@*"));
+        assert_eq!(set.len(), 0);
+    }
+
+    #[test]
+    fn test_mask_set_match() {
+        let mut set = MaskSet::new();
+        assert!(!set.is_match("a!b@c"));
+        assert_eq!(set.masks().count(), 0);
+
+        assert!(set.insert("a!b@c"));
+        assert!(set.insert("*!*@host"));
+        assert!(set.insert("n?ck!*@*"));
+        assert!(set.is_match("a!b@c"));
+        assert!(!set.is_match("a!b@d"));
+        assert!(set.is_match("anyone!u@host"));
+        assert!(set.is_match("nick!u@h"));
+        assert_eq!(
+            set.masks().collect::<Vec<_>>(),
+            ["a!b@c", "*!*@host", "n?ck!*@*"]
+        );
+
+        assert!(set.remove("*!*@host"));
+        assert!(set.remove("a!b@c"));
+        assert!(!set.is_match("anyone!u@host"));
+        assert!(!set.is_match("a!b@c"));
+        assert!(set.is_match("nick!u@h"));
+    }
+
+    #[test]
+    fn test_untagged_replies() {
+        let replies = "@label=a BATCH +0 labeled-response\r\n\
+                       @batch=0 :nick!u@h JOIN #c\r\n\
+                       :srv 353 nick = #c :nick\r\n\
+                       @batch=0 :srv BATCH -0\r\n";
+        assert_eq!(
+            untagged_replies(replies),
+            ":nick!u@h JOIN #c\r\n:srv 353 nick = #c :nick\r\n"
+        );
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let var = |name: &str| (name == "users").then(|| String::from("42"));
+        let cases = [
+            ("", ""),
+            ("hello", "hello"),
+            ("{users} users", "42 users"),
+            ("users: {users}", "users: 42"),
+            ("{unknown} {users}{users}", "{unknown} 4242"),
+            ("{users", "{users"),
+            ("{{users}}", "{{users}}"),
+        ];
+
+        for (s, expected) in &cases {
+            assert_eq!(expand_vars(s, var), *expected, "expand_vars({s:?})");
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let cases = [
+            ("600", Some(600)),
+            ("30s", Some(30)),
+            ("10m", Some(600)),
+            ("2h", Some(7200)),
+            ("1d", Some(86400)),
+            ("0", None),
+            ("", None),
+            ("m", None),
+            ("5w", None),
+            ("1h30m", None),
+            ("-5", None),
+        ];
+
+        for (s, expected) in &cases {
+            assert_eq!(parse_duration(s), *expected, "parse_duration({s:?})");
+        }
+    }
+
+    #[test]
+    fn test_is_valid_host() {
+        let cases = [
+            ("127.0.0.1", true),
+            ("::1", false),
+            ("2001:db8::1", true),
+            ("staff/ellidri", true),
+            ("my-cool.host", true),
+            ("", false),
+            ("has space", false),
+            ("nick!user", false),
+            ("user@host", false),
+        ];
+
+        for (host, expected) in &cases {
+            assert_eq!(is_valid_host(host), *expected, "is_valid_host({host:?})");
+        }
+        assert!(!is_valid_host(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let ip = |s: &str| s.parse::<net::IpAddr>().unwrap();
+        let cidr = Cidr::parse("192.0.2.0/24").unwrap();
+        assert!(cidr.contains(ip("192.0.2.42")));
+        assert!(!cidr.contains(ip("192.0.3.1")));
+        assert!(!cidr.contains(ip("::1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("10.1.2.3")));
+        assert!(Cidr::parse("10.1.2.3/32").unwrap().contains(ip("10.1.2.3")));
+
+        let cidr = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(cidr.contains(ip("2001:db8:1::1")));
+        assert!(!cidr.contains(ip("2001:db9::1")));
+        assert_eq!(cidr.to_string(), "2001:db8::/32");
+
+        assert_eq!(Cidr::parse("192.0.2.0"), None);
+        assert_eq!(Cidr::parse("192.0.2.0/33"), None);
+        assert_eq!(Cidr::parse("*!*@host/24"), None);
+    }
+
+    #[test]
+    fn test_expand_args() {
+        let args = ["#chan", "nick", "some", "text"];
+        let cases = [
+            ("MODE $1 +o $2", "MODE #chan +o nick"),
+            ("TOPIC $1 :$2-", "TOPIC #chan :nick some text"),
+            ("KICK $1 $2 :$3-", "KICK #chan nick :some text"),
+            ("INVITE $2 $1", "INVITE nick #chan"),
+            ("WHOIS $5", "WHOIS "),
+            ("COST $ $0 $$1", "COST $ $0 $#chan"),
+        ];
+
+        for (template, expected) in &cases {
+            assert_eq!(
+                expand_args(template, &args),
+                *expected,
+                "expand_args({template:?})"
+            );
+        }
+    }
+
     #[test]
     fn test_mask_match() {
         let cases = [
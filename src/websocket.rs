@@ -0,0 +1,674 @@
+//! IRC over WebSocket, for web clients.
+//!
+//! TLS bindings serve WebSocket connections to clients that negotiate `http/1.1` with ALPN (see
+//! `net::handle_tls`).  Each WebSocket message carries one IRC line, without CRLF, as described by
+//! the IRCv3 specification: <https://ircv3.net/specs/extensions/websocket>
+//!
+//! Once the HTTP handshake is done, `bridge` turns messages into lines and back, so that the
+//! connection is handled by `net::handle` like any other.
+
+use std::str;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+/// The maximum length of a message sent by clients, including tags.
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+/// The maximum length of the HTTP request that opens a WebSocket.
+const MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+/// Appended to the key of the client to compute `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The subprotocols of the IRCv3 specification.  Clients that ask for none get text messages.
+const TEXT_PROTOCOL: &str = "text.ircv3.net";
+const BINARY_PROTOCOL: &str = "binary.ircv3.net";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Close status codes.
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+
+fn invalid_data(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Whether `err` comes from a text message that is not valid UTF-8.
+fn is_utf8_error(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|err| err.is::<str::Utf8Error>())
+}
+
+/// Returns the `Sec-WebSocket-Accept` header value for the given `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64::encode(sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+/// SHA-1, which WebSocket handshakes depend on.  It is not used for anything else.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_be_bytes());
+
+    for block in msg.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (w, word) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Why an HTTP request cannot open a WebSocket.
+#[derive(Debug, PartialEq, Eq)]
+enum Rejection {
+    /// The request is not a valid WebSocket handshake.
+    BadRequest(&'static str),
+
+    /// The client asked for another version of the WebSocket protocol than 13.
+    UnsupportedVersion,
+}
+
+impl Rejection {
+    /// The HTTP response sent to the client.
+    fn response(&self) -> &'static [u8] {
+        match self {
+            Self::BadRequest(_) => b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n",
+            Self::UnsupportedVersion => {
+                b"HTTP/1.1 426 Upgrade Required\r\n\
+                  Sec-WebSocket-Version: 13\r\n\
+                  Connection: close\r\n\r\n"
+            }
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Self::BadRequest(reason) => reason,
+            Self::UnsupportedVersion => "unsupported WebSocket version",
+        }
+    }
+}
+
+/// Reads the HTTP request that opens a WebSocket, and accepts it.
+///
+/// Returns whether the client asked for binary messages.  Invalid requests are answered with
+/// "400 Bad Request", or with "426 Upgrade Required" if only the version of the protocol is wrong.
+pub async fn accept<S>(conn: &mut S) -> io::Result<bool>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let request = match read_request(conn).await {
+        Ok(request) => request,
+        Err(err) => {
+            let _ = conn.write_all(Rejection::BadRequest("").response()).await;
+            return Err(err);
+        }
+    };
+    let (key, protocol) = match handshake(&request) {
+        Ok(request) => request,
+        Err(rejection) => {
+            let _ = conn.write_all(rejection.response()).await;
+            return Err(invalid_data(rejection.reason()));
+        }
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n",
+        accept_key(key),
+    );
+    if let Some(protocol) = protocol {
+        response.push_str("Sec-WebSocket-Protocol: ");
+        response.push_str(protocol);
+        response.push_str("\r\n");
+    }
+    response.push_str("\r\n");
+    conn.write_all(response.as_bytes()).await?;
+    conn.flush().await?;
+    Ok(protocol == Some(BINARY_PROTOCOL))
+}
+
+/// Reads an HTTP request, up to and including the empty line that ends its headers.
+async fn read_request<S>(conn: &mut S) -> io::Result<String>
+where
+    S: AsyncBufRead + Unpin,
+{
+    let mut request = conn.take(MAX_REQUEST_LEN);
+    let mut buf = String::new();
+    loop {
+        let start = buf.len();
+        if request.read_line(&mut buf).await? == 0 {
+            return Err(invalid_data("HTTP request too long"));
+        }
+        if buf[start..].trim_end().is_empty() {
+            return Ok(buf);
+        }
+    }
+}
+
+/// Checks that `request` opens a WebSocket, as described by RFC 6455 section 4.2.1.
+///
+/// Returns the `Sec-WebSocket-Key` of the client, and the subprotocol chosen among those it asked
+/// for, if any.
+fn handshake(request: &str) -> Result<(&str, Option<&'static str>), Rejection> {
+    let mut lines = request.lines();
+    let request_line = lines.next().unwrap_or_default();
+    if !request_line.starts_with("GET ") || !request_line.ends_with(" HTTP/1.1") {
+        return Err(Rejection::BadRequest("not an HTTP/1.1 GET request"));
+    }
+
+    let mut host = false;
+    let mut upgrade = false;
+    let mut connection = false;
+    let mut version = None;
+    let mut key = None;
+    let mut protocol = None;
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return Err(Rejection::BadRequest("invalid HTTP header")),
+        };
+        let mut values = value.split(',').map(str::trim);
+        if name.eq_ignore_ascii_case("Host") {
+            host = true;
+        } else if name.eq_ignore_ascii_case("Upgrade") {
+            upgrade = values.any(|v| v.eq_ignore_ascii_case("websocket"));
+        } else if name.eq_ignore_ascii_case("Connection") {
+            connection = values.any(|v| v.eq_ignore_ascii_case("Upgrade"));
+        } else if name.eq_ignore_ascii_case("Sec-WebSocket-Version") {
+            version = Some(value);
+        } else if name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+            key = Some(value);
+        } else if name.eq_ignore_ascii_case("Sec-WebSocket-Protocol") {
+            // The first protocol that ellidri supports is chosen.
+            protocol = values.find_map(|v| {
+                [TEXT_PROTOCOL, BINARY_PROTOCOL]
+                    .into_iter()
+                    .find(|&protocol| protocol == v)
+            });
+        }
+    }
+
+    if !host {
+        return Err(Rejection::BadRequest("missing Host header"));
+    }
+    if !upgrade || !connection {
+        return Err(Rejection::BadRequest("not a WebSocket upgrade"));
+    }
+    match version {
+        Some("13") => {}
+        Some(_) => return Err(Rejection::UnsupportedVersion),
+        None => {
+            return Err(Rejection::BadRequest(
+                "missing Sec-WebSocket-Version header",
+            ))
+        }
+    }
+    // The key is 16 random bytes, encoded in base64.
+    match key {
+        Some(key) if base64::decode(key).is_ok_and(|nonce| nonce.len() == 16) => {
+            Ok((key, protocol))
+        }
+        _ => Err(Rejection::BadRequest("invalid Sec-WebSocket-Key header")),
+    }
+}
+
+/// Reads a frame, and returns whether it is the last of its message, its opcode and its payload.
+async fn read_frame(conn: &mut (impl AsyncRead + Unpin)) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    conn.read_exact(&mut head).await?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0f;
+    if head[0] & 0x70 != 0 {
+        // No extension is negotiated, so the reserved bits must be unset.
+        return Err(invalid_data("reserved bits set"));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(invalid_data("unmasked frame"));
+    }
+    let len = match head[1] & 0x7f {
+        126 => u64::from(conn.read_u16().await?),
+        127 => conn.read_u64().await?,
+        len => u64::from(len),
+    };
+    if opcode & 0x8 != 0 && (!fin || 125 < len) {
+        return Err(invalid_data("fragmented or long control frame"));
+    }
+    if MAX_MESSAGE_LEN as u64 <= len {
+        return Err(invalid_data("message too long"));
+    }
+    let mut mask = [0; 4];
+    conn.read_exact(&mut mask).await?;
+    let mut payload = vec![0; len as usize];
+    conn.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((fin, opcode, payload))
+}
+
+/// Reads the next message, and sends the replies to control frames to `control`.  Returns `None`
+/// when the client closes the WebSocket.
+///
+/// Messages must be a single IRC line, without line breaks, and text messages must be valid UTF-8.
+/// The error of an invalid text message wraps the `Utf8Error`.
+async fn read_message(
+    conn: &mut (impl AsyncRead + Unpin),
+    control: &mpsc::UnboundedSender<(u8, Vec<u8>)>,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut message = Vec::new();
+    let mut started = false;
+    let mut text = false;
+    loop {
+        let (fin, opcode, payload) = read_frame(conn).await?;
+        match opcode {
+            OP_CLOSE => return Ok(None),
+            OP_PING => {
+                let _ = control.send((OP_PONG, payload));
+                continue;
+            }
+            OP_PONG => continue,
+            OP_TEXT | OP_BINARY if !started => {
+                started = true;
+                text = opcode == OP_TEXT;
+            }
+            OP_CONTINUATION if started => {}
+            _ => return Err(invalid_data("unexpected frame")),
+        }
+        if MAX_MESSAGE_LEN < message.len() + payload.len() {
+            return Err(invalid_data("message too long"));
+        }
+        message.extend_from_slice(&payload);
+        if !fin {
+            continue;
+        }
+        if text {
+            if let Err(err) = str::from_utf8(&message) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        }
+        if message.contains(&b'\r') || message.contains(&b'\n') {
+            return Err(invalid_data("line break in a message"));
+        }
+        return Ok(Some(message));
+    }
+}
+
+async fn write_frame(
+    conn: &mut (impl AsyncWrite + Unpin),
+    opcode: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    conn.write_all(&frame).await?;
+    conn.flush().await
+}
+
+/// Exchanges the messages of the WebSocket `ws` with the lines of `irc`, until either is closed.
+pub async fn bridge(
+    ws: impl AsyncRead + AsyncWrite,
+    irc: impl AsyncRead + AsyncWrite,
+    binary: bool,
+) -> io::Result<()> {
+    let (mut ws_reader, mut ws_writer) = io::split(ws);
+    let (irc_reader, mut irc_writer) = io::split(irc);
+    let (control, mut controls) = mpsc::unbounded_channel();
+
+    let incoming = async {
+        while let Some(message) = read_message(&mut ws_reader, &control).await? {
+            irc_writer.write_all(&message).await?;
+            irc_writer.write_all(b"\r\n").await?;
+        }
+        Ok(())
+    };
+
+    let ws_writer_ref = &mut ws_writer;
+    let outgoing = async move {
+        let opcode = if binary { OP_BINARY } else { OP_TEXT };
+        let mut lines = BufReader::new(irc_reader).lines();
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => write_frame(ws_writer_ref, opcode, line.as_bytes()).await?,
+                    None => return Ok(()),
+                },
+                Some((opcode, payload)) = controls.recv() => {
+                    write_frame(ws_writer_ref, opcode, &payload).await?;
+                }
+            }
+        }
+    };
+
+    let res: io::Result<()> = tokio::select! {
+        res = incoming => res,
+        res = outgoing => res,
+    };
+    let status = match res {
+        Err(ref err) if is_utf8_error(err) => CLOSE_INVALID_PAYLOAD,
+        Err(ref err) if err.kind() == io::ErrorKind::InvalidData => CLOSE_PROTOCOL_ERROR,
+        _ => CLOSE_NORMAL,
+    };
+    let _ = write_frame(&mut ws_writer, OP_CLOSE, &status.to_be_bytes()).await;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: &str = "GET / HTTP/1.1\r\n\
+                           Host: irc.example.com\r\n\
+                           Upgrade: websocket\r\n\
+                           Connection: keep-alive, Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                           Sec-WebSocket-Protocol: binary.ircv3.net, text.ircv3.net\r\n\
+                           Sec-WebSocket-Version: 13\r\n\r\n";
+
+    /// Returns a frame as clients send it, masked.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![u8::from(fin) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn test_sha1() {
+        let hex =
+            |digest: [u8; 20]| -> String { digest.iter().map(|b| format!("{b:02x}")).collect() };
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(sha1(&[b'a'; 1000])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+        // Example of RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    /// Reads a message from the given frames.
+    async fn read(frames: &[Vec<u8>]) -> io::Result<Option<Vec<u8>>> {
+        let (control, _controls) = mpsc::unbounded_channel();
+        let bytes = frames.concat();
+        read_message(&mut &bytes[..], &control).await
+    }
+
+    #[test]
+    fn test_handshake() {
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let with = |from: &str, to: &str| REQUEST.replace(from, to);
+        assert_eq!(handshake(REQUEST), Ok((key, Some(BINARY_PROTOCOL))));
+        assert_eq!(
+            handshake(&with("binary.ircv3.net, ", "")),
+            Ok((key, Some(TEXT_PROTOCOL)))
+        );
+        assert_eq!(
+            handshake(&with("ircv3.net", "example.com")),
+            Ok((key, None))
+        );
+
+        let upgrade = Err(Rejection::BadRequest("not a WebSocket upgrade"));
+        assert_eq!(
+            handshake(&with("keep-alive, Upgrade", "keep-alive")),
+            upgrade
+        );
+        assert_eq!(handshake(&with("Upgrade: websocket\r\n", "")), upgrade);
+        assert_eq!(
+            handshake(&with("Version: 13", "Version: 8")),
+            Err(Rejection::UnsupportedVersion)
+        );
+        assert_eq!(
+            handshake(&with("Sec-WebSocket-Version: 13\r\n", "")),
+            Err(Rejection::BadRequest(
+                "missing Sec-WebSocket-Version header"
+            ))
+        );
+        assert_eq!(
+            handshake(&with(key, "c2hvcnQ=")),
+            Err(Rejection::BadRequest("invalid Sec-WebSocket-Key header"))
+        );
+        assert_eq!(
+            handshake(&with("Host: irc.example.com\r\n", "")),
+            Err(Rejection::BadRequest("missing Host header"))
+        );
+        assert_eq!(
+            handshake(&with("GET /", "POST /")),
+            Err(Rejection::BadRequest("not an HTTP/1.1 GET request"))
+        );
+    }
+
+    #[test]
+    fn test_accept_rejected() {
+        crate::sim::run(async {
+            let (server, mut client) = io::duplex(4096);
+            let request = REQUEST.replace("Version: 13", "Version: 8");
+            client.write_all(request.as_bytes()).await.unwrap();
+            let mut server = BufReader::new(server);
+            assert!(accept(&mut server).await.is_err());
+            drop(server);
+
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"),
+                "{response}"
+            );
+            assert!(
+                response.contains("\r\nSec-WebSocket-Version: 13\r\n"),
+                "{response}"
+            );
+        });
+    }
+
+    #[test]
+    fn test_read_message() {
+        crate::sim::run(async {
+            // Fragmented and masked frames, with a UTF-8 character split across them.
+            let frames = [
+                client_frame(false, OP_TEXT, b"PRIVMSG #a :caf"),
+                client_frame(false, OP_CONTINUATION, b"\xc3"),
+                client_frame(true, OP_CONTINUATION, b"\xa9"),
+            ];
+            let message = read(&frames).await.unwrap().unwrap();
+            assert_eq!(message, "PRIVMSG #a :café".as_bytes());
+
+            // Frames whose length takes two more bytes.
+            let long = format!("PRIVMSG #a :{}", "a".repeat(300));
+            let frames = [client_frame(true, OP_TEXT, long.as_bytes())];
+            assert_eq!(read(&frames).await.unwrap().unwrap(), long.as_bytes());
+
+            let frames = [client_frame(true, OP_BINARY, b"PRIVMSG #a :\xff")];
+            assert_eq!(read(&frames).await.unwrap().unwrap(), b"PRIVMSG #a :\xff");
+            let frames = [client_frame(true, OP_CLOSE, b"")];
+            assert_eq!(read(&frames).await.unwrap(), None);
+
+            let frames = [client_frame(true, OP_TEXT, b"PRIVMSG #a :\xc3(")];
+            let err = read(&frames).await.unwrap_err();
+            assert!(is_utf8_error(&err), "{err}");
+
+            let invalid_messages = [
+                // Unmasked frame.
+                vec![0x81, 4, b'P', b'I', b'N', b'G'],
+                // Reserved bit.
+                vec![0xc1, 0x80, 0, 0, 0, 0],
+                // Continuation of nothing.
+                client_frame(true, OP_CONTINUATION, b"PING"),
+                // Fragmented control frame.
+                client_frame(false, OP_PING, b"ping"),
+                // Several IRC lines in one message.
+                client_frame(true, OP_TEXT, b"NICK a\r\nQUIT"),
+                client_frame(true, OP_BINARY, b"NICK a\n"),
+            ];
+            for frame in invalid_messages {
+                let err = read(&[frame]).await.unwrap_err();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                assert!(!is_utf8_error(&err), "{err}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_bridge_invalid_utf8() {
+        crate::sim::run(async {
+            let (server, mut client) = io::duplex(4096);
+            let (irc, _irc_client) = io::duplex(4096);
+            let bridge = tokio::spawn(bridge(server, irc, false));
+
+            client
+                .write_all(&client_frame(true, OP_TEXT, b"NICK \xff"))
+                .await
+                .unwrap();
+            let mut frame = [0; 4];
+            client.read_exact(&mut frame).await.unwrap();
+            assert_eq!(frame, [0x88, 2, 0x03, 0xef]); // Close with status 1007.
+            assert!(bridge.await.unwrap().is_err());
+        });
+    }
+
+    #[test]
+    fn test_bridge() {
+        crate::sim::run(async {
+            let (server, mut client) = io::duplex(4096);
+            let (irc, irc_client) = io::duplex(4096);
+            tokio::spawn(async move {
+                let mut server = BufReader::new(server);
+                let binary = accept(&mut server).await.unwrap();
+                assert!(!binary);
+                let _ = bridge(server, irc, binary).await;
+            });
+
+            client
+                .write_all(
+                    b"GET / HTTP/1.1\r\n\
+                      Host: irc.example.com\r\n\
+                      Upgrade: websocket\r\n\
+                      Connection: Upgrade\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                      Sec-WebSocket-Protocol: text.ircv3.net\r\n\
+                      Sec-WebSocket-Version: 13\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut client = BufReader::new(client);
+            let mut response = String::new();
+            while !response.ends_with("\r\n\r\n") {
+                client.read_line(&mut response).await.unwrap();
+            }
+            assert!(response.starts_with("HTTP/1.1 101 "), "{response}");
+            assert!(
+                response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+                "{response}"
+            );
+
+            let (irc_reader, mut irc_writer) = io::split(irc_client);
+            let mut irc_reader = BufReader::new(irc_reader);
+            client
+                .write_all(&client_frame(false, OP_TEXT, b"NICK "))
+                .await
+                .unwrap();
+            client
+                .write_all(&client_frame(true, OP_PING, b"ping"))
+                .await
+                .unwrap();
+            client
+                .write_all(&client_frame(true, OP_CONTINUATION, b"alice"))
+                .await
+                .unwrap();
+            let mut line = String::new();
+            irc_reader.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "NICK alice\r\n");
+
+            let mut frame = [0; 6];
+            client.read_exact(&mut frame).await.unwrap();
+            assert_eq!(&frame, b"\x8a\x04ping");
+
+            irc_writer
+                .write_all(b":irc.example.com 001 alice :Hi\r\n")
+                .await
+                .unwrap();
+            let mut head = [0; 2];
+            client.read_exact(&mut head).await.unwrap();
+            assert_eq!(head, [0x81, 30]);
+            let mut payload = [0; 30];
+            client.read_exact(&mut payload).await.unwrap();
+            assert_eq!(&payload, b":irc.example.com 001 alice :Hi");
+
+            client
+                .write_all(&client_frame(true, OP_CLOSE, b""))
+                .await
+                .unwrap();
+            line.clear();
+            assert_eq!(irc_reader.read_line(&mut line).await.unwrap(), 0);
+        });
+    }
+}